@@ -0,0 +1,295 @@
+//! Actions of the application, and the default bindings of keys and
+//! controller buttons to them.
+//!
+//! Every input (keys, mouse buttons, controllers, the command palette) is
+//! turned into an Action, which is run by `App::perform`. The same entry
+//! point can thus be used by tests and by scripted input, without going
+//! through SDL events.
+
+use sdl2::controller::Button;
+use sdl2::keyboard::Keycode;
+
+use crate::application::PanDirection;
+
+
+/// Direction of a zoom.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ZoomDirection {
+    In,
+    Out,
+}
+
+/// An action of the application.
+///
+/// Pan pans by one step and keeps panning while the pan is not stopped by
+/// StopPan, as when a pan key is held. Move(i) moves the current image with
+/// the move action at index i of the configuration. BlindPick(i) picks the
+/// first (left or top) or second pane in blind mode.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Action {
+    NextImage,
+    PrevImage,
+    NextToReview,
+    NextCmd,
+    PrevCmd,
+    SelectBest,
+    Validate,
+    Undo,
+    ApplyDuplicateDecision,
+    MarkRange,
+    PlanExtension,
+    Move(usize),
+    OpenCmdList,
+    OpenPalette,
+    OpenNote,
+    OpenStats,
+    OpenTrash,
+    ToggleProcessAll,
+    ToggleSavingsOrder,
+    RetryFailed,
+    Reprocess,
+    Zoom(ZoomDirection),
+    Fit,
+    ToggleActualSize,
+    Pan(PanDirection),
+    StopPan(PanDirection),
+    ToggleFullscreen,
+    SwapPanes,
+    ToggleSinglePane,
+    UnlockPanes,
+    LockPanes,
+    Rotate,
+    Mirror,
+    Brighten,
+    Darken,
+    ResetExposure,
+    CycleGrid,
+    ToggleHotspots,
+    TogglePerfOverlay,
+    ToggleMetadata,
+    ToggleProxy,
+    ToggleBlind,
+    BlindPick(usize),
+    ToggleMacroRecording,
+    ReplayMacro,
+    SaveSettings,
+    PinVariant,
+    JumpToPinned,
+    TourNext,
+    TourPrev,
+    QualityUp,
+    QualityDown,
+}
+
+/// Actions listed in the command palette, with their name and their key
+/// (empty if they have none).
+pub const PALETTE_ACTIONS: &[(&str, &str, Action)] = &[
+    ("Next image", ";", Action::NextImage),
+    ("Previous image", ",", Action::PrevImage),
+    ("Next image to review", ".", Action::NextToReview),
+    ("Next command", "n", Action::NextCmd),
+    ("Previous command", "p", Action::PrevCmd),
+    ("Pin the current variant, or unpin it", "ctrl+k", Action::PinVariant),
+    ("Switch between the pinned and the current variant", "ctrl+j", Action::JumpToPinned),
+    ("Select best variant", "b", Action::SelectBest),
+    ("Validate image", "space", Action::Validate),
+    ("Cancel validated image", "u", Action::Undo),
+    ("Apply decision of duplicate image", "=", Action::ApplyDuplicateDecision),
+    ("Mark the start of a range to validate", "ctrl+v", Action::MarkRange),
+    ("Plan the command for the remaining images with the same extension", "", Action::PlanExtension),
+    ("Open the command list", "tab", Action::OpenCmdList),
+    ("Toggle processing of all commands", "c", Action::ToggleProcessAll),
+    ("Toggle ordering by savings", "v", Action::ToggleSavingsOrder),
+    ("Zoom in", "o", Action::Zoom(ZoomDirection::In)),
+    ("Zoom out", "i", Action::Zoom(ZoomDirection::Out)),
+    ("Fit images", "s", Action::Fit),
+    ("Toggle real size", "", Action::ToggleActualSize),
+    ("Guided tour: show the next region at real size", "y", Action::TourNext),
+    ("Guided tour: show the previous region", "ctrl+y", Action::TourPrev),
+    ("Raise the quality of the variant (%q)", "+", Action::QualityUp),
+    ("Lower the quality of the variant (%q)", "-", Action::QualityDown),
+    ("Retry failed processing", "", Action::RetryFailed),
+    ("Reprocess the image after its source was modified", "ctrl+r", Action::Reprocess),
+    ("Show command statistics", "", Action::OpenStats),
+    ("Review the trash", "ctrl+t", Action::OpenTrash),
+    ("Toggle performance overlay", "F3", Action::TogglePerfOverlay),
+    ("Toggle metadata panel", "ctrl+m", Action::ToggleMetadata),
+    ("Toggle fast preview / full quality", "ctrl+f", Action::ToggleProxy),
+    ("Toggle blind comparison mode", "ctrl+b", Action::ToggleBlind),
+    ("Pick the left or top pane in blind mode", "ctrl+1", Action::BlindPick(0)),
+    ("Pick the right or bottom pane in blind mode", "ctrl+2", Action::BlindPick(1)),
+    ("Toggle full screen", "f", Action::ToggleFullscreen),
+    ("Swap panes", "e", Action::SwapPanes),
+    ("Toggle single pane (processed image only)", "w", Action::ToggleSinglePane),
+    ("Unlock panes / switch focused pane", "x", Action::UnlockPanes),
+    ("Lock and re-sync panes", "z", Action::LockPanes),
+    ("Rotate the view by 90° clockwise", "r", Action::Rotate),
+    ("Mirror the view horizontally", "m", Action::Mirror),
+    ("Brighten the view", "]", Action::Brighten),
+    ("Darken the view", "[", Action::Darken),
+    ("Reset the view brightness", "\\", Action::ResetExposure),
+    ("Cycle grid overlay", "g", Action::CycleGrid),
+    ("Toggle the artifact hotspots of the variant", "ctrl+h", Action::ToggleHotspots),
+    ("Attach a note to the image", "t", Action::OpenNote),
+    ("Start or stop recording a macro", "q", Action::ToggleMacroRecording),
+    ("Replay the recorded macro", "@", Action::ReplayMacro),
+    ("Save the settings changed at runtime to the config file", "ctrl+s", Action::SaveSettings),
+];
+
+
+/// Returns the action bound to a key press, if any. `ctrl` and `shift` are
+/// true when a control or a shift key is held.
+///
+/// Quitting, and the move actions bound in the configuration, are handled by
+/// the caller.
+pub fn key_action(key: Keycode, ctrl: bool, shift: bool) -> Option<Action> {
+    let action = match key {
+        // @ is shift+2 on most layouts, SDL then reports the 2 key.
+        Keycode::Num2 if shift && !ctrl => Action::ReplayMacro,
+        Keycode::At => Action::ReplayMacro,
+        // + is shift+= on most layouts, SDL then reports the = key.
+        Keycode::Equals if shift && !ctrl => Action::QualityUp,
+        Keycode::Plus | Keycode::KpPlus => Action::QualityUp,
+        Keycode::Minus | Keycode::KpMinus => Action::QualityDown,
+        Keycode::Q => Action::ToggleMacroRecording,
+        Keycode::P if ctrl => Action::OpenPalette,
+        Keycode::T if ctrl => Action::OpenTrash,
+        Keycode::V if ctrl => Action::MarkRange,
+        Keycode::M if ctrl => Action::ToggleMetadata,
+        Keycode::F if ctrl => Action::ToggleProxy,
+        Keycode::B if ctrl => Action::ToggleBlind,
+        Keycode::S if ctrl => Action::SaveSettings,
+        Keycode::K if ctrl => Action::PinVariant,
+        Keycode::J if ctrl => Action::JumpToPinned,
+        Keycode::R if ctrl => Action::Reprocess,
+        Keycode::Y if ctrl => Action::TourPrev,
+        Keycode::H if ctrl => Action::ToggleHotspots,
+        Keycode::Num1 if ctrl => Action::BlindPick(0),
+        Keycode::Num2 if ctrl => Action::BlindPick(1),
+        Keycode::Semicolon => Action::NextImage,
+        Keycode::Comma => Action::PrevImage,
+        Keycode::Period => Action::NextToReview,
+        Keycode::N => Action::NextCmd,
+        Keycode::P => Action::PrevCmd,
+        Keycode::B => Action::SelectBest,
+        Keycode::V => Action::ToggleSavingsOrder,
+        Keycode::C => Action::ToggleProcessAll,
+        Keycode::Tab => Action::OpenCmdList,
+        Keycode::Space => Action::Validate,
+        Keycode::U => Action::Undo,
+        Keycode::Equals => Action::ApplyDuplicateDecision,
+        Keycode::O => Action::Zoom(ZoomDirection::In),
+        Keycode::I => Action::Zoom(ZoomDirection::Out),
+        Keycode::H => Action::Pan(PanDirection::Left),
+        Keycode::J => Action::Pan(PanDirection::Down),
+        Keycode::K => Action::Pan(PanDirection::Up),
+        Keycode::L => Action::Pan(PanDirection::Right),
+        Keycode::F => Action::ToggleFullscreen,
+        Keycode::E => Action::SwapPanes,
+        Keycode::W => Action::ToggleSinglePane,
+        Keycode::X => Action::UnlockPanes,
+        Keycode::Z => Action::LockPanes,
+        Keycode::R => Action::Rotate,
+        Keycode::M => Action::Mirror,
+        Keycode::RightBracket => Action::Brighten,
+        Keycode::LeftBracket => Action::Darken,
+        Keycode::Backslash => Action::ResetExposure,
+        Keycode::F3 => Action::TogglePerfOverlay,
+        Keycode::G => Action::CycleGrid,
+        Keycode::T => Action::OpenNote,
+        Keycode::S => Action::Fit,
+        Keycode::Y => Action::TourNext,
+        _ => return None,
+    };
+
+    Some(action)
+}
+
+/// Returns the action run when a key is released, i.e. stopping the pan of a
+/// pan key.
+pub fn key_release_action(key: Keycode) -> Option<Action> {
+    match key_action(key, false, false) {
+        Some(Action::Pan(direction)) => Some(Action::StopPan(direction)),
+        _ => None,
+    }
+}
+
+/// Returns the action bound to a controller button, if any.
+pub fn controller_action(button: Button) -> Option<Action> {
+    let action = match button {
+        Button::DPadRight => Action::NextImage,
+        Button::DPadLeft => Action::PrevImage,
+        Button::DPadDown => Action::NextCmd,
+        Button::DPadUp => Action::PrevCmd,
+        Button::A => Action::Validate,
+        Button::B => Action::Undo,
+        Button::X => Action::ToggleActualSize,
+        Button::Y => Action::SelectBest,
+        Button::Start => Action::NextToReview,
+        Button::Back => Action::ToggleFullscreen,
+        Button::RightShoulder => Action::Zoom(ZoomDirection::In),
+        Button::LeftShoulder => Action::Zoom(ZoomDirection::Out),
+        _ => return None,
+    };
+
+    Some(action)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns the key and control state described by a key of the palette,
+    /// e.g. "ctrl+t".
+    fn parse_key(key: &str) -> (Keycode, bool) {
+        let (ctrl, name) = match key.strip_prefix("ctrl+") {
+            Some(name) => (true, name),
+            None => (false, key),
+        };
+
+        (Keycode::from_name(name).unwrap_or_else(|| panic!("unknown key {name}")), ctrl)
+    }
+
+    #[test]
+    fn palette_keys_match_key_bindings() {
+        for &(name, key, action) in PALETTE_ACTIONS.iter().filter(|a| !a.1.is_empty()) {
+            let (keycode, ctrl) = parse_key(key);
+            assert_eq!(key_action(keycode, ctrl, false), Some(action), "{name} ({key})");
+        }
+    }
+
+    #[test]
+    fn control_changes_the_action() {
+        assert_eq!(key_action(Keycode::P, false, false), Some(Action::PrevCmd));
+        assert_eq!(key_action(Keycode::P, true, false), Some(Action::OpenPalette));
+        assert_eq!(key_action(Keycode::T, true, false), Some(Action::OpenTrash));
+        assert_eq!(key_action(Keycode::V, true, false), Some(Action::MarkRange));
+    }
+
+    #[test]
+    fn shift_2_replays_macro() {
+        assert_eq!(key_action(Keycode::Num2, false, true), Some(Action::ReplayMacro));
+        assert_eq!(key_action(Keycode::Num2, true, false), Some(Action::BlindPick(1)));
+        assert_eq!(key_action(Keycode::Num2, false, false), None);
+    }
+
+    #[test]
+    fn shift_equals_raises_quality() {
+        assert_eq!(key_action(Keycode::Equals, false, true), Some(Action::QualityUp));
+        assert_eq!(key_action(Keycode::Equals, false, false), Some(Action::ApplyDuplicateDecision));
+    }
+
+    #[test]
+    fn releasing_pan_key_stops_pan() {
+        assert_eq!(key_release_action(Keycode::H), Some(Action::StopPan(PanDirection::Left)));
+        assert_eq!(key_release_action(Keycode::L), Some(Action::StopPan(PanDirection::Right)));
+        assert_eq!(key_release_action(Keycode::N), None);
+    }
+
+    #[test]
+    fn unbound_keys_have_no_action() {
+        assert_eq!(key_action(Keycode::D, false, false), None);
+        assert_eq!(key_action(Keycode::Escape, false, false), None);
+    }
+}
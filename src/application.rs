@@ -1,44 +1,361 @@
 use sdl2::rect::Point;
 use sdl2::rect::Rect;
-use sdl2::ttf::Font;
+use sdl2::ttf::{Font, FontStyle};
 use sdl2::ttf::Sdl2TtfContext;
 use sdl2::video::FullscreenType;
+use std::cell::{Ref, RefCell};
+use std::collections::{BTreeSet, HashMap, HashSet, VecDeque};
 use std::fs;
 use std::path::Path;
 use std::path::PathBuf;
 use std::sync::mpsc;
 use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+
+use chrono::Utc;
 
 use sdl2::image::LoadTexture;
-use sdl2::pixels::Color;
-use sdl2::render::{Canvas, Texture, TextureCreator};
+use sdl2::keyboard::Keycode;
+use sdl2::mouse::MouseButton;
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{BlendMode, Canvas, Texture, TextureCreator};
 use sdl2::video::{Window, WindowContext};
 
-use crate::rect_utils::ViewRect;
+use crate::rect_utils::{checkmark_lines, checkmark_rect, pane_rects, progress_fill, selection_border, split_side_by_side, tour_regions, Orientation, ViewRect};
 
 use crate::processing_order::*;
 use crate::settings::*;
 use crate::utils::*;
 use crate::sdl_utils::*;
 use crate::img::*;
+use crate::phash::*;
+use crate::rules::*;
+use crate::preferences::CmdPreferences;
+use crate::session::{write_session_file, Decision, Session};
+use crate::stats::Stats;
+use crate::blind::BlindResults;
+use crate::artifacts::{ArtifactKind, Hotspot};
+use crate::compute::{ComputePool, Job, JobResult};
+use crate::cache::LruCache;
+use crate::decode::{spawn_decode, spawn_preview, PixelBuffer, Preview};
+use crate::trash::{self, TrashEntry};
+use crate::palette::{self, Palette};
+use crate::perf::Perf;
+use crate::renderer::{Blit, Renderer};
+use crate::builtin::builtin_command;
+use crate::action::{Action, ZoomDirection, PALETTE_ACTIONS};
+use crate::metadata::{self, FileMetadata};
+use crate::paths;
+use crate::theme::Theme;
+use crate::recent::{common_directory, Recent, RecentSession};
+use crate::report::{send_report, SessionReport};
+use crate::progress::{diagnostic, ProgressEvent};
+use crate::moves::{MoveRequest, MoveResult, MoveWorker};
+
+/// Number of commands above which processing all commands at once requires a
+/// confirmation.
+const PROCESS_ALL_WARNING: usize = 10;
+
+/// Delay within which a destructive action must be repeated to be confirmed,
+/// when confirm_destructive is set.
+const CONFIRM_DELAY: Duration = Duration::from_secs(2);
+
+/// Maximum number of processing threads running at once, for the prepass of
+/// the savings order.
+const PREPASS_JOBS: usize = 4;
+
+/// Number of images before and after the current one for which previews are
+/// computed in advance. Previews further than twice this range are dropped.
+const PREVIEW_RANGE: usize = 5;
+
+/// Number of images before and after the current one whose sources are
+/// decoded in advance into the texture cache, so they are displayed at once.
+const PRELOAD_RANGE: usize = 2;
+
+/// Distance panned when a pan key is pressed, in pixels.
+const PAN_STEP: f32 = 50.;
+
+/// Delay after which a held pan key pans continuously.
+const PAN_HOLD_DELAY: Duration = Duration::from_millis(200);
+
+/// Speed of the continuous pan when it starts, in pixels per second.
+const PAN_SPEED: f32 = 600.;
+
+/// Increase of the continuous pan speed, in pixels per second per second.
+const PAN_ACCELERATION: f32 = 2000.;
+
+/// Maximum speed of the continuous pan, in pixels per second.
+const PAN_MAX_SPEED: f32 = 5000.;
+
+/// Maximum number of images being processed listed in the queue status.
+const QUEUE_STATUS_PATHS: usize = 4;
+
+/// Position of a controller stick below which it is considered at rest.
+const STICK_DEADZONE: i16 = 8000;
+
+/// Number of entries listed at once by the trash screen.
+const TRASH_SCREEN_ROWS: usize = 10;
+
+/// Minimum zoom factor for the pixel grid to be drawn, below it the lines
+/// would hide the image.
+const PIXEL_GRID_MIN_ZOOM: f32 = 4.;
+
+/// Ratio between the size of the images and of their proxies, in fast
+/// preview mode.
+const PROXY_SCALE: u32 = 2;
+
+/// Half size of the crosshair drawn at the split in continuous mode.
+const CROSSHAIR_SIZE: i32 = 15;
+
+/// Background of the text drawn over the images, translucent so that the
+/// image stays visible below it.
+const OVERLAY_BACKGROUND: Color = Color::RGBA(0, 0, 0, 176);
+
+/// Space between the text drawn over the images and the border of its
+/// background, in pixels.
+const OVERLAY_PADDING: u32 = 4;
+
+/// Feedback shown when the source of the current image was modified since its
+/// variants were processed.
+const OUTDATED_FEEDBACK: &str = "Source modified since it was processed, ctrl+r reprocesses it";
+
+/// Number of images of the list added per frame after the first one, see
+/// App::ingest.
+const INGEST_CHUNK: usize = 256;
+
+/// Direction of a pan, i.e. the direction the image moves on screen.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PanDirection {
+    Left,
+    Down,
+    Up,
+    Right,
+}
+
+impl PanDirection {
+    /// Returns the unit vector of the direction, in screen coordinates.
+    fn vector(&self) -> (f32, f32) {
+        match self {
+            PanDirection::Left => (-1., 0.),
+            PanDirection::Down => (0., 1.),
+            PanDirection::Up => (0., -1.),
+            PanDirection::Right => (1., 0.),
+        }
+    }
+}
+
+/// Returns the speed of the continuous pan after the key was held for the
+/// provided duration, in pixels per second.
+fn pan_speed(held: Duration) -> f32 {
+    let accelerating = held.saturating_sub(PAN_HOLD_DELAY).as_secs_f32();
+
+    f32::min(PAN_SPEED + PAN_ACCELERATION * accelerating, PAN_MAX_SPEED)
+}
+
+/// Identifies a loaded texture: the path of the file, and its modification
+/// time so that a file modified on disk is loaded again.
+type TextureKey = (PathBuf, Option<SystemTime>);
+
+/// Identifies the files compared by the metadata panel: the source, and the
+/// variant if it is processed.
+type MetadataKey = (PathBuf, Option<PathBuf>);
+
+/// Overlay drawn over both panes to help matching pixels between them.
+#[derive(Clone, Copy, PartialEq)]
+enum GridMode {
+    Off,
+    Thirds,
+    Pixels,
+}
+
+/// State of the trash review screen.
+struct TrashScreen<'a> {
+    /// Entries of the trash manifest, latest first, with the size of the file.
+    entries: Vec<(TrashEntry, Option<u64>)>,
+    selected: usize,
+
+    /// True once delete was pressed, pressing it again deletes the selected
+    /// entry permanently.
+    confirm_delete: bool,
+    message: Option<String>,
+    thumbnail: Option<Texture<'a>>,
+    thumbnail_decode: Option<mpsc::Receiver<Result<Preview, String>>>,
+}
+
+/// State of the guided tour of the regions of an image, see tour_regions.
+struct Tour {
+    /// Image toured, the tour ends when another image is displayed.
+    img: usize,
+    regions: Vec<Point>,
+    index: usize,
+
+    /// When the current region was shown, the next one is shown after the
+    /// pause of the settings.
+    shown: Instant,
+}
 
 /// This struct is used to mannage the program. Key presses will trigger methods
 /// attached to it. There should only be one instance of this.
 pub struct App<'a> {
     settings: AppSettings,
+
+    /// Position of the source pane, with Auto resolved for the current image.
+    /// It is never Auto, which is handled as Left.
+    source_position: SourcePosition,
+
+    /// True when the panes are swapped at runtime, the source is then on the
+    /// other side of the split than in the settings.
+    panes_swapped: bool,
+
+    /// True when only the processed pane is displayed, over the whole window.
+    single_pane: bool,
+
+    /// Canvas of the window, every frame is drawn through its Renderer
+    /// implementation.
     canvas: &'a mut Canvas<Window>,
     cmds: Vec<String>,
+
+    /// Number of threads used by every command, see running_weight.
+    cmd_weights: Vec<u32>,
+
+    /// Sum of the weights of the commands being run. New processing only
+    /// starts while it stays below max_process_weight.
+    running_weight: u32,
     imgs: Vec<ImgItem>,
-    rxs: Vec<mpsc::Receiver<((usize, usize), ProcessItem)>>,
+
+    /// Sources of the list whose item isn't created yet. Only the first image
+    /// is created before showing the window, the rest a chunk per frame.
+    pending_paths: VecDeque<PathBuf>,
+
+    /// Chunks of the list sent by the list reader, None once the whole list
+    /// is received.
+    list_rx: Option<mpsc::Receiver<Vec<PathBuf>>>,
+
+    /// Channel of the processed variants, the sender is cloned into every
+    /// processing thread. The message of the panic is sent along the variant
+    /// if the processing panicked.
+    process_tx: mpsc::Sender<((usize, usize), ProcessItem, Option<String>)>,
+    process_rx: mpsc::Receiver<((usize, usize), ProcessItem, Option<String>)>,
+
+    /// Variants being processed, as (image, command).
+    processing: BTreeSet<(usize, usize)>,
+
+    /// Counts of the variants of all the images, kept up to date with
+    /// recount_variants when their states change.
+    variant_counts: VariantCounts,
+
+    /// Order in which the images around the current one are processed,
+    /// advanced as they are sent to be processed.
+    process_order: Closest2D,
+    pool: ComputePool,
+
+    /// Thread moving the files of the decisions, see moves.
+    moves: MoveWorker,
+
+    /// Prefix of the temporary files of this session, so that they don't
+    /// collide with the files of another instance.
+    tmp_namespace: String,
     index: usize,
     cmd_index: usize,
+
+    /// Command of the pinned variant, compared with the current one by
+    /// switching between them, see jump_to_pinned.
+    pinned: Option<usize>,
+    move_keys: Vec<Keycode>,
+    rules: Vec<Rule>,
+    quality_threshold: Filter,
+    process_all: bool,
+    process_all_confirm: bool,
+
+    /// Action and time of the first validation request, waiting for the
+    /// confirmation.
+    validate_confirm: Option<(Action, Instant)>,
+
+    /// Image at the start of the marked range. While a range is marked,
+    /// validating validates every image between it and the current one.
+    range_start: Option<usize>,
+
+    /// True when images are reviewed by savings, in the order of the ranking,
+    /// computed again once the savings changed.
+    savings_order: bool,
+    savings_ranking: RefCell<Option<SavingsRanking>>,
+    feedback: Option<String>,
+    cmds_enabled: Vec<bool>,
+    cmd_list: Option<usize>,
+    palette: Option<Palette>,
+    note_input: Option<String>,
+    preferences: CmdPreferences,
+    panes_locked: bool,
+    processed_focused: bool,
+    mouse_position: Option<Point>,
+    pan_keys: [Option<Instant>; 4],
+    pan_remainder: (f32, f32),
+    last_frame: Instant,
+    orientation: Orientation,
+    exposure: Exposure,
+    grid_mode: GridMode,
+
+    /// True when the artifact hotspots of the variant are marked, with the
+    /// hotspots of every (image, command), None while they are computed.
+    hotspot_overlay: bool,
+    hotspots: HashMap<(usize, usize), Option<Vec<Hotspot>>>,
+    last_autosave: Instant,
+    saved_session: String,
+    stats_baseline: Stats,
+    stats_screen: Option<String>,
+
+    /// In blind mode, whether the panes were swapped before entering it, to
+    /// restore them when leaving. None when not in blind mode.
+    blind: Option<bool>,
+    blind_results: BlindResults,
+
+    /// Actions recorded so far while recording a macro, and the last
+    /// recorded macro.
+    macro_recording: Option<Vec<Action>>,
+    macro_actions: Vec<Action>,
+
+    /// Whether the macro being replayed was confirmed to validate, its
+    /// validations are then not confirmed one by one.
+    replay_confirmed: bool,
+    perf: Perf,
+    perf_overlay: bool,
+
+    /// Format of the progress printed on stdout, if any.
+    progress: Option<ProgressFormat>,
+
+    /// True when the metadata panel is shown, with the lines of the last
+    /// diff computed and the source and processed files they describe.
+    metadata_panel: bool,
+    metadata_diff: Option<(MetadataKey, Vec<String>)>,
+    trash_screen: Option<TrashScreen<'a>>,
+    tour: Option<Tour>,
     source_view: ViewRect,
     processed_view: ViewRect,
     texture_creator: &'a TextureCreator<WindowContext>,
     source_texture: Texture<'a>,
     processed_texture: Texture<'a>,
+    source_key: Option<TextureKey>,
+    processed_key: Option<TextureKey>,
+    textures: LruCache<TextureKey, Texture<'a>>,
+    text_cache: TextCache<'a>,
+
+    /// True in fast preview mode, where images are displayed from proxies
+    /// of lower resolution, with the full size of the image of every proxy.
+    proxy: bool,
+    proxy_sizes: HashMap<TextureKey, (u32, u32)>,
+    previews: HashMap<usize, Preview>,
+    previews_requested: HashSet<usize>,
+
+    /// Images whose source is being decoded in advance.
+    preloads_requested: HashSet<usize>,
+
+    /// Images whose source is being copied to the processing directory.
+    copies_requested: HashSet<usize>,
+    source_decode: Option<(TextureKey, mpsc::Receiver<Result<PixelBuffer, String>>)>,
     ttf_context: &'a Sdl2TtfContext,
     font: Font<'a, 'a>,
+    bold_font: Font<'a, 'a>,
+    theme: Theme,
 }
 
 impl<'a> App<'a> {
@@ -46,9 +363,13 @@ impl<'a> App<'a> {
         canvas: &'a mut Canvas<Window>,
         texture_creator: &'a TextureCreator<WindowContext>,
         ttf_context: &'a Sdl2TtfContext,
-        img_paths: Vec<PathBuf>,
+        img_list: mpsc::Receiver<Vec<PathBuf>>,
+        resume: bool,
+        profile: Option<&str>,
+        accessible: bool,
     ) -> Result<Self, String> {
-        let settings = AppSettings::new().map_err(|e| format!("Error: {e}"))?;
+        let settings = AppSettings::new(profile).map_err(|e| format!("Error: {e}"))?;
+        let theme = Theme::new(settings.accessible || accessible, settings.color_palette);
 
         /*  The external conversion command must be provided with special characters
            denoting where to put the input and output file names in the command.
@@ -59,12 +380,54 @@ impl<'a> App<'a> {
                    be changed, it needs to be specified as `%o.ext` where `ext` is
                    the new extension.
         */
-        let cmds = read_file_lines(&settings.cmds_file).map_err(|e| e.to_string())?;
+        let (cmds, cmd_weights): (Vec<String>, Vec<u32>) = read_file_lines(&settings.cmds_file)
+            .map_err(|e| e.to_string())?
+            .iter()
+            .map(|line| parse_command_line(line).map_err(|e| format!("Error: {e}")))
+            .collect::<Result<Vec<(String, u32)>, String>>()?
+            .into_iter()
+            .unzip();
+
+        // Any quality is valid where %q is substituted.
+        for cmd in cmds.iter().map(|cmd| with_quality(cmd, settings.quality)) {
+            for stage in pipeline_stages(&cmd) {
+                if let Some(Err(e)) = builtin_command(stage) {
+                    return Err(format!("Error: {e}"));
+                }
+            }
+        }
+
+        // In sandbox mode, commands which are not allowed are refused before
+        // running anything.
+        if settings.sandbox {
+            let metric_cmds = settings.metrics.iter().map(|m| &m.command);
+            for cmd in cmds.iter().chain(metric_cmds) {
+                check_allowed_command(cmd, &settings.allowed_commands).map_err(|e| format!("Error: {e}"))?;
+            }
+        }
+
+        let move_keys = settings.move_actions
+            .iter()
+            .map(|a| Keycode::from_name(&a.key)
+                 .ok_or_else(|| format!("Error: unknown key '{}' in move_actions", a.key)))
+            .collect::<Result<Vec<Keycode>, String>>()?;
+
+        let rules = settings.rules
+            .iter()
+            .map(|r| r.parse::<Rule>().map_err(|e| format!("Error: {e}")))
+            .collect::<Result<Vec<Rule>, String>>()?;
+
+        let quality_threshold = match &settings.quality_threshold {
+            Some(t) => t.parse::<Filter>().map_err(|e| format!("Error: quality_threshold: {e}"))?,
+            None => Filter::default(),
+        };
         //
         // Load font
-        let font_path = expand_tilde("~/bimgo/fonts/FiraMono-Medium.ttf")
+        let font_path = expand_tilde(paths::font_file())
                 .map_err(|e| format!("{e}"))?;
-        let font = ttf_context.load_font(font_path, 30)?;
+        let font = ttf_context.load_font(&font_path, theme.font_size)?;
+        let mut bold_font = ttf_context.load_font(&font_path, theme.font_size)?;
+        bold_font.set_style(FontStyle::BOLD);
 
         let source_texture = texture_creator
             .create_texture_static(None, 1, 1)
@@ -74,30 +437,134 @@ impl<'a> App<'a> {
             .create_texture_static(None, 1, 1)
             .map_err(|e| e.to_string())?;
 
-        let imgs = img_paths
-            .iter()
-            .map(|item| ImgItem::new(item, cmds.len()))
-            .collect::<Vec<ImgItem>>();
+        // The session refers to images anywhere in the list, which is read
+        // entirely before resuming. Otherwise only the first path is awaited.
+        let mut pending_paths = VecDeque::new();
+        let list_rx = match resume {
+            true => {
+                pending_paths.extend(img_list.iter().flatten());
+                None
+            }
+            false => {
+                pending_paths.extend(img_list.recv().unwrap_or_default());
+                Some(img_list)
+            }
+        };
+
+        let (process_tx, process_rx) = mpsc::channel();
+        let tmp_namespace = settings.tmp_namespace.clone().unwrap_or_else(session_namespace);
+        let pool = ComputePool::new(settings.compute_threads);
+        let moves = MoveWorker::new(settings.clone());
+
+        if !(settings.min_zoom > 0. && settings.min_zoom <= settings.max_zoom) {
+            return Err("Error: min_zoom must be positive and lower than max_zoom".to_string());
+        }
+
+        let mut source_view = ViewRect::default();
+        let mut processed_view = ViewRect::default();
+        source_view.set_zoom_limits(settings.min_zoom, settings.max_zoom);
+        processed_view.set_zoom_limits(settings.min_zoom, settings.max_zoom);
+
+        let process_all = settings.process_all_commands;
+        let proxy = settings.fast_preview;
+        let cmds_len = cmds.len();
 
         let mut app = App {
+            source_position: SourcePosition::Left,
+            panes_swapped: false,
+            single_pane: settings.display_mode == DisplayMode::Single,
             settings,
             canvas,
             cmds,
-            imgs,
-            rxs: Vec::new(),
+            cmd_weights,
+            running_weight: 0,
+            imgs: Vec::new(),
+            pending_paths,
+            list_rx,
+            process_tx,
+            process_rx,
+            processing: BTreeSet::new(),
+            variant_counts: VariantCounts::default(),
+            tmp_namespace,
+            process_order: Closest2D::new(0, 0, 0, 0, 0, 0),
+            pool,
+            moves,
             index: 0,
             cmd_index: 0,
-            source_view: ViewRect::default(),
-            processed_view: ViewRect::default(),
+            pinned: None,
+            move_keys,
+            rules,
+            quality_threshold,
+            process_all,
+            process_all_confirm: false,
+            validate_confirm: None,
+            range_start: None,
+            savings_order: false,
+            savings_ranking: RefCell::new(None),
+            feedback: None,
+            cmds_enabled: vec![true; cmds_len],
+            cmd_list: None,
+            palette: None,
+            note_input: None,
+            preferences: CmdPreferences::default(),
+            panes_locked: true,
+            processed_focused: false,
+            mouse_position: None,
+            pan_keys: [None; 4],
+            pan_remainder: (0., 0.),
+            last_frame: Instant::now(),
+            orientation: Orientation::default(),
+            exposure: Exposure::default(),
+            grid_mode: GridMode::Off,
+            hotspot_overlay: false,
+            hotspots: HashMap::new(),
+            last_autosave: Instant::now(),
+            saved_session: String::new(),
+            stats_baseline: Stats::default(),
+            stats_screen: None,
+            blind: None,
+            blind_results: BlindResults::default(),
+            macro_recording: None,
+            macro_actions: Vec::new(),
+            replay_confirmed: false,
+            perf: Perf::default(),
+            perf_overlay: false,
+            progress: None,
+            metadata_panel: false,
+            metadata_diff: None,
+            trash_screen: None,
+            tour: None,
+            source_view,
+            processed_view,
             texture_creator,
             source_texture,
             processed_texture,
+            source_key: None,
+            processed_key: None,
+            textures: LruCache::default(),
+            text_cache: TextCache::new(texture_creator),
+            proxy,
+            proxy_sizes: HashMap::new(),
+            previews: HashMap::new(),
+            previews_requested: HashSet::new(),
+            preloads_requested: HashSet::new(),
+            copies_requested: HashSet::new(),
+            source_decode: None,
             ttf_context,
             font,
+            bold_font,
+            theme,
         };
 
+        app.ingest(match resume {
+            true => usize::MAX,
+            false => 1,
+        });
         app.update_views()?;
         app.first_image()?;
+        if resume {
+            app.resume_session()?;
+        }
 
         Ok(app)
     }
@@ -115,77 +582,57 @@ impl<'a> App<'a> {
         Rect::new(0, 0, w, h)
     }
 
+    /// Returns the current location of the source image, which differs from
+    /// its original location if it was moved to trash or to a folder.
     fn get_source_path(&self) -> PathBuf {
-        if self.imgs[self.index].is_validated() {
-            // load source is validated
-            if let Some(d) = &self.imgs[self.index].deleted {
-                return d.clone();
-            } 
-        } else {
-            // load source is not validated
-            return self.imgs[self.index].source.clone();
-        }
+        let img = &self.imgs[self.index];
 
-        PathBuf::from("")
+        img.moved()
+            .or(img.deleted())
+            .unwrap_or(img.input())
+            .to_path_buf()
     }
 
-    fn get_current_processed_path(&self) -> Result<PathBuf, String> {
-        if let Some(p) = self.imgs[self.index].get_validated() {
-            // load processed is validated
-            if let Some(ref o) = p.processed_path {
-                return Ok(o.clone());
-            }
-        } else if let Some(processed_img) = &self.imgs[self.index].processed[self.cmd_index] {
-            // load processed is not validated but processed
-            if let Some(ref processed_path) = processed_img.tmp_path {
-                return Ok(processed_path.clone());
-            }
-        }
+    /// Returns the process item displayed in the processed pane, i.e. the
+    /// validated one if any, otherwise the one of the current command.
+    fn get_current_process_item(&self) -> Option<&ProcessItem> {
+        self.imgs[self.index]
+            .get_validated()
+            .or(self.imgs[self.index].processed[self.cmd_index].as_ref())
+    }
 
-        Err(String::from("Processed image at index currently not available"))
+    fn get_current_processed_path(&self) -> Result<PathBuf, String> {
+        self.get_current_process_item()
+            .and_then(ProcessItem::current_path)
+            .map(Path::to_path_buf)
+            .ok_or_else(|| String::from("Processed image at index currently not available"))
     }
 
     /// Draws a border around validated pictures, so the user has a visual cue
     /// of which file has been saved on disk.
+    ///
+    /// A checkmark is drawn in a corner as well, so that the cue doesn't only
+    /// rely on the color of the border. It stays upright whatever the
+    /// orientation of the view.
     fn draw_selected(&mut self) -> Result<(), String> {
         let clip = self
             .processed_view
             .clip_rect
             .intersection(self.processed_view.virt_rect);
         if let Some(clip) = clip {
-            let rect = self.processed_view.clip_rect;
-            let thickness = std::cmp::min(rect.height(), rect.width()) / 20;
-
-            let mut outer = clip;
-            let mut side_1 = clip;
-            let mut side_2;
-
-            match self.settings.source_position {
-                SourcePosition::Left | SourcePosition::Right => {
-                    outer.set_width(thickness);
-                    side_1.set_height(thickness);
-                    side_1.set_y(clip.top());
-                    side_2 = side_1;
-                    side_2.set_bottom(clip.bottom());
-                }
-                SourcePosition::Top | SourcePosition::Bottom => {
-                    outer.set_height(thickness);
-                    side_1.set_width(thickness);
-                    side_1.set_x(clip.left());
-                    side_2 = side_1;
-                    side_2.set_right(clip.right());
-                }
-            };
+            let pane = self.processed_view.clip_rect;
+            let rects = selection_border(clip, pane, self.source_position, self.theme.border_fraction);
 
-            match self.settings.source_position {
-                SourcePosition::Left => outer.set_right(clip.right()),
-                SourcePosition::Right => outer.set_x(clip.left()),
-                SourcePosition::Top => outer.set_bottom(clip.bottom()),
-                SourcePosition::Bottom => outer.set_y(clip.top()),
-            }
+            let center = self.orientation_center(&self.processed_view);
+            let rects = rects.map(|r| self.orientation.transform_rect(r, center));
+
+            self.canvas.draw_border(&rects, self.theme.selected)?;
 
-            self.canvas.set_draw_color(Color::RGBA(0, 128, 128, 255));
-            self.canvas.fill_rects(&[outer, side_1, side_2])?;
+            let size = 3 * pane.width().min(pane.height()) / self.theme.border_fraction;
+            let mark = checkmark_rect(clip, self.source_position, size);
+            let mark = self.orientation.transform_rect(mark, center);
+            self.canvas.draw_border(&[mark], OVERLAY_BACKGROUND)?;
+            self.canvas.draw_segments(&checkmark_lines(mark), self.theme.selected, None)?;
         }
 
         Ok(())
@@ -204,29 +651,65 @@ impl<'a> App<'a> {
             return Ok(());
         };
 
-        let info_str = format!("{}\nsize: {}", 
+        let mut info_str = format!("{}\nsize: {}", 
                                source_path.display(), 
                                human_readable_size(source_md.len()));
 
+        match self.imgs[self.index].auto_decision {
+            Some(RuleAction::Accept) => info_str += "\nauto: accepted by rules",
+            Some(RuleAction::Reject) => info_str += "\nauto: rejected by rules",
+            None => (),
+        }
+
+        if let Some(d) = self.find_duplicate() {
+            let dup = &self.imgs[d];
+            let decision = match dup.decision() {
+                ImageDecision::Moved { .. } => "moved, = to apply",
+                ImageDecision::Validated { .. } => "validated, = to apply",
+                ImageDecision::Undecided => "undecided",
+            };
+            info_str += &format!("\nduplicate of: {} ({decision})", dup.source.display());
+        }
+
+        if let Some(note) = &self.imgs[self.index].note {
+            info_str += &format!("\nnote: {note}");
+        }
+
+        if let Some(c) = self.imgs[self.index].planned {
+            info_str += &format!("\nplanned: {} on quit, u to cancel", self.cmds[c]);
+        }
+
+        if let Some((a, b)) = self.marked_positions() {
+            info_str += &format!("\nrange: {} images marked, space to validate", b - a + 1);
+        }
+
         // Draw at correct position
-        let (w, h) = self.window_size();
+        let (_, h) = self.window_size();
+        let x = self.source_view.clip_rect.left();
 
-        let (position, anchor) = match self.settings.source_position {
+        let (position, anchor) = match self.source_position {
             SourcePosition::Top     => (Point::new(0, 0), Anchor::TopLeft),
             SourcePosition::Bottom  => (Point::new(0, h as i32), Anchor::BottomLeft),
-            SourcePosition::Left    => (Point::new(0, h as i32), Anchor::BottomLeft),
-            SourcePosition::Right   => (Point::new(w as i32 / 2, h as i32), Anchor::BottomLeft),
+            SourcePosition::Left | SourcePosition::Auto
+                                    => (Point::new(0, h as i32), Anchor::BottomLeft),
+            SourcePosition::Right   => (Point::new(x, h as i32), Anchor::BottomLeft),
         };
 
-        let txt = TextBox::new(&info_str, &self.font, self.texture_creator)
-            .wrapped(self.source_view.clip_rect.width());
+        let txt = TextBox::new(&info_str, &self.font)
+            .wrapped(self.source_view.clip_rect.width().saturating_sub(2 * OVERLAY_PADDING).max(1))
+            .padded(OVERLAY_PADDING)
+            .background(OVERLAY_BACKGROUND);
 
-        txt.draw(self.canvas, position, anchor)?;
+        txt.draw(self.canvas, &mut self.text_cache, position, anchor)?;
 
         Ok(())
     }
 
     fn draw_processed_data(&mut self) -> Result<(), String>{
+        let (metrics, rule) = self.get_current_process_item()
+            .map(|p| (p.metrics.clone(), p.rule))
+            .unwrap_or_default();
+
         let processed_path = if let Ok(path) = self.get_current_processed_path(){
             path
         } else {
@@ -239,215 +722,1267 @@ impl<'a> App<'a> {
             return Ok(());
         };
 
-        let info_str = format!("{}\nsize: {}", 
-                               processed_path.display(), 
-                               human_readable_size(processed_md.len()));
+        // The command is emphasized, and the change of size is colored.
+        let quality = self.get_current_process_item().and_then(|p| p.quality).unwrap_or(self.settings.quality);
+        let mut segments = vec![
+            Segment::bold(with_quality(&self.cmds[self.cmd_index], quality)),
+            Segment::plain(format!("\n{}\nsize: {}",
+                                   processed_path.display(),
+                                   human_readable_size(processed_md.len()))),
+        ];
+
+        if let Ok(source_md) = fs::metadata(self.get_source_path()) {
+            let source_len = source_md.len().max(1) as f64;
+            let change = (processed_md.len() as f64 - source_len) / source_len * 100.;
+            let color = match change <= 0. {
+                true => self.theme.savings,
+                false => self.theme.growth,
+            };
+            segments.push(Segment::colored(format!(" ({change:+.0}%)"), color));
+        }
+
+        let mut info_str = String::new();
+        for (name, value) in metrics {
+            info_str += &format!("\n{name}: {value}");
+        }
+
+        if self.imgs[self.index].best_variant(&self.quality_threshold, &self.cmds_enabled) == Some(self.cmd_index) {
+            info_str += "\nbest variant";
+        }
+
+        match rule {
+            Some(RuleAction::Accept) => info_str += "\nrule: accept",
+            Some(RuleAction::Reject) => info_str += "\nrule: reject",
+            None => (),
+        }
+        segments.push(Segment::plain(info_str));
 
         // Draw at correct position
-        let (w, h) = self.window_size();
+        let (_, h) = self.window_size();
+        let x = self.processed_view.clip_rect.left();
 
-        let (position, anchor) = match self.settings.source_position {
+        let (position, anchor) = match self.source_position {
             SourcePosition::Bottom  => (Point::new(0, 0), Anchor::TopLeft),
             SourcePosition::Top     => (Point::new(0, h as i32), Anchor::BottomLeft),
             SourcePosition::Right   => (Point::new(0, h as i32), Anchor::BottomLeft),
-            SourcePosition::Left    => (Point::new(w as i32 / 2, h as i32), Anchor::BottomLeft),
+            SourcePosition::Left | SourcePosition::Auto
+                                    => (Point::new(x, h as i32), Anchor::BottomLeft),
         };
 
-        let txt = TextBox::new(&info_str, &self.font, self.texture_creator)
-            .wrapped(self.processed_view.clip_rect.width());
+        let txt = TextBox::rich(segments, &self.font, &self.bold_font)
+            .wrapped(self.processed_view.clip_rect.width().saturating_sub(2 * OVERLAY_PADDING).max(1))
+            .padded(OVERLAY_PADDING)
+            .background(OVERLAY_BACKGROUND);
 
-        txt.draw(self.canvas, position, anchor)?;
+        txt.draw(self.canvas, &mut self.text_cache, position, anchor)?;
 
         Ok(())
     }
 
-    fn draw(&mut self) -> Result<(), String> {
-        self.canvas.set_draw_color(Color::RGB(36, 40, 59));
-        self.canvas.clear();
-
-        match self.settings.display_mode {
-            DisplayMode::Continuous => self.processed_view.sync_continuous_with(&self.source_view),
-            DisplayMode::Duplicate => self.processed_view.sync_duplicate_with(&self.source_view),
-        };
-
-        self.canvas.copy(
-            &self.source_texture,
-            Some(self.source_view.src_rect),
-            Some(self.source_view.dst_rect),
-        )?;
-        self.canvas.copy(
-            &self.processed_texture,
-            Some(self.processed_view.src_rect),
-            Some(self.processed_view.dst_rect),
-        )?;
-        if self.imgs[self.index].is_validated() {
-            self.draw_selected()?;
+    /// Draws the feedback message, if any, at the top of the window.
+    fn draw_feedback(&mut self) -> Result<(), String> {
+        if let Some(msg) = &self.feedback {
+            let (w, _) = self.window_size();
+            let txt = TextBox::new(msg, &self.font)
+                .padded(OVERLAY_PADDING)
+                .background(OVERLAY_BACKGROUND);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 2, 0), Anchor::Top)?;
         }
 
-        self.draw_source_data()?;
-        self.draw_processed_data()?;
-        self.canvas.present(); // Update the screen with canvas.
-
         Ok(())
     }
 
-    /// Calls the appropriate fit function based on settings then draws the image
-    pub fn fit_draw(&mut self) -> Result<(), String> {
-        let fit_rect = match self.settings.display_mode {
-            DisplayMode::Continuous => self.window_rect(),
-            DisplayMode::Duplicate => self.source_view.clip_rect,
+    /// Returns the pixel of the image displayed at a point of the window, in
+    /// the pane under the point, or None if there is no image there.
+    fn window_img_point(&self, pt: Point) -> Option<(u32, u32)> {
+        let view = match self.single_pane || self.processed_view.clip_rect.contains_point(pt) {
+            true => &self.processed_view,
+            false => &self.source_view,
         };
+        let pt = self.orientation.inverse_point(pt, self.orientation_center(view));
 
-        match self.settings.fit_mode {
-            FitMode::FitBest => self.source_view.fit_best_to_rect(fit_rect),
-            FitMode::FitWidth => self.source_view.fit_width_to_rect(fit_rect),
-            FitMode::FitHeight => self.source_view.fit_height_to_rect(fit_rect),
-            FitMode::Fill => self.source_view.fit_fill_to_rect(fit_rect),
-            _ => (),
+        view.img_point(pt)
+    }
+
+    /// Returns the image coordinates of the mouse cursor and of the center of
+    /// the focused view, e.g. "cursor 1420,980  center 1000,750".
+    fn coordinates_status(&self) -> String {
+        let format_point = |pt: Option<(u32, u32)>| match pt {
+            Some((x, y)) => format!("{x},{y}"),
+            None => "-".to_string(),
         };
-        self.draw()?;
 
-        Ok(())
+        let view = match !self.panes_locked && self.processed_focused || self.single_pane {
+            true => &self.processed_view,
+            false => &self.source_view,
+        };
+        let center = self.window_img_point(self.orientation_center(view));
+        let cursor = self.mouse_position.and_then(|pt| self.window_img_point(pt));
+
+        format!("cursor {}  center {}", format_point(cursor), format_point(center))
     }
 
-    /// Zooms towards the center of the image.
-    ///
-    /// Scale factor above 1.0 zooms in, while scale factor below 1.0 zooms out
-    fn zoom(&mut self, scale: f32) -> Result<(), String> {
-        let zoom_point = match self.settings.display_mode {
-            DisplayMode::Duplicate => self.source_view.clip_rect.center(),
-            DisplayMode::Continuous => {
-                (self.source_view.clip_rect.center() + self.processed_view.clip_rect.center()) / 2
-            }
+    /// Draws the status at the top right of the window: the image coordinates
+    /// of the cursor and of the view center, the macro being recorded, the
+    /// bytes saved by the validated images of the session, and while there is
+    /// background work, the images whose files are being moved, the
+    /// processing counts, the computations of the pool, and the images being
+    /// processed.
+    fn draw_queue_status(&mut self) -> Result<(), String> {
+        let mut status = self.coordinates_status();
+        if let Some(recording) = &self.macro_recording {
+            status += &format!("\nrecording macro: {} actions", recording.len());
+        }
+        if self.imgs.get(self.index).is_some_and(|img| img.outdated) {
+            status += "\nsource modified, ctrl+r reprocess";
+        }
+        if let Some(cmd) = self.pinned.filter(|_| self.blind.is_none()).and_then(|c| self.cmds.get(c)) {
+            status += &format!("\npinned: {cmd}");
+        }
+        let saved = self.imgs.iter().filter_map(|img| img.saved_bytes()).collect::<Vec<i64>>();
+        match saved.iter().sum::<i64>() {
+            _ if saved.is_empty() => (),
+            total if total >= 0 => status += &format!("\nsaved {} so far", human_readable_size(total as u64)),
+            total => status += &format!("\ngrew by {} so far", human_readable_size(total.unsigned_abs())),
         };
 
-        let (w, h) = self.window_size();
-        let window_rect = Rect::new(0, 0, w, h);
-        self.source_view
-            .zoom_towards_point_on_rect(zoom_point, window_rect, scale);
-        self.draw()?;
+        let (queued, computing) = self.pool.status();
+        let VariantCounts { done, failed } = self.variant_counts;
+
+        if self.moves.pending() > 0 {
+            status += &format!("\nmoving the files of {} images", self.moves.pending());
+        }
+
+        if !self.processing.is_empty() || queued > 0 || computing > 0 {
+            status += &format!(
+                "\nprocessing: {} running, {done} done, {failed} failed\ncompute: {computing} running, {queued} queued",
+                self.processing.len(),
+            );
+            for &(i, c) in self.processing.iter().take(QUEUE_STATUS_PATHS) {
+                let name = self.imgs[i].source.file_name().unwrap_or_default().to_string_lossy();
+                status += &format!("\n{name} (command {})", c + 1);
+            }
+            if self.processing.len() > QUEUE_STATUS_PATHS {
+                status += &format!("\n{} more", self.processing.len() - QUEUE_STATUS_PATHS);
+            }
+        }
+
+        let (w, _) = self.window_size();
+        let txt = TextBox::new(&status, &self.font)
+            .padded(OVERLAY_PADDING)
+            .background(OVERLAY_BACKGROUND);
+        txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32, 0), Anchor::TopRight)?;
 
         Ok(())
     }
 
-    pub fn zoom_in(&mut self) -> Result<(), String> {
-        self.zoom(1.1)?;
+    /// Draws a frame, and records the time it took.
+    fn draw(&mut self) -> Result<(), String> {
+        let start = Instant::now();
+        self.update_window_title()?;
+        let result = self.draw_frame();
+        self.perf.record_frame(start.elapsed(), self.pool.status());
 
-        Ok(())
+        result
     }
 
-    pub fn zoom_out(&mut self) -> Result<(), String> {
-        self.zoom(0.9)?;
+    /// Sets the title of the window to the progress of the review, the current
+    /// image and the current command, if they changed.
+    fn update_window_title(&mut self) -> Result<(), String> {
+        let (img, cmd) = match (self.imgs.get(self.index), self.cmds.get(self.cmd_index)) {
+            (Some(img), Some(cmd)) => (img, cmd),
+            _ => return Ok(()),
+        };
+        let total = self.imgs.len() + self.pending_paths.len();
+        let title = window_title(self.index, total, self.list_rx.is_none(), &img.source, cmd);
+        if title != self.canvas.window().title() {
+            self.canvas.window_mut().set_title(&title).map_err(|e| e.to_string())?;
+        }
 
         Ok(())
     }
 
-    /// Updates the source_view and processed_view.
-    ///
-    /// There are several instances where it might be necessary to update them,
-    /// such as when the window size has changed, or when settings that impact
-    /// the Views' geometry have changed.
-    pub fn update_views(&mut self) -> Result<(), String> {
-        let (w, h) = self.window_size();
-        let padding = self.settings.padding;
+    fn draw_frame(&mut self) -> Result<(), String> {
+        Renderer::clear(self.canvas, Color::RGB(36, 40, 59));
 
-        println!("Updating view with window parameters: w={w}, h={h}");
+        // The trash screen replaces the panes.
+        if self.trash_screen.is_some() {
+            self.draw_trash_screen()?;
+            Renderer::present(self.canvas);
+            return Ok(());
+        }
 
-        let (source_rect, processed_rect) = match self.settings.source_position {
-            SourcePosition::Left => (
-                Rect::new(0, 0, w / 2 - padding, h),
-                Rect::new(w as i32 / 2 + padding as i32, 0, w / 2 - padding, h),
-            ),
+        if self.panes_locked {
+            match self.display_mode() {
+                DisplayMode::Continuous => self.processed_view.sync_continuous_with(&self.source_view),
+                DisplayMode::Duplicate | DisplayMode::Single => self.processed_view.sync_duplicate_with(&self.source_view),
+            };
+        }
 
-            SourcePosition::Top => (
-                Rect::new(0, 0, w, h / 2 - padding),
-                Rect::new(0, h as i32 / 2 + padding as i32, w, h / 2 - padding),
-            ),
+        let source_center = self.orientation_center(&self.source_view);
+        let processed_center = self.orientation_center(&self.processed_view);
+        let clip_to_pane = matches!(self.display_mode(), DisplayMode::Duplicate | DisplayMode::Single);
+        let failed = self.current_variant_failed();
+        let panes = [
+            (false, &self.source_view, source_center),
+            (true, &self.processed_view, processed_center),
+        ];
+        // In single pane mode the source pane is hidden.
+        for (is_processed, view, center) in panes.into_iter().skip(self.single_pane as usize) {
+            // The source stands in for a variant which failed.
+            let texture = match is_processed && !failed {
+                true => &mut self.processed_texture,
+                false => &mut self.source_texture,
+            };
+            copy_exposed(self.canvas, texture, &self.exposure, |canvas, texture| {
+                copy_oriented(canvas, texture, view, &self.orientation, center, clip_to_pane)
+            })?;
+        }
+        if failed {
+            self.draw_failed_banner()?;
+        }
+        if !self.single_pane {
+            self.draw_unreadable_banner()?;
+        }
+        self.draw_grid()?;
+        // Nothing may tell the panes apart in blind mode.
+        let blind = self.blind.is_some();
+        if self.hotspot_overlay && !blind && !failed {
+            self.draw_hotspots()?;
+        }
+        if self.imgs[self.index].is_validated() && !blind {
+            self.draw_selected()?;
+        }
 
-            SourcePosition::Right => (
-                Rect::new(w as i32 / 2 + padding as i32, 0, w / 2 - padding, h),
-                Rect::new(0, 0, w / 2 - padding, h),
-            ),
+        if !self.single_pane && !blind {
+            self.draw_source_data()?;
+        }
+        if !blind {
+            self.draw_processed_data()?;
+        }
+        self.draw_feedback()?;
+        self.draw_proxy_badge()?;
+        self.draw_queue_status()?;
+        self.draw_cmd_list()?;
+        self.draw_palette()?;
+        self.draw_note_input()?;
+        self.draw_stats_screen()?;
+        self.draw_perf_overlay()?;
+        self.draw_metadata_panel()?;
+        Renderer::present(self.canvas); // Update the screen with canvas.
 
-            SourcePosition::Bottom => (
-                Rect::new(0, h as i32 / 2 + padding as i32, w, h / 2 - padding),
-                Rect::new(0, 0, w, h / 2 - padding),
-            ),
-        };
+        Ok(())
+    }
 
-        self.source_view.set_clip_rect(source_rect);
-        self.processed_view.set_clip_rect(processed_rect);
-        self.fit_draw()?;
+    /// Draws the performance counters at the bottom left, if enabled.
+    fn draw_perf_overlay(&mut self) -> Result<(), String> {
+        if self.perf_overlay {
+            let (_, h) = self.window_size();
+            let summary = format!(
+                "{}\ncached textures: {} of {}",
+                self.perf.summary(),
+                human_readable_size(self.textures.used()),
+                human_readable_size(self.settings.texture_budget * 1024 * 1024),
+            );
+            let txt = TextBox::new(&summary, &self.font);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(0, h as i32), Anchor::BottomLeft)?;
+        }
 
         Ok(())
     }
 
-    /// Pans the image to the left.
-    pub fn pan_left(&mut self) -> Result<(), String> {
-        self.source_view.pan_left(50);
+    /// Shows or hides the performance counters.
+    pub fn toggle_perf_overlay(&mut self) -> Result<(), String> {
+        self.perf_overlay = !self.perf_overlay;
         self.draw()?;
 
         Ok(())
     }
 
-    /// Pans the image to the right.
-    pub fn pan_right(&mut self) -> Result<(), String> {
-        self.source_view.pan_right(50);
+    /// Shows or hides the metadata panel.
+    pub fn toggle_metadata_panel(&mut self) -> Result<(), String> {
+        self.metadata_panel = !self.metadata_panel;
         self.draw()?;
 
         Ok(())
     }
 
-    /// Pans the image down.
-    pub fn pan_down(&mut self) -> Result<(), String> {
-        self.source_view.pan_down(50);
-        self.draw()?;
+    /// Draws the metadata panel, if shown, at the right of the window: the
+    /// differences between the metadata of the source and of the variant.
+    ///
+    /// The files are only read again when the source or the variant changed.
+    fn draw_metadata_panel(&mut self) -> Result<(), String> {
+        if !self.metadata_panel || self.blind.is_some() {
+            return Ok(());
+        }
+
+        let key = (self.get_source_path(), self.get_current_processed_path().ok());
+        if self.metadata_diff.as_ref().map(|(k, _)| k) != Some(&key) {
+            let lines = match (FileMetadata::read(&key.0), key.1.as_deref().map(FileMetadata::read)) {
+                (Ok(source), Some(Ok(processed))) => metadata::diff(&source, &processed),
+                (Ok(_), None) => vec!["Variant not processed yet".to_string()],
+                (Err(e), _) | (_, Some(Err(e))) => vec![e],
+            };
+            self.metadata_diff = Some((key, lines));
+        }
+
+        if let Some((_, lines)) = &self.metadata_diff {
+            let (w, h) = self.window_size();
+            let panel = format!("Metadata (source -> processed)\n{}", lines.join("\n"));
+            let txt = TextBox::new(&panel, &self.font)
+                .wrapped(w / 2)
+                .padded(OVERLAY_PADDING)
+                .background(OVERLAY_BACKGROUND);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32, h as i32 / 2), Anchor::Right)?;
+        }
 
         Ok(())
     }
 
-    /// Pans the image up.
-    pub fn pan_up(&mut self) -> Result<(), String> {
-        self.source_view.pan_up(50);
-        self.draw()?;
+    /// Starts writing the performance counters to a CSV file, one line per
+    /// frame drawn.
+    pub fn open_perf_log(&mut self, path: &Path) -> Result<(), String> {
+        self.perf.open_log(path)
+    }
 
-        Ok(())
+    /// Starts printing the progress of the session on stdout, in the provided
+    /// format.
+    pub fn set_progress(&mut self, format: Option<ProgressFormat>) {
+        self.progress = format;
     }
 
-    /// Pans the virtual rectangle relative to mouse movement.
-    pub fn pan_mouse_relative(&mut self, m_x: i32, m_y: i32) -> Result<(), String> {
-        // let (w, h) = match self.settings.display_mode {
-        //     DisplayMode::Continuous => self.window_size(),
-        //     DisplayMode::Duplicate => self.source_view.clip_rect.size(),
-        // };
+    /// Prints the event on stdout, if the progress is printed.
+    fn emit_progress(&self, event: ProgressEvent) {
+        if let Some(ProgressFormat::Json) = self.progress {
+            println!("{}", event.json());
+        }
+    }
 
-        let (w, h) = self.window_size();
-        let (v_w, v_h) = self.source_view.virt_rect.size();
-        let v_x = if v_w > w {
-            (w as i32 - m_x) - v_w as i32 * (w as i32 - m_x) / w as i32
-        } else {
-            m_x - v_w as i32 * m_x / w as i32
+    /// Prints the progress event of variant c of image i once processed,
+    /// successfully or not.
+    fn emit_processed(&self, i: usize, c: usize) {
+        let image = &self.imgs[i].source;
+        let command = &self.cmds[c];
+        match self.imgs[i].processed[c].as_ref() {
+            Some(p) if p.is_processed() => self.emit_progress(ProgressEvent::Processed {
+                image,
+                command,
+                source_size: p.source_size,
+                processed_size: p.processed_size,
+            }),
+            Some(p) => if let Some(reason) = p.failure() {
+                self.emit_progress(ProgressEvent::Failed { image, command: Some(command), reason });
+            },
+            None => (),
+        }
+    }
+
+    /// Records the time between an input event and the end of its handling.
+    pub fn record_event_latency(&mut self, latency: Duration) {
+        self.perf.event_latency.record(latency);
+    }
+
+    /// Returns true if processing the current image with the current command
+    /// failed, and no variant is validated.
+    fn current_variant_failed(&self) -> bool {
+        let img = &self.imgs[self.index];
+
+        !img.is_validated() && img.processed[self.cmd_index].as_ref().is_some_and(|p| p.has_failed())
+    }
+
+    /// Dims the processed pane, where the source is displayed in place of the
+    /// variant which failed, and writes a banner over it.
+    fn draw_failed_banner(&mut self) -> Result<(), String> {
+        let clip = self.processed_view.clip_rect;
+        self.canvas.draw_border(&[clip], Color::RGBA(0, 0, 0, 160))?;
+
+        let reason = self.get_current_process_item().and_then(|p| p.failure()).unwrap_or_default();
+        let msg = format!("processing failed with command {}\n{reason}", self.cmd_index + 1);
+        let txt = TextBox::new(&msg, &self.font)
+            .wrapped(clip.width() * 3 / 4);
+        txt.draw(self.canvas, &mut self.text_cache, clip.center(), Anchor::Center)?;
+
+        Ok(())
+    }
+
+    /// Draws the error over the source pane if the source couldn't be
+    /// loaded.
+    fn draw_unreadable_banner(&mut self) -> Result<(), String> {
+        let e = match &self.imgs[self.index].load_error {
+            Some(e) => e,
+            None => return Ok(()),
         };
 
-        let v_y = if v_h > h {
-            (h as i32 - m_y) - v_h as i32 * (h as i32 - m_y) / h as i32
-        } else {
-            m_y - v_h as i32 * m_y / h as i32
+        let clip = self.source_view.clip_rect;
+        let msg = format!("unable to load the source\n{e}");
+        let txt = TextBox::new(&msg, &self.font)
+            .wrapped(clip.width() * 3 / 4);
+        txt.draw(self.canvas, &mut self.text_cache, clip.center(), Anchor::Center)?;
+
+        Ok(())
+    }
+
+    /// Calls the appropriate fit function based on settings then draws the image
+    ///
+    /// If the panes are unlocked, each pane is fitted independently.
+    pub fn fit_draw(&mut self) -> Result<(), String> {
+        let (source_fit_rect, processed_fit_rect) = match self.display_mode() {
+            DisplayMode::Continuous => (self.window_rect(), self.window_rect()),
+            DisplayMode::Duplicate | DisplayMode::Single => (self.source_view.clip_rect, self.processed_view.clip_rect),
         };
 
-        let mut v_rect = self.source_view.virt_rect;
+        // When rotated by a quarter turn, the image is fitted in the rotated
+        // rectangle so that it fits the pane once displayed.
+        let (source_fit_rect, processed_fit_rect) = match self.orientation.swaps_axes() {
+            true => (swapped_rect(source_fit_rect), swapped_rect(processed_fit_rect)),
+            false => (source_fit_rect, processed_fit_rect),
+        };
 
-        v_rect.set_x(v_x);
-        v_rect.set_y(v_y);
-        self.source_view.set_virt_rect(v_rect);
+        fit_view(&mut self.source_view, &self.settings.fit_mode, source_fit_rect);
+        if !self.panes_locked {
+            fit_view(&mut self.processed_view, &self.settings.fit_mode, processed_fit_rect);
+        }
         self.draw()?;
 
         Ok(())
     }
 
-    /// Sends the images close to the current position to be processed in other
-    /// threads.
-    ///
+    /// Returns the view affected by pan and zoom actions, i.e. the source view
+    /// unless the panes are unlocked and the processed pane has the focus.
+    fn focused_view(&mut self) -> &mut ViewRect {
+        if !self.panes_locked && self.processed_focused {
+            &mut self.processed_view
+        } else {
+            &mut self.source_view
+        }
+    }
+
+    /// Unlocks the panes so they can be panned and zoomed independently.
+    ///
+    /// If the panes are already unlocked, the focus switches to the other pane.
+    pub fn unlock_panes(&mut self) -> Result<(), String> {
+        if self.panes_locked {
+            self.panes_locked = false;
+            self.processed_focused = false;
+        } else {
+            self.processed_focused = !self.processed_focused;
+        }
+
+        self.feedback = Some(match self.processed_focused {
+            true => "Panes unlocked, moving processed pane".to_string(),
+            false => "Panes unlocked, moving source pane".to_string(),
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Locks the panes again, the processed pane is re-synced with the source
+    /// pane.
+    pub fn lock_panes(&mut self) -> Result<(), String> {
+        self.panes_locked = true;
+        self.processed_focused = false;
+        self.feedback = Some("Panes locked".to_string());
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Zooms towards the mouse cursor if it is over the window, otherwise
+    /// towards the center of the image.
+    ///
+    /// Scale factor above 1.0 zooms in, while scale factor below 1.0 zooms out
+    fn zoom(&mut self, scale: f32) -> Result<(), String> {
+        let zoom_point = match self.mouse_position {
+            Some(pt) => self.mouse_point_on_focused_view(pt),
+            None => match self.display_mode() {
+                DisplayMode::Duplicate | DisplayMode::Single => self.focused_view().clip_rect.center(),
+                DisplayMode::Continuous => {
+                    (self.source_view.clip_rect.center() + self.processed_view.clip_rect.center()) / 2
+                }
+            },
+        };
+
+        let center = match !self.panes_locked && self.processed_focused {
+            true => self.orientation_center(&self.processed_view),
+            false => self.orientation_center(&self.source_view),
+        };
+        let zoom_point = self.orientation.inverse_point(zoom_point, center);
+
+        let (w, h) = self.window_size();
+        let window_rect = Rect::new(0, 0, w, h);
+        self.focused_view()
+            .zoom_towards_point_on_rect(zoom_point, window_rect, scale);
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Converts the mouse position to the matching point of the focused view.
+    ///
+    /// In duplicate mode, a position over the other pane is translated to the
+    /// same location of the focused pane. In continuous mode, both panes share
+    /// the same coordinates.
+    fn mouse_point_on_focused_view(&self, pt: Point) -> Point {
+        if let DisplayMode::Continuous = self.display_mode() {
+            return pt;
+        }
+
+        let (focused_clip, other_clip) = if !self.panes_locked && self.processed_focused {
+            (self.processed_view.clip_rect, self.source_view.clip_rect)
+        } else {
+            (self.source_view.clip_rect, self.processed_view.clip_rect)
+        };
+
+        if other_clip.contains_point(pt) {
+            pt - other_clip.top_left() + focused_clip.top_left()
+        } else {
+            pt
+        }
+    }
+
+    /// Stores the position of the mouse cursor, used as zoom target.
+    ///
+    /// The window is redrawn when the cursor moves to another pixel of the
+    /// image, to update the coordinates of the status.
+    pub fn set_mouse_position(&mut self, x: i32, y: i32) -> Result<(), String> {
+        let before = self.mouse_position.and_then(|pt| self.window_img_point(pt));
+        self.mouse_position = Some(Point::new(x, y));
+        if self.window_img_point(Point::new(x, y)) != before {
+            self.draw()?;
+        }
+
+        Ok(())
+    }
+
+    /// Forgets the position of the mouse cursor, i.e. when it leaves the
+    /// window.
+    pub fn clear_mouse_position(&mut self) -> Result<(), String> {
+        self.mouse_position = None;
+        self.draw()
+    }
+
+    /// Switches between the real size of the image, zooming towards the mouse
+    /// cursor, and the fit mode.
+    pub fn toggle_actual_size(&mut self) -> Result<(), String> {
+        let zoom_factor = self.focused_view().zoom_factor();
+        if (zoom_factor - 1.).abs() < 0.001 {
+            self.fit_draw()?;
+        } else {
+            self.zoom(1. / zoom_factor)?;
+        }
+
+        Ok(())
+    }
+
+    /// Runs an action. Every input, from keys, mouse buttons, controllers or
+    /// the command palette, goes through this entry point.
+    pub fn perform(&mut self, action: Action) -> Result<(), String> {
+        // Any other action than the one which asked for a confirmation
+        // cancels it.
+        if self.validate_confirm.is_some_and(|(requested_by, _)| requested_by != action) {
+            self.validate_confirm = None;
+        }
+
+        if let Some(recording) = &mut self.macro_recording {
+            if is_recordable(action) {
+                recording.push(action);
+            }
+        }
+
+        // Moving the view by hand ends the guided tour.
+        if matches!(action, Action::Zoom(_) | Action::Fit | Action::ToggleActualSize | Action::Pan(_)) {
+            self.tour = None;
+        }
+
+        match action {
+            Action::NextImage => self.next_image(),
+            Action::PrevImage => self.prev_image(),
+            Action::NextToReview => self.next_to_review(),
+            Action::NextCmd => self.next_cmd(),
+            Action::PrevCmd => self.prev_cmd(),
+            Action::SelectBest => self.select_best(),
+            Action::Validate => self.validate_current(action),
+            Action::Undo => self.undo_current(),
+            Action::ApplyDuplicateDecision => self.apply_duplicate_decision(),
+            Action::MarkRange => self.mark_range(),
+            Action::PlanExtension => self.plan_extension(),
+            Action::Move(i) if i < self.settings.move_actions.len() => self.move_current_with(i),
+            Action::Move(_) => Ok(()),
+            Action::OpenCmdList => self.open_cmd_list(),
+            Action::OpenPalette => self.open_palette(),
+            Action::OpenNote => self.open_note_input(),
+            Action::OpenStats => self.open_stats_screen(),
+            Action::OpenTrash => self.open_trash_screen(),
+            Action::ToggleProcessAll => self.toggle_process_all(),
+            Action::ToggleSavingsOrder => self.toggle_savings_order(),
+            Action::RetryFailed => self.retry_failed(),
+            Action::Reprocess => self.reprocess_current(),
+            Action::Zoom(ZoomDirection::In) => self.zoom_in(),
+            Action::Zoom(ZoomDirection::Out) => self.zoom_out(),
+            Action::Fit => self.update_views(),
+            Action::ToggleActualSize => self.toggle_actual_size(),
+            Action::Pan(direction) => self.start_pan(direction),
+            Action::StopPan(direction) => {
+                self.stop_pan(direction);
+                Ok(())
+            }
+            Action::ToggleFullscreen => self.toggle_fullscreen(),
+            Action::SwapPanes | Action::ToggleSinglePane if self.blind.is_some() => {
+                self.feedback = Some("Not available in blind mode".to_string());
+                self.draw()
+            }
+            Action::SwapPanes => self.swap_panes(),
+            Action::ToggleSinglePane => self.toggle_single_pane(),
+            Action::UnlockPanes => self.unlock_panes(),
+            Action::LockPanes => self.lock_panes(),
+            Action::Rotate => self.rotate_view(),
+            Action::Mirror => self.mirror_view(),
+            Action::Brighten => self.brighten_view(),
+            Action::Darken => self.darken_view(),
+            Action::ResetExposure => self.reset_exposure(),
+            Action::CycleGrid => self.toggle_grid(),
+            Action::ToggleHotspots => self.toggle_hotspots(),
+            Action::TogglePerfOverlay => self.toggle_perf_overlay(),
+            Action::ToggleMetadata => self.toggle_metadata_panel(),
+            Action::ToggleProxy => self.toggle_proxy(),
+            Action::ToggleBlind => self.toggle_blind(),
+            Action::BlindPick(pane) => self.blind_pick(pane),
+            Action::ToggleMacroRecording => self.toggle_macro_recording(),
+            Action::SaveSettings => self.save_settings(),
+            Action::PinVariant => self.pin_variant(),
+            Action::JumpToPinned => self.jump_to_pinned(),
+            Action::TourNext => self.tour_next(),
+            Action::TourPrev => self.tour_prev(),
+            Action::QualityUp => self.change_quality(true),
+            Action::QualityDown => self.change_quality(false),
+            Action::ReplayMacro => self.replay_macro(),
+        }
+    }
+
+    /// Shows the next region of the guided tour at real size, or the first
+    /// one if no tour is running. After the last region the tour ends, and
+    /// the images are fitted again.
+    pub fn tour_next(&mut self) -> Result<(), String> {
+        match &mut self.tour {
+            Some(tour) if tour.index + 1 < tour.regions.len() => tour.index += 1,
+            Some(_) => {
+                self.tour = None;
+                self.feedback = Some("Tour done".to_string());
+                return self.fit_draw();
+            }
+            None => {
+                self.tour = Some(Tour {
+                    img: self.index,
+                    regions: self.tour_regions(),
+                    index: 0,
+                    shown: Instant::now(),
+                });
+            }
+        }
+
+        self.show_tour_region()
+    }
+
+    /// Shows the previous region of the guided tour, if a tour is running.
+    pub fn tour_prev(&mut self) -> Result<(), String> {
+        match &mut self.tour {
+            Some(tour) => tour.index = tour.index.saturating_sub(1),
+            None => return Ok(()),
+        }
+
+        self.show_tour_region()
+    }
+
+    /// Returns the regions of the tour of the current image, each the size
+    /// of a pane at real size.
+    fn tour_regions(&self) -> Vec<Point> {
+        let pane = match self.display_mode() {
+            DisplayMode::Continuous => self.window_rect(),
+            DisplayMode::Duplicate | DisplayMode::Single => self.source_view.clip_rect,
+        };
+        let pane = match self.orientation.swaps_axes() {
+            true => swapped_rect(pane),
+            false => pane,
+        };
+
+        tour_regions(self.source_view.img_size(), pane.size())
+    }
+
+    /// Shows the current region of the tour at real size, at the center of
+    /// the panes, or at the split in continuous mode.
+    fn show_tour_region(&mut self) -> Result<(), String> {
+        let Some(tour) = &mut self.tour else {
+            return Ok(());
+        };
+        tour.shown = Instant::now();
+        let (region, k, n) = (tour.regions[tour.index], tour.index, tour.regions.len());
+
+        let center = self.orientation_center(&self.source_view);
+        show_real_size(&mut self.source_view, region, center);
+
+        // Unlocked panes are not synced with the source pane, the region is
+        // located in the processed image, which may be scaled.
+        if !self.panes_locked {
+            let (sw, sh) = self.source_view.img_size();
+            let (pw, ph) = self.processed_view.img_size();
+            let pixel = Point::new(
+                (region.x as i64 * pw as i64 / sw as i64) as i32,
+                (region.y as i64 * ph as i64 / sh as i64) as i32,
+            );
+            let center = self.orientation_center(&self.processed_view);
+            show_real_size(&mut self.processed_view, pixel, center);
+        }
+
+        self.feedback = Some(format!("Region {} of {n} (y next, ctrl+y previous)", k + 1));
+        self.draw()
+    }
+
+    /// Ends the guided tour once another image is displayed, and shows the
+    /// next region once the pause is over.
+    fn update_tour(&mut self) -> Result<(), String> {
+        let pause = Duration::from_millis(self.settings.tour_pause);
+        match &self.tour {
+            Some(tour) if tour.img != self.index => self.tour = None,
+            Some(tour) if !pause.is_zero() && tour.shown.elapsed() >= pause => self.tour_next()?,
+            _ => (),
+        }
+
+        Ok(())
+    }
+
+    /// Starts recording the actions performed, or stops the recording and
+    /// keeps the recorded actions as the macro replayed by replay_macro.
+    pub fn toggle_macro_recording(&mut self) -> Result<(), String> {
+        self.feedback = Some(match self.macro_recording.take() {
+            Some(actions) => {
+                self.macro_actions = actions;
+                format!("Macro recorded, {} actions, @ to replay", self.macro_actions.len())
+            }
+            None => {
+                self.macro_recording = Some(Vec::new());
+                "Recording macro, q to stop".to_string()
+            }
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Performs the actions of the recorded macro, in order. While recording,
+    /// the replayed actions are recorded as well.
+    ///
+    /// With confirm_destructive, a macro validating images is confirmed once
+    /// for the whole replay, since its next action would cancel the
+    /// confirmation of every validation.
+    pub fn replay_macro(&mut self) -> Result<(), String> {
+        if self.macro_actions.is_empty() {
+            self.feedback = Some("No macro recorded, q to start recording".to_string());
+            return self.draw();
+        }
+
+        let validates = self.macro_actions
+            .iter()
+            .any(|a| matches!(a, Action::Validate | Action::ApplyDuplicateDecision));
+        if validates && self.settings.confirm_destructive && !self.confirmed(Action::ReplayMacro) {
+            self.feedback = Some("Press again to replay the macro, it replaces originals".to_string());
+            return self.draw();
+        }
+
+        self.replay_confirmed = validates;
+        let result = self.macro_actions.clone().into_iter().try_for_each(|action| self.perform(action));
+        self.replay_confirmed = false;
+
+        result
+    }
+
+    /// Runs the action bound to the mouse button in the configuration, if
+    /// any. `clicks` is the number of consecutive clicks, e.g. 2 for a double
+    /// click.
+    pub fn mouse_button(&mut self, button: MouseButton, clicks: u8) -> Result<(), String> {
+        let name = match button {
+            MouseButton::Left => MouseButtonName::Left,
+            MouseButton::Middle => MouseButtonName::Middle,
+            MouseButton::Right => MouseButtonName::Right,
+            MouseButton::X1 => MouseButtonName::X1,
+            MouseButton::X2 => MouseButtonName::X2,
+            MouseButton::Unknown => return Ok(()),
+        };
+
+        let action = self.settings.mouse_bindings
+            .iter()
+            .find(|b| b.button == name && b.clicks == clicks)
+            .map(|b| b.action);
+
+        match action {
+            Some(action) => self.perform(action.action()),
+            None => Ok(()),
+        }
+    }
+
+    pub fn zoom_in(&mut self) -> Result<(), String> {
+        self.zoom(1.1)?;
+
+        Ok(())
+    }
+
+    pub fn zoom_out(&mut self) -> Result<(), String> {
+        self.zoom(0.9)?;
+
+        Ok(())
+    }
+
+    /// Updates the source_view and processed_view.
+    ///
+    /// There are several instances where it might be necessary to update them,
+    /// such as when the window size has changed, or when settings that impact
+    /// the Views' geometry have changed.
+    pub fn update_views(&mut self) -> Result<(), String> {
+        self.resolve_source_position();
+        self.set_pane_rects();
+        self.fit_draw()?;
+
+        Ok(())
+    }
+
+    /// Returns the display mode in use: Single in single pane mode, otherwise
+    /// the mode of the settings, where Single stands for Duplicate once single
+    /// pane mode is toggled off.
+    fn display_mode(&self) -> DisplayMode {
+        match (self.single_pane, self.settings.display_mode) {
+            (true, _) => DisplayMode::Single,
+            (false, DisplayMode::Single) => DisplayMode::Duplicate,
+            (false, mode) => mode,
+        }
+    }
+
+    /// Switches between the split view and the single pane mode, where only
+    /// the processed image is displayed over the whole window.
+    pub fn toggle_single_pane(&mut self) -> Result<(), String> {
+        self.single_pane = !self.single_pane;
+        self.feedback = Some(match self.single_pane {
+            true => "Single pane: processed image only".to_string(),
+            false => "Split view".to_string(),
+        });
+        self.update_views()?;
+
+        Ok(())
+    }
+
+    /// Swaps the source and processed panes, e.g. Left to Right or Top to
+    /// Bottom, for the rest of the session.
+    pub fn swap_panes(&mut self) -> Result<(), String> {
+        self.panes_swapped = !self.panes_swapped;
+        self.update_views()?;
+
+        Ok(())
+    }
+
+    /// Enters or leaves the blind mode, where the source and the processed
+    /// image are randomly assigned to the panes, without any label, and the
+    /// better looking one is picked with blind_pick.
+    pub fn toggle_blind(&mut self) -> Result<(), String> {
+        match self.blind.take() {
+            Some(swapped) => {
+                self.panes_swapped = swapped;
+                self.feedback = Some(format!(
+                    "Blind mode off, {} comparisons this session",
+                    self.blind_results.len(),
+                ));
+            }
+            None => {
+                self.blind = Some(self.panes_swapped);
+                self.single_pane = false;
+                self.panes_swapped = coin_flip();
+                self.feedback = Some("Blind mode: ctrl+1 or ctrl+2 to pick the better image".to_string());
+            }
+        }
+        self.update_views()?;
+
+        Ok(())
+    }
+
+    /// Picks the better looking pane in blind mode, 0 for the left or top
+    /// pane and 1 for the right or bottom pane, then goes to the next image
+    /// with a new random assignment of the panes.
+    pub fn blind_pick(&mut self, pane: usize) -> Result<(), String> {
+        if self.blind.is_none() {
+            self.feedback = Some("Not in blind mode, ctrl+b to enter it".to_string());
+            return self.draw();
+        }
+
+        if self.get_current_processed_path().is_err() || self.current_variant_failed() {
+            self.feedback = Some("Variant not ready, pick once it is processed".to_string());
+            return self.draw();
+        }
+
+        let source_first = matches!(
+            self.source_position,
+            SourcePosition::Left | SourcePosition::Top | SourcePosition::Auto,
+        );
+        self.blind_results.record(&self.cmds[self.cmd_index], source_first == (pane == 0));
+        self.feedback = Some(format!("Pick recorded, {} comparisons this session", self.blind_results.len()));
+
+        self.panes_swapped = coin_flip();
+        self.update_views()?;
+        self.next_image()
+    }
+
+    /// Resolves the position of the source pane for the current image, when
+    /// set to Auto in the settings, and mirrors it if the panes are swapped.
+    ///
+    /// Returns true if the position changed.
+    fn resolve_source_position(&mut self) -> bool {
+        let position = match self.settings.source_position {
+            SourcePosition::Auto => {
+                let (w, h) = self.source_view.img_size();
+                let size = match self.orientation.swaps_axes() {
+                    true => (h, w),
+                    false => (w, h),
+                };
+                match split_side_by_side(size, self.window_size()) {
+                    true => SourcePosition::Left,
+                    false => SourcePosition::Top,
+                }
+            }
+            position => position,
+        };
+        let position = match self.panes_swapped {
+            true => position.mirrored(),
+            false => position,
+        };
+
+        let changed = position != self.source_position;
+        self.source_position = position;
+
+        changed
+    }
+
+    /// Sets the clip rects of the source_view and processed_view, from the
+    /// window size and the source position.
+    fn set_pane_rects(&mut self) {
+        let (w, h) = self.window_size();
+        let padding = self.settings.padding;
+
+        if self.single_pane {
+            self.source_view.set_clip_rect(self.window_rect());
+            self.processed_view.set_clip_rect(self.window_rect());
+            return;
+        }
+
+        let (source_rect, processed_rect) = pane_rects((w, h), self.settings.split_ratio, padding, self.source_position);
+        self.source_view.set_clip_rect(source_rect);
+        self.processed_view.set_clip_rect(processed_rect);
+    }
+
+    /// Moves the image on screen by the provided vector, taking the
+    /// orientation into account.
+    fn pan(&mut self, x: i32, y: i32) -> Result<(), String> {
+        let v = self.orientation.inverse_point(Point::new(x, y), Point::new(0, 0));
+        let view = self.focused_view();
+        match v.x < 0 {
+            true => view.pan_left(v.x.unsigned_abs()),
+            false => view.pan_right(v.x as u32),
+        }
+        match v.y < 0 {
+            true => view.pan_up(v.y.unsigned_abs()),
+            false => view.pan_down(v.y as u32),
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Returns the center of the rotation and mirroring of the view: the
+    /// center of its pane in duplicate mode, or the center of the window in
+    /// continuous mode so that both panes stay continuous.
+    fn orientation_center(&self, view: &ViewRect) -> Point {
+        match self.display_mode() {
+            DisplayMode::Duplicate | DisplayMode::Single => view.clip_rect.center(),
+            DisplayMode::Continuous => self.window_rect().center(),
+        }
+    }
+
+    /// Rotates the displayed images by 90° clockwise. Files are not modified.
+    pub fn rotate_view(&mut self) -> Result<(), String> {
+        self.orientation.rotate_cw();
+        self.feedback = Some(format!("View rotated by {}°", self.orientation.angle()));
+        if self.resolve_source_position() {
+            self.set_pane_rects();
+        }
+        self.fit_draw()?;
+
+        Ok(())
+    }
+
+    /// Mirrors the displayed images horizontally. Files are not modified.
+    pub fn mirror_view(&mut self) -> Result<(), String> {
+        self.orientation.toggle_mirror();
+        self.feedback = Some(match self.orientation.mirrored {
+            true => "View mirrored".to_string(),
+            false => "View not mirrored".to_string(),
+        });
+        self.fit_draw()?;
+
+        Ok(())
+    }
+
+    /// Cycles the grid overlay between off, rule of thirds and pixel grid.
+    pub fn toggle_grid(&mut self) -> Result<(), String> {
+        self.grid_mode = match self.grid_mode {
+            GridMode::Off => GridMode::Thirds,
+            GridMode::Thirds => GridMode::Pixels,
+            GridMode::Pixels => GridMode::Off,
+        };
+        self.feedback = Some(match self.grid_mode {
+            GridMode::Off => "Grid off".to_string(),
+            GridMode::Thirds => "Rule of thirds grid".to_string(),
+            GridMode::Pixels => format!("Pixel grid (visible from zoom x{PIXEL_GRID_MIN_ZOOM})"),
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Draws the grid overlay over both panes, and in continuous mode, a
+    /// crosshair at the split where both panes meet.
+    fn draw_grid(&mut self) -> Result<(), String> {
+        if self.grid_mode == GridMode::Off {
+            return Ok(());
+        }
+
+        for view in [&self.source_view, &self.processed_view].into_iter().skip(self.single_pane as usize) {
+            let lines = match self.grid_mode {
+                GridMode::Thirds => view.grid_lines(3, 3),
+                GridMode::Pixels if view.zoom_factor() >= PIXEL_GRID_MIN_ZOOM => {
+                    let (w, h) = view.img_size();
+                    view.grid_lines(w, h)
+                }
+                _ => Vec::new(),
+            };
+
+            let center = self.orientation_center(view);
+            let lines = lines
+                .into_iter()
+                .map(|(a, b)| (self.orientation.transform_point(a, center), self.orientation.transform_point(b, center)))
+                .collect::<Vec<(Point, Point)>>();
+            let clip = match self.display_mode() {
+                DisplayMode::Duplicate | DisplayMode::Single => Some(view.clip_rect),
+                DisplayMode::Continuous => None,
+            };
+            self.canvas.draw_segments(&lines, Color::RGBA(255, 255, 255, 96), clip)?;
+        }
+
+        if let DisplayMode::Continuous = self.display_mode() {
+            let split = (self.source_view.clip_rect.center() + self.processed_view.clip_rect.center()) / 2;
+            let crosshair = [
+                (split - Point::new(CROSSHAIR_SIZE, 0), split + Point::new(CROSSHAIR_SIZE, 0)),
+                (split - Point::new(0, CROSSHAIR_SIZE), split + Point::new(0, CROSSHAIR_SIZE)),
+            ];
+            self.canvas.draw_segments(&crosshair, Color::RGBA(255, 64, 64, 192), None)?;
+        }
+
+        Ok(())
+    }
+
+    /// Shows or hides the markers on the regions of the variant most likely
+    /// to show artifacts, see artifacts.
+    pub fn toggle_hotspots(&mut self) -> Result<(), String> {
+        self.hotspot_overlay = !self.hotspot_overlay;
+        self.feedback = Some(match self.hotspot_overlay {
+            true => "Artifact hotspots: orange for blockiness, magenta for ringing".to_string(),
+            false => "Artifact hotspots hidden".to_string(),
+        });
+        self.request_hotspots();
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Submits the computation of the hotspots of the current variant to the
+    /// compute pool, if the overlay is shown and it was not computed yet.
+    fn request_hotspots(&mut self) {
+        let key = (self.index, self.cmd_index);
+        if !self.hotspot_overlay || self.hotspots.contains_key(&key) {
+            return;
+        }
+
+        let img = &self.imgs[self.index];
+        let processed = img.processed[self.cmd_index].as_ref().and_then(ProcessItem::current_path);
+        if let Some(processed) = processed {
+            self.pool.submit(Job::Hotspots {
+                i: key.0,
+                c: key.1,
+                source: img.input().to_path_buf(),
+                processed: processed.to_path_buf(),
+            });
+            self.hotspots.insert(key, None);
+        }
+    }
+
+    /// Outlines the artifact hotspots of the current variant in the processed
+    /// pane, with the color of their kind.
+    fn draw_hotspots(&mut self) -> Result<(), String> {
+        let hotspots = match self.hotspots.get(&(self.index, self.cmd_index)) {
+            Some(Some(hotspots)) => hotspots,
+            _ => return Ok(()),
+        };
+
+        let view = &self.processed_view;
+        let center = self.orientation_center(view);
+        for kind in [ArtifactKind::Blockiness, ArtifactKind::Ringing] {
+            let lines = hotspots
+                .iter()
+                .filter(|h| h.kind == kind)
+                .map(|h| view.window_rect(Rect::new(h.x as i32, h.y as i32, h.width, h.height)))
+                .map(|r| self.orientation.transform_rect(r, center))
+                .flat_map(|r| {
+                    let (a, b) = (r.top_left(), r.top_right() - Point::new(1, 0));
+                    let (c, d) = (r.bottom_right() - Point::new(1, 1), r.bottom_left() - Point::new(0, 1));
+                    [(a, b), (b, c), (c, d), (d, a)]
+                })
+                .collect::<Vec<(Point, Point)>>();
+            let color = match kind {
+                ArtifactKind::Blockiness => Color::RGBA(255, 160, 0, 224),
+                ArtifactKind::Ringing => Color::RGBA(255, 0, 255, 224),
+            };
+            self.canvas.draw_segments(&lines, color, Some(view.clip_rect))?;
+        }
+
+        Ok(())
+    }
+
+    /// Brightens the displayed images by half a stop. Files are not modified.
+    pub fn brighten_view(&mut self) -> Result<(), String> {
+        self.exposure.brighten();
+        self.show_exposure()
+    }
+
+    /// Darkens the displayed images by half a stop. Files are not modified.
+    pub fn darken_view(&mut self) -> Result<(), String> {
+        self.exposure.darken();
+        self.show_exposure()
+    }
+
+    /// Restores the original brightness of the displayed images.
+    pub fn reset_exposure(&mut self) -> Result<(), String> {
+        self.exposure.reset();
+        self.show_exposure()
+    }
+
+    fn show_exposure(&mut self) -> Result<(), String> {
+        self.feedback = Some(format!("Exposure {:+.1} EV", self.exposure.stops()));
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Pans the image by one step when a pan key is pressed. While the key
+    /// is held, the image keeps panning continuously, see update_pan.
+    pub fn start_pan(&mut self, direction: PanDirection) -> Result<(), String> {
+        self.pan_keys[direction as usize] = Some(Instant::now());
+
+        let (x, y) = direction.vector();
+        self.pan((x * PAN_STEP) as i32, (y * PAN_STEP) as i32)?;
+
+        Ok(())
+    }
+
+    /// Stops panning when a pan key is released.
+    pub fn stop_pan(&mut self, direction: PanDirection) {
+        self.pan_keys[direction as usize] = None;
+    }
+
+    /// Stops every pan, e.g. when the window loses the focus and key
+    /// releases are not received anymore.
+    pub fn stop_all_pans(&mut self) {
+        self.pan_keys = [None; 4];
+    }
+
+    /// Pans with a controller stick, as if the pan key of the direction the
+    /// stick is pushed towards was held. `negative` and `positive` are the
+    /// directions of the axis.
+    pub fn pan_axis(&mut self, negative: PanDirection, positive: PanDirection, value: i16) -> Result<(), String> {
+        for (direction, pushed) in [(negative, value < -STICK_DEADZONE), (positive, value > STICK_DEADZONE)] {
+            match (pushed, self.pan_keys[direction as usize].is_some()) {
+                (true, false) => self.start_pan(direction)?,
+                (false, true) => self.stop_pan(direction),
+                _ => (),
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Pans continuously while pan keys are held, by a distance depending on
+    /// the time elapsed since the last frame, so the speed doesn't depend on
+    /// the frame rate. The speed increases the longer the keys are held.
+    fn update_pan(&mut self) -> Result<(), String> {
+        let now = Instant::now();
+        let dt = now.duration_since(self.last_frame).as_secs_f32();
+        self.last_frame = now;
+
+        let (mut dx, mut dy) = self.pan_remainder;
+        let directions = [PanDirection::Left, PanDirection::Down, PanDirection::Up, PanDirection::Right];
+        for direction in directions {
+            let held = match self.pan_keys[direction as usize] {
+                Some(pressed) if now.duration_since(pressed) > PAN_HOLD_DELAY => now.duration_since(pressed),
+                _ => continue,
+            };
+            let (x, y) = direction.vector();
+            let distance = pan_speed(held) * dt;
+            dx += x * distance;
+            dy += y * distance;
+        }
+
+        // Fractions of pixels are kept for the next frame.
+        let (x, y) = (dx.trunc(), dy.trunc());
+        self.pan_remainder = (dx - x, dy - y);
+        if self.pan_keys.iter().all(Option::is_none) {
+            self.pan_remainder = (0., 0.);
+        }
+
+        if x != 0. || y != 0. {
+            self.pan(x as i32, y as i32)?;
+        }
+
+        Ok(())
+    }
+
+    /// Pans the virtual rectangle relative to mouse movement.
+    pub fn pan_mouse_relative(&mut self, m_x: i32, m_y: i32) -> Result<(), String> {
+        // let (w, h) = match self.display_mode() {
+        //     DisplayMode::Continuous => self.window_size(),
+        //     DisplayMode::Duplicate => self.source_view.clip_rect.size(),
+        // };
+
+        let (w, h) = self.window_size();
+        let (v_w, v_h) = self.source_view.virt_rect.size();
+        let v_x = if v_w > w {
+            (w as i32 - m_x) - v_w as i32 * (w as i32 - m_x) / w as i32
+        } else {
+            m_x - v_w as i32 * m_x / w as i32
+        };
+
+        let v_y = if v_h > h {
+            (h as i32 - m_y) - v_h as i32 * (h as i32 - m_y) / h as i32
+        } else {
+            m_y - v_h as i32 * m_y / h as i32
+        };
+
+        let mut v_rect = self.source_view.virt_rect;
+
+        v_rect.set_x(v_x);
+        v_rect.set_y(v_y);
+        self.source_view.set_virt_rect(v_rect);
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Sends the images close to the current position to be processed in other
+    /// threads.
+    ///
     /// This allows to process several images in parallel. It also prevents
     /// blocking the main thread which mannages the user interface.
     fn update_process_threads(&mut self) {
@@ -455,231 +1990,2416 @@ impl<'a> App<'a> {
         //for (i, c) in (0..self.imgs.len()).flat_map(|i| (0..self.cmds.len()).map(move |c| (i, c))){
         // for (i, c) in VFirst2D::new(self.index, self.index.saturating_sub(5), usize::min(self.index + 5, self.imgs.len()-1),
         //                             self.cmd_index, self.cmd_index.saturating_sub(5), usize::min(self.cmd_index + 5, self.cmds.len()-1)) {
-        for (i, c) in Closest2D::new(
+        // Images are sent in order of priority, nothing is sent once the
+        // maximum weight is reached, so that the closest images go first.
+        let all_sent = !self.process_all || (0..self.cmds.len()).all(|c| self.spawn_process(self.index, c));
+
+        // The order is kept between calls, the elements already passed are
+        // processed or being processed. It restarts when the user moves.
+        let window = self.process_window();
+        if !window.same_grid(&self.process_order) {
+            self.process_order = window;
+        }
+
+        let mut order = self.process_order;
+        while let Some((i, c)) = order.next().filter(|_| all_sent) {
+            if !self.spawn_process(i, c) {
+                break;
+            }
+            self.process_order = order;
+        }
+
+        self.update_background();
+    }
+
+    /// Returns the order in which the images around the current one are
+    /// processed, from the start.
+    fn process_window(&self) -> Closest2D {
+        Closest2D::new(
             self.index,
             self.index.saturating_sub(5),
             usize::min(self.index + 5, self.imgs.len() - 1),
             self.cmd_index,
             self.cmd_index.saturating_sub(5),
             usize::min(self.cmd_index + 5, self.cmds.len() - 1),
-        ) {
-            if self.imgs[i].processed[c].is_some() {
-                let mut p = self.imgs[i].processed[c].take().unwrap();
-                if !p.is_processed(){
-                    let (tx, rx) = mpsc::channel();
-                    self.rxs.push(rx);
-                    let source_path = self.imgs[i].source.clone();
-                    let output_directory = self.settings.processing_directory.clone();
-                    let cmd = self.cmds[c].to_string();
-                    thread::spawn(move || {
-                        p.process(source_path, output_directory, cmd, c);
-
-                        tx.send(((i, c), p)).unwrap();
-                    });
-                } else {
-                    self.imgs[i].processed[c] = Some(p);
+        )
+        .with_forward_bias(self.settings.forward_bias as usize)
+    }
+
+    /// Updates the background work which doesn't depend on the processing
+    /// threads: the prepass, the previews, and the priorities of the pool.
+    fn update_background(&mut self) {
+        self.spawn_prepass();
+        self.request_previews();
+        self.request_preloads();
+        self.pool.set_position(self.index, self.cmd_index);
+    }
+
+    /// When images are ordered by savings, processes every image with the
+    /// current command in the background, a few at a time, so that their
+    /// savings are known.
+    fn spawn_prepass(&mut self) {
+        if !self.savings_order {
+            return;
+        }
+
+        let c = self.cmd_index;
+        for i in 0..self.imgs.len() {
+            if self.processing.len() >= PREPASS_JOBS {
+                break;
+            }
+
+            let pending = self.imgs[i].processed[c]
+                .as_ref()
+                .is_some_and(ProcessItem::is_pending);
+            if pending && !self.spawn_process(i, c) {
+                break;
+            }
+        }
+    }
+
+    /// Called once image i is processed with command c. If metrics are
+    /// configured, they are submitted to the compute pool and the rules are
+    /// applied once they are computed, otherwise the rules are applied now.
+    fn on_processed(&mut self, i: usize, c: usize) {
+        let source = self.imgs[i].input().to_path_buf();
+        let processed = self.imgs[i].processed[c].as_mut().filter(|_| !self.settings.metrics.is_empty());
+        if let Some(p) = processed {
+            if let Some(tmp_path) = p.tmp_path().map(Path::to_path_buf) {
+                p.metrics_pending = true;
+                self.pool.submit(Job::Metrics {
+                    i,
+                    c,
+                    source,
+                    processed: tmp_path,
+                    metrics: self.settings.metrics.clone(),
+                    sandbox: self.settings.sandbox.then(|| self.settings.processing_directory.clone()),
+                });
+
+                return;
+            }
+        }
+
+        self.apply_rules(i, c);
+    }
+
+    /// Returns the bytes saved by the current command on image i, if it is
+    /// processed.
+    fn savings(&self, i: usize) -> Option<i64> {
+        self.imgs[i].processed[self.cmd_index]
+            .as_ref()
+            .filter(|p| p.is_processed())
+            .map(|p| p.source_size as i64 - p.processed_size as i64)
+    }
+
+    /// Returns the ranking of the images by savings with the current command,
+    /// computed if the command, the images or their savings changed.
+    fn savings_ranking(&self) -> Ref<'_, SavingsRanking> {
+        let outdated = match &*self.savings_ranking.borrow() {
+            Some(ranking) => ranking.cmd != self.cmd_index || ranking.order.len() != self.imgs.len(),
+            None => true,
+        };
+        if outdated {
+            let savings = (0..self.imgs.len())
+                .map(|i| self.savings(i))
+                .collect::<Vec<Option<i64>>>();
+            *self.savings_ranking.borrow_mut() = Some(SavingsRanking::new(self.cmd_index, &savings));
+        }
+
+        Ref::map(self.savings_ranking.borrow(), |r| r.as_ref().unwrap())
+    }
+
+    /// Called when the savings of a variant may have changed.
+    fn savings_changed(&mut self) {
+        *self.savings_ranking.get_mut() = None;
+    }
+
+    /// Updates the variant counts of the queue status after the variants of
+    /// image i changed state.
+    fn recount_variants(&mut self, i: usize) {
+        let (old, new) = self.imgs[i].processed.recount();
+        self.variant_counts.update(old, new);
+    }
+
+    /// Returns the position of image i in the order images are reviewed.
+    fn review_position(&self, i: usize) -> usize {
+        match self.savings_order {
+            true => self.savings_ranking().positions[i],
+            false => i,
+        }
+    }
+
+    /// Returns the image at a position of the order images are reviewed, or
+    /// None past the last image.
+    fn review_image(&self, pos: usize) -> Option<usize> {
+        match self.savings_order {
+            true => self.savings_ranking().order.get(pos).copied(),
+            false => (pos < self.imgs.len()).then_some(pos),
+        }
+    }
+
+    /// Toggles the review order between the original order of the list, and
+    /// the order of the largest savings with the current command first.
+    ///
+    /// Savings are only known once images are processed, so enabling it starts
+    /// processing every image with the current command in the background.
+    /// Images are ordered by the savings known when navigating.
+    pub fn toggle_savings_order(&mut self) -> Result<(), String> {
+        self.savings_order = !self.savings_order;
+        self.feedback = Some(match self.savings_order {
+            true => "Ordered by savings, largest first".to_string(),
+            false => "Original order".to_string(),
+        });
+        self.spawn_prepass();
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Returns the options of the processing commands, from the settings.
+    fn process_options(&self) -> ProcessOptions {
+        ProcessOptions {
+            retries: self.settings.process_retries,
+            backoff: Duration::from_millis(self.settings.retry_backoff),
+            sandbox: self.settings.sandbox,
+            tmp_namespace: self.tmp_namespace.clone(),
+            output_check: self.settings.output_check,
+        }
+    }
+
+    /// Processes again every variant which failed, e.g. after freeing disk
+    /// space. The variants close to the current image are processed first.
+    pub fn retry_failed(&mut self) -> Result<(), String> {
+        let mut count = 0;
+        for p in self.imgs.iter_mut().flat_map(|img| img.processed.touched_mut().flatten()) {
+            if p.has_failed() {
+                p.clear_failure()?;
+                count += 1;
+            }
+        }
+        for i in 0..self.imgs.len() {
+            self.recount_variants(i);
+        }
+
+        self.process_order = self.process_window();
+        self.feedback = Some(format!("Retrying {count} failed variants"));
+        self.load_processed_at_index()?;
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Raises or lowers the quality of the current variant by quality_step,
+    /// and processes it again with the new quality substituted for %q. This
+    /// is quick with a built-in encoder, e.g. `builtin:jpeg?q=%q`, to look for
+    /// the lowest quality without visible artifacts.
+    pub fn change_quality(&mut self, up: bool) -> Result<(), String> {
+        let (i, c) = (self.index, self.cmd_index);
+        let step = self.settings.quality_step;
+        let default = self.settings.quality;
+        if !self.cmds[c].contains(QUALITY_PLACEHOLDER) {
+            self.feedback = Some(format!("Command {} has no {QUALITY_PLACEHOLDER} quality placeholder", c + 1));
+        } else if self.imgs[i].is_decided() || self.moves.is_pending(i) {
+            self.feedback = Some("Only the quality of undecided images can be changed".to_string());
+        } else if let Some(p) = self.imgs[i].processed[c].as_mut() {
+            let quality = p.quality.unwrap_or(default);
+            let quality = match up {
+                true => quality.saturating_add(step),
+                false => quality.saturating_sub(step),
+            };
+            let quality = quality.clamp(1, 100);
+            *p = ProcessItem::at_quality(quality);
+            self.recount_variants(i);
+            self.hotspots.remove(&(i, c));
+            self.savings_changed();
+
+            self.process_order = self.process_window();
+            self.update_process_threads();
+            self.load_processed_at_index()?;
+            self.feedback = Some(format!("Quality {quality} for command {}", c + 1));
+        } else {
+            // The variant is being processed, it is sent back once done.
+            self.feedback = Some("The variant is being processed, try again once it is done".to_string());
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Processes the current image again after its source was modified:
+    /// its variants and the computations on its source are discarded.
+    pub fn reprocess_current(&mut self) -> Result<(), String> {
+        let i = self.index;
+        if self.imgs[i].is_decided() || self.moves.is_pending(i) {
+            self.feedback = Some("Only undecided images can be reprocessed".to_string());
+            self.draw()?;
+
+            return Ok(());
+        }
+
+        let count = self.imgs[i].reprocess();
+        self.recount_variants(i);
+        self.savings_changed();
+        self.copies_requested.remove(&i);
+        self.previews.remove(&i);
+        self.hotspots.retain(|&(img, _), _| img != i);
+        self.pool.submit(Job::Hash { i, path: self.imgs[i].source.clone() });
+
+        self.process_order = self.process_window();
+        self.update_process_threads();
+        self.load_image_at_index()?;
+        self.feedback = Some(format!("Reprocessing {count} variants"));
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Returns true if the source of image i can be processed: it is not
+    /// under one of the copy_sources_from directories, or its local copy is
+    /// done. Otherwise, the copy is requested and false is returned.
+    fn input_ready(&mut self, i: usize) -> bool {
+        let img = &self.imgs[i];
+        let remote = self.settings.copy_sources_from.iter().any(|d| img.source.starts_with(d));
+        if !remote || img.local_copy.is_some() || img.is_decided() {
+            return true;
+        }
+
+        if self.copies_requested.insert(i) {
+            let processing_directory = &self.settings.processing_directory;
+            match local_copy_path(&img.source, processing_directory, &self.tmp_namespace, i) {
+                Ok(destination) => self.pool.submit(Job::Copy { i, source: img.source.clone(), destination }),
+                Err(e) => {
+                    diagnostic!("Error: {e}");
+                    self.imgs[i].local_copy = Some(self.imgs[i].source.clone());
+                    return true;
+                }
+            }
+        }
+
+        false
+    }
+
+    /// Returns the command c as run for a variant of provided quality: with
+    /// %q substituted, and throttled as set in the settings.
+    fn variant_command(&self, c: usize, quality: Option<u8>) -> String {
+        let cmd = with_quality(&self.cmds[c], quality.unwrap_or(self.settings.quality));
+
+        throttled_command(&cmd, self.settings.process_niceness, self.settings.process_cpu_limit)
+    }
+
+    /// Sends image i to be processed with command c in another thread, unless
+    /// it is already processed or being processed.
+    ///
+    /// Returns false if the processing can't start because the commands
+    /// running already use all of max_process_weight. A command heavier than
+    /// the maximum still starts when nothing else runs.
+    fn spawn_process(&mut self, i: usize, c: usize) -> bool {
+        if !self.input_ready(i) {
+            return true;
+        }
+
+        if self.cmds_enabled[c] && self.imgs[i].processed[c].is_some() {
+            let weight = self.cmd_weights[c];
+            let needs_processing = self.imgs[i].processed[c].as_ref().is_some_and(ProcessItem::is_pending);
+            if needs_processing
+                && self.running_weight > 0
+                && self.running_weight + weight > self.settings.max_process_weight
+            {
+                return false;
+            }
+
+            let mut p = self.imgs[i].processed[c].take().unwrap();
+            if p.is_pending() {
+                let tx = self.process_tx.clone();
+                self.processing.insert((i, c));
+                self.running_weight += weight;
+                let source_path = self.imgs[i].input().to_path_buf();
+                let output_directory = self.settings.processing_directory.clone();
+                let cmd = self.variant_command(c, p.quality);
+                let options = self.process_options();
+                thread::spawn(move || {
+                    let panicked = catch_panic(|| p.process(source_path, output_directory, cmd, c, &options)).err();
+                    if let Some(e) = &panicked {
+                        if let Err(e) = p.set_failure(format!("Processing panicked: {e}")) {
+                            diagnostic!("Error: {e}");
+                        }
+                    }
+
+                    // The receiver is only dropped when the application quits.
+                    let _ = tx.send(((i, c), p, panicked));
+                });
+            } else {
+                self.imgs[i].processed[c] = Some(p);
+            }
+        }
+
+        true
+    }
+
+    /// Returns the texture of the file at provided path, from the texture
+    /// cache if it was recently viewed, otherwise loaded from disk.
+    fn load_texture(&mut self, key: &TextureKey) -> Result<Texture<'a>, String> {
+        match self.textures.take(key) {
+            Some(texture) => Ok(texture),
+            None => {
+                let start = Instant::now();
+                let texture = match self.proxy {
+                    true => PixelBuffer::decode(&key.0).and_then(|pixels| self.pixels_texture(key, pixels)),
+                    false => self.texture_creator.load_texture(&key.0),
+                };
+                self.perf.upload.record(start.elapsed());
+
+                texture
+            }
+        }
+    }
+
+    /// Uploads decoded pixels to a texture. In fast preview mode, the texture
+    /// is a proxy of lower resolution, and the size of the image is recorded.
+    fn pixels_texture(&mut self, key: &TextureKey, pixels: PixelBuffer) -> Result<Texture<'a>, String> {
+        if !self.proxy {
+            return pixels.to_texture(self.texture_creator);
+        }
+
+        self.proxy_sizes.insert(key.clone(), (pixels.width, pixels.height));
+        let size = u32::max(pixels.width, pixels.height) / PROXY_SCALE;
+        pixels.downscaled(size.max(1)).to_texture(self.texture_creator)
+    }
+
+    /// Returns the size of the image displayed by the texture, larger than
+    /// the texture if it is a proxy.
+    fn image_size(&self, key: Option<&TextureKey>, texture: &Texture) -> (u32, u32) {
+        match key.and_then(|k| self.proxy_sizes.get(k)) {
+            Some(&size) => size,
+            None => {
+                let query = texture.query();
+                (query.width, query.height)
+            }
+        }
+    }
+
+    /// Switches between the fast preview mode, displaying proxies of lower
+    /// resolution, and the full quality.
+    pub fn toggle_proxy(&mut self) -> Result<(), String> {
+        self.proxy = !self.proxy;
+
+        // The cached textures are loaded again in the new mode.
+        self.textures = LruCache::default();
+        self.proxy_sizes.clear();
+        self.source_key = None;
+        self.processed_key = None;
+        self.load_image_at_index()?;
+
+        self.feedback = Some(match self.proxy {
+            true => "Fast preview, images at half resolution".to_string(),
+            false => "Full quality".to_string(),
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Draws a badge at the top left of the window in fast preview mode.
+    fn draw_proxy_badge(&mut self) -> Result<(), String> {
+        if self.proxy {
+            let txt = TextBox::new("proxy 1/2", &self.font)
+                .padded(OVERLAY_PADDING)
+                .background(OVERLAY_BACKGROUND);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(0, 0), Anchor::TopLeft)?;
+        }
+
+        Ok(())
+    }
+
+    /// Puts a texture which is no longer displayed in the texture cache, then
+    /// evicts the least recently viewed textures if the memory used by the
+    /// cache and the displayed textures exceeds the budget.
+    fn stash_texture(&mut self, key: TextureKey, texture: Texture<'a>) {
+        let bytes = texture_bytes(&texture);
+        self.textures.insert(key, texture, bytes);
+
+        let budget = self.settings.texture_budget * 1024 * 1024;
+        let displayed = texture_bytes(&self.source_texture) + texture_bytes(&self.processed_texture);
+        self.textures.evict_to(budget.saturating_sub(displayed));
+    }
+
+    /// Displays the texture in the source pane. The previous texture is put
+    /// in the texture cache, unless it was a preview (i.e. without key).
+    fn set_source_texture(&mut self, texture: Texture<'a>, key: Option<TextureKey>) {
+        let old = std::mem::replace(&mut self.source_texture, texture);
+        if let Some(old_key) = std::mem::replace(&mut self.source_key, key) {
+            self.stash_texture(old_key, old);
+        }
+    }
+
+    /// Returns true if the file is large enough to be displayed progressively.
+    fn is_large(&self, path: &Path) -> bool {
+        let size = self.settings.progressive_size * 1024 * 1024;
+        size > 0 && fs::metadata(path).is_ok_and(|md| md.len() >= size)
+    }
+
+    /// Loads the source of the current image in the source pane.
+    ///
+    /// If the source is large and its preview is available, the preview is
+    /// displayed immediately and the full image is decoded in the background.
+    fn load_source_at_index(&mut self) -> Result<(), String> {
+        let key = texture_key(&self.get_source_path());
+        let mut full_size = None;
+
+        if self.source_decode.as_ref().is_some_and(|(k, _)| k == &key) {
+            // The preview is already displayed.
+            full_size = Some(self.source_view.img_size());
+        } else if self.source_key.as_ref() == Some(&key) {
+            // The image is already displayed.
+            self.source_decode = None;
+        } else if let Some(texture) = self.textures.take(&key) {
+            self.source_decode = None;
+            self.set_source_texture(texture, Some(key));
+        } else if let Some(preview) = self.previews.get(&self.index).filter(|_| self.is_large(&key.0)) {
+            full_size = Some(preview.full_size);
+            self.source_decode = None;
+            let start = Instant::now();
+            let texture = preview.pixels.to_texture(self.texture_creator)?;
+            self.perf.upload.record(start.elapsed());
+            self.set_source_texture(texture, None);
+            self.source_decode = Some((key.clone(), spawn_decode(key.0)));
+        } else {
+            self.source_decode = None;
+            match self.load_texture(&key) {
+                Ok(texture) => self.set_source_texture(texture, Some(key)),
+                Err(e) => {
+                    self.set_unreadable(self.index, e);
+                    let placeholder = self.placeholder_texture()?;
+                    self.set_source_texture(placeholder, None);
+                }
+            }
+        }
+        if self.source_key.is_some() {
+            self.imgs[self.index].load_error = None;
+        }
+
+        let (w, h) = full_size.unwrap_or_else(|| self.image_size(self.source_key.as_ref(), &self.source_texture));
+        self.source_view.set_img_rect(Rect::new(0, 0, w, h));
+        if self.resolve_source_position() {
+            self.set_pane_rects();
+        }
+
+        Ok(())
+    }
+
+    /// Records that the source of image i couldn't be loaded. The image is
+    /// kept in the list, with a placeholder instead of its source.
+    fn set_unreadable(&mut self, i: usize, e: String) {
+        diagnostic!("Error: unable to load {}: {e}", self.imgs[i].source.display());
+        self.imgs[i].load_error = Some(e);
+    }
+
+    /// Returns a texture of a single dark pixel, displayed in place of an
+    /// image which couldn't be loaded.
+    fn placeholder_texture(&self) -> Result<Texture<'a>, String> {
+        let mut texture = self.texture_creator
+            .create_texture_static(PixelFormatEnum::RGB24, 1, 1)
+            .map_err(|e| e.to_string())?;
+        texture.update(None, &[24, 26, 38], 3).map_err(|e| e.to_string())?;
+
+        Ok(texture)
+    }
+
+    /// Returns the summary of the sources which couldn't be loaded, printed
+    /// when quitting. Returns None if every source could be loaded.
+    pub fn unreadable_summary(&self) -> Option<String> {
+        let unreadable = self.imgs
+            .iter()
+            .filter_map(|img| Some((&img.source, img.load_error.as_ref()?)))
+            .map(|(source, e)| format!("\n  {}: {e}", source.display()))
+            .collect::<Vec<String>>();
+
+        match unreadable.is_empty() {
+            true => None,
+            false => Some(format!("{} unreadable inputs:{}", unreadable.len(), unreadable.concat())),
+        }
+    }
+
+    /// Replaces the preview of the source pane by the full image, once its
+    /// decoding is done.
+    ///
+    /// Returns true if the source pane was updated.
+    fn receive_source_decode(&mut self) -> Result<bool, String> {
+        let result = match &self.source_decode {
+            Some((_, rx)) => match rx.try_recv() {
+                Ok(result) => result,
+                Err(mpsc::TryRecvError::Empty) => return Ok(false),
+                Err(mpsc::TryRecvError::Disconnected) => Err("Decoding thread stopped".to_string()),
+            },
+            None => return Ok(false),
+        };
+
+        let (key, _) = self.source_decode.take().unwrap();
+        match result {
+            Ok(pixels) => {
+                let start = Instant::now();
+                let texture = self.pixels_texture(&key, pixels)?;
+                self.perf.upload.record(start.elapsed());
+                self.set_source_texture(texture, Some(key));
+
+                Ok(true)
+            }
+            Err(e) => {
+                self.set_unreadable(self.index, e);
+                let placeholder = self.placeholder_texture()?;
+                self.set_source_texture(placeholder, None);
+                self.source_view.set_img_rect(Rect::new(0, 0, 1, 1));
+
+                Ok(true)
+            }
+        }
+    }
+
+    /// Computes in the background the previews of the large images close to
+    /// the current one, and drops the previews of images far from it.
+    fn request_previews(&mut self) {
+        if self.settings.progressive_size == 0 || self.imgs.is_empty() {
+            return;
+        }
+
+        let index = self.index;
+        let is_close = |i: usize| i + 2 * PREVIEW_RANGE >= index && i <= index + 2 * PREVIEW_RANGE;
+        self.previews.retain(|&i, _| is_close(i));
+        self.previews_requested.retain(|&i| is_close(i));
+
+        let last = usize::min(index + PREVIEW_RANGE, self.imgs.len() - 1);
+        for i in index.saturating_sub(PREVIEW_RANGE)..=last {
+            let path = self.imgs[i].input();
+            if !self.previews_requested.contains(&i) && self.is_large(path) {
+                self.previews_requested.insert(i);
+                self.pool.submit(Job::Preview { i, path: path.to_path_buf() });
+            }
+        }
+    }
+
+    /// Decodes in the background the sources of the images close to the
+    /// current one, unless their texture is already cached. Large images are
+    /// left to their previews.
+    fn request_preloads(&mut self) {
+        if self.imgs.is_empty() {
+            return;
+        }
+
+        let index = self.index;
+        let last = usize::min(index + PRELOAD_RANGE, self.imgs.len() - 1);
+        for i in index.saturating_sub(PRELOAD_RANGE)..=last {
+            let path = self.imgs[i].input();
+            if i == index || self.preloads_requested.contains(&i) || self.is_large(path) {
+                continue;
+            }
+
+            let key = texture_key(path);
+            if !self.textures.contains(&key) && self.source_key.as_ref() != Some(&key) {
+                self.preloads_requested.insert(i);
+                self.pool.submit(Job::Decode { i, path: path.to_path_buf() });
+            }
+        }
+    }
+
+    /// Puts the source decoded in advance in the texture cache, if its image
+    /// is still close to the current one.
+    fn receive_preload(&mut self, i: usize, path: &Path, pixels: PixelBuffer) -> Result<(), String> {
+        let key = texture_key(path);
+        let is_close = i + PRELOAD_RANGE >= self.index && i <= self.index + PRELOAD_RANGE;
+        if is_close && !self.textures.contains(&key) && self.source_key.as_ref() != Some(&key) {
+            let start = Instant::now();
+            let texture = self.pixels_texture(&key, pixels)?;
+            self.perf.upload.record(start.elapsed());
+            self.stash_texture(key, texture);
+        }
+
+        Ok(())
+    }
+
+    fn load_processed_at_index(&mut self) -> Result<(), String> {
+        // Load processed picture
+        if let Ok(path) = self.get_current_processed_path() {
+            let key = texture_key(&path);
+            if self.processed_key.as_ref() != Some(&key) {
+                match self.load_texture(&key) {
+                    Ok(texture) => {
+                        let old = std::mem::replace(&mut self.processed_texture, texture);
+                        if let Some(old_key) = self.processed_key.replace(key) {
+                            self.stash_texture(old_key, old);
+                        }
+                    }
+                    // A variant which can't be loaded is displayed as failed.
+                    Err(e) => {
+                        let p = self.imgs[self.index].processed[self.cmd_index].as_mut();
+                        if let Err(e) = p.map_or(Ok(()), |p| p.set_failure(format!("Unable to load the output: {e}"))) {
+                            diagnostic!("Error: {e}");
+                        }
+                        self.recount_variants(self.index);
+                    }
+                }
+            }
+        }
+
+        // When unlocked, the processed pane keeps its own position and zoom.
+        // If the variant failed, the source is displayed in its place.
+        let virt_rect = self.processed_view.virt_rect;
+        let (w, h) = match self.current_variant_failed() {
+            true => self.source_view.img_size(),
+            false => self.image_size(self.processed_key.as_ref(), &self.processed_texture),
+        };
+        self.processed_view.set_img_rect(Rect::new(0, 0, w, h));
+        if !self.panes_locked {
+            self.processed_view.set_virt_rect(virt_rect);
+        }
+
+        self.update_process_threads();
+
+        Ok(())
+    }
+
+    fn load_image_at_index(&mut self) -> Result<(), String> {
+        self.feedback = None;
+        self.process_all_confirm = false;
+        self.load_source_at_index()?;
+        self.load_processed_at_index()?;
+        if self.imgs[self.index].check_source() {
+            self.feedback = Some(OUTDATED_FEEDBACK.to_string());
+        }
+
+        Ok(())
+    }
+
+    /// Adds the chunks of the list read since the last call to the pending
+    /// sources.
+    ///
+    /// Returns true if chunks were received, or if the list is now complete.
+    fn receive_list(&mut self) -> bool {
+        let rx = match &self.list_rx {
+            Some(rx) => rx,
+            None => return false,
+        };
+
+        let mut received = false;
+        loop {
+            match rx.try_recv() {
+                Ok(chunk) => {
+                    self.pending_paths.extend(chunk);
+                    received = true;
+                }
+                Err(mpsc::TryRecvError::Empty) => return received,
+                Err(mpsc::TryRecvError::Disconnected) => {
+                    self.list_rx = None;
+                    return true;
+                }
+            }
+        }
+    }
+
+    /// Creates the items of up to n more sources of the list, and submits
+    /// their hashes for the detection of duplicates.
+    ///
+    /// Returns true if items were added.
+    fn ingest(&mut self, n: usize) -> bool {
+        let start = self.imgs.len();
+        let cmds_len = self.cmds.len();
+        let n = n.min(self.pending_paths.len());
+        self.imgs.extend(self.pending_paths.drain(..n).map(|path| ImgItem::new(&path, cmds_len)));
+
+        if self.settings.detect_duplicates {
+            for i in start..self.imgs.len() {
+                self.pool.submit(Job::Hash { i, path: self.imgs[i].source.clone() });
+            }
+        }
+
+        self.imgs.len() > start
+    }
+
+    /// Displays the first image. Its source is shown right away, its first
+    /// variant once processed in the background like any other.
+    fn first_image(&mut self) -> Result<(), String> {
+        self.index = 0;
+        self.cmd_index = 0;
+        self.load_image_at_index()?;
+        self.fit_draw()?;
+
+        Ok(())
+    }
+
+    pub fn next_image(&mut self) -> Result<(), String> {
+        if let Some(next) = self.review_image(self.review_position(self.index) + 1) {
+            self.index = next;
+            self.auto_select_cmd();
+            self.load_image_at_index()?;
+            self.fit_draw()?;
+        }
+
+        Ok(())
+    }
+
+    pub fn prev_image(&mut self) -> Result<(), String> {
+        let pos = self.review_position(self.index);
+        if let Some(prev) = pos.checked_sub(1).and_then(|pos| self.review_image(pos)) {
+            self.index = prev;
+            self.auto_select_cmd();
+            self.load_image_at_index()?;
+            self.fit_draw()?;
+        }
+
+        Ok(())
+    }
+
+
+    /// Toggles the processing of every command for the current image, instead
+    /// of only the commands close to the current one.
+    ///
+    /// If there are more than PROCESS_ALL_WARNING commands, enabling it requires
+    /// a confirmation, i.e. a second call.
+    pub fn toggle_process_all(&mut self) -> Result<(), String> {
+        if !self.process_all && !self.process_all_confirm && self.cmds.len() > PROCESS_ALL_WARNING {
+            self.process_all_confirm = true;
+            self.feedback = Some(format!(
+                "{} commands, press c again to process them all",
+                self.cmds.len()
+            ));
+            self.draw()?;
+
+            return Ok(());
+        }
+
+        self.process_all_confirm = false;
+        self.process_all = !self.process_all;
+        self.feedback = Some(match self.process_all {
+            true => "Processing all commands".to_string(),
+            false => "Processing nearby commands".to_string(),
+        });
+
+        if self.process_all {
+            self.update_process_threads();
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+
+    /// Switch processed pane image to the smallest variant which passes the
+    /// quality threshold.
+    ///
+    /// If the choice is unsure, the function does nothing and returns Ok(())
+    pub fn select_best(&mut self) -> Result<(), String> {
+        match self.imgs[self.index].best_variant(&self.quality_threshold, &self.cmds_enabled) {
+            Some(c) => {
+                self.cmd_index = c;
+                self.load_processed_at_index()?;
+                self.draw()?;
+            }
+            None => diagnostic!("Unable to select best variant, review with n/p"),
+        }
+
+        Ok(())
+    }
+
+
+    /// Selects the initial command of the current image without loading it.
+    ///
+    /// If enabled in settings, the command validated most for similar images
+    /// is selected first, then the best variant if the choice is not unsure.
+    fn auto_select_cmd(&mut self) {
+        if self.settings.remember_preferred_cmd {
+            let preferred = self.preferences.preferred(&self.imgs[self.index].source);
+            if let Some(c) = preferred.filter(|&c| self.cmds_enabled[c]) {
+                self.cmd_index = c;
+            }
+        }
+
+        if !self.settings.auto_select_best {
+            return;
+        }
+
+        if let Some(c) = self.imgs[self.index].best_variant(&self.quality_threshold, &self.cmds_enabled) {
+            self.cmd_index = c;
+        }
+    }
+
+
+    /// Switch processed pane image to image processed with next command in 
+    /// the list
+    ///
+    /// If we reached the end of the list, the function does nothing and returns 
+    /// Ok(())
+    pub fn next_cmd(&mut self) -> Result<(), String> {
+        let next = (self.cmd_index + 1..self.cmds.len()).find(|&c| self.cmds_enabled[c]);
+        if let Some(c) = next {
+            self.cmd_index = c;
+            self.load_processed_at_index()?;
+            self.draw()?;
+        }
+
+        Ok(())
+    }
+
+
+    /// Pins the variant of the current command, so that it can be compared
+    /// with the variants of the other commands with jump_to_pinned. Unpins it
+    /// if it is already pinned.
+    pub fn pin_variant(&mut self) -> Result<(), String> {
+        self.pinned = match self.pinned == Some(self.cmd_index) {
+            true => None,
+            false => Some(self.cmd_index),
+        };
+        self.feedback = Some(match self.pinned {
+            Some(c) => format!("Pinned {}, browse the commands and press ctrl+j to compare", self.cmds[c]),
+            None => "Unpinned".to_string(),
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Switches the processed pane between the current variant and the pinned
+    /// one. The variant left becomes the pinned one, so switching again goes
+    /// back to it.
+    pub fn jump_to_pinned(&mut self) -> Result<(), String> {
+        match self.pinned {
+            None => self.feedback = Some("No pinned variant, pin one with ctrl+k".to_string()),
+            Some(c) if c == self.cmd_index => self.feedback = Some("The current variant is the pinned one".to_string()),
+            Some(c) => {
+                self.pinned = Some(self.cmd_index);
+                self.cmd_index = c;
+                self.load_processed_at_index()?;
+            }
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Switch processed pane image to image processed with previous command in 
+    /// the list
+    ///
+    /// If we reached the begining of the list, the function does nothing and 
+    /// returns Ok(())
+    pub fn prev_cmd(&mut self) -> Result<(), String> {
+        let prev = (0..self.cmd_index).rev().find(|&c| self.cmds_enabled[c]);
+        if let Some(c) = prev {
+            self.cmd_index = c;
+            self.load_processed_at_index()?;
+            self.draw()?;
+        }
+
+        Ok(())
+    }
+
+
+    /// Returns true if the command list popup is open.
+    pub fn cmd_list_is_open(&self) -> bool {
+        self.cmd_list.is_some()
+    }
+
+    /// Opens the command list popup, where commands can be enabled or
+    /// disabled for the rest of the session.
+    pub fn open_cmd_list(&mut self) -> Result<(), String> {
+        self.cmd_list = Some(self.cmd_index);
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Handles a key press while the command list popup is open.
+    ///
+    /// j/k or arrows move the selection, space or return toggles the selected
+    /// command, tab, escape or q close the popup.
+    pub fn cmd_list_key(&mut self, key: Keycode) -> Result<(), String> {
+        let selected = match self.cmd_list {
+            Some(selected) => selected,
+            None => return Ok(()),
+        };
+
+        match key {
+            Keycode::J | Keycode::Down if selected + 1 < self.cmds.len() => {
+                self.cmd_list = Some(selected + 1);
+            }
+            Keycode::K | Keycode::Up if selected > 0 => {
+                self.cmd_list = Some(selected - 1);
+            }
+            Keycode::Space | Keycode::Return => self.toggle_cmd(selected)?,
+            Keycode::Tab | Keycode::Escape | Keycode::Q => self.cmd_list = None,
+            _ => (),
+        }
+
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Enables or disables the command at index c.
+    ///
+    /// Disabled commands are neither processed nor reachable with next_cmd and
+    /// prev_cmd. The last enabled command can't be disabled. If the current
+    /// command is disabled, the closest enabled command becomes current.
+    fn toggle_cmd(&mut self, c: usize) -> Result<(), String> {
+        if self.cmds_enabled[c] && self.cmds_enabled.iter().filter(|&&e| e).count() == 1 {
+            self.feedback = Some("At least one command must be enabled".to_string());
+            return Ok(());
+        }
+
+        self.cmds_enabled[c] = !self.cmds_enabled[c];
+        self.process_order = self.process_window();
+
+        if !self.cmds_enabled[self.cmd_index] {
+            let closest = (0..self.cmds.len())
+                .filter(|&k| self.cmds_enabled[k])
+                .min_by_key(|&k| k.abs_diff(self.cmd_index));
+            if let Some(k) = closest {
+                self.cmd_index = k;
+            }
+        }
+
+        self.load_processed_at_index()?;
+
+        Ok(())
+    }
+
+    /// Draws the command list popup, if open, at the center of the window.
+    fn draw_cmd_list(&mut self) -> Result<(), String> {
+        let selected = match self.cmd_list {
+            Some(selected) => selected,
+            None => return Ok(()),
+        };
+
+        let list = self.cmds
+            .iter()
+            .enumerate()
+            .map(|(c, cmd)| format!(
+                "{} [{}] {cmd}{}",
+                if c == selected { ">" } else { " " },
+                if self.cmds_enabled[c] { "x" } else { " " },
+                match self.imgs[self.index].processed[c].as_ref().is_some_and(|p| p.has_failed()) {
+                    true => " (failed)",
+                    false => "",
+                },
+            ))
+            .collect::<Vec<String>>()
+            .join("\n");
+
+        let (w, h) = self.window_size();
+        let txt = TextBox::new(&list, &self.font)
+            .wrapped(w * 3 / 4);
+        txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 8, h as i32 / 2), Anchor::Left)?;
+
+        Ok(())
+    }
+
+    /// Enables or disables the text input events of SDL. They are only
+    /// enabled while text is typed, otherwise the key opening an input would
+    /// also be typed in it.
+    fn set_text_input(&self, active: bool) {
+        let text_input = self.canvas.window().subsystem().text_input();
+        match active {
+            true => text_input.start(),
+            false => text_input.stop(),
+        }
+    }
+
+    /// Returns true if the command palette is open.
+    pub fn palette_is_open(&self) -> bool {
+        self.palette.is_some()
+    }
+
+    /// Opens the command palette, listing every action with a fuzzy search.
+    pub fn open_palette(&mut self) -> Result<(), String> {
+        self.stop_all_pans();
+        self.palette = Some(Palette::default());
+        self.set_text_input(true);
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Appends text typed while the command palette is open to its query.
+    pub fn palette_text(&mut self, text: &str) -> Result<(), String> {
+        if let Some(palette) = &mut self.palette {
+            palette.push_str(text);
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Handles a key press while the command palette is open.
+    ///
+    /// Arrows move the selection, return runs the selected action and closes
+    /// the palette, backspace erases the query, escape closes the palette.
+    pub fn palette_key(&mut self, key: Keycode) -> Result<(), String> {
+        let actions = PALETTE_ACTIONS;
+        let palette = match &mut self.palette {
+            Some(palette) => palette,
+            None => return Ok(()),
+        };
+        let matches = palette.matches(actions.iter().map(|a| a.0));
+
+        match key {
+            Keycode::Down if palette.selected + 1 < matches.len().min(palette::MAX_RESULTS) => {
+                palette.selected += 1;
+            }
+            Keycode::Up if palette.selected > 0 => palette.selected -= 1,
+            Keycode::Backspace => palette.pop(),
+            Keycode::Escape => self.palette = None,
+            Keycode::Return => {
+                if let Some(&k) = matches.get(palette.selected) {
+                    self.palette = None;
+                    self.set_text_input(false);
+                    self.perform(actions[k].2)?;
+                }
+            }
+            _ => (),
+        }
+        self.set_text_input(self.palette.is_some());
+
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Draws the command palette, if open: the query, then the matching
+    /// actions with their keys.
+    fn draw_palette(&mut self) -> Result<(), String> {
+        let palette = match &self.palette {
+            Some(palette) => palette,
+            None => return Ok(()),
+        };
+
+        let actions = PALETTE_ACTIONS;
+        let mut list = format!("> {}_", palette.query);
+        for (n, &k) in palette.matches(actions.iter().map(|a| a.0)).iter().take(palette::MAX_RESULTS).enumerate() {
+            let (name, key, _) = actions[k];
+            list.push_str(&format!(
+                "\n{} {name}{}",
+                if n == palette.selected { ">" } else { " " },
+                if key.is_empty() { String::new() } else { format!(" ({key})") },
+            ));
+        }
+
+        let (w, h) = self.window_size();
+        let txt = TextBox::new(&list, &self.font)
+            .wrapped(w * 3 / 4);
+        txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 8, h as i32 / 2), Anchor::Left)?;
+
+        Ok(())
+    }
+
+    /// Returns true if a note is being typed.
+    pub fn note_input_is_open(&self) -> bool {
+        self.note_input.is_some()
+    }
+
+    /// Starts typing the note of the current image, from its current note.
+    pub fn open_note_input(&mut self) -> Result<(), String> {
+        self.stop_all_pans();
+        self.note_input = Some(self.imgs[self.index].note.clone().unwrap_or_default());
+        self.set_text_input(true);
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Appends text typed while the note input is open to the note.
+    pub fn note_text(&mut self, text: &str) -> Result<(), String> {
+        if let Some(note) = &mut self.note_input {
+            note.push_str(text);
+        }
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Handles a key press while the note input is open.
+    ///
+    /// Return attaches the note to the current image (an empty note removes
+    /// it), backspace erases the last character, escape cancels.
+    pub fn note_key(&mut self, key: Keycode) -> Result<(), String> {
+        match key {
+            Keycode::Backspace => {
+                if let Some(note) = &mut self.note_input {
+                    note.pop();
+                }
+            }
+            Keycode::Escape => self.note_input = None,
+            Keycode::Return => {
+                if let Some(note) = self.note_input.take() {
+                    let note = note.trim();
+                    self.imgs[self.index].note = (!note.is_empty()).then(|| note.to_string());
+                }
+            }
+            _ => (),
+        }
+        self.set_text_input(self.note_input.is_some());
+
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Draws the note being typed, if any.
+    fn draw_note_input(&mut self) -> Result<(), String> {
+        if let Some(note) = &self.note_input {
+            let (w, h) = self.window_size();
+            let note = format!("note: {note}_");
+            let txt = TextBox::new(&note, &self.font)
+                .wrapped(w * 3 / 4);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 8, h as i32 / 2), Anchor::Left)?;
+        }
+
+        Ok(())
+    }
+
+    /// Validates the current image, or the marked range, with the current
+    /// command. With confirm_destructive, the action validating must be
+    /// repeated to confirm, see confirm_validate.
+    pub fn validate_current(&mut self, action: Action) -> Result<(), String> {
+        if self.range_start.is_some() {
+            return self.validate_range(action);
+        }
+
+        let count = self.validation_count(&[self.index]);
+        if self.settings.confirm_destructive && !self.confirm_validate(count, action) {
+            self.draw()?;
+
+            return Ok(());
+        }
+
+        if self.imgs[self.index].check_source() {
+            self.feedback = Some(OUTDATED_FEEDBACK.to_string());
+        } else if self.imgs[self.index].processed[self.cmd_index].is_some() {
+            self.send_move(self.index, FileOperation::Validate(self.cmd_index), false);
+        }
+
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Sends an operation on the files of image i to the thread moving them.
+    /// Rules is true if the rules took the decision.
+    ///
+    /// Returns false if the operation can't be sent, e.g. since the files of
+    /// the image are still being moved.
+    fn send_move(&mut self, i: usize, operation: FileOperation, rules: bool) -> bool {
+        let request = MoveRequest { i, operation, rules };
+        match self.moves.send(request, &self.imgs[i]) {
+            Ok(()) => true,
+            Err(e) => {
+                diagnostic!("Error: {e}");
+                self.feedback = Some(e);
+                false
+            }
+        }
+    }
+
+    /// Takes the outcome of an operation done by the thread moving the files,
+    /// and records the preferred command of the validations.
+    ///
+    /// Returns true if the operation was on the current image.
+    fn receive_move(&mut self, result: MoveResult) -> bool {
+        let MoveResult { request, img, result } = result;
+        let MoveRequest { i, operation, rules } = request;
+
+        match result {
+            Ok(()) => {
+                match operation {
+                    FileOperation::Validate(c) => self.preferences.record(&self.imgs[i].source, c),
+                    FileOperation::Undo => if let Some(c) = self.imgs[i].validated_index() {
+                        self.preferences.forget(&self.imgs[i].source, c);
+                    },
+                    FileOperation::MoveToFolder(_) => (),
+                }
+                if rules {
+                    self.imgs[i].auto_decision = Some(RuleAction::Accept);
+                }
+                self.imgs[i].take_decision(&operation, img);
+                self.recount_variants(i);
+                if let FileOperation::Validate(c) = operation {
+                    self.emit_validated(i, c);
+                }
+            }
+            Err(e) => {
+                diagnostic!("Error: {e}");
+                self.emit_progress(ProgressEvent::Failed { image: &self.imgs[i].source, command: None, reason: &e });
+                self.feedback = Some(e);
+            }
+        }
+
+        i == self.index
+    }
+
+    /// Prints the progress event of the validation of variant c of image i.
+    fn emit_validated(&self, i: usize, c: usize) {
+        self.emit_progress(ProgressEvent::Validated {
+            image: &self.imgs[i].source,
+            command: &self.cmds[c],
+            saved: self.imgs[i].saved_bytes().unwrap_or(0),
+        });
+    }
+
+    /// Waits for the files being moved, before quitting.
+    pub fn finish_moves(&mut self) {
+        for result in self.moves.finish() {
+            self.receive_move(result);
+        }
+    }
+
+    /// Returns the number of images among the provided ones which
+    /// validating with the current command would replace, i.e. undecided
+    /// with a processed variant.
+    fn validation_count(&self, images: &[usize]) -> usize {
+        images
+            .iter()
+            .map(|&i| &self.imgs[i])
+            .filter(|img| !img.is_decided())
+            .filter(|img| img.processed[self.cmd_index].as_ref().is_some_and(|p| p.is_processed()))
+            .count()
+    }
+
+    /// Returns true if the validation of `count` images is confirmed, i.e. it
+    /// was already requested by the same action less than CONFIRM_DELAY ago.
+    /// Otherwise, the request is recorded and a confirmation is asked for.
+    ///
+    /// Nothing needs to be confirmed when validating would do nothing, or
+    /// when replaying a confirmed macro.
+    fn confirm_validate(&mut self, count: usize, action: Action) -> bool {
+        if count == 0 || self.replay_confirmed {
+            return true;
+        }
+
+        let confirmed = self.confirmed(action);
+        if !confirmed {
+            self.feedback = Some(match count {
+                1 => "Press again to replace the original".to_string(),
+                n => format!("Press again to replace {n} originals"),
+            });
+        }
+
+        confirmed
+    }
+
+    /// Returns true if the action was already requested less than
+    /// CONFIRM_DELAY ago. Otherwise, the request is recorded, to be
+    /// confirmed.
+    fn confirmed(&mut self, action: Action) -> bool {
+        match self.validate_confirm.take() {
+            Some((requested_by, requested)) if requested_by == action && requested.elapsed() < CONFIRM_DELAY => true,
+            _ => {
+                self.validate_confirm = Some((action, Instant::now()));
+                false
+            }
+        }
+    }
+
+    /// Returns the images of the marked range, from its start to the current
+    /// image, in review order. Returns None if no range is marked.
+    fn marked_range(&self) -> Option<Vec<usize>> {
+        let (a, b) = self.marked_positions()?;
+
+        Some((a..=b).filter_map(|pos| self.review_image(pos)).collect())
+    }
+
+    /// Returns the first and last positions of the marked range in review
+    /// order, or None if no range is marked.
+    fn marked_positions(&self) -> Option<(usize, usize)> {
+        let start = self.review_position(self.range_start?);
+        let current = self.review_position(self.index);
+
+        Some((start.min(current), start.max(current)))
+    }
+
+    /// Marks the current image as the start of a range, or clears the marked
+    /// range.
+    pub fn mark_range(&mut self) -> Result<(), String> {
+        self.range_start = match self.range_start {
+            Some(_) => None,
+            None => Some(self.index),
+        };
+        self.feedback = Some(match self.range_start {
+            Some(_) => "Range marked, move to its end and validate".to_string(),
+            None => "Range cleared".to_string(),
+        });
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Validates every image of the marked range with the current command,
+    /// and clears the range.
+    ///
+    /// Images which are already decided, whose variant is not processed
+    /// (pending or failed), or whose source was modified, are skipped.
+    fn validate_range(&mut self, action: Action) -> Result<(), String> {
+        let range = self.marked_range().unwrap_or_default();
+        let count = self.validation_count(&range);
+        if self.settings.confirm_destructive && !self.confirm_validate(count, action) {
+            self.draw()?;
+
+            return Ok(());
+        }
+
+        let mut validated = 0;
+        for &i in &range {
+            let img = &mut self.imgs[i];
+            let processed = img.processed[self.cmd_index].as_ref().is_some_and(|p| p.is_processed());
+            if img.is_decided() || !processed || img.check_source() {
+                continue;
+            }
+            if self.send_move(i, FileOperation::Validate(self.cmd_index), false) {
+                validated += 1;
+            }
+        }
+
+        self.range_start = None;
+        self.feedback = Some(format!("Validating {validated} images, {} skipped", range.len() - validated));
+        self.draw()?;
+
+        Ok(())
+    }
+
+
+    /// Moves the current source image to the folder of the move action bound
+    /// to the provided key.
+    ///
+    /// If no move action is bound to the key, the function does nothing and 
+    /// returns Ok(())
+    pub fn move_current(&mut self, key: Keycode) -> Result<(), String> {
+        match self.move_keys.iter().position(|&k| k == key) {
+            Some(i) => self.perform(Action::Move(i)),
+            None => Ok(()),
+        }
+    }
+
+    /// Moves the current source image to the folder of the move action at the
+    /// provided index.
+    fn move_current_with(&mut self, action_index: usize) -> Result<(), String> {
+        let folder = self.settings.move_actions[action_index].folder_for(&self.imgs[self.index].source);
+        self.send_move(self.index, FileOperation::MoveToFolder(folder), false);
+        self.draw()?;
+
+        Ok(())
+    }
+
+
+    /// Returns the index of an image visually identical to the current one.
+    ///
+    /// Images which have already been decided upon (validated or moved) are
+    /// preferred, so that their decision can be applied to the current image.
+    fn find_duplicate(&self) -> Option<usize> {
+        let hash = self.imgs[self.index].phash?;
+        let distance = self.settings.duplicate_distance;
+
+        let mut duplicates = self.imgs
+            .iter()
+            .enumerate()
+            .filter(|&(i, _)| i != self.index)
+            .filter(|(_, img)| img.phash.is_some_and(|h| hamming_distance(h, hash) <= distance));
+
+        let first = duplicates.next()?;
+        std::iter::once(first)
+            .chain(duplicates)
+            .find(|(_, img)| img.is_decided())
+            .or(Some(first))
+            .map(|(i, _)| i)
+    }
+
+
+    /// Applies to the current image the same decision that was taken for a
+    /// visually identical image, i.e. validates the same command, or moves it
+    /// with the same move action.
+    pub fn apply_duplicate_decision(&mut self) -> Result<(), String> {
+        let dup = match self.find_duplicate() {
+            Some(d) => &self.imgs[d],
+            None => return Ok(()),
+        };
+
+        if let Some(moved) = dup.moved() {
+            let action_index = self.settings.move_actions
+                .iter()
+                .position(|a| Some(a.folder_for(&dup.source).as_path()) == moved.parent());
+
+            match action_index {
+                Some(i) => self.move_current_with(i)?,
+                None => diagnostic!("Error: no move action matches {}", moved.display()),
+            }
+        } else if let Some(c) = dup.validated_index() {
+            self.cmd_index = c;
+            self.load_processed_at_index()?;
+            self.validate_current(Action::ApplyDuplicateDecision)?;
+        } else {
+            diagnostic!("Error: duplicate image has not been decided upon");
+        }
+
+        Ok(())
+    }
+
+
+    /// Plans the validation of the current image, and of every following
+    /// undecided image with the same extension, with the current command.
+    ///
+    /// Planned decisions are not applied right away, but when quitting, see
+    /// commit_planned. They can be cancelled image by image with undo.
+    pub fn plan_extension(&mut self) -> Result<(), String> {
+        let extension = |path: &Path| path.extension().map(|e| e.to_ascii_lowercase());
+        let current = extension(&self.imgs[self.index].source);
+
+        let mut pos = self.review_position(self.index);
+        let mut planned = 0;
+        while let Some(i) = self.review_image(pos) {
+            let img = &mut self.imgs[i];
+            if !img.is_decided() && extension(&img.source) == current {
+                img.planned = Some(self.cmd_index);
+                planned += 1;
+            }
+            pos += 1;
+        }
+
+        self.feedback = Some(format!(
+            "{planned} .{} images planned with {}, validated on quit",
+            current.unwrap_or_default().to_string_lossy(),
+            self.cmds[self.cmd_index],
+        ));
+        self.draw()?;
+
+        Ok(())
+    }
+
+    /// Validates the images whose decision was planned and which are still
+    /// undecided. Their variants which are not processed yet are processed
+    /// first, one at a time.
+    ///
+    /// A progress bar shows the file being committed. Cancelled is called
+    /// after every file, and stops the commit there if it returns true: the
+    /// remaining images are left undecided.
+    ///
+    /// This is called when quitting, before the session is saved.
+    pub fn commit_planned<F: FnMut() -> bool>(&mut self, mut cancelled: F) {
+        let planned = (0..self.imgs.len())
+            .filter(|&i| !self.imgs[i].is_decided())
+            .filter_map(|i| Some((i, self.imgs[i].planned?)))
+            .collect::<Vec<(usize, usize)>>();
+        if planned.is_empty() {
+            return;
+        }
+
+        // Variants being processed are received first.
+        while !self.processing.is_empty() {
+            match self.process_rx.recv() {
+                Ok(((i, c), process_item, _)) => {
+                    self.processing.remove(&(i, c));
+                    self.running_weight -= self.cmd_weights[c];
+                    self.imgs[i].processed[c] = Some(process_item);
+                }
+                Err(_) => break,
+            }
+        }
+
+        let options = self.process_options();
+        let (mut validated, mut committed) = (0, 0);
+        for &(i, c) in &planned {
+            let current = self.imgs[i].source.clone();
+            if let Err(e) = self.draw_commit_progress(committed, planned.len(), &current) {
+                diagnostic!("Error: {e}");
+            }
+
+            let source = self.imgs[i].input().to_path_buf();
+            let cmd = self.variant_command(c, self.imgs[i].processed[c].as_ref().and_then(|p| p.quality));
+            if let Some(p) = self.imgs[i].processed[c].as_mut() {
+                p.process(source, self.settings.processing_directory.clone(), cmd, c, &options);
+            }
+
+            match self.imgs[i].validate(c, &self.settings) {
+                Ok(()) => {
+                    validated += 1;
+                    self.emit_validated(i, c);
+                }
+                Err(e) => {
+                    diagnostic!("Error: planned validation of {}: {e}", self.imgs[i].source.display());
+                    self.emit_progress(ProgressEvent::Failed { image: &self.imgs[i].source, command: Some(&self.cmds[c]), reason: &e });
                 }
             }
+            committed += 1;
+
+            if committed < planned.len() && cancelled() {
+                diagnostic!("Commit cancelled, {} planned images left undecided", planned.len() - committed);
+                break;
+            }
         }
+        diagnostic!("Validated {validated} of {} planned images", planned.len());
+    }
+
+    /// Draws the progress of the commit of the planned decisions: a bar, the
+    /// counts, and the file being committed.
+    fn draw_commit_progress(&mut self, committed: usize, total: usize, current: &Path) -> Result<(), String> {
+        let (w, h) = self.window_size();
+        Renderer::clear(self.canvas, Color::RGB(36, 40, 59));
+
+        let track = Rect::new(w as i32 / 16, h as i32 / 2, w * 7 / 8, self.theme.font_size as u32);
+        self.canvas.draw_border(&[track], OVERLAY_BACKGROUND)?;
+        if let Some(fill) = progress_fill(track, committed, total) {
+            self.canvas.draw_border(&[fill], self.theme.selected)?;
+        }
+
+        let text = format!(
+            "Committing planned decisions, {committed} of {total} (escape cancel after this file)\n{}",
+            current.display(),
+        );
+        let txt = TextBox::new(&text, &self.font)
+            .wrapped(w * 7 / 8);
+        txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 16, track.bottom() + track.height() as i32), Anchor::TopLeft)?;
+        Renderer::present(self.canvas);
+
+        Ok(())
     }
 
-    fn load_source_at_index(&mut self) -> Result<(), String> {
-        // Load image on screen.
-        if let Some(v) = self.imgs[self.index].get_validated() {
-            println!("load_source_is_validated");
-            if let Some(d) = &self.imgs[self.index].deleted {
-                self.source_texture = self.texture_creator.load_texture(d)?;
-            }
+    /// Undo the selection/validation/move of currently selected image
+    pub fn undo_current(&mut self) -> Result<(), String> {
+        let img = &mut self.imgs[self.index];
+        if !img.is_decided() && img.planned.take().is_some() {
+            self.feedback = Some("Planned decision cancelled".to_string());
+            self.draw()?;
+
+            return Ok(());
+        }
+        if !img.is_decided() {
+            diagnostic!("Error: No decision to undo");
+        } else if img.is_permanent() {
+            self.feedback = Some("The original was deleted from trash, the validation can't be undone".to_string());
         } else {
-            println!("load_source_is_not_validated");
-            self.source_texture = self
-                .texture_creator
-                .load_texture(&self.imgs[self.index].source)?;
+            self.send_move(self.index, FileOperation::Undo, false);
         }
 
-        let texture_info = self.source_texture.query();
-        self.source_view
-            .set_img_rect(Rect::new(0, 0, texture_info.width, texture_info.height));
+        self.draw()?;
 
         Ok(())
     }
 
-    fn load_processed_at_index(&mut self) -> Result<(), String> {
-        // Load processed picture
-        if let Some(p) = self.imgs[self.index].get_validated() {
-            println!("load_processed_is_validated");
-            if let Some(o) = &p.processed_path {
-                self.processed_texture = self.texture_creator.load_texture(&o)?;
-            }
-        } else if let Some(processed_img) = &self.imgs[self.index].processed[self.cmd_index] {
-            println!("load_processed_is_not_validated_but_processed");
-            if let Some(processed_path) = &processed_img.tmp_path {
-                // println!("processed_path: {}", processed_path.display());
-                self.processed_texture = self.texture_creator.load_texture(&processed_path)?;
+
+    /// Switches the application between fullscreen and normal
+    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
+        let window = self.canvas.window_mut();
+
+        if window.fullscreen_state() == FullscreenType::Off {
+            window.set_fullscreen(FullscreenType::Desktop)?;
+        } else {
+            window.set_fullscreen(FullscreenType::Off)?;
+        }
+
+        self.update_views()?;
+        Ok(())
+    }
+
+
+    /// Evaluates the rules on the variant of image i processed with command c,
+    /// and pre-decides the image if possible.
+    ///
+    /// An image is accepted, i.e. validated, as soon as one of its variants is
+    /// accepted. It is rejected, i.e. kept as is, once all its variants are
+    /// rejected or failed. Images already decided upon are left untouched.
+    ///
+    /// The validation is sent to the thread moving the files, see moves.
+    fn apply_rules(&mut self, i: usize, c: usize) {
+        let img = &mut self.imgs[i];
+        let outcome = match img.processed[c].as_mut() {
+            Some(p) if p.is_processed() && !p.metrics_pending => {
+                p.rule = evaluate(&self.rules, &p.variant());
+                p.rule
             }
+            _ => None,
+        };
+
+        if img.auto_decision.is_some() || img.is_decided() || self.moves.is_pending(i) || img.check_source() {
+            return;
         }
 
-        let texture_info = self.processed_texture.query();
-        self.processed_view
-            .set_img_rect(Rect::new(0, 0, texture_info.width, texture_info.height));
+        if outcome == Some(RuleAction::Accept) {
+            self.send_move(i, FileOperation::Validate(c), true);
 
-        self.update_process_threads();
+            return;
+        }
 
-        Ok(())
+        let all_rejected = img.processed
+            .iter()
+            .zip(self.cmds_enabled.iter())
+            .filter(|(_, &enabled)| enabled)
+            .all(|(p, _)| p.as_ref().is_some_and(|p| p.rule == Some(RuleAction::Reject) || p.has_failed()));
+        if !self.rules.is_empty() && all_rejected {
+            img.auto_decision = Some(RuleAction::Reject);
+        }
     }
 
-    fn load_image_at_index(&mut self) -> Result<(), String> {
-        self.load_source_at_index()?;
-        self.load_processed_at_index()?;
+
+    /// Goes to the next image which still needs a review from the user, i.e.
+    /// which was neither decided upon by the user nor by the rules.
+    pub fn next_to_review(&mut self) -> Result<(), String> {
+        let pos = self.review_position(self.index);
+        let next = (pos + 1..self.imgs.len())
+            .filter_map(|pos| self.review_image(pos))
+            .find(|&i| {
+                let img = &self.imgs[i];
+                img.auto_decision.is_none() && !img.is_decided() && img.load_error.is_none()
+            });
+
+        if let Some(i) = next {
+            self.index = i;
+            self.auto_select_cmd();
+            self.load_image_at_index()?;
+            self.fit_draw()?;
+        }
 
         Ok(())
     }
 
-    fn first_image(&mut self) -> Result<(), String> {
-        self.index = 0;
-        self.cmd_index = 0;
-        // Processing first image here before other processes
-        if !self.imgs.is_empty()
-            && !self.cmds.is_empty()
-            && self.imgs[self.index].processed[self.cmd_index].is_some()
-        {
-            let mut p = self.imgs[self.index].processed[self.cmd_index]
-                .take()
-                .unwrap();
-            p.process(
-                self.imgs[self.index].source.clone(),
-                self.settings.processing_directory.clone(),
-                self.cmds[self.cmd_index].to_string(),
-                self.cmd_index,
-            );
-            self.imgs[self.index].processed[self.cmd_index] = Some(p);
+
+    /// Restores the decisions recorded in the session file, for the images of
+    /// the current list, then goes back to the image displayed at the time.
+    ///
+    /// Decisions whose files are no longer where they were left are skipped.
+    fn resume_session(&mut self) -> Result<(), String> {
+        let session = match Session::load(&self.settings.session_file) {
+            Ok(session) => session,
+            Err(e) => {
+                diagnostic!("Error: {e}");
+                return Ok(());
+            }
+        };
+
+        let mut restored = 0;
+        for (source, decision) in &session.decisions {
+            let img = match self.imgs.iter_mut().find(|img| &img.source == source) {
+                Some(img) => img,
+                None => continue,
+            };
+
+            let result = match decision {
+                Decision::Validated { cmd_index, tmp_path, validated_path, deleted_path, permanent } => img
+                    .restore_validated(*cmd_index, tmp_path, validated_path, deleted_path.as_deref())
+                    .map(|_| {
+                        if *permanent {
+                            img.make_permanent();
+                        }
+                        self.preferences.record(source, *cmd_index)
+                    }),
+                Decision::Moved { moved_path } => img.restore_moved(moved_path),
+            };
+
+            match result {
+                Ok(()) => restored += 1,
+                Err(e) => diagnostic!("Error: unable to resume decision on {}: {e}", source.display()),
+            }
+        }
+
+        for (source, note) in &session.notes {
+            if let Some(img) = self.imgs.iter_mut().find(|img| &img.source == source) {
+                img.note = Some(note.clone());
+            }
+        }
+
+        for (source, e) in &session.unreadable {
+            if let Some(img) = self.imgs.iter_mut().find(|img| &img.source == source) {
+                img.load_error = Some(e.clone());
+            }
         }
 
+        for i in 0..self.imgs.len() {
+            self.recount_variants(i);
+        }
+        if session.index < self.imgs.len() {
+            self.index = session.index;
+        }
         self.load_image_at_index()?;
+        self.stats_baseline = Stats::from_imgs(&self.imgs, &self.cmds);
+        self.feedback = Some(format!("Resumed session, {restored} decisions restored"));
         self.fit_draw()?;
 
         Ok(())
     }
 
-    pub fn next_image(&mut self) -> Result<(), String> {
-        if self.index + 1 < self.imgs.len() {
-            self.index += 1;
-            self.load_image_at_index()?;
-            self.fit_draw()?;
+    /// Writes the decisions of the session to the session file, if they
+    /// changed since the last write.
+    pub fn save_session(&mut self) {
+        let content = Session::from_imgs(self.index, &self.imgs).format();
+        if content == self.saved_session {
+            return;
         }
 
-        Ok(())
+        match write_session_file(&self.settings.session_file, &content) {
+            Ok(()) => self.saved_session = content,
+            Err(e) => diagnostic!("Error: {e}"),
+        }
     }
 
-    pub fn prev_image(&mut self) -> Result<(), String> {
-        if self.index > 0 {
-            self.index -= 1;
-            self.load_image_at_index()?;
-            self.fit_draw()?;
+    /// Returns the statistics of the commands: the ones of the stats file,
+    /// plus the decisions of this run.
+    fn stats(&self) -> Result<Stats, String> {
+        let mut stats = Stats::load(&self.settings.stats_file)?;
+        stats.merge(&Stats::from_imgs(&self.imgs, &self.cmds));
+        stats.remove(&self.stats_baseline);
+
+        Ok(stats)
+    }
+
+    /// Adds the decisions of this run to the stats file.
+    ///
+    /// The decisions restored from the session were already counted when
+    /// they were taken, they are removed before writing.
+    pub fn save_stats(&mut self) {
+        let result = self.stats().and_then(|stats| write_session_file(&self.settings.stats_file, &stats.format()));
+        if let Err(e) = result {
+            diagnostic!("Error: {e}");
         }
+    }
+
+    /// Writes the settings changed at runtime (processing of all commands,
+    /// fast preview, side of the source, single pane) to the config file,
+    /// keeping its comments.
+    pub fn save_settings(&mut self) -> Result<(), String> {
+        // In blind mode, the panes are swapped at random.
+        let source_position = match self.blind.unwrap_or(self.panes_swapped) {
+            true => self.settings.source_position.mirrored(),
+            false => self.settings.source_position,
+        };
+        let runtime = RuntimeSettings {
+            process_all_commands: self.process_all,
+            fast_preview: self.proxy,
+            source_position,
+            display_mode: self.display_mode(),
+        };
+
+        let result = AppSettings::config_path()
+            .map_err(|e| e.to_string())
+            .and_then(|path| {
+                let text = fs::read_to_string(&path).map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+                write_session_file(&path, &runtime.update_config(&text)?)?;
+                Ok(path)
+            });
+        self.feedback = Some(match result {
+            Ok(path) => format!("Settings saved to {}", path.display()),
+            Err(e) => format!("Settings not saved: {e}"),
+        });
+        self.draw()?;
 
         Ok(())
     }
 
+    /// Adds the blind comparisons of this run to the blind results file.
+    pub fn save_blind_results(&self) {
+        if self.blind_results.cmds.is_empty() {
+            return;
+        }
 
-    /// Switch processed pane image to image processed with next command in 
-    /// the list
-    ///
-    /// If we reached the end of the list, the function does nothing and returns 
-    /// Ok(())
-    pub fn next_cmd(&mut self) -> Result<(), String> {
-        if self.cmd_index + 1 < self.cmds.len() {
-            self.cmd_index += 1;
-            self.load_processed_at_index()?;
-            self.draw()?;
+        let result = BlindResults::load(&self.settings.blind_file).and_then(|mut results| {
+            results.merge(&self.blind_results);
+            write_session_file(&self.settings.blind_file, &results.format())
+        });
+        if let Err(e) = result {
+            diagnostic!("Error: {e}");
         }
+    }
 
-        Ok(())
+    /// Adds this session to the recent file listed by the launcher, with the
+    /// deepest directory containing its images.
+    pub fn save_recent(&self) {
+        let Some(directory) = common_directory(self.imgs.iter().map(|img| img.source.as_path())) else {
+            return;
+        };
+
+        let session = RecentSession {
+            date: Utc::now().format("%Y-%m-%d %H:%M UTC").to_string(),
+            images: self.imgs.len(),
+            directory,
+        };
+        let result = Recent::load(&self.settings.recent_file).and_then(|mut recent| {
+            recent.record(session);
+            write_session_file(&self.settings.recent_file, &recent.format())
+        });
+        if let Err(e) = result {
+            diagnostic!("Error: {e}");
+        }
     }
 
+    /// Sends the report of the session to the report command and webhook of
+    /// the settings, if any.
+    pub fn send_report(&self) {
+        if self.settings.report_command.is_none() && self.settings.report_webhook.is_none() {
+            return;
+        }
 
-    /// Switch processed pane image to image processed with previous command in 
-    /// the list
-    ///
-    /// If we reached the begining of the list, the function does nothing and 
-    /// returns Ok(())
-    pub fn prev_cmd(&mut self) -> Result<(), String> {
-        if self.cmd_index > 0 {
-            self.cmd_index -= 1;
-            self.load_processed_at_index()?;
-            self.draw()?;
+        let date = Utc::now().format("%Y-%m-%d %H:%M UTC").to_string();
+        let report = SessionReport::from_imgs(&self.imgs, &self.cmds, date);
+        if let Err(e) = send_report(&report.json(), &self.settings) {
+            diagnostic!("Error: {e}");
+        }
+    }
+
+    /// Removes the temporary files of the variants which were not validated.
+    /// Variants still being processed when quitting are left behind.
+    pub fn remove_tmp_files(&self) {
+        if let Err(e) = remove_tmp_files(&self.settings.processing_directory, &self.tmp_namespace) {
+            diagnostic!("Error: {e}");
         }
+    }
+
+    /// Returns true if the statistics screen is open.
+    pub fn stats_screen_is_open(&self) -> bool {
+        self.stats_screen.is_some()
+    }
+
+    /// Opens a screen summarizing the statistics of the commands, including
+    /// the decisions of this run.
+    pub fn open_stats_screen(&mut self) -> Result<(), String> {
+        self.stop_all_pans();
+        self.stats_screen = Some(self.stats()?.summary());
+        self.draw()?;
 
         Ok(())
     }
 
-    pub fn validate_current(&mut self) -> Result<(), String> {
-        let img = &mut self.imgs[self.index];
+    /// Closes the statistics screen.
+    pub fn close_stats_screen(&mut self) -> Result<(), String> {
+        self.stats_screen = None;
+        self.draw()?;
 
-        if img.processed[self.cmd_index].is_some() {
-            // Catch the error but don't panic.
-            if let Err(s) = img.validate(self.cmd_index, &self.settings) {
-                println!("Error: {s}");
-            }
+        Ok(())
+    }
+
+    /// Draws the statistics screen, if open.
+    fn draw_stats_screen(&mut self) -> Result<(), String> {
+        if let Some(summary) = &self.stats_screen {
+            let (w, h) = self.window_size();
+            let summary = format!("Command statistics (any key to close)\n{summary}");
+            let txt = TextBox::new(&summary, &self.font)
+                .wrapped(w * 3 / 4);
+            txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 8, h as i32 / 2), Anchor::Left)?;
         }
 
+        Ok(())
+    }
+
+    /// Returns true if the trash screen is open.
+    pub fn trash_screen_is_open(&self) -> bool {
+        self.trash_screen.is_some()
+    }
+
+    /// Opens a screen listing the originals in the trash, latest first, where
+    /// they can be restored or deleted permanently.
+    pub fn open_trash_screen(&mut self) -> Result<(), String> {
+        self.stop_all_pans();
+        let entries = trash::manifest_entries(&self.settings.trash_directory)?
+            .into_iter()
+            .rev()
+            .map(|e| {
+                let size = fs::metadata(&e.trash_path).ok().map(|md| md.len());
+                (e, size)
+            })
+            .collect();
+
+        self.trash_screen = Some(TrashScreen {
+            entries,
+            selected: 0,
+            confirm_delete: false,
+            message: None,
+            thumbnail: None,
+            thumbnail_decode: None,
+        });
+        self.request_trash_thumbnail();
         self.draw()?;
 
         Ok(())
     }
 
+    /// Starts decoding the thumbnail of the selected trash entry.
+    fn request_trash_thumbnail(&mut self) {
+        if let Some(screen) = &mut self.trash_screen {
+            screen.thumbnail = None;
+            screen.thumbnail_decode = screen
+                .entries
+                .get(screen.selected)
+                .map(|(e, _)| spawn_preview(e.trash_path.clone()));
+        }
+    }
 
-    /// Undo the selection/validation of currently selected image
-    pub fn undo_current(&mut self) -> Result<(), String> {
-        let img = &mut self.imgs[self.index];
+    /// Creates the texture of the thumbnail of the trash screen once decoded.
+    /// Returns true if the screen must be redrawn.
+    fn update_trash_thumbnail(&mut self) -> bool {
+        let screen = match &mut self.trash_screen {
+            Some(screen) => screen,
+            None => return false,
+        };
 
-        // Catch the error but don't panic.
-        if let Err(s) = img.undo() {
-            println!("Error: {s}");
+        let preview = match screen.thumbnail_decode.as_ref().map(|rx| rx.try_recv()) {
+            Some(Ok(preview)) => preview,
+            _ => return false,
+        };
+        screen.thumbnail_decode = None;
+        match preview.and_then(|p| p.pixels.to_texture(self.texture_creator)) {
+            Ok(texture) => screen.thumbnail = Some(texture),
+            Err(e) => screen.message = Some(format!("No thumbnail: {e}")),
         }
 
-        self.load_processed_at_index()?;
+        true
+    }
+
+    /// Handles a key press while the trash screen is open.
+    ///
+    /// j/k or arrows move the selection, r restores the selected original,
+    /// delete pressed twice deletes it permanently, escape or q close the
+    /// screen.
+    pub fn trash_screen_key(&mut self, key: Keycode) -> Result<(), String> {
+        let screen = match &mut self.trash_screen {
+            Some(screen) => screen,
+            None => return Ok(()),
+        };
+        let confirm_delete = std::mem::take(&mut screen.confirm_delete);
+        screen.message = None;
+
+        match key {
+            Keycode::J | Keycode::Down if screen.selected + 1 < screen.entries.len() => {
+                screen.selected += 1;
+                self.request_trash_thumbnail();
+            }
+            Keycode::K | Keycode::Up if screen.selected > 0 => {
+                screen.selected -= 1;
+                self.request_trash_thumbnail();
+            }
+            Keycode::R if !screen.entries.is_empty() => self.restore_trash_entry(),
+            Keycode::Delete if !screen.entries.is_empty() => match confirm_delete {
+                true => self.delete_trash_entry(),
+                false => {
+                    screen.confirm_delete = true;
+                    screen.message = Some("Press delete again to delete permanently".to_string());
+                }
+            },
+            Keycode::Escape | Keycode::Q => self.trash_screen = None,
+            _ => (),
+        }
+
+        // The image may have been restored by undoing its validation.
+        if self.trash_screen.is_none() {
+            self.load_image_at_index()?;
+        }
         self.draw()?;
 
         Ok(())
     }
 
+    /// Restores the selected original of the trash screen.
+    ///
+    /// Originals trashed during this session are restored by undoing their
+    /// validation, so that the session stays consistent.
+    fn restore_trash_entry(&mut self) {
+        let screen = match &mut self.trash_screen {
+            Some(screen) => screen,
+            None => return,
+        };
+        let trash_path = screen.entries[screen.selected].0.trash_path.clone();
 
-    /// Switches the application between fullscreen and normal
-    pub fn toggle_fullscreen(&mut self) -> Result<(), String> {
-        let window = self.canvas.window_mut();
+        let result = match self.imgs.iter().position(|img| img.deleted() == Some(trash_path.as_path())) {
+            Some(i) if self.moves.is_pending(i) => Err("its files are still being moved".to_string()),
+            Some(i) => self.imgs[i].undo(&self.settings).map(|()| self.imgs[i].source.clone()),
+            None => trash::restore(&self.settings.trash_directory, &trash_path),
+        };
 
-        if window.fullscreen_state() == FullscreenType::Off {
-            window.set_fullscreen(FullscreenType::Desktop)?;
-        } else {
-            window.set_fullscreen(FullscreenType::Off)?;
+        match result {
+            Ok(original) => {
+                screen.message = Some(format!("Restored {}", original.display()));
+                screen.entries.remove(screen.selected);
+                screen.selected = screen.selected.min(screen.entries.len().saturating_sub(1));
+                self.request_trash_thumbnail();
+            }
+            Err(e) => screen.message = Some(format!("Error: {e}")),
+        }
+    }
+
+    /// Deletes the selected original of the trash screen permanently.
+    fn delete_trash_entry(&mut self) {
+        let screen = match &mut self.trash_screen {
+            Some(screen) => screen,
+            None => return,
+        };
+        let trash_path = screen.entries[screen.selected].0.trash_path.clone();
+
+        match trash::delete(&self.settings.trash_directory, &trash_path) {
+            Ok(()) => {
+                // The validated file of an image of this session is now its
+                // only copy.
+                for img in self.imgs.iter_mut().filter(|img| img.deleted() == Some(trash_path.as_path())) {
+                    img.make_permanent();
+                }
+                screen.message = Some(format!("Deleted {}", trash_path.display()));
+                screen.entries.remove(screen.selected);
+                screen.selected = screen.selected.min(screen.entries.len().saturating_sub(1));
+                self.request_trash_thumbnail();
+            }
+            Err(e) => screen.message = Some(format!("Error: {e}")),
+        }
+    }
+
+    /// Draws the trash screen: the entries around the selected one on the
+    /// left, the thumbnail of the selected one on the right.
+    fn draw_trash_screen(&mut self) -> Result<(), String> {
+        let screen = match &self.trash_screen {
+            Some(screen) => screen,
+            None => return Ok(()),
+        };
+        let (w, h) = self.window_size();
+
+        let mut list = format!(
+            "Trash, {} originals (r restore, delete twice to delete, q close)",
+            screen.entries.len(),
+        );
+        if let Some(message) = &screen.message {
+            list.push_str(&format!("\n{message}"));
+        }
+        let first = screen.selected.saturating_sub(TRASH_SCREEN_ROWS / 2);
+        for (k, (entry, size)) in screen.entries.iter().enumerate().skip(first).take(TRASH_SCREEN_ROWS) {
+            list.push_str(&format!(
+                "\n{} {}\n    {}, {}",
+                if k == screen.selected { ">" } else { " " },
+                entry.original.display(),
+                entry.date.as_deref().unwrap_or("unknown date"),
+                size.map_or("missing".to_string(), human_readable_size),
+            ));
+        }
+
+        let txt = TextBox::new(&list, &self.font)
+            .wrapped(w / 2);
+        txt.draw(self.canvas, &mut self.text_cache, Point::new(w as i32 / 16, h as i32 / 2), Anchor::Left)?;
+
+        if let Some(thumbnail) = &screen.thumbnail {
+            let query = thumbnail.query();
+            let area = Rect::new(w as i32 * 5 / 8, h as i32 / 8, w * 5 / 16, h * 3 / 4);
+            let scale = f32::min(area.width() as f32 / query.width as f32, area.height() as f32 / query.height as f32).min(1.);
+            let dst = Rect::from_center(
+                area.center(),
+                ((query.width as f32 * scale) as u32).max(1),
+                ((query.height as f32 * scale) as u32).max(1),
+            );
+            self.canvas.blit_view(thumbnail, &Blit::new(None, dst))?;
         }
 
-        self.update_views()?;
         Ok(())
     }
 
+    /// Saves the session if the autosave interval has elapsed. An interval of
+    /// 0 disables the autosave.
+    fn autosave(&mut self) {
+        let interval = self.settings.autosave_interval;
+        if interval > 0 && self.last_autosave.elapsed() >= Duration::from_secs(interval) {
+            self.save_session();
+            self.last_autosave = Instant::now();
+        }
+    }
 
     /// Function to be ran in the main loop, it handles processing
     /// the images through multi threading.
     pub fn run(&mut self) -> Result<(), String> {
+        self.autosave();
+        self.update_pan()?;
+        self.update_tour()?;
+        self.request_hotspots();
+
         let mut update_image = false;
+        let mut received = false;
+
+        while let Ok(((i, c), process_item, panicked)) = self.process_rx.try_recv() {
+            received = true;
+            if let Some(e) = panicked {
+                diagnostic!("Error: processing {} with {} panicked: {e}", self.imgs[i].source.display(), self.cmds[c]);
+                self.feedback = Some(format!("Processing panicked: {e}"));
+            }
+            self.processing.remove(&(i, c));
+            self.running_weight -= self.cmd_weights[c];
+            self.imgs[i].processed[c] = Some(process_item);
+            self.recount_variants(i);
+            self.hotspots.remove(&(i, c));
+            self.savings_changed();
+            self.emit_processed(i, c);
+            self.on_processed(i, c);
+            if self.index == i && self.cmd_index == c {
+                update_image = true;
+            }
+        }
+
+        if self.update_trash_thumbnail() {
+            self.draw()?;
+        }
+
+        // Processing which waited for the running commands can start.
+        if received {
+            self.update_process_threads();
+        }
+
+        // The queue status changes with every result.
+        let results = self.pool.results().collect::<Vec<JobResult>>();
+        let mut update_overlay = received || !results.is_empty();
+        for result in results {
+            match result {
+                JobResult::Hash { i, hash } => {
+                    self.imgs[i].phash = hash;
+                    update_overlay = true;
+                }
+                JobResult::Preview { i, preview } => {
+                    if let Some(preview) = preview.filter(|_| self.previews_requested.contains(&i)) {
+                        self.previews.insert(i, preview);
+                    }
+                }
+                JobResult::Decode { i, path, pixels } => {
+                    self.preloads_requested.remove(&i);
+                    if let Some(pixels) = pixels {
+                        self.receive_preload(i, &path, pixels)?;
+                    }
+                }
+                JobResult::Copy { i, path } => {
+                    // A failed copy falls back to the source.
+                    let img = &mut self.imgs[i];
+                    img.local_copy = Some(path.unwrap_or_else(|| img.source.clone()));
 
-        for k in (0..self.rxs.len()).rev() {
-            if let Ok(((i, c), process_item)) = self.rxs[k].try_recv() {
-                self.imgs[i].processed[c] = Some(process_item);
-                if self.index == i && self.cmd_index == c {
-                    update_image = true;
+                    // The variants of the image were passed by the processing
+                    // order while it was copied.
+                    self.process_order = self.process_window();
+                    self.update_process_threads();
+                    if self.index == i {
+                        self.load_source_at_index()?;
+                        update_overlay = true;
+                    }
+                }
+                JobResult::Metrics { i, c, values } => {
+                    if let Some(p) = self.imgs[i].processed[c].as_mut() {
+                        p.metrics = values;
+                        p.metrics_pending = false;
+                    }
+                    self.apply_rules(i, c);
+                    if self.index == i {
+                        update_overlay = true;
+                    }
+                }
+                JobResult::Hotspots { i, c, hotspots } => {
+                    // The variant may have been processed again meanwhile.
+                    if let Some(entry) = self.hotspots.get_mut(&(i, c)).filter(|h| h.is_none()) {
+                        *entry = Some(hotspots);
+                    }
                 }
-                self.rxs.swap_remove(k);
             }
         }
 
+        if self.receive_source_decode()? {
+            update_overlay = true;
+        }
+
+        // The files of the current image moved, its source or variant are
+        // elsewhere.
+        let mut moved_current = false;
+        for result in self.moves.results() {
+            update_overlay = true;
+            moved_current |= self.receive_move(result);
+        }
+        if moved_current {
+            self.load_image_at_index()?;
+        }
+
+        // The total changes with every chunk of the list.
+        let listed = self.receive_list();
+        let ingested = self.ingest(INGEST_CHUNK);
+        if ingested {
+            self.update_process_threads();
+        }
+        update_overlay |= listed || ingested;
+
         if update_image {
             self.load_processed_at_index()?;
             self.draw()?;
+        } else if update_overlay {
+            self.draw()?;
         }
         Ok(())
     }
 }
 
+
+/// Returns the key of the texture of the file at provided path.
+fn texture_key(path: &Path) -> TextureKey {
+    let modified = fs::metadata(path).and_then(|md| md.modified()).ok();
+
+    (path.to_path_buf(), modified)
+}
+
+
+/// Returns the approximate memory used by a texture, assuming 4 bytes per
+/// pixel.
+fn texture_bytes(texture: &Texture) -> u64 {
+    let query = texture.query();
+
+    query.width as u64 * query.height as u64 * 4
+}
+
+
+/// Copies the texture with the provided copy function, as many times as
+/// needed to render the exposure adjustment.
+///
+/// The color modulation and blend mode of the texture are restored afterwards.
+fn copy_exposed<F>(renderer: &mut dyn Renderer, texture: &mut Texture, exposure: &Exposure, copy: F) -> Result<(), String>
+where
+    F: Fn(&mut dyn Renderer, &Texture) -> Result<(), String>,
+{
+    let blend_mode = texture.blend_mode();
+    let mut result = Ok(());
+
+    for (i, color_mod) in exposure.passes().into_iter().enumerate() {
+        if i == 1 {
+            texture.set_blend_mode(BlendMode::Add);
+        }
+        texture.set_color_mod(color_mod, color_mod, color_mod);
+        result = copy(renderer, texture);
+        if result.is_err() {
+            break;
+        }
+    }
+
+    texture.set_color_mod(255, 255, 255);
+    texture.set_blend_mode(blend_mode);
+
+    result
+}
+
+
+/// Copies the texture of the view to the renderer, rotated and mirrored
+/// according to the orientation around the provided center.
+///
+/// The copy can be clipped to the pane, since the rotated image may overflow
+/// it.
+fn copy_oriented(
+    renderer: &mut dyn Renderer,
+    texture: &Texture,
+    view: &ViewRect,
+    orientation: &Orientation,
+    center: Point,
+    clip_to_pane: bool,
+) -> Result<(), String> {
+    let src_rect = texture_src_rect(texture, view);
+    if orientation.is_identity() {
+        return renderer.blit_view(texture, &Blit::new(Some(src_rect), view.dst_rect));
+    }
+
+    renderer.blit_view(
+        texture,
+        &Blit {
+            src: Some(src_rect),
+            dst: orientation.copy_dst_rect(view.dst_rect, center),
+            angle: orientation.angle(),
+            mirrored: orientation.mirrored,
+            clip: clip_to_pane.then_some(view.clip_rect),
+        },
+    )
+}
+
+
+/// Returns the src rect of the view in the coordinates of the texture, which
+/// differ from the image coordinates when the texture is a preview.
+fn texture_src_rect(texture: &Texture, view: &ViewRect) -> Rect {
+    let query = texture.query();
+    let (w, h) = view.img_size();
+    if (query.width, query.height) == (w, h) {
+        return view.src_rect;
+    }
+
+    let (sx, sy) = (query.width as f32 / w as f32, query.height as f32 / h as f32);
+    let src = view.src_rect;
+    Rect::new(
+        (src.x() as f32 * sx) as i32,
+        (src.y() as f32 * sy) as i32,
+        ((src.width() as f32 * sx) as u32).max(1),
+        ((src.height() as f32 * sy) as u32).max(1),
+    )
+}
+
+
+/// Returns true if the action is recorded in macros.
+///
+/// The actions opening a screen are left out, the screen would stay open
+/// when replaying, while the actions picked from the palette are recorded.
+fn is_recordable(action: Action) -> bool {
+    !matches!(
+        action,
+        Action::ToggleMacroRecording
+            | Action::ReplayMacro
+            | Action::OpenCmdList
+            | Action::OpenPalette
+            | Action::OpenNote
+            | Action::OpenStats
+            | Action::OpenTrash
+    )
+}
+
+/// Returns true or false at random, from the random keys of the standard
+/// hasher which change with every RandomState.
+fn coin_flip() -> bool {
+    use std::collections::hash_map::RandomState;
+    use std::hash::{BuildHasher, Hasher};
+
+    RandomState::new().build_hasher().finish() & 1 == 1
+}
+
+/// Returns the title of the window, e.g.
+/// "bimgo — 42/1380 — IMG_2034.jpg — cmd: mozjpeg q80". The total is followed
+/// by a + until the list is complete.
+fn window_title(index: usize, total: usize, complete: bool, source: &Path, cmd: &str) -> String {
+    let name = source.file_name().unwrap_or_default().to_string_lossy();
+    let more = if complete { "" } else { "+" };
+
+    format!("bimgo — {}/{total}{more} — {name} — cmd: {cmd}", index + 1)
+}
+
+/// Returns the rectangle with the same center, and swapped width and height.
+fn swapped_rect(rect: Rect) -> Rect {
+    Rect::from_center(rect.center(), rect.height(), rect.width())
+}
+
+
+/// Shows the pixel of the image at real size, at the point in window
+/// coordinates.
+fn show_real_size(view: &mut ViewRect, pixel: Point, pt: Point) {
+    view.zoom_towards_view_center(1. / view.zoom_factor());
+    view.show_img_point(pixel, pt);
+}
+
+
+/// Applies the fit mode to the view, using the provided rectangle.
+fn fit_view(view: &mut ViewRect, fit_mode: &FitMode, fit_rect: Rect) {
+    match fit_mode {
+        FitMode::FitBest => view.fit_best_to_rect(fit_rect),
+        FitMode::FitWidth => view.fit_width_to_rect(fit_rect),
+        FitMode::FitHeight => view.fit_height_to_rect(fit_rect),
+        FitMode::Fill => view.fit_fill_to_rect(fit_rect),
+        _ => (),
+    };
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn window_title_shows_progress() {
+        assert_eq!(
+            window_title(41, 1380, true, Path::new("/photos/IMG_2034.jpg"), "mozjpeg q80"),
+            "bimgo — 42/1380 — IMG_2034.jpg — cmd: mozjpeg q80",
+        );
+        assert_eq!(
+            window_title(0, 4097, false, Path::new("/photos/IMG_0001.jpg"), "mozjpeg q80"),
+            "bimgo — 1/4097+ — IMG_0001.jpg — cmd: mozjpeg q80",
+        );
+    }
+
+    #[test]
+    fn pan_speed_accelerates_up_to_max() {
+        assert_eq!(pan_speed(Duration::ZERO), PAN_SPEED);
+        assert_eq!(pan_speed(PAN_HOLD_DELAY + Duration::from_millis(500)), PAN_SPEED + PAN_ACCELERATION / 2.);
+        assert_eq!(pan_speed(Duration::from_secs(60)), PAN_MAX_SPEED);
+    }
+}
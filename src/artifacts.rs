@@ -0,0 +1,258 @@
+//! Detection of the regions of a variant most likely to show compression
+//! artifacts, marked by the hotspot overlay.
+//!
+//! The variant is divided in cells of HOTSPOT_CELL pixels, and two heuristics
+//! are computed on the luma of every cell:
+//! - Blockiness  How much larger the differences between neighbour pixels
+//!   are across the borders of the 8×8 blocks of JPEG-like codecs than
+//!   inside the blocks.
+//! - Ringing     The oscillations of the flat pixels close to a strong edge,
+//!   measured by their laplacian.
+//!
+//! When the source has the same size, its own scores are subtracted, so that
+//! a textured region which is just as busy in the source is not marked. The
+//! cells scoring the most are the hotspots, where to zoom first.
+
+/// Width and height of the cells scored, in pixels, a multiple of BLOCK.
+pub const HOTSPOT_CELL: u32 = 64;
+
+/// Size of the blocks of JPEG-like codecs, in pixels.
+const BLOCK: usize = 8;
+
+/// Maximum number of hotspots returned.
+const MAX_HOTSPOTS: usize = 8;
+
+/// Minimum score of a hotspot, in luma levels (0 to 255).
+const MIN_SCORE: f32 = 1.5;
+
+/// Gradient above which a pixel is on a strong edge, and below which it is
+/// flat, in luma levels.
+const EDGE_GRADIENT: f32 = 64.;
+const FLAT_GRADIENT: f32 = 16.;
+
+/// Distance from a strong edge within which flat pixels may ring, in pixels.
+const RINGING_RADIUS: usize = 3;
+
+
+/// The kind of artifact a hotspot is most likely to show.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ArtifactKind {
+    Blockiness,
+    Ringing,
+}
+
+/// A region likely to show artifacts, in pixels of the variant.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Hotspot {
+    pub x: u32,
+    pub y: u32,
+    pub width: u32,
+    pub height: u32,
+    pub kind: ArtifactKind,
+    pub score: f32,
+}
+
+/// Luma of the pixels of an image, between 0 and 255, row by row.
+pub struct Luma {
+    pub width: usize,
+    pub height: usize,
+    pub values: Vec<f32>,
+}
+
+impl Luma {
+    /// Computes the luma of RGBA pixels, rows stored contiguously.
+    pub fn from_rgba(width: u32, height: u32, data: &[u8]) -> Luma {
+        let values = data
+            .chunks_exact(4)
+            .map(|p| 0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32)
+            .collect();
+
+        Luma { width: width as usize, height: height as usize, values }
+    }
+
+    fn at(&self, x: usize, y: usize) -> f32 {
+        self.values[y * self.width + x]
+    }
+
+    /// Returns the absolute gradient of every pixel, from the differences
+    /// with its right and bottom neighbours.
+    fn gradients(&self) -> Vec<f32> {
+        let mut gradients = vec![0.; self.values.len()];
+        for y in 0..self.height {
+            for x in 0..self.width {
+                let v = self.at(x, y);
+                let dx = if x + 1 < self.width { (self.at(x + 1, y) - v).abs() } else { 0. };
+                let dy = if y + 1 < self.height { (self.at(x, y + 1) - v).abs() } else { 0. };
+                gradients[y * self.width + x] = dx + dy;
+            }
+        }
+
+        gradients
+    }
+
+    /// Returns for every pixel whether a strong edge is within RINGING_RADIUS,
+    /// by dilating the edges horizontally then vertically.
+    fn near_edges(&self, gradients: &[f32]) -> Vec<bool> {
+        let (w, h) = (self.width, self.height);
+        let mut rows = vec![false; gradients.len()];
+        for y in 0..h {
+            for x in (0..w).filter(|&x| gradients[y * w + x] > EDGE_GRADIENT) {
+                for near in x.saturating_sub(RINGING_RADIUS)..(x + RINGING_RADIUS + 1).min(w) {
+                    rows[y * w + near] = true;
+                }
+            }
+        }
+
+        let mut near = vec![false; gradients.len()];
+        for x in 0..w {
+            for y in (0..h).filter(|&y| rows[y * w + x]) {
+                for n in y.saturating_sub(RINGING_RADIUS)..(y + RINGING_RADIUS + 1).min(h) {
+                    near[n * w + x] = true;
+                }
+            }
+        }
+
+        near
+    }
+
+    /// Returns the (blockiness, ringing) scores of every cell, row by row.
+    fn cell_scores(&self) -> Vec<(f32, f32)> {
+        let (w, h) = (self.width, self.height);
+        let cell = HOTSPOT_CELL as usize;
+        let (columns, rows) = (w.div_ceil(cell), h.div_ceil(cell));
+        let gradients = self.gradients();
+        let near_edges = self.near_edges(&gradients);
+
+        // Sums and counts of the differences across and inside blocks, and of
+        // the laplacian of the ringing candidates.
+        let mut sums = vec![[0f32; 3]; columns * rows];
+        let mut counts = vec![[0u32; 3]; columns * rows];
+
+        for y in 0..h {
+            for x in 0..w {
+                let k = (y / cell) * columns + x / cell;
+                let v = self.at(x, y);
+                if x > 0 {
+                    let across = (x % BLOCK == 0) as usize;
+                    sums[k][across] += (v - self.at(x - 1, y)).abs();
+                    counts[k][across] += 1;
+                }
+                if y > 0 {
+                    let across = (y % BLOCK == 0) as usize;
+                    sums[k][across] += (v - self.at(x, y - 1)).abs();
+                    counts[k][across] += 1;
+                }
+
+                // The pixels next to an edge are part of its slope, ringing is
+                // looked for beyond them.
+                let inside = x > 0 && y > 0 && x + 1 < w && y + 1 < h;
+                let flat = gradients[y * w + x] < FLAT_GRADIENT;
+                let beside_edge = inside
+                    && [(x - 1, y), (x + 1, y), (x, y - 1), (x, y + 1)]
+                        .iter()
+                        .any(|&(x, y)| gradients[y * w + x] > EDGE_GRADIENT);
+                if inside && flat && !beside_edge && near_edges[y * w + x] {
+                    let neighbours = self.at(x - 1, y) + self.at(x + 1, y) + self.at(x, y - 1) + self.at(x, y + 1);
+                    sums[k][2] += (4. * v - neighbours).abs();
+                    counts[k][2] += 1;
+                }
+            }
+        }
+
+        let mean = |sum: f32, count: u32| if count == 0 { 0. } else { sum / count as f32 };
+        sums.iter()
+            .zip(counts.iter())
+            .map(|(s, c)| ((mean(s[1], c[1]) - mean(s[0], c[0])).max(0.), mean(s[2], c[2])))
+            .collect()
+    }
+}
+
+/// Returns the hotspots of the variant, the most likely to show artifacts
+/// first. The scores of the source are subtracted if it has the same size.
+pub fn find_hotspots(processed: &Luma, source: Option<&Luma>) -> Vec<Hotspot> {
+    let mut scores = processed.cell_scores();
+    let source = source.filter(|s| (s.width, s.height) == (processed.width, processed.height));
+    if let Some(source) = source {
+        for (score, source) in scores.iter_mut().zip(source.cell_scores()) {
+            score.0 = (score.0 - source.0).max(0.);
+            score.1 = (score.1 - source.1).max(0.);
+        }
+    }
+
+    let cell = HOTSPOT_CELL as usize;
+    let columns = processed.width.div_ceil(cell);
+    let mut hotspots = scores
+        .iter()
+        .enumerate()
+        .map(|(k, &(blockiness, ringing))| {
+            let (x, y) = ((k % columns) * cell, (k / columns) * cell);
+            let (kind, score) = match blockiness >= ringing {
+                true => (ArtifactKind::Blockiness, blockiness),
+                false => (ArtifactKind::Ringing, ringing),
+            };
+            Hotspot {
+                x: x as u32,
+                y: y as u32,
+                width: cell.min(processed.width - x) as u32,
+                height: cell.min(processed.height - y) as u32,
+                kind,
+                score,
+            }
+        })
+        .filter(|h| h.score >= MIN_SCORE)
+        .collect::<Vec<Hotspot>>();
+
+    hotspots.sort_by(|a, b| b.score.total_cmp(&a.score));
+    hotspots.truncate(MAX_HOTSPOTS);
+
+    hotspots
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns a gray image of 3×2 cells, with the provided pixels changed.
+    fn image(change: impl Fn(usize, usize) -> Option<f32>) -> Luma {
+        let (width, height) = (3 * HOTSPOT_CELL as usize, 2 * HOTSPOT_CELL as usize);
+        let values = (0..width * height)
+            .map(|k| change(k % width, k / width).unwrap_or(128.))
+            .collect();
+
+        Luma { width, height, values }
+    }
+
+    #[test]
+    fn blocks_and_ringing_are_hotspots() {
+        assert!(find_hotspots(&image(|_, _| None), None).is_empty());
+
+        // Blocks of alternating levels in the second cell of the first row.
+        let blocky = image(|x, y| {
+            let cell = HOTSPOT_CELL as usize;
+            (x >= cell && x < 2 * cell && y < cell).then(|| 120. + 16. * ((x / BLOCK + y / BLOCK) % 2) as f32)
+        });
+        let hotspots = find_hotspots(&blocky, None);
+        assert_eq!(hotspots.len(), 1);
+        assert_eq!((hotspots[0].x, hotspots[0].y, hotspots[0].kind), (HOTSPOT_CELL, 0, ArtifactKind::Blockiness));
+
+        // The same blocks in the source are not artifacts.
+        assert!(find_hotspots(&blocky, Some(&blocky)).is_empty());
+
+        // A vertical edge inside the blocks of the last column of cells, with
+        // ripples on both sides.
+        let edge = 5 * HOTSPOT_CELL as usize / 2 + 3;
+        let ringing = image(|x, _| match x {
+            x if x == edge - 3 => Some(122.),
+            x if x < edge => None,
+            x if x == edge + 2 => Some(242.),
+            _ => Some(248.),
+        });
+        let hotspots = find_hotspots(&ringing, None);
+        assert_eq!(hotspots.len(), 2);
+        assert_eq!((hotspots[0].x, hotspots[0].kind), (2 * HOTSPOT_CELL, ArtifactKind::Ringing));
+
+        // Without ripples, the edge is sharp but clean.
+        assert!(find_hotspots(&image(|x, _| (x >= edge).then_some(248.)), None).is_empty());
+    }
+}
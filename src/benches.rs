@@ -0,0 +1,90 @@
+//! Micro-benchmarks of the paths run on every frame or keystroke.
+//!
+//! They are ignored by default, run them with
+//! `cargo test --release benches -- --ignored --nocapture`. Every benchmark
+//! prints the time per iteration, and fails if it exceeds a budget set far
+//! above the expected time, which only catches serious regressions.
+
+use std::hint::black_box;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use sdl2::rect::{Point, Rect};
+
+use crate::processing_order::Closest2D;
+use crate::rect_utils::ViewRect;
+use crate::utils::{build_command, human_readable_size};
+
+/// Number of iterations of every benchmark.
+const ITERATIONS: u32 = 100_000;
+
+
+/// Runs f ITERATIONS times, prints the time per iteration, and checks it
+/// against the budget.
+fn bench(name: &str, budget: Duration, mut f: impl FnMut(u32)) {
+    // Warm up the caches.
+    for k in 0..ITERATIONS / 10 {
+        f(k);
+    }
+
+    let start = Instant::now();
+    for k in 0..ITERATIONS {
+        f(k);
+    }
+    let per_iteration = start.elapsed() / ITERATIONS;
+
+    println!("{name}: {per_iteration:?} per iteration (budget {budget:?})");
+    assert!(per_iteration <= budget, "{name} took {per_iteration:?}, over its budget of {budget:?}");
+}
+
+
+#[test]
+#[ignore]
+fn bench_view_rect_pan_and_zoom() {
+    let mut view = ViewRect::new((6000, 4000), Rect::new(0, 0, 800, 1200));
+    view.fit_best_to_rect(Rect::new(0, 0, 800, 1200));
+
+    bench("ViewRect pan and zoom", Duration::from_micros(20), |k| {
+        match k % 4 {
+            0 => view.pan_left(7),
+            1 => view.pan_right(7),
+            2 => view.zoom_towards_point(Point::new(400, 600), 1.1),
+            _ => view.zoom_towards_point(Point::new(400, 600), 1. / 1.1),
+        }
+        black_box(&view);
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_closest2d_window() {
+    bench("Closest2D 11x11 window", Duration::from_micros(100), |k| {
+        let i = (k % 100) as usize + 5;
+        let order = Closest2D::new(i, i - 5, i + 5, 5, 0, 10).with_forward_bias(2);
+        black_box(order.count());
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_human_readable_size() {
+    bench("human_readable_size", Duration::from_micros(20), |k| {
+        black_box(human_readable_size(black_box(k as u64 * 7919 * 1024)));
+    });
+}
+
+#[test]
+#[ignore]
+fn bench_command_substitution() {
+    let input = Path::new("/photos/2024/05/shoot/img.jpg");
+    let output = Path::new("/tmp/img_processed_0.jpg");
+
+    bench("command substitution", Duration::from_micros(50), |_| {
+        let cmd = build_command(
+            black_box("cwebp -q 80 -m 6 -mt %i -o %o"),
+            &[("%i", input), ("%o", output)],
+            None,
+        );
+        black_box(cmd.ok());
+    });
+}
@@ -0,0 +1,180 @@
+//! Results of the blind comparisons, kept across sessions.
+//!
+//! In blind mode the source and the processed image are randomly assigned to
+//! the two panes, without labels, and the user picks the better looking one.
+//! If a command is transparent, the source is picked about half of the time.
+//! The results of a session are added to the file when quitting.
+//!
+//! The file is a list of tab-separated lines:
+//! `<source picks> <processed picks> <command>`
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+
+/// Below this probability of picking the source that often by chance, the
+/// difference is considered visible.
+const SIGNIFICANCE: f64 = 0.05;
+
+/// Blind comparisons of a command.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct BlindCounts {
+    /// Number of comparisons where the source was picked.
+    pub source: u64,
+
+    /// Number of comparisons where the processed image was picked.
+    pub processed: u64,
+}
+
+impl BlindCounts {
+    /// Returns the probability of picking the source at least that often by
+    /// chance, i.e. if the user can't tell the images apart.
+    pub fn p_value(&self) -> f64 {
+        let n = self.source + self.processed;
+
+        // Binomial distribution with p = 1/2, summed from source to n. The
+        // coefficients are computed as floats to avoid overflows.
+        let mut coefficient = 1.;
+        let mut total = 0.;
+        for k in 0..=n {
+            if k >= self.source {
+                total += coefficient;
+            }
+            coefficient = coefficient * (n - k) as f64 / (k + 1) as f64;
+        }
+
+        total / 2f64.powi(n as i32)
+    }
+}
+
+/// Blind comparisons of every command, by command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct BlindResults {
+    pub cmds: BTreeMap<String, BlindCounts>,
+}
+
+impl BlindResults {
+    /// Records a comparison of the variant of cmd with its source.
+    pub fn record(&mut self, cmd: &str, source_picked: bool) {
+        let entry = self.cmds.entry(cmd.to_string()).or_default();
+        match source_picked {
+            true => entry.source += 1,
+            false => entry.processed += 1,
+        }
+    }
+
+    /// Returns the number of comparisons.
+    pub fn len(&self) -> u64 {
+        self.cmds.values().map(|c| c.source + c.processed).sum()
+    }
+
+    /// Adds the results of other to these results.
+    pub fn merge(&mut self, other: &BlindResults) {
+        for (cmd, c) in &other.cmds {
+            let entry = self.cmds.entry(cmd.clone()).or_default();
+            entry.source += c.source;
+            entry.processed += c.processed;
+        }
+    }
+
+    /// Formats the results as the content of the blind results file.
+    pub fn format(&self) -> String {
+        self.cmds
+            .iter()
+            .map(|(cmd, c)| format!("{}\t{}\t{cmd}\n", c.source, c.processed))
+            .collect()
+    }
+
+    /// Parses the content of a blind results file.
+    pub fn parse(content: &str) -> Result<BlindResults, String> {
+        let mut results = BlindResults::default();
+
+        for line in content.lines().filter(|l| !l.is_empty()) {
+            let invalid = || format!("Invalid line in blind results: '{line}'");
+            let fields = line.splitn(3, '\t').collect::<Vec<&str>>();
+            match fields.as_slice() {
+                [source, processed, cmd] => {
+                    let c = BlindCounts {
+                        source: source.parse().map_err(|_| invalid())?,
+                        processed: processed.parse().map_err(|_| invalid())?,
+                    };
+                    results.merge(&BlindResults { cmds: BTreeMap::from([(cmd.to_string(), c)]) });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(results)
+    }
+
+    /// Reads the blind results file at provided path. A missing file has no
+    /// results yet.
+    pub fn load(path: &Path) -> Result<BlindResults, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => BlindResults::parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(BlindResults::default()),
+            Err(e) => Err(format!("Unable to read {}: {e}", path.display())),
+        }
+    }
+
+    /// Returns a summary of the results, one command per line.
+    pub fn summary(&self) -> String {
+        if self.cmds.is_empty() {
+            return "No blind comparisons yet, toggle the blind mode with ctrl+b".to_string();
+        }
+
+        self.cmds
+            .iter()
+            .map(|(cmd, c)| {
+                let verdict = match c.p_value() < SIGNIFICANCE {
+                    true => "visible difference",
+                    false => "transparent so far",
+                };
+                format!(
+                    "source picked {} of {} (p = {:.3}), {verdict}: {cmd}",
+                    c.source,
+                    c.source + c.processed,
+                    c.p_value(),
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn counts(source: u64, processed: u64) -> BlindCounts {
+        BlindCounts { source, processed }
+    }
+
+    #[test]
+    fn blind_results_round_trip() {
+        let mut results = BlindResults::default();
+        results.record("cwebp -q 80 %i -o %o.webp", true);
+        results.record("cwebp -q 80 %i -o %o.webp", false);
+        results.record("avifenc %i %o.avif", false);
+        assert_eq!(results.len(), 3);
+        assert_eq!(BlindResults::parse(&results.format()), Ok(results));
+        assert!(BlindResults::parse("1\tx\tcmd").is_err());
+        assert!(BlindResults::parse("1\t2").is_err());
+    }
+
+    #[test]
+    fn p_value_of_picks() {
+        assert_eq!(counts(0, 0).p_value(), 1.);
+        assert_eq!(counts(0, 10).p_value(), 1.);
+        assert_eq!(counts(1, 0).p_value(), 0.5);
+        assert_eq!(counts(10, 0).p_value(), 1. / 1024.);
+        assert_eq!(counts(2, 1).p_value(), 0.5);
+
+        // Picking the source 15 times of 20 is unlikely by chance.
+        assert!(counts(15, 5).p_value() < SIGNIFICANCE);
+        assert!(counts(11, 9).p_value() > SIGNIFICANCE);
+    }
+}
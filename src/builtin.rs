@@ -0,0 +1,389 @@
+//! Built-in processing backends, run in process instead of an external
+//! command.
+//!
+//! A built-in backend is selected in the commands file as
+//! `builtin:<name>?<option>=<value>&...`, e.g. `builtin:pngstrip?strip=text`.
+//! It reads the source and writes the output file itself, so it doesn't
+//! depend on any external binary.
+//!
+//! pngstrip removes the chunks of a PNG image which don't change how it is
+//! rendered, without recompressing its data:
+//! - strip=text removes the text and modification time chunks.
+//! - strip=metadata (the default) removes every ancillary chunk which doesn't
+//!   change the rendering, and merges the image data chunks.
+//!
+//! jpeg encodes the image as JPEG with the quality `q` (80 by default), in
+//! memory with SDL_image, which is much faster than running an encoder when
+//! trying several qualities.
+//!
+//! resize downscales the image so that it fits in a square of `max` pixels,
+//! with a Lanczos3 filter, and writes it as PNG. It is meant as the first
+//! stage of a pipeline, e.g. `builtin:resize?max=2000 | cwebp %i -o %o`.
+
+use std::fs;
+use std::io::Read;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::decode::PixelBuffer;
+
+/// Prefix of the built-in commands.
+pub const BUILTIN_PREFIX: &str = "builtin:";
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Chunks removed by pngstrip with strip=text.
+const TEXT_CHUNKS: [&[u8; 4]; 4] = [b"tEXt", b"zTXt", b"iTXt", b"tIME"];
+
+/// Ancillary chunks which change how the image is rendered (transparency,
+/// color space, orientation, animation), always kept.
+const RENDERING_CHUNKS: [&[u8; 4]; 13] = [
+    b"tRNS", b"gAMA", b"cHRM", b"sRGB", b"iCCP", b"sBIT", b"cICP", b"mDCv", b"cLLi", b"eXIf", b"acTL", b"fcTL",
+    b"fdAT",
+];
+
+/// Table of the CRC of the PNG chunks (CRC-32, polynomial 0xedb88320).
+const CRC_TABLE: [u32; 256] = crc_table();
+
+
+/// Chunks removed by pngstrip.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StripChunks {
+    /// The text and modification time chunks.
+    Text,
+
+    /// Every ancillary chunk which doesn't change the rendering. The image
+    /// data chunks are merged.
+    Metadata,
+}
+
+/// A built-in backend, with its options.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Builtin {
+    PngStrip { strip: StripChunks },
+    Resize { max: u32 },
+    Jpeg { quality: u8 },
+}
+
+impl FromStr for Builtin {
+    type Err = String;
+
+    /// Parses a built-in command, without its prefix.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, options) = s.split_once('?').unwrap_or((s, ""));
+        let options = options
+            .split('&')
+            .filter(|o| !o.is_empty())
+            .map(|o| o.split_once('=').ok_or_else(|| format!("Invalid option '{o}' in builtin:{s}")))
+            .collect::<Result<Vec<(&str, &str)>, String>>()?;
+
+        match name {
+            "pngstrip" => {
+                let mut strip = StripChunks::Metadata;
+                for (key, value) in options {
+                    match (key, value) {
+                        ("strip", "text") => strip = StripChunks::Text,
+                        ("strip", "metadata") => strip = StripChunks::Metadata,
+                        ("strip", _) => {
+                            return Err(format!("Invalid strip '{value}' in builtin:{s}, expected text or metadata"));
+                        }
+                        _ => return Err(format!("Unknown option '{key}' in builtin:{s}")),
+                    }
+                }
+                Ok(Builtin::PngStrip { strip })
+            }
+            "resize" => {
+                let mut max = None;
+                for (key, value) in options {
+                    match key {
+                        "max" => {
+                            max = Some(value
+                                .parse()
+                                .ok()
+                                .filter(|&m| m > 0)
+                                .ok_or_else(|| format!("Invalid max '{value}' in builtin:{s}"))?);
+                        }
+                        _ => return Err(format!("Unknown option '{key}' in builtin:{s}")),
+                    }
+                }
+                let max = max.ok_or_else(|| format!("Missing max in builtin:{s}, e.g. builtin:resize?max=2000"))?;
+                Ok(Builtin::Resize { max })
+            }
+            "jpeg" => {
+                let mut quality = 80;
+                for (key, value) in options {
+                    match key {
+                        "q" => {
+                            quality = value
+                                .parse()
+                                .ok()
+                                .filter(|q| (1..=100).contains(q))
+                                .ok_or_else(|| format!("Invalid quality '{value}' in builtin:{s}, expected 1 to 100"))?;
+                        }
+                        _ => return Err(format!("Unknown option '{key}' in builtin:{s}")),
+                    }
+                }
+                Ok(Builtin::Jpeg { quality })
+            }
+            _ => Err(format!("Unknown builtin '{name}'")),
+        }
+    }
+}
+
+impl Builtin {
+    /// Processes the file at input path, writing the result to output path.
+    pub fn run(&self, input: &Path, output: &Path) -> Result<(), String> {
+        match self {
+            Builtin::PngStrip { strip } => {
+                let data = fs::read(input).map_err(|e| format!("Couldn't read {}: {e}", input.display()))?;
+                let processed = strip_png(&data, *strip).map_err(|e| format!("{}: {e}", input.display()))?;
+                fs::write(output, processed).map_err(|e| format!("Couldn't write {}: {e}", output.display()))
+            }
+            Builtin::Resize { max } => resize(input, output, *max),
+            Builtin::Jpeg { quality } => PixelBuffer::decode(input)
+                .map_err(|e| format!("Couldn't decode {}: {e}", input.display()))?
+                .save_jpeg(output, *quality)
+                .map_err(|e| format!("Couldn't write {}: {e}", output.display())),
+        }
+    }
+
+    /// Returns the extension of the files written by the backend, None if it
+    /// writes the format of its input.
+    pub fn output_extension(&self) -> Option<&'static str> {
+        match self {
+            Builtin::PngStrip { .. } => None,
+            Builtin::Resize { .. } => Some("png"),
+            Builtin::Jpeg { .. } => Some("jpg"),
+        }
+    }
+
+    /// Returns whether the extension, in any case, names the format of the
+    /// files written by the backend, e.g. `JPEG` for jpeg.
+    pub fn writes_extension(&self, extension: &str) -> bool {
+        let extension = extension.to_ascii_lowercase();
+        match self {
+            Builtin::PngStrip { .. } => true,
+            Builtin::Resize { .. } => extension == "png",
+            Builtin::Jpeg { .. } => ["jpg", "jpeg", "jpe"].contains(&extension.as_str()),
+        }
+    }
+}
+
+
+/// Returns the built-in backend of a command, None if it isn't built-in.
+pub fn builtin_command(command: &str) -> Option<Result<Builtin, String>> {
+    command.strip_prefix(BUILTIN_PREFIX).map(str::parse)
+}
+
+
+/// Downscales the image at input path to fit in a square of max pixels, and
+/// writes it as PNG. PNG images already small enough are copied unchanged,
+/// other formats are still written as PNG.
+fn resize(input: &Path, output: &Path, max: u32) -> Result<(), String> {
+    let pixels = PixelBuffer::decode(input).map_err(|e| format!("Couldn't decode {}: {e}", input.display()))?;
+    let pixels = match fitted_size(pixels.width, pixels.height, max) {
+        Some((width, height)) => pixels.resized(width, height),
+        None if is_png(input) => {
+            return fs::copy(input, output)
+                .map(|_| ())
+                .map_err(|e| format!("Couldn't copy {}: {e}", input.display()));
+        }
+        None => pixels,
+    };
+
+    pixels.save_png(output).map_err(|e| format!("Couldn't write {}: {e}", output.display()))
+}
+
+/// Returns whether the file at path starts with the PNG signature.
+fn is_png(path: &Path) -> bool {
+    let mut signature = [0; PNG_SIGNATURE.len()];
+    fs::File::open(path).and_then(|mut f| f.read_exact(&mut signature)).is_ok() && signature == PNG_SIGNATURE
+}
+
+/// Returns the size of an image downscaled to fit in a square of max pixels,
+/// None if it already fits.
+fn fitted_size(width: u32, height: u32, max: u32) -> Option<(u32, u32)> {
+    let scale = max as f64 / width.max(height) as f64;
+    if scale >= 1. {
+        return None;
+    }
+
+    let fitted = |size: u32| ((size as f64 * scale).round() as u32).max(1);
+    Some((fitted(width), fitted(height)))
+}
+
+
+/// Removes the chunks of a PNG image as set by the strip option of pngstrip.
+fn strip_png(data: &[u8], strip: StripChunks) -> Result<Vec<u8>, String> {
+    let mut rest = data.strip_prefix(PNG_SIGNATURE).ok_or("not a PNG image")?;
+
+    let mut output = PNG_SIGNATURE.to_vec();
+    let mut image_data: Option<Vec<u8>> = None;
+    loop {
+        if rest.len() < 12 {
+            return Err("truncated PNG image".to_string());
+        }
+        let length = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+        if rest.len() < length + 12 {
+            return Err("truncated PNG image".to_string());
+        }
+        let kind: &[u8; 4] = rest[4..8].try_into().unwrap();
+        let content = &rest[8..8 + length];
+        let crc = u32::from_be_bytes(rest[8 + length..12 + length].try_into().unwrap());
+        if crc != crc32(&rest[4..8 + length]) {
+            return Err(format!("corrupt {} chunk", String::from_utf8_lossy(kind)));
+        }
+        rest = &rest[12 + length..];
+
+        // Consecutive image data chunks are merged into a single one when
+        // stripping the metadata, written before the next chunk.
+        if strip == StripChunks::Metadata && kind == b"IDAT" {
+            image_data.get_or_insert_with(Vec::new).extend_from_slice(content);
+            continue;
+        }
+        if let Some(image_data) = image_data.take() {
+            write_chunk(&mut output, b"IDAT", &image_data);
+        }
+
+        let critical = kind[0].is_ascii_uppercase();
+        let keep = match strip {
+            StripChunks::Text => critical || !TEXT_CHUNKS.contains(&kind),
+            StripChunks::Metadata => critical || RENDERING_CHUNKS.contains(&kind),
+        };
+        if keep {
+            write_chunk(&mut output, kind, content);
+        }
+
+        if kind == b"IEND" {
+            return Ok(output);
+        }
+    }
+}
+
+fn write_chunk(output: &mut Vec<u8>, kind: &[u8; 4], content: &[u8]) {
+    output.extend_from_slice(&(content.len() as u32).to_be_bytes());
+    let start = output.len();
+    output.extend_from_slice(kind);
+    output.extend_from_slice(content);
+    let crc = crc32(&output[start..]);
+    output.extend_from_slice(&crc.to_be_bytes());
+}
+
+fn crc32(bytes: &[u8]) -> u32 {
+    !bytes
+        .iter()
+        .fold(!0u32, |crc, &b| CRC_TABLE[((crc ^ b as u32) & 0xff) as usize] ^ (crc >> 8))
+}
+
+const fn crc_table() -> [u32; 256] {
+    let mut table = [0; 256];
+    let mut n = 0;
+    while n < 256 {
+        let mut c = n as u32;
+        let mut k = 0;
+        while k < 8 {
+            c = match c & 1 {
+                0 => c >> 1,
+                _ => 0xedb88320 ^ (c >> 1),
+            };
+            k += 1;
+        }
+        table[n] = c;
+        n += 1;
+    }
+
+    table
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn png(chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut data = PNG_SIGNATURE.to_vec();
+        for (kind, content) in chunks {
+            write_chunk(&mut data, kind, content);
+        }
+
+        data
+    }
+
+    #[test]
+    fn crc_of_iend() {
+        assert_eq!(crc32(b"IEND"), 0xae426082);
+    }
+
+    #[test]
+    fn parse_builtin() {
+        let metadata = Builtin::PngStrip { strip: StripChunks::Metadata };
+        assert_eq!(builtin_command("builtin:pngstrip"), Some(Ok(metadata)));
+        let text = Builtin::PngStrip { strip: StripChunks::Text };
+        assert_eq!(builtin_command("builtin:pngstrip?strip=text"), Some(Ok(text)));
+        assert!(builtin_command("builtin:pngstrip?strip=all").unwrap().is_err());
+        assert!(builtin_command("builtin:pngstrip?level=2").unwrap().is_err());
+        assert!(builtin_command("builtin:pngstrip?speed=3").unwrap().is_err());
+        assert!(builtin_command("builtin:pngcrush").unwrap().is_err());
+        assert_eq!(builtin_command("builtin:resize?max=2000"), Some(Ok(Builtin::Resize { max: 2000 })));
+        assert!(builtin_command("builtin:resize").unwrap().is_err());
+        assert_eq!(builtin_command("builtin:jpeg"), Some(Ok(Builtin::Jpeg { quality: 80 })));
+        assert_eq!(builtin_command("builtin:jpeg?q=92"), Some(Ok(Builtin::Jpeg { quality: 92 })));
+        assert!(builtin_command("builtin:jpeg?q=101").unwrap().is_err());
+        assert!(builtin_command("builtin:resize?max=0").unwrap().is_err());
+        assert_eq!(builtin_command("optipng %i -out %o"), None);
+    }
+
+    #[test]
+    fn fitted_sizes() {
+        assert_eq!(fitted_size(4000, 3000, 2000), Some((2000, 1500)));
+        assert_eq!(fitted_size(3000, 4000, 1000), Some((750, 1000)));
+        assert_eq!(fitted_size(10000, 1, 100), Some((100, 1)));
+        assert_eq!(fitted_size(2000, 1000, 2000), None);
+    }
+
+    #[test]
+    fn strip_chunks() {
+        let source = png(&[
+            (b"IHDR", b"header"),
+            (b"gAMA", b"gamma"),
+            (b"tEXt", b"Comment\0made with gimp"),
+            (b"pHYs", b"dpi"),
+            (b"eXIf", b"orientation"),
+            (b"IDAT", b"first"),
+            (b"IDAT", b"second"),
+            (b"IEND", b""),
+        ]);
+
+        let text = png(&[
+            (b"IHDR", b"header"),
+            (b"gAMA", b"gamma"),
+            (b"pHYs", b"dpi"),
+            (b"eXIf", b"orientation"),
+            (b"IDAT", b"first"),
+            (b"IDAT", b"second"),
+            (b"IEND", b""),
+        ]);
+        assert_eq!(strip_png(&source, StripChunks::Text), Ok(text));
+
+        let metadata = png(&[
+            (b"IHDR", b"header"),
+            (b"gAMA", b"gamma"),
+            (b"eXIf", b"orientation"),
+            (b"IDAT", b"firstsecond"),
+            (b"IEND", b""),
+        ]);
+        assert_eq!(strip_png(&source, StripChunks::Metadata), Ok(metadata));
+    }
+
+    #[test]
+    fn strip_rejects_invalid_images() {
+        assert!(strip_png(b"GIF89a", StripChunks::Metadata).is_err());
+
+        let image = png(&[(b"IHDR", b"header"), (b"IEND", b"")]);
+        assert!(strip_png(&image[..image.len() - 1], StripChunks::Metadata).is_err());
+
+        let mut corrupt = image.clone();
+        corrupt[PNG_SIGNATURE.len() + 8] ^= 1;
+        assert_eq!(strip_png(&corrupt, StripChunks::Metadata), Err("corrupt IHDR chunk".to_string()));
+    }
+}
@@ -0,0 +1,120 @@
+//! Least recently used cache with a memory budget, used to keep the textures
+//! of previously viewed images, so that going back to them doesn't require
+//! decoding them again.
+
+use std::collections::HashMap;
+use std::hash::Hash;
+
+
+struct Entry<T> {
+    value: T,
+    bytes: u64,
+    last_used: u64,
+}
+
+/// Cache of values with an approximate size in bytes. Values are taken out of
+/// the cache while in use, and inserted back once they are not displayed
+/// anymore, which makes them the most recently used.
+pub struct LruCache<K, T> {
+    entries: HashMap<K, Entry<T>>,
+    used: u64,
+    clock: u64,
+}
+
+impl<K: Eq + Hash + Clone, T> Default for LruCache<K, T> {
+    fn default() -> Self {
+        LruCache { entries: HashMap::new(), used: 0, clock: 0 }
+    }
+}
+
+impl<K: Eq + Hash + Clone, T> LruCache<K, T> {
+    /// Inserts a value, replacing any value with the same key.
+    pub fn insert(&mut self, key: K, value: T, bytes: u64) {
+        self.clock += 1;
+        let entry = Entry { value, bytes, last_used: self.clock };
+        if let Some(old) = self.entries.insert(key, entry) {
+            self.used -= old.bytes;
+        }
+        self.used += bytes;
+    }
+
+    /// Returns true if the cache holds a value for the key.
+    pub fn contains(&self, key: &K) -> bool {
+        self.entries.contains_key(key)
+    }
+
+    /// Removes the value from the cache and returns it.
+    pub fn take(&mut self, key: &K) -> Option<T> {
+        let entry = self.entries.remove(key)?;
+        self.used -= entry.bytes;
+
+        Some(entry.value)
+    }
+
+    /// Evicts the least recently used values until the total size is at most
+    /// the provided limit.
+    pub fn evict_to(&mut self, limit: u64) {
+        while self.used > limit {
+            let oldest = self
+                .entries
+                .iter()
+                .min_by_key(|(_, e)| e.last_used)
+                .map(|(k, _)| k.clone());
+
+            match oldest {
+                Some(key) => {
+                    self.take(&key);
+                }
+                None => break,
+            }
+        }
+    }
+
+    /// Returns the total size of the values in cache.
+    pub fn used(&self) -> u64 {
+        self.used
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn take_removes_value() {
+        let mut cache = LruCache::default();
+        cache.insert("a", 1, 10);
+        assert_eq!(cache.take(&"a"), Some(1));
+        assert_eq!(cache.take(&"a"), None);
+        assert_eq!(cache.used(), 0);
+    }
+
+    #[test]
+    fn insert_replaces_value() {
+        let mut cache = LruCache::default();
+        cache.insert("a", 1, 10);
+        cache.insert("a", 2, 30);
+        assert_eq!(cache.used(), 30);
+        assert_eq!(cache.take(&"a"), Some(2));
+    }
+
+    #[test]
+    fn evicts_least_recently_used() {
+        let mut cache = LruCache::default();
+        cache.insert("a", 1, 10);
+        cache.insert("b", 2, 10);
+        cache.insert("c", 3, 10);
+
+        // Viewing a again makes it the most recently used.
+        let a = cache.take(&"a").unwrap();
+        cache.insert("a", a, 10);
+
+        cache.evict_to(20);
+        assert_eq!(cache.take(&"b"), None);
+        assert_eq!(cache.used(), 20);
+
+        cache.evict_to(0);
+        assert_eq!(cache.used(), 0);
+    }
+}
@@ -0,0 +1,350 @@
+//! Shared background compute pool for the in-app computations (perceptual
+//! hashes, quality metrics, previews, local copies of the sources, artifact
+//! hotspots).
+//!
+//! The pool is separate from the threads running the external processing
+//! commands, so that these computations never wait behind the encoders. Jobs
+//! are not run in submission order: every worker picks the pending job
+//! closest to the image and command displayed, with the same distance as
+//! Closest2D, so the computations the user is about to see come first.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+use std::sync::{mpsc, Arc, Condvar, Mutex};
+use std::thread;
+
+use crate::artifacts::{find_hotspots, Hotspot, Luma};
+use crate::decode::{PixelBuffer, Preview};
+use crate::img::compute_metrics;
+use crate::phash::dhash;
+use crate::processing_order::distance_2d;
+use crate::settings::MetricCommand;
+use crate::utils::catch_panic;
+use crate::progress::diagnostic;
+
+
+/// A computation to run in the pool.
+pub enum Job {
+    /// Perceptual hash of the source of image i.
+    Hash { i: usize, path: PathBuf },
+
+    /// Low resolution preview of the source of image i.
+    Preview { i: usize, path: PathBuf },
+
+    /// Full decoding of the source of image i, displayed soon.
+    Decode { i: usize, path: PathBuf },
+
+    /// Copy of the source of image i to a local file, before processing it.
+    Copy { i: usize, source: PathBuf, destination: PathBuf },
+
+    /// Metrics between the source of image i and its variant for command c.
+    Metrics {
+        i: usize,
+        c: usize,
+        source: PathBuf,
+        processed: PathBuf,
+        metrics: Vec<MetricCommand>,
+
+        /// Working directory of the sandboxed metric commands, if sandboxed.
+        sandbox: Option<PathBuf>,
+    },
+
+    /// Artifact hotspots of the variant of image i for command c.
+    Hotspots { i: usize, c: usize, source: PathBuf, processed: PathBuf },
+}
+
+/// The result of a job.
+pub enum JobResult {
+    /// Hash of image i, None if it could not be computed.
+    Hash { i: usize, hash: Option<u64> },
+    Preview { i: usize, preview: Option<Preview> },
+    Decode { i: usize, path: PathBuf, pixels: Option<PixelBuffer> },
+
+    /// Local copy of image i, None if the copy failed.
+    Copy { i: usize, path: Option<PathBuf> },
+    Metrics { i: usize, c: usize, values: Vec<(String, f64)> },
+
+    /// Hotspots of the variant of image i for command c, empty if it could
+    /// not be decoded.
+    Hotspots { i: usize, c: usize, hotspots: Vec<Hotspot> },
+}
+
+impl Job {
+    /// Returns the (image, command) position of the job. Hashes, previews and
+    /// decodings don't depend on the command, they are placed on the current command so
+    /// that only the image distance counts.
+    fn position(&self, current_cmd: usize) -> (usize, usize) {
+        match self {
+            Job::Hash { i, .. } | Job::Preview { i, .. } | Job::Decode { i, .. } | Job::Copy { i, .. } => {
+                (*i, current_cmd)
+            }
+            Job::Metrics { i, c, .. } | Job::Hotspots { i, c, .. } => (*i, *c),
+        }
+    }
+
+    /// Returns the result of the job when it could not be run.
+    fn failed(&self) -> JobResult {
+        match self {
+            Job::Hash { i, .. } => JobResult::Hash { i: *i, hash: None },
+            Job::Preview { i, .. } => JobResult::Preview { i: *i, preview: None },
+            Job::Decode { i, path } => JobResult::Decode { i: *i, path: path.clone(), pixels: None },
+            Job::Copy { i, .. } => JobResult::Copy { i: *i, path: None },
+            Job::Metrics { i, c, .. } => JobResult::Metrics { i: *i, c: *c, values: Vec::new() },
+            Job::Hotspots { i, c, .. } => JobResult::Hotspots { i: *i, c: *c, hotspots: Vec::new() },
+        }
+    }
+
+    fn run(self) -> JobResult {
+        match self {
+            Job::Hash { i, path } => {
+                let hash = dhash(&path)
+                    .map_err(|e| diagnostic!("Unable to hash {}: {e}", path.display()))
+                    .ok();
+                JobResult::Hash { i, hash }
+            }
+            Job::Preview { i, path } => {
+                let preview = Preview::compute(&path)
+                    .map_err(|e| diagnostic!("Unable to decode {}: {e}", path.display()))
+                    .ok();
+                JobResult::Preview { i, preview }
+            }
+            Job::Decode { i, path } => {
+                let pixels = PixelBuffer::decode(&path)
+                    .map_err(|e| diagnostic!("Unable to decode {}: {e}", path.display()))
+                    .ok();
+                JobResult::Decode { i, path, pixels }
+            }
+            Job::Copy { i, source, destination } => {
+                let path = fs::copy(&source, &destination)
+                    .map(|_| destination)
+                    .map_err(|e| diagnostic!("Unable to copy {}: {e}", source.display()))
+                    .ok();
+                JobResult::Copy { i, path }
+            }
+            Job::Metrics { i, c, source, processed, metrics, sandbox } => JobResult::Metrics {
+                i,
+                c,
+                values: compute_metrics(&source, &processed, &metrics, sandbox.as_deref()),
+            },
+            Job::Hotspots { i, c, source, processed } => {
+                let luma = |path: &PathBuf| {
+                    PixelBuffer::decode(path)
+                        .map(|p| Luma::from_rgba(p.width, p.height, &p.data))
+                        .map_err(|e| diagnostic!("Unable to decode {}: {e}", path.display()))
+                        .ok()
+                };
+                // Without its source, the variant is scored alone.
+                let hotspots = match luma(&processed) {
+                    Some(variant) => find_hotspots(&variant, luma(&source).as_ref()),
+                    None => Vec::new(),
+                };
+                JobResult::Hotspots { i, c, hotspots }
+            }
+        }
+    }
+}
+
+
+/// Pending jobs, shared between the pool handle and its workers.
+#[derive(Default)]
+struct Queue {
+    /// Jobs by image, with their submission number. Only the images close to
+    /// the position are looked at to find the closest job, so that draining a
+    /// queue of one job per image doesn't scan it entirely for every job.
+    jobs: BTreeMap<usize, Vec<(u64, Job)>>,
+    pending: usize,
+    submitted: u64,
+    position: (usize, usize),
+    closed: bool,
+
+    /// Number of jobs being run by the workers.
+    running: usize,
+}
+
+impl Queue {
+    fn push(&mut self, job: Job) {
+        let i = job.position(0).0;
+        self.jobs.entry(i).or_default().push((self.submitted, job));
+        self.submitted += 1;
+        self.pending += 1;
+    }
+
+    /// Removes and returns the job closest to the current position. Ties are
+    /// broken by submission order.
+    fn pop_closest(&mut self) -> Option<Job> {
+        let position = self.position;
+        let mut before = self.jobs.range(..=position.0).rev().peekable();
+        let mut after = self.jobs.range(position.0 + 1..).peekable();
+
+        // The images are visited by increasing distance, until they are all
+        // farther than the closest job found.
+        let mut best: Option<((usize, u64), usize, usize)> = None;
+        loop {
+            let next = match (before.peek().map(|e| *e.0), after.peek().map(|e| *e.0)) {
+                (Some(b), Some(a)) if a - position.0 < position.0 - b => after.next(),
+                (Some(_), _) => before.next(),
+                (None, _) => after.next(),
+            };
+            let (&i, jobs) = match next {
+                Some(next) => next,
+                None => break,
+            };
+            if best.is_some_and(|((distance, _), _, _)| i.abs_diff(position.0) > distance) {
+                break;
+            }
+
+            for (k, (submitted, job)) in jobs.iter().enumerate() {
+                let key = (distance_2d(job.position(position.1), position), *submitted);
+                if best.is_none_or(|(best, _, _)| key < best) {
+                    best = Some((key, i, k));
+                }
+            }
+        }
+
+        let (_, i, k) = best?;
+        let jobs = self.jobs.get_mut(&i)?;
+        let (_, job) = jobs.remove(k);
+        if jobs.is_empty() {
+            self.jobs.remove(&i);
+        }
+        self.pending -= 1;
+
+        Some(job)
+    }
+}
+
+
+/// Handle on the compute pool. Dropping it stops the workers once their
+/// current job is done.
+pub struct ComputePool {
+    queue: Arc<(Mutex<Queue>, Condvar)>,
+    rx: mpsc::Receiver<JobResult>,
+}
+
+impl ComputePool {
+    /// Starts a pool with the provided number of worker threads (at least 1).
+    pub fn new(threads: usize) -> ComputePool {
+        let queue = Arc::new((Mutex::new(Queue::default()), Condvar::new()));
+        let (tx, rx) = mpsc::channel();
+
+        for _ in 0..threads.max(1) {
+            let queue = Arc::clone(&queue);
+            let tx = tx.clone();
+            thread::spawn(move || worker(&queue, tx));
+        }
+
+        ComputePool { queue, rx }
+    }
+
+    /// Adds a job to the pool.
+    pub fn submit(&self, job: Job) {
+        let (lock, cvar) = &*self.queue;
+        lock.lock().unwrap().push(job);
+        cvar.notify_one();
+    }
+
+    /// Sets the position of the image and command displayed, around which
+    /// jobs are prioritized.
+    pub fn set_position(&self, i: usize, c: usize) {
+        self.queue.0.lock().unwrap().position = (i, c);
+    }
+
+    /// Returns the number of jobs waiting, and the number being run.
+    pub fn status(&self) -> (usize, usize) {
+        let queue = self.queue.0.lock().unwrap();
+
+        (queue.pending, queue.running)
+    }
+
+    /// Returns an iterator over the results available, without blocking.
+    pub fn results(&self) -> mpsc::TryIter<'_, JobResult> {
+        self.rx.try_iter()
+    }
+}
+
+impl Drop for ComputePool {
+    fn drop(&mut self) {
+        let (lock, cvar) = &*self.queue;
+        lock.lock().unwrap().closed = true;
+        cvar.notify_all();
+    }
+}
+
+
+fn worker(queue: &(Mutex<Queue>, Condvar), tx: mpsc::Sender<JobResult>) {
+    let (lock, cvar) = queue;
+    loop {
+        let job = {
+            let mut queue = lock.lock().unwrap();
+            loop {
+                if queue.closed {
+                    return;
+                }
+                if let Some(job) = queue.pop_closest() {
+                    queue.running += 1;
+                    break job;
+                }
+                queue = cvar.wait(queue).unwrap();
+            }
+        };
+
+        // A panicking job gives the result of a failed one, so that the
+        // application doesn't wait for it forever.
+        let failed = job.failed();
+        let result = catch_panic(|| job.run()).unwrap_or_else(|e| {
+            diagnostic!("Error: background computation panicked: {e}");
+            failed
+        });
+        // The job is not running anymore once its result is received.
+        lock.lock().unwrap().running -= 1;
+        if tx.send(result).is_err() {
+            return;
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn hash_job(i: usize) -> Job {
+        Job::Hash { i, path: PathBuf::new() }
+    }
+
+    #[test]
+    fn queue_pops_closest_first() {
+        let mut queue = Queue::default();
+        for i in [0, 9, 4, 6, 5] {
+            queue.push(hash_job(i));
+        }
+        queue.position = (5, 0);
+
+        let order: Vec<usize> = std::iter::from_fn(|| queue.pop_closest())
+            .map(|job| job.position(0).0)
+            .collect();
+        assert_eq!(order, vec![5, 4, 6, 9, 0]);
+    }
+
+    #[test]
+    fn queue_prioritizes_metrics_by_command() {
+        let mut queue = Queue::default();
+        for c in [0, 3, 1] {
+            queue.push(Job::Metrics {
+                i: 2,
+                c,
+                source: PathBuf::new(),
+                processed: PathBuf::new(),
+                metrics: Vec::new(),
+                sandbox: None,
+            });
+        }
+        queue.position = (2, 3);
+
+        let order: Vec<(usize, usize)> = std::iter::from_fn(|| queue.pop_closest())
+            .map(|job| job.position(3))
+            .collect();
+        assert_eq!(order, vec![(2, 3), (2, 1), (2, 0)]);
+    }
+}
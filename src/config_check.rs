@@ -0,0 +1,212 @@
+//! Validation of the configuration file, with messages pointing at the line
+//! of the problem.
+//!
+//! The file is first parsed as plain TOML, so that syntax errors are reported
+//! before anything else. The keys are then compared with the fields of the
+//! settings, suggesting the closest field for a misspelled key, before the
+//! values are deserialized. Every problem found is reported at once.
+
+use serde::de::{self, Deserialize, Deserializer, Visitor};
+use serde::forward_to_deserialize_any;
+
+
+/// A problem in the configuration file, with its position (line and column,
+/// from 0) if known.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ConfigProblem {
+    pub position: Option<(usize, usize)>,
+    pub message: String,
+}
+
+impl ConfigProblem {
+    /// Returns a problem about a key, positioned at its first definition in
+    /// the file.
+    pub fn at_key(text: &str, key: &str, message: String) -> ConfigProblem {
+        ConfigProblem { position: key_position(text, key), message }
+    }
+
+    /// Returns the problem of a TOML error. An invalid value is positioned at
+    /// its key, since the position of the error is then the start of the
+    /// table, other errors at the position of the error if known.
+    pub fn from_toml(text: &str, e: &toml::de::Error) -> ConfigProblem {
+        let mut message = e.to_string();
+        if let Some(i) = message.find(" at line ") {
+            message.truncate(i);
+        }
+
+        let key = message
+            .split_once("for key `")
+            .and_then(|(_, key)| key.split('`').next())
+            .and_then(|key| key.rsplit('.').next());
+        let position = key.and_then(|key| key_position(text, key)).or_else(|| e.line_col());
+
+        ConfigProblem { position, message }
+    }
+}
+
+/// Formats the problems of the configuration file, each followed by the line
+/// where it is, with a caret under the column.
+pub fn format_problems(path: &str, text: &str, problems: &[ConfigProblem]) -> String {
+    let mut message = format!("Invalid config file {path}:");
+
+    for problem in problems {
+        match problem.position {
+            Some((line, col)) => {
+                message += &format!("\n  line {}, column {}: {}", line + 1, col + 1, problem.message);
+                if let Some(content) = text.lines().nth(line) {
+                    message += &format!("\n    {content}\n    {}^", " ".repeat(col));
+                }
+            }
+            None => message += &format!("\n  {}", problem.message),
+        }
+    }
+
+    message
+}
+
+/// Returns the position of the first definition of key in the file, either
+/// as `key = ...` or in an inline table.
+pub fn key_position(text: &str, key: &str) -> Option<(usize, usize)> {
+    text.lines().enumerate().find_map(|(i, line)| {
+        let content = line.split('#').next().unwrap_or("");
+        content.match_indices(key).find_map(|(col, _)| {
+            let before = content[..col].chars().next_back();
+            let after = content[col + key.len()..].trim_start();
+            let starts_word = !before.is_some_and(|c| c.is_alphanumeric() || c == '_');
+            (starts_word && after.starts_with('=')).then_some((i, col))
+        })
+    })
+}
+
+/// Returns the names of the fields of a struct deriving Deserialize.
+pub fn field_names<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldNames(&mut fields));
+    fields
+}
+
+/// Deserializer which only records the fields of the struct it is asked for.
+struct FieldNames<'a>(&'a mut &'static [&'static str]);
+
+impl<'de, 'a> Deserializer<'de> for FieldNames<'a> {
+    type Error = de::value::Error;
+
+    fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+        Err(de::Error::custom("not a struct"))
+    }
+
+    fn deserialize_struct<V: Visitor<'de>>(
+        self,
+        _name: &'static str,
+        fields: &'static [&'static str],
+        _visitor: V,
+    ) -> Result<V::Value, Self::Error> {
+        *self.0 = fields;
+        Err(de::Error::custom("fields recorded"))
+    }
+
+    forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+        bytes byte_buf option unit unit_struct newtype_struct seq tuple
+        tuple_struct map enum identifier ignored_any
+    }
+}
+
+/// Returns the number of single character insertions, deletions and
+/// substitutions needed to turn a into b.
+fn edit_distance(a: &str, b: &str) -> usize {
+    let b = b.chars().collect::<Vec<char>>();
+    let mut previous = (0..=b.len()).collect::<Vec<usize>>();
+
+    for (i, ca) in a.chars().enumerate() {
+        let mut current = vec![i + 1];
+        for (j, cb) in b.iter().enumerate() {
+            let substitution = previous[j] + usize::from(ca != *cb);
+            current.push(substitution.min(previous[j + 1] + 1).min(current[j] + 1));
+        }
+        previous = current;
+    }
+
+    previous[b.len()]
+}
+
+/// Returns the field closest to a misspelled key, if it is close enough to be
+/// a likely typo.
+pub fn closest_field(key: &str, fields: &[&'static str]) -> Option<&'static str> {
+    fields
+        .iter()
+        .map(|&f| (edit_distance(key, f), f))
+        .filter(|&(d, f)| d <= (f.len() / 3).max(2))
+        .min()
+        .map(|(_, f)| f)
+}
+
+/// Returns the problems of the keys of a table which aren't fields. Section
+/// is the name of the table, empty at the top level.
+pub fn unknown_keys(
+    text: &str,
+    table: &toml::value::Table,
+    fields: &[&'static str],
+    section: &str,
+) -> Vec<ConfigProblem> {
+    table
+        .keys()
+        .filter(|k| !fields.contains(&k.as_str()))
+        .map(|k| {
+            let place = match section.is_empty() {
+                true => String::new(),
+                false => format!(" in {section}"),
+            };
+            let message = match closest_field(k, fields) {
+                Some(f) => format!("unknown key `{k}`{place}, did you mean `{f}`?"),
+                None => format!("unknown key `{k}`{place}, expected one of {}", fields.join(", ")),
+            };
+            ConfigProblem::at_key(text, k, message)
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::settings::AppSettings;
+
+    #[test]
+    fn misspelled_key_suggests_field() {
+        let fields = field_names::<AppSettings>();
+        assert!(fields.contains(&"fit_mode"));
+        assert_eq!(closest_field("fit_mod", fields), Some("fit_mode"));
+        assert_eq!(closest_field("procesing_directory", fields), Some("processing_directory"));
+        assert_eq!(closest_field("colour", fields), None);
+    }
+
+    #[test]
+    fn problems_point_at_key() {
+        let text = "padding = 3\n# fit_mod = 1\nfit_mod = \"FitBest\"\n";
+        assert_eq!(key_position(text, "fit_mod"), Some((2, 0)));
+        assert_eq!(key_position(text, "mod"), None);
+
+        let table = toml::from_str::<toml::Value>(text).unwrap();
+        let problems = unknown_keys(text, table.as_table().unwrap(), &["padding", "fit_mode"], "");
+        let message = format_problems("bimgo.toml", text, &problems);
+        assert_eq!(
+            message,
+            "Invalid config file bimgo.toml:\n  line 3, column 1: unknown key `fit_mod`, did you mean `fit_mode`?\n    fit_mod = \"FitBest\"\n    ^",
+        );
+    }
+
+    #[test]
+    fn invalid_value_points_at_key() {
+        let text = "padding = 3\nfit_mode = \"FitBst\"\n";
+        let e = toml::from_str::<AppSettings>(text).err().unwrap();
+        let problem = ConfigProblem::from_toml(text, &e);
+        assert_eq!(problem.position, Some((1, 0)));
+        assert!(problem.message.starts_with("unknown variant `FitBst`"));
+        assert!(!problem.message.contains("at line"));
+
+        let text = "padding = [3";
+        let e = toml::from_str::<toml::Value>(text).unwrap_err();
+        assert!(ConfigProblem::from_toml(text, &e).position.is_some());
+    }
+}
@@ -0,0 +1,396 @@
+//! Decoding of images outside of the main thread.
+//!
+//! SDL textures can only be created in the main thread, but decoding a huge
+//! image takes long enough to freeze the interface. Images are thus decoded
+//! to raw pixels in other threads, and turned into textures once decoded.
+//! Small previews are also computed in advance for large images, so that a
+//! low resolution version can be shown while the full image is decoded.
+//!
+//! The decoded pixels are kept in a PixelBuffer, uploaded directly to a
+//! texture, so generated images (previews, thumbnails) never go through a
+//! file. Its pixels are only copied when they must be.
+
+use std::ffi::CString;
+use std::fs::File;
+use std::os::raw::c_int;
+use std::os::unix::ffi::OsStrExt;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use sdl2::image::{LoadSurface, SaveSurface};
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::render::{BlendMode, Texture, TextureCreator};
+use sdl2::surface::{Surface, SurfaceRef};
+
+use crate::utils::catch_panic;
+
+/// Maximum width or height of a preview.
+const PREVIEW_SIZE: u32 = 512;
+
+/// Number of bytes of a pixel in decoded images.
+const BYTES_PER_PIXEL: usize = 4;
+
+/// Radius of the Lanczos filter, in source pixels when upscaling.
+const LANCZOS_RADIUS: f32 = 3.;
+
+
+/// Decoded RGBA pixels of an image, rows stored contiguously without padding.
+pub struct PixelBuffer {
+    pub width: u32,
+    pub height: u32,
+    pub data: Vec<u8>,
+}
+
+/// A low resolution version of an image, and the size of the full image.
+pub struct Preview {
+    pub pixels: PixelBuffer,
+    pub full_size: (u32, u32),
+}
+
+impl PixelBuffer {
+    /// Decodes the image at provided path.
+    pub fn decode(path: &Path) -> Result<PixelBuffer, String> {
+        let surface = Surface::from_file(path)?;
+        PixelBuffer::from_surface(&surface)
+    }
+
+    /// Copies the pixels of the surface, converted to RGBA.
+    pub fn from_surface(surface: &SurfaceRef) -> Result<PixelBuffer, String> {
+        let surface = surface.convert_format(PixelFormatEnum::RGBA32)?;
+        let (width, height) = (surface.width(), surface.height());
+        let pitch = surface.pitch() as usize;
+        let row_len = width as usize * BYTES_PER_PIXEL;
+
+        let mut data = Vec::with_capacity(row_len * height as usize);
+        surface.with_lock(|pixels| match pitch == row_len {
+            // Rows without padding are copied at once.
+            true => data.extend_from_slice(&pixels[..row_len * height as usize]),
+            false => {
+                for row in pixels.chunks(pitch).take(height as usize) {
+                    data.extend_from_slice(&row[..row_len]);
+                }
+            }
+        });
+
+        Ok(PixelBuffer { width, height, data })
+    }
+
+    /// Returns the image downscaled so that it fits in a square of provided
+    /// size, using nearest neighbour sampling. Images already smaller are
+    /// returned as is, without copying their pixels.
+    pub fn downscaled(self, size: u32) -> PixelBuffer {
+        let scale = f32::max(self.width as f32, self.height as f32) / size as f32;
+        if scale <= 1. {
+            return self;
+        }
+
+        let width = ((self.width as f32 / scale) as u32).max(1);
+        let height = ((self.height as f32 / scale) as u32).max(1);
+        let mut data = Vec::with_capacity(width as usize * height as usize * BYTES_PER_PIXEL);
+        for y in 0..height {
+            let src_y = (y as u64 * self.height as u64 / height as u64) as usize;
+            for x in 0..width {
+                let src_x = (x as u64 * self.width as u64 / width as u64) as usize;
+                let k = (src_y * self.width as usize + src_x) * BYTES_PER_PIXEL;
+                data.extend_from_slice(&self.data[k..k + BYTES_PER_PIXEL]);
+            }
+        }
+
+        PixelBuffer { width, height, data }
+    }
+
+    /// Returns the image resized to the provided size with a Lanczos3 filter,
+    /// which keeps it sharp without aliasing. The colors are weighted by
+    /// their alpha, so that transparent pixels don't bleed into the others.
+    pub fn resized(&self, width: u32, height: u32) -> PixelBuffer {
+        let mut premultiplied = Vec::with_capacity(self.data.len());
+        for pixel in self.data.chunks_exact(BYTES_PER_PIXEL) {
+            let alpha = pixel[3] as f32 / 255.;
+            premultiplied.extend_from_slice(&[pixel[0] as f32 * alpha, pixel[1] as f32 * alpha, pixel[2] as f32 * alpha, pixel[3] as f32]);
+        }
+
+        // Resampled horizontally, then vertically.
+        let columns = lanczos_weights(self.width, width);
+        let mut horizontal = Vec::with_capacity(width as usize * self.height as usize * BYTES_PER_PIXEL);
+        for row in premultiplied.chunks_exact(self.width as usize * BYTES_PER_PIXEL) {
+            for (start, weights) in &columns {
+                let mut sum = [0f32; BYTES_PER_PIXEL];
+                for (k, w) in weights.iter().enumerate() {
+                    let pixel = &row[(start + k) * BYTES_PER_PIXEL..][..BYTES_PER_PIXEL];
+                    for (s, p) in sum.iter_mut().zip(pixel) {
+                        *s += w * p;
+                    }
+                }
+                horizontal.extend_from_slice(&sum);
+            }
+        }
+
+        let row_len = width as usize * BYTES_PER_PIXEL;
+        let mut data = Vec::with_capacity(row_len * height as usize);
+        for (start, weights) in lanczos_weights(self.height, height) {
+            let mut row = vec![0f32; row_len];
+            for (k, w) in weights.iter().enumerate() {
+                let src = &horizontal[(start + k) * row_len..][..row_len];
+                for (r, p) in row.iter_mut().zip(src) {
+                    *r += w * p;
+                }
+            }
+            for pixel in row.chunks_exact(BYTES_PER_PIXEL) {
+                let alpha = pixel[3].clamp(0., 255.);
+                let unpremultiply = |c: f32| match alpha > 0. {
+                    true => (c * 255. / alpha).round().clamp(0., 255.) as u8,
+                    false => 0,
+                };
+                data.extend_from_slice(&[
+                    unpremultiply(pixel[0]),
+                    unpremultiply(pixel[1]),
+                    unpremultiply(pixel[2]),
+                    alpha.round() as u8,
+                ]);
+            }
+        }
+
+        PixelBuffer { width, height, data }
+    }
+
+    /// Writes the pixels to a PNG file at provided path.
+    pub fn save_png(mut self, path: &Path) -> Result<(), String> {
+        let pitch = self.width * BYTES_PER_PIXEL as u32;
+        let surface = Surface::from_data(&mut self.data, self.width, self.height, pitch, PixelFormatEnum::RGBA32)?;
+
+        surface.save(path)
+    }
+
+    /// Writes the pixels to a JPEG file at provided path, with the provided
+    /// quality between 1 and 100. The image is encoded in memory by
+    /// SDL_image, without running any external program.
+    pub fn save_jpeg(mut self, path: &Path, quality: u8) -> Result<(), String> {
+        let pitch = self.width * BYTES_PER_PIXEL as u32;
+        let surface = Surface::from_data(&mut self.data, self.width, self.height, pitch, PixelFormatEnum::RGBA32)?;
+        let path = CString::new(path.as_os_str().as_bytes()).map_err(|e| e.to_string())?;
+
+        // SAFETY: the surface and the path are valid for the whole call.
+        let result = unsafe { sdl2::sys::image::IMG_SaveJPG(surface.raw() as *mut _, path.as_ptr(), quality as c_int) };
+        match result {
+            0 => Ok(()),
+            _ => Err(sdl2::get_error()),
+        }
+    }
+
+    /// Creates a texture from the pixels, uploaded directly from the buffer.
+    pub fn to_texture<'a, T>(&self, texture_creator: &'a TextureCreator<T>) -> Result<Texture<'a>, String> {
+        let mut texture = texture_creator
+            .create_texture_static(PixelFormatEnum::RGBA32, self.width, self.height)
+            .map_err(|e| e.to_string())?;
+        texture
+            .update(None, &self.data, self.width as usize * BYTES_PER_PIXEL)
+            .map_err(|e| e.to_string())?;
+        texture.set_blend_mode(BlendMode::Blend);
+
+        Ok(texture)
+    }
+}
+
+impl Preview {
+    /// Decodes the image at provided path, and keeps a low resolution version.
+    pub fn compute(path: &Path) -> Result<Preview, String> {
+        let pixels = PixelBuffer::decode(path)?;
+
+        Ok(Preview {
+            full_size: (pixels.width, pixels.height),
+            pixels: pixels.downscaled(PREVIEW_SIZE),
+        })
+    }
+}
+
+
+/// Returns the weights of the source pixels contributing to every pixel
+/// resampled from a line of src pixels to dst pixels, with a Lanczos3
+/// filter: the first contributing source pixel, and the weights of the
+/// contributing pixels, which sum to 1.
+fn lanczos_weights(src: u32, dst: u32) -> Vec<(usize, Vec<f32>)> {
+    let scale = src as f32 / dst as f32;
+    // When downscaling, the filter is stretched to cover every source pixel.
+    let stretch = scale.max(1.);
+    let radius = LANCZOS_RADIUS * stretch;
+
+    (0..dst)
+        .map(|i| {
+            let center = (i as f32 + 0.5) * scale;
+            let start = (center - radius).floor().max(0.) as usize;
+            let end = ((center + radius).ceil() as usize).min(src as usize);
+            let weights = (start..end)
+                .map(|j| lanczos3((j as f32 + 0.5 - center) / stretch))
+                .collect::<Vec<f32>>();
+            let total = weights.iter().sum::<f32>();
+
+            (start, weights.iter().map(|w| w / total).collect())
+        })
+        .collect()
+}
+
+fn lanczos3(x: f32) -> f32 {
+    if x == 0. {
+        return 1.;
+    }
+    if x.abs() >= LANCZOS_RADIUS {
+        return 0.;
+    }
+    let px = std::f32::consts::PI * x;
+
+    LANCZOS_RADIUS * px.sin() * (px / LANCZOS_RADIUS).sin() / (px * px)
+}
+
+
+/// Returns the name of the image format of a file starting with the provided
+/// bytes, if it is a known format.
+pub fn image_format(header: &[u8]) -> Option<&'static str> {
+    let at = |offset: usize, signature: &[u8]| header.get(offset..offset + signature.len()) == Some(signature);
+
+    if at(0, b"\xff\xd8\xff") {
+        Some("JPEG")
+    } else if at(0, b"\x89PNG\r\n\x1a\n") {
+        Some("PNG")
+    } else if at(0, b"GIF87a") || at(0, b"GIF89a") {
+        Some("GIF")
+    } else if at(0, b"RIFF") && at(8, b"WEBP") {
+        Some("WebP")
+    } else if at(4, b"ftyp") {
+        Some("AVIF/HEIF")
+    } else if at(0, b"\xff\x0a") || at(4, b"JXL \r\n\x87\n") {
+        Some("JPEG XL")
+    } else if at(0, b"II*\0") || at(0, b"MM\0*") {
+        Some("TIFF")
+    } else if at(0, b"BM") {
+        Some("BMP")
+    } else if at(0, b"qoif") {
+        Some("QOI")
+    } else if header.len() >= 3 && header[0] == b'P' && (b'1'..=b'7').contains(&header[1]) && header[2].is_ascii_whitespace() {
+        Some("PNM")
+    } else {
+        None
+    }
+}
+
+
+/// Verifies that the file at provided path is an image: that it starts like
+/// an image of a known format, and with decode, that it can be decoded.
+pub fn verify_image(path: &Path, decode: bool) -> Result<(), String> {
+    let mut header = Vec::with_capacity(16);
+    File::open(path)
+        .and_then(|f| f.take(16).read_to_end(&mut header))
+        .map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+
+    if image_format(&header).is_none() {
+        return Err(format!("{} is not an image of a known format", path.display()));
+    }
+    if decode {
+        Surface::from_file(path).map_err(|e| format!("{} can't be decoded: {e}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+
+/// Spawns a thread decoding the image at provided path, the result is sent
+/// once decoded.
+pub fn spawn_decode(path: PathBuf) -> mpsc::Receiver<Result<PixelBuffer, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        // The receiver is dropped if the user moved to another image.
+        let pixels = catch_panic(|| PixelBuffer::decode(&path))
+            .unwrap_or_else(|e| Err(format!("Decoding panicked: {e}")));
+        let _ = tx.send(pixels);
+    });
+
+    rx
+}
+
+
+/// Spawns a thread computing the preview of the image at provided path, the
+/// result is sent once computed.
+pub fn spawn_preview(path: PathBuf) -> mpsc::Receiver<Result<Preview, String>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let preview = catch_panic(|| Preview::compute(&path))
+            .unwrap_or_else(|e| Err(format!("Decoding panicked: {e}")));
+        let _ = tx.send(preview);
+    });
+
+    rx
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn gradient(width: u32, height: u32) -> PixelBuffer {
+        let data = (0..width * height)
+            .flat_map(|k| [(k % width) as u8, (k / width) as u8, 0, 255])
+            .collect();
+
+        PixelBuffer { width, height, data }
+    }
+
+    #[test]
+    fn downscaled_fits_size() {
+        let small = gradient(200, 100).downscaled(50);
+        assert_eq!((small.width, small.height), (50, 25));
+        assert_eq!(small.data.len(), 50 * 25 * 4);
+
+        // Last pixel of the preview samples the bottom right of the image.
+        assert_eq!(&small.data[small.data.len() - 4..], &[196, 96, 0, 255]);
+    }
+
+    #[test]
+    fn downscaled_keeps_small_images() {
+        let small = gradient(20, 10).downscaled(50);
+        assert_eq!((small.width, small.height), (20, 10));
+    }
+
+    #[test]
+    fn lanczos_weights_are_normalized() {
+        for (src, dst) in [(100, 33), (10, 10), (3, 7), (2000, 1)] {
+            let weights = lanczos_weights(src, dst);
+            assert_eq!(weights.len(), dst as usize);
+            for (start, w) in weights {
+                assert!(start + w.len() <= src as usize);
+                assert!((w.iter().sum::<f32>() - 1.).abs() < 1e-4);
+            }
+        }
+    }
+
+    #[test]
+    fn resized_keeps_uniform_colors() {
+        let uniform = PixelBuffer { width: 40, height: 30, data: [10, 200, 30, 255].repeat(40 * 30) };
+        let small = uniform.resized(13, 7);
+        assert_eq!((small.width, small.height), (13, 7));
+        assert!(small.data.chunks(4).all(|p| p == [10, 200, 30, 255]));
+
+        // Transparent pixels don't darken their opaque neighbours.
+        let mut data = Vec::new();
+        for x in 0..40 {
+            data.extend_from_slice(match x % 2 {
+                0 => &[255, 255, 255, 255],
+                _ => &[0, 0, 0, 0],
+            });
+        }
+        let half = PixelBuffer { width: 40, height: 1, data }.resized(20, 1);
+        assert!(half.data.chunks(4).all(|p| p[..3] == [255, 255, 255] && (100..=155).contains(&p[3])));
+    }
+
+    #[test]
+    fn image_format_from_header() {
+        assert_eq!(image_format(b"\xff\xd8\xff\xe0\0\x10JFIF"), Some("JPEG"));
+        assert_eq!(image_format(b"\x89PNG\r\n\x1a\n\0\0\0\rIHDR"), Some("PNG"));
+        assert_eq!(image_format(b"RIFF\x1a\0\0\0WEBPVP8 "), Some("WebP"));
+        assert_eq!(image_format(b"\0\0\0\x1cftypavif"), Some("AVIF/HEIF"));
+        assert_eq!(image_format(b"P6\n640 480\n255\n"), Some("PNM"));
+        assert_eq!(image_format(b"RIFF\x1a\0\0\0WAVEfmt "), None);
+        assert_eq!(image_format(b"not an image"), None);
+        assert_eq!(image_format(b""), None);
+    }
+}
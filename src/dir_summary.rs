@@ -0,0 +1,120 @@
+//! Summary of the images of a directory, printed by `bimgo stats <dir>`.
+//!
+//! The images are counted by format, with their total size, and the savings
+//! of a review are projected from the statistics of the commands, see stats.
+//! This helps deciding which directories are worth reviewing first.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::stats::Stats;
+use crate::utils::human_readable_size;
+use crate::progress::diagnostic;
+
+
+/// Number and total size of the images of a format.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct FormatSummary {
+    pub count: u64,
+    pub size: u64,
+}
+
+/// The images of a directory, by lowercase extension.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DirectorySummary {
+    pub formats: BTreeMap<String, FormatSummary>,
+}
+
+impl DirectorySummary {
+    /// Adds an image of provided size.
+    pub fn add(&mut self, path: &Path, size: u64) {
+        let format = path
+            .extension()
+            .map(|e| e.to_string_lossy().to_lowercase())
+            .unwrap_or_default();
+        let entry = self.formats.entry(format).or_default();
+        entry.count += 1;
+        entry.size += size;
+    }
+
+    /// Summarizes the images. The ones which can no longer be read are skipped.
+    pub fn of<I: IntoIterator<Item = PathBuf>>(paths: I) -> DirectorySummary {
+        let mut summary = DirectorySummary::default();
+        for path in paths {
+            match fs::metadata(&path) {
+                Ok(metadata) => summary.add(&path, metadata.len()),
+                Err(e) => diagnostic!("Error: unable to read {}: {e}", path.display()),
+            }
+        }
+
+        summary
+    }
+
+    /// Returns the total number and size of the images.
+    pub fn total(&self) -> FormatSummary {
+        self.formats.values().fold(FormatSummary::default(), |total, f| FormatSummary {
+            count: total.count + f.count,
+            size: total.size + f.size,
+        })
+    }
+
+    /// Returns the summary of the directory, one format per line, the largest
+    /// first, then the projected savings.
+    pub fn text(&self, directory: &Path, stats: &Stats) -> String {
+        let total = self.total();
+        if total.count == 0 {
+            return format!("No images in {}", directory.display());
+        }
+
+        let mut formats = self.formats.iter().collect::<Vec<_>>();
+        formats.sort_by_key(|(_, f)| std::cmp::Reverse(f.size));
+
+        let mut text = format!("{}: {} images, {}", directory.display(), total.count, human_readable_size(total.size));
+        for (format, f) in formats {
+            let format = if format.is_empty() { "(none)" } else { format };
+            text.push_str(&format!("\n{format:>8} {:>7} images {:>7}", f.count, human_readable_size(f.size)));
+        }
+
+        text.push_str(&match stats.projected_saved() {
+            Some(saved) if saved > 0 => format!(
+                "\nProjected savings: {} ({} per image, from the win rates of the commands)",
+                human_readable_size(saved as u64 * total.count),
+                human_readable_size(saved as u64),
+            ),
+            Some(_) => "\nProjected savings: none, the commands have not saved space so far".to_string(),
+            None => "\nProjected savings: unknown, validate some images first".to_string(),
+        });
+
+        text
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::stats::CmdStats;
+
+    #[test]
+    fn images_are_summarized_by_format() {
+        let mut summary = DirectorySummary::default();
+        summary.add(Path::new("/photos/a.JPG"), 3000);
+        summary.add(Path::new("/photos/b.jpg"), 1000);
+        summary.add(Path::new("/photos/c.png"), 5000);
+        assert_eq!(summary.formats["jpg"], FormatSummary { count: 2, size: 4000 });
+        assert_eq!(summary.total(), FormatSummary { count: 3, size: 9000 });
+
+        let text = summary.text(Path::new("/photos"), &Stats::default());
+        let lines = text.lines().collect::<Vec<&str>>();
+        assert_eq!(lines.len(), 4);
+        assert!(lines[1].trim_start().starts_with("png"));
+        assert!(lines[3].contains("unknown"));
+
+        let cwebp = CmdStats { wins: 1, losses: 0, saved: 1000 };
+        let stats = Stats { cmds: BTreeMap::from([("cwebp %i -o %o.webp".to_string(), cwebp)]) };
+        let projection = format!("Projected savings: {} ({} per image", human_readable_size(3000), human_readable_size(1000));
+        assert!(summary.text(Path::new("/photos"), &stats).contains(&projection));
+        assert_eq!(DirectorySummary::default().text(Path::new("/empty"), &stats), "No images in /empty");
+    }
+}
@@ -1,20 +1,154 @@
-use std::ffi::OsString;
+use std::collections::BTreeMap;
+use std::ffi::OsStr;
 use std::fs;
+use std::ops::{Index, IndexMut};
 use std::path::Path;
 use std::path::PathBuf;
-use crate::utils::{attempt_double_move, execute_command_str, check_is_existing_directory};
-use crate::settings::AppSettings;
-use chrono::{DateTime, Utc};
+use std::thread;
+use std::time::{Duration, SystemTime};
+use chrono::Utc;
+use crate::utils::{attempt_double_move, execute_command_str, pipeline_stages, execute_metric_command_str, check_is_existing_directory, is_transient_error, is_transient_status, move_file, stderr_summary};
+use crate::settings::{AppSettings, MetricCommand, OutputCheck};
+use crate::decode::verify_image;
+use crate::builtin::builtin_command;
+use crate::rules::{Filter, RuleAction, Variant};
+use crate::trash::{deleted_file_path, manifest_add, manifest_remove};
+use crate::sidecar::carry_sidecars;
+use crate::progress::diagnostic;
+
+
+/// Options of the processing commands.
+#[derive(Clone)]
+pub struct ProcessOptions {
+    /// Number of retries after a transient failure.
+    pub retries: u32,
+
+    /// Delay before the first retry, doubled before every other one.
+    pub backoff: Duration,
+
+    /// True to run the commands in a restricted environment, with the
+    /// processing directory as working directory.
+    pub sandbox: bool,
+
+    /// Prefix of the temporary files of the session.
+    pub tmp_namespace: String,
+
+    /// Verification of the outputs.
+    pub output_check: OutputCheck,
+}
+
+
+/// Reason why a processing attempt failed.
+struct Failure {
+    reason: String,
+
+    /// True if the failure may not happen again, the processing is then
+    /// retried.
+    transient: bool,
+}
+
+impl From<String> for Failure {
+    fn from(reason: String) -> Self {
+        Failure { reason, transient: false }
+    }
+}
+
+
+/// State of a processed variant.
+///
+/// A variant is Pending until processed, then either Processed or Failed. A
+/// failed variant goes back to Pending when it is retried. A processed
+/// variant becomes Validated when the user validates it, and Processed again
+/// when the validation is undone.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum VariantState {
+    #[default]
+    Pending,
+    Processed { tmp_path: PathBuf },
+    Failed { reason: String },
+    Validated { tmp_path: PathBuf, validated_path: PathBuf },
+}
+
+impl VariantState {
+    /// Returns the name of the state, for messages.
+    pub fn name(&self) -> &'static str {
+        match self {
+            VariantState::Pending => "pending",
+            VariantState::Processed { .. } => "processed",
+            VariantState::Failed { .. } => "failed",
+            VariantState::Validated { .. } => "validated",
+        }
+    }
+}
+
+/// Decision of the user on an image.
+///
+/// An image is Undecided until one of its variants is validated, or it is
+/// moved to a folder. Undoing the decision makes it Undecided again.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum ImageDecision {
+    #[default]
+    Undecided,
+
+    /// The variant of command cmd_index replaced the source, which was moved
+    /// to deleted unless it was kept. The validation is permanent once the
+    /// original was deleted from trash, it can no longer be undone.
+    Validated { cmd_index: usize, deleted: Option<PathBuf>, permanent: bool },
+
+    /// The source was moved to a folder.
+    Moved { moved_path: PathBuf },
+}
+
+/// Size and modification time of a file, to notice when it is modified.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FileSnapshot {
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl FileSnapshot {
+    /// Returns the snapshot of the file, or None if it can't be read.
+    pub fn of(path: &Path) -> Option<FileSnapshot> {
+        let metadata = fs::metadata(path).ok()?;
+
+        Some(FileSnapshot { size: metadata.len(), modified: metadata.modified().ok() })
+    }
+}
+
+/// A decision moving the files of an image, see ImgItem::apply.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FileOperation {
+    /// Validates the variant of the command.
+    Validate(usize),
+
+    /// Moves the source to the folder.
+    MoveToFolder(PathBuf),
+
+    /// Reverses the decision.
+    Undo,
+}
 
 
 #[derive(Clone, Default, Debug)]
 pub struct ProcessItem {
-    pub tmp_path: Option<PathBuf>,
-    pub processed_path: Option<PathBuf>,
-    processing_failed: bool,
+    pub metrics: Vec<(String, f64)>,
+    pub source_size: u64,
+    pub processed_size: u64,
+    pub rule: Option<RuleAction>,
+    pub metrics_pending: bool,
+
+    /// Quality substituted for %q in the command, None for the default
+    /// quality of the settings.
+    pub quality: Option<u8>,
+
+    state: VariantState,
 }
 
 impl ProcessItem {
+    /// Returns a pending variant, processed with %q replaced by the quality.
+    pub fn at_quality(quality: u8) -> ProcessItem {
+        ProcessItem { quality: Some(quality), ..ProcessItem::default() }
+    }
 
     /// Attempt to process the file at provided source path, with provided cmd, 
     /// and place it in provided output directory.
@@ -22,10 +156,35 @@ impl ProcessItem {
     /// If this function is called more than once, it will redo the processing.
     /// Unlike ProcessItem::process(...) which will skip if file has already
     /// been processed.
-    fn attempt_process(&mut self, source: PathBuf, output_dir: PathBuf, cmd: String, cmd_index: usize) -> Result<(), String>{
-        let tmp_filepath = process_tmp_path(&source, &output_dir, cmd_index)?;
+    fn attempt_process(&mut self, source: &Path, output_dir: &Path, cmd: &str, cmd_index: usize, options: &ProcessOptions) -> Result<(), Failure>{
+        let mut tmp_filepath = process_tmp_path(source, output_dir, &options.tmp_namespace, cmd_index)?;
+        let sandbox = options.sandbox.then_some(output_dir);
 
-        execute_command_str(&cmd, &source, &tmp_filepath);
+        // The output of every stage but the last one is an intermediate file,
+        // removed once the pipeline is done.
+        let stages = pipeline_stages(cmd);
+        let mut input = source.to_path_buf();
+        let mut intermediates = Vec::new();
+        let mut result = Ok(());
+        for (k, stage) in stages.iter().enumerate() {
+            let output = match k + 1 == stages.len() {
+                true => {
+                    tmp_filepath = final_tmp_path(&tmp_filepath, &input, stage);
+                    tmp_filepath.clone()
+                }
+                false => stage_tmp_path(&tmp_filepath, &input, stage, k),
+            };
+            result = run_stage(stage, &input, &output, sandbox);
+            if result.is_err() {
+                break;
+            }
+            intermediates.push(output.clone());
+            input = output;
+        }
+        for intermediate in intermediates.iter().filter(|p| **p != tmp_filepath) {
+            let _ = fs::remove_file(intermediate);
+        }
+        result?;
 
         let file_md = fs::metadata(&tmp_filepath)
             .map_err(|e| format!("Couldn't open {}: {e}", tmp_filepath.display()))?;
@@ -33,8 +192,18 @@ impl ProcessItem {
         (file_md.len() > 0)
             .then(|| ())
             .ok_or_else(|| format!("{} is empty", tmp_filepath.display()))?;
-        
-        self.tmp_path = Some(tmp_filepath);
+
+        match options.output_check {
+            OutputCheck::None => (),
+            OutputCheck::Header => verify_image(&tmp_filepath, false)?,
+            OutputCheck::Decode => verify_image(&tmp_filepath, true)?,
+        }
+
+        self.source_size = fs::metadata(source)
+            .map_err(|e| format!("Couldn't open {}: {e}", source.display()))?
+            .len();
+        self.processed_size = file_md.len();
+        self.transition(VariantState::Processed { tmp_path: tmp_filepath })?;
 
         Ok(())
     }
@@ -45,34 +214,240 @@ impl ProcessItem {
     ///
     /// The function can always be called, if the processing has already been 
     /// done for this instance.
-    pub fn process(&mut self, source: PathBuf, output_dir: PathBuf, cmd: String, cmd_index: usize){
+    ///
+    /// Metrics are not computed here, but in the compute pool once the
+    /// processing is done.
+    ///
+    /// Transient failures are retried as set in the options.
+    pub fn process(&mut self, source: PathBuf, output_dir: PathBuf, cmd: String, cmd_index: usize, options: &ProcessOptions) {
         // Return early if already processed, or processing failed.
-        if self.is_processed() || self.processing_failed {
+        if !self.is_pending() {
             return;
         }
 
-        if let Err(e) = self.attempt_process(source, output_dir, cmd, cmd_index) {
-            self.processing_failed = true;
-            println!("Processing failed: {e}");
+        for attempt in 0.. {
+            match self.attempt_process(&source, &output_dir, &cmd, cmd_index, options) {
+                Ok(()) => return,
+                Err(f) if f.transient && attempt < options.retries => {
+                    let delay = options.backoff * 2u32.saturating_pow(attempt);
+                    diagnostic!("Processing failed, retrying in {delay:?}: {}", f.reason);
+                    thread::sleep(delay);
+                }
+                Err(f) => {
+                    diagnostic!("Processing failed: {}", f.reason);
+                    if let Err(e) = self.set_failure(f.reason) {
+                        diagnostic!("Error: {e}");
+                    }
+                    return;
+                }
+            }
         }
     }
 
+    pub fn state(&self) -> &VariantState {
+        &self.state
+    }
+
+    /// Moves the variant to the provided state, if the lifecycle described by
+    /// VariantState allows it.
+    fn transition(&mut self, to: VariantState) -> Result<(), String> {
+        let allowed = matches!(
+            (&self.state, &to),
+            (VariantState::Pending, VariantState::Processed { .. })
+                | (VariantState::Pending, VariantState::Failed { .. })
+                | (VariantState::Failed { .. }, VariantState::Pending)
+                | (VariantState::Processed { .. }, VariantState::Validated { .. })
+                | (VariantState::Validated { .. }, VariantState::Processed { .. })
+        );
+        if !allowed {
+            return Err(format!("A {} variant can't become {}", self.state.name(), to.name()));
+        }
+        self.state = to;
+
+        Ok(())
+    }
+
+    /// Forgets a failed processing, so that it is attempted again.
+    pub fn clear_failure(&mut self) -> Result<(), String> {
+        self.transition(VariantState::Pending)
+    }
+
+    /// Marks the processing as failed, for provided reason.
+    pub fn set_failure(&mut self, reason: String) -> Result<(), String> {
+        self.transition(VariantState::Failed { reason })
+    }
+
+    /// Returns true if the variant was processed, even if it was validated
+    /// since.
     pub fn is_processed(&self) -> bool {
-        self.tmp_path.is_some()
+        self.tmp_path().is_some()
+    }
+
+    pub fn is_pending(&self) -> bool {
+        self.state == VariantState::Pending
+    }
+
+    pub fn has_failed(&self) -> bool {
+        self.failure().is_some()
+    }
+
+    /// Returns the reason of the failure, if processing failed.
+    pub fn failure(&self) -> Option<&str> {
+        match &self.state {
+            VariantState::Failed { reason } => Some(reason),
+            _ => None,
+        }
     }
 
-    fn is_validated(&self) -> bool {
-        self.processed_path.is_some()
+    /// Returns the path of the processed file in the processing directory,
+    /// where it is put back if the validation is undone.
+    pub fn tmp_path(&self) -> Option<&Path> {
+        match &self.state {
+            VariantState::Processed { tmp_path } | VariantState::Validated { tmp_path, .. } => Some(tmp_path),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of the processed file once validated.
+    pub fn validated_path(&self) -> Option<&Path> {
+        match &self.state {
+            VariantState::Validated { validated_path, .. } => Some(validated_path),
+            _ => None,
+        }
+    }
+
+    /// Returns the path of the processed file, wherever it currently is.
+    pub fn current_path(&self) -> Option<&Path> {
+        self.validated_path().or(self.tmp_path())
+    }
+
+    /// Returns the data on which rules are evaluated.
+    pub fn variant(&self) -> Variant<'_> {
+        Variant {
+            source_size: self.source_size,
+            processed_size: self.processed_size,
+            metrics: &self.metrics,
+        }
+    }
+}
+
+/// Pending variant, returned for the variants which were never touched.
+static PENDING: Option<ProcessItem> = Some(ProcessItem {
+    metrics: Vec::new(),
+    source_size: 0,
+    processed_size: 0,
+    rule: None,
+    metrics_pending: false,
+    quality: None,
+    state: VariantState::Pending,
+});
+
+/// Number of variants processed (validated or not) and failed.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct VariantCounts {
+    pub done: usize,
+    pub failed: usize,
+}
+
+impl VariantCounts {
+    /// Replaces the counts of old by the ones of new, e.g. the counts of an
+    /// image in the counts of all images.
+    pub fn update(&mut self, old: VariantCounts, new: VariantCounts) {
+        self.done = self.done + new.done - old.done;
+        self.failed = self.failed + new.failed - old.failed;
+    }
+}
+
+/// Variants of an image, one per command, indexed by the index of the
+/// command.
+///
+/// Only the variants which were accessed mutably (processed, sent to be
+/// processed, validated) are stored, the others are pending. The memory used
+/// thus grows with the variants touched rather than with the number of images
+/// times the number of commands.
+///
+/// Variants are options, so that they can be sent to other threads with
+/// Option::take (leaving None in place).
+#[derive(Clone, Debug, Default)]
+pub struct Variants {
+    len: usize,
+    touched: BTreeMap<usize, Option<ProcessItem>>,
+
+    /// Counts of the variants when they were last counted, see recount.
+    counts: VariantCounts,
+}
+
+impl Variants {
+    /// Returns len pending variants.
+    pub fn new(len: usize) -> Variants {
+        Variants { len, touched: BTreeMap::new(), counts: VariantCounts::default() }
+    }
+
+    /// Counts the variants processed and failed again, after their states
+    /// changed. Variants being processed are not counted.
+    ///
+    /// Returns the previous counts and the new ones.
+    pub fn recount(&mut self) -> (VariantCounts, VariantCounts) {
+        let mut counts = VariantCounts::default();
+        for p in self.touched.values().flatten() {
+            match p.state() {
+                VariantState::Pending => (),
+                VariantState::Failed { .. } => counts.failed += 1,
+                VariantState::Processed { .. } | VariantState::Validated { .. } => counts.done += 1,
+            }
+        }
+
+        (std::mem::replace(&mut self.counts, counts), counts)
+    }
+
+    /// Returns the variant of command c, or None if there is no such command.
+    pub fn get_mut(&mut self, c: usize) -> Option<&mut Option<ProcessItem>> {
+        (c < self.len).then(|| &mut self[c])
+    }
+
+    /// Returns every variant, in the order of the commands.
+    pub fn iter(&self) -> impl Iterator<Item = &Option<ProcessItem>> {
+        (0..self.len).map(|c| &self[c])
+    }
+
+    /// Returns the variants which were touched, with the index of their
+    /// command. The others are pending.
+    pub fn touched(&self) -> impl Iterator<Item = (usize, &Option<ProcessItem>)> {
+        self.touched.iter().map(|(&c, p)| (c, p))
+    }
+
+    /// Returns the variants which were touched, the others are pending.
+    pub fn touched_mut(&mut self) -> impl Iterator<Item = &mut Option<ProcessItem>> {
+        self.touched.values_mut()
+    }
+}
+
+impl Index<usize> for Variants {
+    type Output = Option<ProcessItem>;
+
+    fn index(&self, c: usize) -> &Option<ProcessItem> {
+        assert!(c < self.len, "no variant for command {c} of {}", self.len);
+        self.touched.get(&c).unwrap_or(&PENDING)
+    }
+}
+
+impl IndexMut<usize> for Variants {
+    fn index_mut(&mut self, c: usize) -> &mut Option<ProcessItem> {
+        assert!(c < self.len, "no variant for command {c} of {}", self.len);
+        self.touched.entry(c).or_insert_with(|| Some(ProcessItem::default()))
     }
 }
 
 /// Container for an image and its processed variants.
 ///
 /// source          is the original path for the file provided by user.
-/// deleted         is the original file location after it has been moved
-///                 if the user validated one of the processed variant.
+/// decision        is the decision of the user, which holds the location of
+///                 the original file once it has been moved to trash or to a
+///                 folder.
 /// processed       is a container of all the variants processed, or to be
 ///                 processed.
+/// phash           is the perceptual hash of the source, once computed.
+/// auto_decision   is the decision taken by the rules, if any.
 ///
 /// Upon loading the image, the file will first be processed by the provided
 /// processor command, and the output will be stored at processed_tmp location.
@@ -87,8 +462,29 @@ impl ProcessItem {
 #[derive(Clone)]
 pub struct ImgItem {
     pub source: PathBuf,
-    pub deleted: Option<PathBuf>,
-    pub processed: Vec<Option<ProcessItem>>,
+    pub processed: Variants,
+    pub phash: Option<u64>,
+    pub auto_decision: Option<RuleAction>,
+    pub note: Option<String>,
+
+    /// Command planned to be validated for this image, only applied when
+    /// quitting if the image is still undecided.
+    pub planned: Option<usize>,
+
+    /// Copy of the source in the processing directory, read instead of the
+    /// source when it is on a slow network mount.
+    pub local_copy: Option<PathBuf>,
+
+    /// Error of the last attempt to load the source, if it is unreadable.
+    pub load_error: Option<String>,
+
+    /// Snapshot of the source when the image was added, or reprocessed. The
+    /// variants are outdated once the source differs from it.
+    snapshot: Option<FileSnapshot>,
+
+    /// True once the source was found modified, until it is reprocessed.
+    pub outdated: bool,
+    decision: ImageDecision,
 }
 
 impl ImgItem {
@@ -96,120 +492,511 @@ impl ImgItem {
     /// Creates an instance of img, with the provided source path of the image
     /// to process
     ///
-    /// The instance will contain an option for the deleted path set to None,
-    /// to store the new path of the image when it will be moved.
-    /// It will also contain a vector of options of size cmds_len for every
-    /// processed variants (one for every command provided by user)
-    ///
-    /// ProcessItem are options, so that they can be sent to other threads with
-    /// Option::take (leaving None in place).
+    /// The instance is undecided, and will contain cmds_len pending variants
+    /// (one for every command provided by user), see Variants. The size and
+    /// modification time of the source are recorded, see source_modified.
     pub fn new(source: &Path, cmds_len: usize) -> ImgItem {
-        let processed = (0..cmds_len)
-            .map(|_| Some(ProcessItem::default()))
-            .collect();
-
         ImgItem {
             source: source.to_path_buf(),
-            processed,
-            deleted: None,
+            processed: Variants::new(cmds_len),
+            decision: ImageDecision::Undecided,
+            note: None,
+            phash: None,
+            auto_decision: None,
+            planned: None,
+            local_copy: None,
+            load_error: None,
+            snapshot: FileSnapshot::of(source),
+            outdated: false,
+        }
+    }
+
+    /// Returns true if the source was modified (or removed) since the image
+    /// was added or reprocessed.
+    pub fn source_modified(&self) -> bool {
+        self.snapshot.is_some() && FileSnapshot::of(&self.source) != self.snapshot
+    }
+
+    /// Marks the image as outdated if its source was modified, while it is
+    /// undecided. Returns true if it is outdated.
+    pub fn check_source(&mut self) -> bool {
+        if !self.outdated && !self.is_decided() && self.source_modified() {
+            self.outdated = true;
         }
+
+        self.outdated
+    }
+
+    /// Discards the variants of a modified source so that they are processed
+    /// again, at the same quality, along with the local copy of the source,
+    /// and records the new snapshot of the source. Variants being processed
+    /// are left as is.
+    ///
+    /// Returns the number of variants discarded.
+    pub fn reprocess(&mut self) -> usize {
+        let mut count = 0;
+        for p in self.processed.touched_mut().filter_map(Option::as_mut) {
+            if !p.is_pending() {
+                *p = ProcessItem { quality: p.quality, ..ProcessItem::default() };
+                count += 1;
+            }
+        }
+        if self.local_copy.as_ref() != Some(&self.source) {
+            self.local_copy = None;
+        }
+        self.snapshot = FileSnapshot::of(&self.source);
+        self.outdated = false;
+
+        count
+    }
+
+    /// Returns the path of the file read to process and display the source:
+    /// its local copy if it has one, the source otherwise.
+    pub fn input(&self) -> &Path {
+        self.local_copy.as_deref().unwrap_or(&self.source)
     }
 
     /// Validates the selected variant by moving it to the source directory
     ///
     /// To maximze safety, the original file is first moved to the trash
     /// folder, then the processed file is moved to the source_dir with its
-    /// final filename.
+    /// final filename, given by the rename pattern in settings. If
+    /// `keep_source` is set and the final filename differs from the source,
+    /// the original file is left in place. Otherwise, with `carry_sidecars`,
+    /// the sidecars of the original named by `sidecar_patterns` are renamed
+    /// after the final filename.
+    ///
+    /// The validation is refused if the source was modified since the image
+    /// was added or reprocessed, the variants would replace an outdated
+    /// original.
     pub fn validate(&mut self, cmd_index: usize, settings: &AppSettings) -> Result<(), String> {
+        match self.decision {
+            ImageDecision::Undecided => (),
+            ImageDecision::Validated { .. } => return Err("Image has already been validated".to_string()),
+            ImageDecision::Moved { .. } => return Err("Image has already been moved to a folder".to_string()),
+        }
+
+        if self.source_modified() {
+            return Err(format!("{} was modified since it was processed, reprocess it first", self.source.display()));
+        }
+
         let p = self.processed[cmd_index]
             .as_mut()
-            .ok_or_else(|| "No instance at provided index".to_string())
-            .and_then(|p| match p.is_processed() {
-                true => Ok(p),
-                false => Err("Instance at provided index is not processed.".to_string()),
-            })?;
+            .ok_or_else(|| "No instance at provided index".to_string())?;
+
+        let processed_path = match p.state() {
+            VariantState::Processed { tmp_path } => tmp_path.clone(),
+            state => return Err(format!("Instance at provided index is {}, not processed.", state.name())),
+        };
 
-        let processed_path = p
-            .tmp_path
-            .as_ref()
-            .ok_or_else(|| "No processed path at provided index".to_string())?;
+        let validated_path = validated_file_path(
+            &self.source,
+            &processed_path,
+            settings.rename_pattern.as_deref(),
+        )?;
+
+        if validated_path != self.source && validated_path.exists() {
+            return Err(format!("{} already exists", validated_path.display()));
+        }
 
-        let deleted_path = deleted_file_path(&self.source, &settings.trash_directory)?;
+        let deleted = if settings.keep_source && validated_path != self.source {
+            move_file(&processed_path, &validated_path)
+                .map_err(|e| format!("Unable to move file : {e}"))?;
+            None
+        } else {
+            let deleted_path = deleted_file_path(&self.source, &settings.trash_directory)?;
 
-        attempt_double_move(&self.source, &deleted_path, processed_path, &self.source)?;
-        self.deleted = Some(deleted_path);
-        p.processed_path = Some(self.source.clone());
+            attempt_double_move(&self.source, &deleted_path, &processed_path, &validated_path)?;
+            if let Err(e) = manifest_add(&settings.trash_directory, &deleted_path, &self.source) {
+                diagnostic!("Error: {e}");
+            }
+            if settings.carry_sidecars {
+                carry_sidecars(&self.source, &validated_path, &settings.sidecar_patterns);
+            }
+            Some(deleted_path)
+        };
+        p.transition(VariantState::Validated { tmp_path: processed_path, validated_path })?;
+        self.decision = ImageDecision::Validated { cmd_index, deleted, permanent: false };
 
         Ok(())
     }
 
-    /// Reverse the validation, put back validated image in tmp, and put back
-    /// deleted picture in source.
-    pub fn undo(&mut self) -> Result<(), String> {
-        let p = self
-            .get_validated()
-            .ok_or_else(|| "No validated process available".to_string())?;
+    /// Moves the source image into the provided folder, keeping its filename.
+    ///
+    /// The folder is created if it doesn't exist. The move is refused if a
+    /// file with the same name already exists in the folder, or if a processed
+    /// variant has already been validated for this image.
+    pub fn move_to_folder(&mut self, folder: &Path) -> Result<(), String> {
+        match self.decision {
+            ImageDecision::Undecided => (),
+            ImageDecision::Validated { .. } => return Err("Image has already been validated".to_string()),
+            ImageDecision::Moved { .. } => return Err("Image has already been moved to a folder".to_string()),
+        }
 
-        let processed_path = p
-            .tmp_path
-            .clone()
-            .ok_or_else(|| "No processed file available".to_string())?;
+        fs::create_dir_all(folder)
+            .map_err(|e| format!("Unable to create {}: {e}", folder.display()))?;
 
-        let deleted_path = self
-            .deleted
-            .clone()
-            .ok_or_else(|| "No deleted file available".to_string())?;
+        let mut moved_path = folder.to_path_buf();
+        moved_path.push(
+            self.source
+                .file_name()
+                .ok_or_else(|| format!("No file name in {}", self.source.display()))?,
+        );
 
-        attempt_double_move(
-            &self.source.clone(),
-            &processed_path,
-            &deleted_path,
-            &self.source.clone(),
-        )?;
+        if moved_path.exists() {
+            return Err(format!("{} already exists", moved_path.display()));
+        }
 
-        let mut validated = self.get_validated_mut();
-        let p = validated
-            .as_mut()
+        move_file(&self.source, &moved_path).map_err(|e| format!("Unable to move file : {e}"))?;
+        self.decision = ImageDecision::Moved { moved_path };
+
+        Ok(())
+    }
+
+    /// Applies the operation, see validate, move_to_folder and undo.
+    pub fn apply(&mut self, operation: &FileOperation, settings: &AppSettings) -> Result<(), String> {
+        match operation {
+            FileOperation::Validate(cmd_index) => self.validate(*cmd_index, settings),
+            FileOperation::MoveToFolder(folder) => self.move_to_folder(folder),
+            FileOperation::Undo => self.undo(settings),
+        }
+    }
+
+    /// Takes the outcome of an operation applied to a copy of this image: its
+    /// decision, the snapshot of its source, and the variant whose validation
+    /// the operation changed. The rest of the image is kept, it may have
+    /// changed meanwhile.
+    pub fn take_decision(&mut self, operation: &FileOperation, done: ImgItem) {
+        let changed = match operation {
+            FileOperation::Validate(cmd_index) => Some(*cmd_index),
+            FileOperation::MoveToFolder(_) => None,
+            FileOperation::Undo => self.validated_index(),
+        };
+
+        if let Some(c) = changed {
+            self.processed[c] = done.processed[c].clone();
+        }
+        self.decision = done.decision;
+        self.snapshot = done.snapshot;
+    }
+
+    /// Restores the validation of a variant, as recorded in a previous session.
+    ///
+    /// Nothing is moved, the files are only checked to still be where the
+    /// validation left them, so that the validation can be undone.
+    pub fn restore_validated(
+        &mut self,
+        cmd_index: usize,
+        tmp_path: &Path,
+        validated_path: &Path,
+        deleted_path: Option<&Path>,
+    ) -> Result<(), String> {
+        if self.decision != ImageDecision::Undecided {
+            return Err(format!("{} has already been decided upon", self.source.display()));
+        }
+
+        let processed_size = fs::metadata(validated_path)
+            .map_err(|e| format!("Couldn't open {}: {e}", validated_path.display()))?
+            .len();
+        let source_size = match deleted_path {
+            Some(deleted_path) => fs::metadata(deleted_path)
+                .map_err(|e| format!("Couldn't open {}: {e}", deleted_path.display()))?
+                .len(),
+            None => fs::metadata(&self.source).map(|md| md.len()).unwrap_or_default(),
+        };
+
+        let p = self.processed
+            .get_mut(cmd_index)
+            .ok_or_else(|| format!("No command at index {cmd_index}"))?;
+        *p = Some(ProcessItem {
+            source_size,
+            processed_size,
+            state: VariantState::Validated {
+                tmp_path: tmp_path.to_path_buf(),
+                validated_path: validated_path.to_path_buf(),
+            },
+            ..ProcessItem::default()
+        });
+        self.decision = ImageDecision::Validated {
+            cmd_index,
+            deleted: deleted_path.map(Path::to_path_buf),
+            permanent: false,
+        };
+
+        Ok(())
+    }
+
+    /// Restores the move of the source to a folder, as recorded in a previous
+    /// session.
+    pub fn restore_moved(&mut self, moved_path: &Path) -> Result<(), String> {
+        if self.decision != ImageDecision::Undecided {
+            return Err(format!("{} has already been decided upon", self.source.display()));
+        }
+
+        if !moved_path.exists() {
+            return Err(format!("{} not found", moved_path.display()));
+        }
+        self.decision = ImageDecision::Moved { moved_path: moved_path.to_path_buf() };
+
+        Ok(())
+    }
+
+    /// Reverse the last decision on this image.
+    ///
+    /// If the image was moved to a folder, it is moved back to its source
+    /// location, otherwise the validation is reverted, unless it is permanent.
+    /// The source put back is the new snapshot, since a move across file
+    /// systems changes its modification time.
+    pub fn undo(&mut self, settings: &AppSettings) -> Result<(), String> {
+        match self.decision.clone() {
+            ImageDecision::Undecided => return Err("No decision to undo".to_string()),
+            ImageDecision::Moved { moved_path } => {
+                move_file(&moved_path, &self.source)
+                    .map_err(|e| format!("Unable to move file : {e}"))?;
+                self.decision = ImageDecision::Undecided;
+            }
+            ImageDecision::Validated { permanent: true, .. } => {
+                return Err("The original was deleted from trash, the validation can't be undone".to_string());
+            }
+            ImageDecision::Validated { cmd_index, deleted, .. } => self.undo_validation(cmd_index, deleted, settings)?,
+        }
+        self.snapshot = FileSnapshot::of(&self.source);
+
+        Ok(())
+    }
+
+    /// Reverse the validation, put back validated image in tmp, and put back
+    /// deleted picture in source if it was moved to trash.
+    fn undo_validation(&mut self, cmd_index: usize, deleted: Option<PathBuf>, settings: &AppSettings) -> Result<(), String> {
+        let p = self.processed
+            .get_mut(cmd_index)
+            .and_then(Option::as_mut)
             .ok_or_else(|| "No validated process available".to_string())?;
-        p.processed_path.take();
-        self.deleted.take();
+
+        let (processed_path, validated_path) = match p.state() {
+            VariantState::Validated { tmp_path, validated_path } => (tmp_path.clone(), validated_path.clone()),
+            state => return Err(format!("The variant to undo is {}, not validated", state.name())),
+        };
+
+        match deleted {
+            Some(deleted_path) => {
+                attempt_double_move(
+                    &validated_path,
+                    &processed_path,
+                    &deleted_path,
+                    &self.source,
+                )?;
+                if let Err(e) = manifest_remove(&settings.trash_directory, &deleted_path) {
+                    diagnostic!("Error: {e}");
+                }
+                if settings.carry_sidecars {
+                    carry_sidecars(&validated_path, &self.source, &settings.sidecar_patterns);
+                }
+            }
+            None => move_file(&validated_path, &processed_path)
+                .map_err(|e| format!("Unable to move file : {e}"))?,
+        }
+
+        p.transition(VariantState::Processed { tmp_path: processed_path })?;
+        self.decision = ImageDecision::Undecided;
 
         Ok(())
     }
 
-    /// If we have defined a deleted path, that means that the image has been
-    /// validated.
+    pub fn decision(&self) -> &ImageDecision {
+        &self.decision
+    }
+
+    /// Returns the location of the original file once moved to trash by a
+    /// validation.
+    pub fn deleted(&self) -> Option<&Path> {
+        match &self.decision {
+            ImageDecision::Validated { deleted, .. } => deleted.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Makes the validation permanent once the original file was deleted
+    /// from trash: the validated file is then the only copy of the image, and
+    /// undoing would move it to the processing directory.
+    pub fn make_permanent(&mut self) {
+        if let ImageDecision::Validated { deleted, permanent, .. } = &mut self.decision {
+            *deleted = None;
+            *permanent = true;
+        }
+    }
+
+    /// Returns whether the validation can no longer be undone, see
+    /// make_permanent.
+    pub fn is_permanent(&self) -> bool {
+        matches!(self.decision, ImageDecision::Validated { permanent: true, .. })
+    }
+
+    /// Returns the location of the original file once moved to a folder.
+    pub fn moved(&self) -> Option<&Path> {
+        match &self.decision {
+            ImageDecision::Moved { moved_path } => Some(moved_path),
+            _ => None,
+        }
+    }
+
     pub fn is_validated(&self) -> bool {
-        self.deleted.is_some()
+        matches!(self.decision, ImageDecision::Validated { .. })
+    }
+
+    pub fn is_decided(&self) -> bool {
+        self.decision != ImageDecision::Undecided
     }
 
     /// Retrieves an option on a reference on the processed instance that was
     /// validated.
     pub fn get_validated(&self) -> Option<&ProcessItem> {
-       self.processed.iter().flatten().find(|&p| p.is_validated())
+        self.validated_index().and_then(|c| self.processed[c].as_ref())
     }
 
-    /// Retrieves an option on a mutable reference on the processed instance that
-    /// was validated.
-    fn get_validated_mut(&mut self) -> Option<&mut ProcessItem> {
+    /// Returns the bytes saved by the validated variant, negative if it is
+    /// larger than the source, or None if no variant is validated.
+    pub fn saved_bytes(&self) -> Option<i64> {
+        self.get_validated().map(|p| p.source_size as i64 - p.processed_size as i64)
+    }
+
+    /// Retrieves the index of the command whose processed instance was
+    /// validated.
+    pub fn validated_index(&self) -> Option<usize> {
+        match self.decision {
+            ImageDecision::Validated { cmd_index, .. } => Some(cmd_index),
+            _ => None,
+        }
+    }
+
+    /// Retrieves the index of the smallest processed variant which passes the
+    /// quality threshold.
+    ///
+    /// Only the variants of enabled commands are considered. Returns None if
+    /// the choice is unsure, i.e. if some variants are not yet processed, or if
+    /// no variant passes the threshold.
+    pub fn best_variant(&self, threshold: &Filter, enabled: &[bool]) -> Option<usize> {
+        let pending = self.processed
+            .iter()
+            .zip(enabled.iter())
+            .any(|(p, &e)| e && p.as_ref().is_none_or(|p| p.is_pending() || p.metrics_pending));
+        if pending {
+            return None;
+        }
+
         self.processed
-            .iter_mut()
-            .flatten()
-            .find(|p| p.is_validated())
+            .iter()
+            .enumerate()
+            .filter(|&(c, _)| enabled[c])
+            .filter_map(|(c, p)| p.as_ref().map(|p| (c, p)))
+            .filter(|(_, p)| p.is_processed() && threshold.matches(&p.variant()))
+            .min_by_key(|(_, p)| p.processed_size)
+            .map(|(c, _)| c)
+    }
+}
+
+
+/// Runs every metric command between source and the processed file, the
+/// metrics which fail are skipped.
+pub fn compute_metrics(source: &Path, processed: &Path, metrics: &[MetricCommand], sandbox: Option<&Path>) -> Vec<(String, f64)> {
+    metrics
+        .iter()
+        .filter_map(|m| match execute_metric_command_str(&m.command, source, processed, sandbox) {
+            Ok(value) => Some((m.name.clone(), value)),
+            Err(e) => {
+                diagnostic!("Metric {} failed: {e}", m.name);
+                None
+            }
+        })
+        .collect()
+}
+
+
+/// Runs a stage of a processing command on the input file, writing the
+/// output file.
+fn run_stage(stage: &str, input: &Path, output: &Path, sandbox: Option<&Path>) -> Result<(), Failure> {
+    if let Some(builtin) = builtin_command(stage) {
+        return Ok(builtin?.run(input, output)?);
+    }
+
+    let result = execute_command_str(stage, input, output, sandbox).map_err(|e| Failure {
+        reason: format!("Failed to execute {stage}: {e}"),
+        transient: is_transient_error(&e),
+    })?;
+    if !result.status.success() {
+        let stderr = String::from_utf8_lossy(&result.stderr);
+        return Err(Failure {
+            reason: format!("{stage} failed ({}): {}", result.status, stderr_summary(&stderr)),
+            transient: is_transient_status(&result.status),
+        });
+    }
+
+    Ok(())
+}
+
+
+/// Returns the path of the output of an intermediate stage k of a pipeline,
+/// next to the final output, e.g. `<namespace>_img_processed_0_stage0.png`.
+/// Its extension is the one of the files written by a built-in stage, or the
+/// one of the input of the stage, so that commands choosing the format from
+/// the extension keep it.
+fn stage_tmp_path(tmp_filepath: &Path, input: &Path, stage: &str, k: usize) -> PathBuf {
+    let extension = builtin_extension(input, stage).or_else(|| input.extension());
+
+    let mut filename = tmp_filepath.file_stem().unwrap_or_default().to_os_string();
+    filename.push(format!("_stage{k}"));
+    if let Some(extension) = extension {
+        filename.push(".");
+        filename.push(extension);
     }
+
+    tmp_filepath.with_file_name(filename)
 }
 
 
-/// Given the source path, the processing_directory path, and the command
-/// index, generates the temporary output file path.
+/// Returns the path of the output of the last stage of a pipeline. A built-in
+/// stage writes a known format, its output gets the matching extension, so
+/// that validating never names a file after another format. The output of
+/// other commands keeps the extension of the source.
+fn final_tmp_path(tmp_filepath: &Path, input: &Path, stage: &str) -> PathBuf {
+    match builtin_command(stage).is_some_and(|b| b.is_ok()) {
+        true => tmp_filepath.with_extension(builtin_extension(input, stage).unwrap_or_default()),
+        false => tmp_filepath.to_path_buf(),
+    }
+}
+
+/// Returns the extension of the files written by a built-in stage: the one of
+/// its input if it already names the format written, e.g. `JPG`, the one of
+/// its format otherwise. None if the stage isn't built-in.
+fn builtin_extension<'p>(input: &'p Path, stage: &str) -> Option<&'p OsStr> {
+    let builtin = builtin_command(stage)?.ok()?;
+    match (input.extension(), builtin.output_extension()) {
+        (Some(extension), _) if builtin.writes_extension(&extension.to_string_lossy()) => Some(extension),
+        (_, Some(extension)) => Some(OsStr::new(extension)),
+        (extension, None) => extension,
+    }
+}
+
+
+/// Returns a namespace unique to this session, from the process id and the
+/// start time, e.g. `bimgo-4242-20240521143000`.
+pub fn session_namespace() -> String {
+    format!("bimgo-{}-{}", std::process::id(), Utc::now().format("%Y%m%d%H%M%S"))
+}
+
+
+/// Given the source path, the processing_directory path, the namespace of the
+/// session and the command index, generates the temporary output file path.
 ///
 /// The temporary output file path is generated as follows:
 /// - The storage directory will be the provided processing_directory.
-/// - The filename will be the source filename, with _processed_i appended before
-///   the extension, where `i` is the index of the command.
+/// - The filename will be the source filename, prefixed with the namespace
+///   and with _processed_i appended before the extension, where `i` is the
+///   index of the command.
 fn process_tmp_path(
     source: &Path,
     processing_directory: &Path,
+    namespace: &str,
     i: usize,
 ) -> Result<PathBuf, String> {
     check_is_existing_directory(processing_directory)?;
@@ -218,10 +1005,12 @@ fn process_tmp_path(
     let extension = source.extension();
 
     let mut output_path = processing_directory.to_path_buf();
-    let mut filename = source
-        .file_stem()
-        .ok_or_else(|| format!("No file name in {}", source.display()))?
-        .to_os_string();
+    let mut filename = std::ffi::OsString::from(format!("{namespace}_"));
+    filename.push(
+        source
+            .file_stem()
+            .ok_or_else(|| format!("No file name in {}", source.display()))?,
+    );
 
     filename.push(suffix);
     if let Some(extension) = extension {
@@ -235,43 +1024,208 @@ fn process_tmp_path(
 }
 
 
-/// Given the source path, the and the trash directory path, generates the
-/// deleted file path.
+/// Returns the path of the local copy of the source of image i in the
+/// processing directory, e.g. `<namespace>_source3_img.jpg`, removed with
+/// the other temporary files of the namespace.
+pub fn local_copy_path(source: &Path, processing_directory: &Path, namespace: &str, i: usize) -> Result<PathBuf, String> {
+    let filename = source
+        .file_name()
+        .ok_or_else(|| format!("No file name in {}", source.display()))?;
+
+    let mut local_filename = std::ffi::OsString::from(format!("{namespace}_source{i}_"));
+    local_filename.push(filename);
+
+    Ok(processing_directory.join(local_filename))
+}
+
+
+/// Removes the temporary files of the namespace left in the processing
+/// directory, i.e. the variants which were not validated. Returns the number
+/// of files removed.
+pub fn remove_tmp_files(processing_directory: &Path, namespace: &str) -> Result<usize, String> {
+    let prefix = format!("{namespace}_");
+    let entries = fs::read_dir(processing_directory)
+        .map_err(|e| format!("Unable to read {}: {e}", processing_directory.display()))?;
+
+    let mut count = 0;
+    for entry in entries.flatten() {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) && entry.path().is_file() {
+            fs::remove_file(entry.path())
+                .map_err(|e| format!("Unable to remove {}: {e}", entry.path().display()))?;
+            count += 1;
+        }
+    }
+
+    Ok(count)
+}
+
+
+/// Given the source path, the processed file path and the rename pattern,
+/// generates the path where the validated file will be moved.
 ///
-/// The deleted file path is generated as follows:
-/// - The storage directory will be the provided processing_directory.
-/// - The filename will be the source filename, with _processed_i appended before
-///   the extension, where `i` is the index of the command.
-fn deleted_file_path(source: &Path, trash_directory: &Path) -> Result<PathBuf, String> {
-    check_is_existing_directory(trash_directory)?;
+/// The file is always placed in the source directory. The following
+/// placeholders are replaced in the pattern:
+/// - {stem}  The source filename without its extension.
+/// - {ext}   The extension of the processed file.
+///
+/// Without pattern, the source path is returned, i.e. the original name is
+/// overwritten, with the extension of the processed file if it differs, so
+/// that the name never lies about the format.
+fn validated_file_path(
+    source: &Path,
+    processed: &Path,
+    pattern: Option<&str>,
+) -> Result<PathBuf, String> {
+    let pattern = match pattern {
+        Some(pattern) => pattern,
+        None if processed.extension() == source.extension() => return Ok(source.to_path_buf()),
+        None => return Ok(source.with_extension(processed.extension().unwrap_or_default())),
+    };
 
-    let mut output_path = trash_directory.to_path_buf();
+    let stem = source
+        .file_stem()
+        .ok_or_else(|| format!("No file name in {}", source.display()))?
+        .to_string_lossy();
 
-    let extension = source.extension();
+    let filename = match processed.extension() {
+        Some(ext) => pattern.replace("{ext}", &ext.to_string_lossy()),
+        None => pattern.replace(".{ext}", "").replace("{ext}", ""),
+    }
+    .replace("{stem}", &stem);
 
-    // let mut filename = source
-    //     .file_stem()
-    //     .ok_or_else(|| "Missing file name".to_string())?
-    //     .to_os_string();
-    //             
-    // let dt = format!("_{}", Utc::now().format("%y-%m-%d_%Hh%Mm%Ss"));
-    //
-    // filename.push(dt);
-    // 
-    // if let Some(extension) = extension {
-    //     filename.push(".");
-    //     filename.push(extension);
-    // }
-
-    // FIXME: It doesn't seem ideal to use to_string_lossy, what could be a way
-    // to avoid that?
-    let filename: OsString = source.to_string_lossy()
-        .replace("%","%%")
-        .replace("/","%")
-        .into();
+    if filename.is_empty() || filename.contains('/') {
+        return Err(format!("Invalid file name '{filename}' from rename pattern"));
+    }
 
-    output_path.push(filename);
-    Ok(output_path)
+    Ok(source.with_file_name(filename))
 }
 
 
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn variant_lifecycle() {
+        let mut p = ProcessItem::default();
+        assert!(p.is_pending());
+
+        let tmp_path = PathBuf::from("/tmp/img_processed_0.jpg");
+        let validated_path = PathBuf::from("/a/img.jpg");
+        p.transition(VariantState::Processed { tmp_path: tmp_path.clone() }).unwrap();
+        p.transition(VariantState::Validated { tmp_path: tmp_path.clone(), validated_path: validated_path.clone() }).unwrap();
+        assert_eq!(p.current_path(), Some(validated_path.as_path()));
+        assert_eq!(p.tmp_path(), Some(tmp_path.as_path()));
+
+        p.transition(VariantState::Processed { tmp_path: tmp_path.clone() }).unwrap();
+        assert_eq!(p.current_path(), Some(tmp_path.as_path()));
+        assert!(p.set_failure("late".to_string()).is_err());
+        assert!(p.is_processed());
+    }
+
+    #[test]
+    fn failed_variants_are_retried() {
+        let mut p = ProcessItem::default();
+        assert!(p.clear_failure().is_err());
+
+        p.set_failure("no space left".to_string()).unwrap();
+        assert_eq!(p.failure(), Some("no space left"));
+        assert!(p.transition(VariantState::Processed { tmp_path: PathBuf::from("/tmp/a.jpg") }).is_err());
+
+        p.clear_failure().unwrap();
+        assert!(p.is_pending() && p.failure().is_none());
+    }
+
+    #[test]
+    fn variants_store_touched_only() {
+        let mut variants = Variants::new(1000);
+        assert!(variants.iter().all(|p| p.as_ref().is_some_and(ProcessItem::is_pending)));
+        assert_eq!(variants.touched().count(), 0);
+
+        let p = variants[3].take().unwrap();
+        assert!(variants[3].is_none());
+        variants[3] = Some(p);
+        variants[7].as_mut().unwrap().set_failure("fail".to_string()).unwrap();
+
+        assert_eq!(variants.touched().map(|(c, _)| c).collect::<Vec<usize>>(), [3, 7]);
+        assert_eq!(variants.iter().filter(|p| p.as_ref().is_some_and(ProcessItem::has_failed)).count(), 1);
+        assert!(variants.get_mut(1000).is_none());
+
+        let failed = VariantCounts { done: 0, failed: 1 };
+        assert_eq!(variants.recount(), (VariantCounts::default(), failed));
+        variants[7].as_mut().unwrap().clear_failure().unwrap();
+        assert_eq!(variants.recount(), (failed, VariantCounts::default()));
+    }
+
+    #[test]
+    fn only_processed_variants_are_validated() {
+        let mut img = ImgItem::new(Path::new("/a/img.jpg"), 2);
+        img.processed[1].as_mut().unwrap().set_failure("fail".to_string()).unwrap();
+
+        assert!(img.validate(0, &AppSettings::default()).unwrap_err().contains("pending"));
+        assert!(img.validate(1, &AppSettings::default()).unwrap_err().contains("failed"));
+        assert_eq!(img.decision(), &ImageDecision::Undecided);
+        assert!(img.undo(&AppSettings::default()).is_err());
+    }
+
+    #[test]
+    fn validated_file_path_without_pattern() {
+        let v = validated_file_path(Path::new("/a/img.jpg"), Path::new("/tmp/img_processed_0.jpg"), None);
+        assert_eq!(v, Ok(PathBuf::from("/a/img.jpg")));
+
+        // The name follows the format written.
+        let v = validated_file_path(Path::new("/a/img.jpg"), Path::new("/tmp/img_processed_0.png"), None);
+        assert_eq!(v, Ok(PathBuf::from("/a/img.png")));
+    }
+
+    #[test]
+    fn builtin_last_stage_names_its_format() {
+        let tmp = Path::new("/tmp/ns_img_processed_0.jpg");
+        let input = Path::new("/a/img.jpg");
+        assert_eq!(final_tmp_path(tmp, input, "builtin:resize?max=2000"), Path::new("/tmp/ns_img_processed_0.png"));
+        assert_eq!(final_tmp_path(tmp, input, "cwebp %i -o %o"), tmp);
+
+        // pngstrip writes the format of its input, a PNG after a resize.
+        let resized = Path::new("/tmp/ns_img_processed_0_stage0.png");
+        assert_eq!(final_tmp_path(tmp, resized, "builtin:pngstrip"), Path::new("/tmp/ns_img_processed_0.png"));
+    }
+
+    #[test]
+    fn builtin_jpeg_keeps_the_spelling_of_jpeg_sources() {
+        let tmp = Path::new("/tmp/ns_img_processed_0.png");
+        assert_eq!(final_tmp_path(tmp, Path::new("/a/img.png"), "builtin:jpeg?q=80"), Path::new("/tmp/ns_img_processed_0.jpg"));
+        assert_eq!(final_tmp_path(tmp, Path::new("/a/IMG.JPEG"), "builtin:jpeg?q=80"), Path::new("/tmp/ns_img_processed_0.JPEG"));
+    }
+
+    #[test]
+    fn validated_file_path_with_pattern() {
+        let v = validated_file_path(
+            Path::new("/a/img.jpg"),
+            Path::new("/tmp/img_processed_0.webp"),
+            Some("{stem}_opt.{ext}"),
+        );
+        assert_eq!(v, Ok(PathBuf::from("/a/img_opt.webp")));
+    }
+
+    #[test]
+    fn validated_file_path_without_extension() {
+        let v = validated_file_path(
+            Path::new("/a/img"),
+            Path::new("/tmp/img_processed_0"),
+            Some("{stem}_opt.{ext}"),
+        );
+        assert_eq!(v, Ok(PathBuf::from("/a/img_opt")));
+    }
+
+    #[test]
+    fn local_copy_path_is_in_namespace() {
+        let p = local_copy_path(Path::new("/mnt/nas/img.jpg"), Path::new("/tmp"), "bimgo-1", 3);
+        assert_eq!(p, Ok(PathBuf::from("/tmp/bimgo-1_source3_img.jpg")));
+    }
+
+    #[test]
+    fn validated_file_path_rejects_directories() {
+        let v = validated_file_path(Path::new("/a/img.jpg"), Path::new("/tmp/img.jpg"), Some("x/{stem}"));
+        assert!(v.is_err());
+    }
+}
@@ -0,0 +1,628 @@
+//! Integration tests of the processing and of the decisions, with a fixture
+//! script standing in for the encoders.
+//!
+//! Every test works in its own temporary directory, and checks the effects of
+//! processing, validating, undoing and restoring on the filesystem.
+//!
+//! The moves of files, where a bug loses images, are also checked against
+//! randomly generated scenarios.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::os::linux::fs::MetadataExt;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use crate::decode::PixelBuffer;
+use crate::img::{remove_tmp_files, FileOperation, ImgItem, ProcessOptions};
+use crate::moves::{MoveRequest, MoveResult, MoveWorker};
+use crate::report::{send_report, SessionReport};
+use crate::settings::{AppSettings, OutputCheck};
+use crate::trash;
+use crate::utils::attempt_double_move;
+
+/// Fixture script standing in for an encoder, called as
+/// `sh fixture.sh <mode> %i %o`.
+///
+/// copy      copies the input to the output.
+/// truncate  writes the first 4 bytes of the input to the output.
+/// empty     writes an empty output.
+/// sleep     copies the input after a short while.
+/// corrupt   writes an output which is not an image.
+/// fail      exits with an error message and status 1.
+/// flaky     exits with the temporary failure status the first time, then
+///           copies the input.
+const FIXTURE: &str = r#"
+mode=$1
+in=$2
+out=$3
+case $mode in
+    copy) cp "$in" "$out" ;;
+    truncate) head -c 4 "$in" > "$out" ;;
+    empty) : > "$out" ;;
+    corrupt) echo "not an image" > "$out" ;;
+    sleep) sleep 0.2 && cp "$in" "$out" ;;
+    fail) echo "fixture failure" >&2; exit 1 ;;
+    flaky)
+        if [ -e "$out.attempt" ]; then
+            cp "$in" "$out"
+        else
+            touch "$out.attempt"
+            exit 75
+        fi ;;
+esac
+"#;
+
+/// Content of the source images, starting like a JPEG image.
+const SOURCE_CONTENT: &[u8] = b"\xff\xd8\xff original image content";
+
+
+/// Temporary directory of a test, removed when dropped.
+struct TestDir {
+    root: PathBuf,
+}
+
+impl TestDir {
+    /// Creates the directory with the fixture script, a processing and a trash
+    /// directory, and a source image at photos/img.jpg.
+    fn new(name: &str) -> TestDir {
+        let root = std::env::temp_dir().join(format!("bimgo_test_{}_{name}", std::process::id()));
+        let _ = fs::remove_dir_all(&root);
+        for dir in ["processing", "trash", "photos"] {
+            fs::create_dir_all(root.join(dir)).unwrap();
+        }
+        fs::write(root.join("fixture.sh"), FIXTURE).unwrap();
+        fs::write(root.join("photos/img.jpg"), SOURCE_CONTENT).unwrap();
+
+        // The trash manifest records canonical paths.
+        let root = fs::canonicalize(&root).unwrap();
+
+        TestDir { root }
+    }
+
+    fn path(&self, relative: &str) -> PathBuf {
+        self.root.join(relative)
+    }
+
+    fn source(&self) -> PathBuf {
+        self.path("photos/img.jpg")
+    }
+
+    /// Returns the command running the fixture script in the provided mode.
+    fn cmd(&self, mode: &str) -> String {
+        format!("sh {} {mode} %i %o", self.path("fixture.sh").display())
+    }
+
+    fn settings(&self) -> AppSettings {
+        AppSettings {
+            processing_directory: self.path("processing"),
+            trash_directory: self.path("trash"),
+            ..AppSettings::default()
+        }
+    }
+
+    /// Processes the source image with the command in the provided mode, as
+    /// the only command.
+    fn processed(&self, mode: &str, options: &ProcessOptions) -> ImgItem {
+        let mut img = ImgItem::new(&self.source(), 1);
+        img.processed[0].as_mut().unwrap().process(
+            self.source(),
+            self.path("processing"),
+            self.cmd(mode),
+            0,
+            options,
+        );
+
+        img
+    }
+}
+
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.root);
+    }
+}
+
+
+fn options(retries: u32) -> ProcessOptions {
+    ProcessOptions { retries, backoff: Duration::ZERO, sandbox: false, tmp_namespace: "test".to_string(), output_check: OutputCheck::Header }
+}
+
+fn read(path: &Path) -> Vec<u8> {
+    fs::read(path).unwrap_or_else(|e| panic!("Unable to read {}: {e}", path.display()))
+}
+
+
+#[test]
+fn process_writes_variant_in_processing_directory() {
+    let dir = TestDir::new("process");
+    let img = dir.processed("truncate", &options(0));
+    let p = img.processed[0].as_ref().unwrap();
+
+    assert!(p.is_processed());
+    assert_eq!(p.tmp_path(), Some(dir.path("processing/test_img_processed_0.jpg").as_path()));
+    assert_eq!(read(&dir.path("processing/test_img_processed_0.jpg")), &SOURCE_CONTENT[..4]);
+    assert_eq!((p.source_size, p.processed_size), (SOURCE_CONTENT.len() as u64, 4));
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+}
+
+#[test]
+fn sessions_keep_their_own_tmp_files() {
+    let dir = TestDir::new("namespace");
+    dir.processed("truncate", &options(0));
+    let other = ProcessOptions { tmp_namespace: "other".to_string(), ..options(0) };
+    dir.processed("copy", &other);
+
+    assert_eq!(read(&dir.path("processing/test_img_processed_0.jpg")), &SOURCE_CONTENT[..4]);
+    assert_eq!(read(&dir.path("processing/other_img_processed_0.jpg")), SOURCE_CONTENT);
+
+    assert_eq!(remove_tmp_files(&dir.path("processing"), "test"), Ok(1));
+    assert!(!dir.path("processing/test_img_processed_0.jpg").exists());
+    assert!(dir.path("processing/other_img_processed_0.jpg").exists());
+}
+
+#[test]
+fn process_waits_for_slow_command() {
+    let dir = TestDir::new("slow");
+    let img = dir.processed("sleep", &options(0));
+
+    assert!(img.processed[0].as_ref().unwrap().is_processed());
+    assert_eq!(read(&dir.path("processing/test_img_processed_0.jpg")), SOURCE_CONTENT);
+}
+
+#[test]
+fn failing_command_records_stderr() {
+    let dir = TestDir::new("fail");
+    let img = dir.processed("fail", &options(2));
+    let p = img.processed[0].as_ref().unwrap();
+
+    assert!(p.has_failed() && !p.is_processed());
+    assert!(p.failure().unwrap().contains("fixture failure"));
+}
+
+#[test]
+fn empty_output_is_a_failure() {
+    let dir = TestDir::new("empty");
+    let img = dir.processed("empty", &options(0));
+    let p = img.processed[0].as_ref().unwrap();
+
+    assert!(p.has_failed());
+    assert!(p.failure().unwrap().contains("is empty"));
+}
+
+#[test]
+fn output_which_is_not_an_image_is_a_failure() {
+    let dir = TestDir::new("corrupt");
+    let img = dir.processed("corrupt", &options(0));
+    let p = img.processed[0].as_ref().unwrap();
+    assert!(p.has_failed() && !p.is_processed());
+    assert!(p.failure().unwrap().contains("not an image"));
+
+    let unchecked = ProcessOptions { output_check: OutputCheck::None, ..options(0) };
+    assert!(dir.processed("corrupt", &unchecked).processed[0].as_ref().unwrap().is_processed());
+}
+
+#[test]
+fn transient_failure_is_retried() {
+    let dir = TestDir::new("flaky_retried");
+    assert!(dir.processed("flaky", &options(1)).processed[0].as_ref().unwrap().is_processed());
+
+    let dir = TestDir::new("flaky_failed");
+    assert!(dir.processed("flaky", &options(0)).processed[0].as_ref().unwrap().has_failed());
+}
+
+#[test]
+fn validate_then_undo_restores_files() {
+    let dir = TestDir::new("validate_undo");
+    let settings = dir.settings();
+    let mut img = dir.processed("truncate", &options(0));
+
+    img.validate(0, &settings).unwrap();
+    let deleted = img.deleted().unwrap().to_path_buf();
+    assert!(deleted.starts_with(dir.path("trash")));
+    assert_eq!(read(&deleted), SOURCE_CONTENT);
+    assert_eq!(read(&dir.source()), &SOURCE_CONTENT[..4]);
+    assert!(!dir.path("processing/test_img_processed_0.jpg").exists());
+    assert_eq!(trash::manifest_entries(&dir.path("trash")).unwrap().len(), 1);
+    assert_eq!(img.saved_bytes(), Some(SOURCE_CONTENT.len() as i64 - 4));
+
+    img.undo(&settings).unwrap();
+    assert!(!img.is_validated() && img.deleted().is_none());
+    assert!(!deleted.exists());
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+    assert_eq!(read(&dir.path("processing/test_img_processed_0.jpg")), &SOURCE_CONTENT[..4]);
+    assert!(trash::manifest_entries(&dir.path("trash")).unwrap().is_empty());
+}
+
+#[test]
+fn validate_with_keep_source_leaves_original() {
+    let dir = TestDir::new("keep_source");
+    let settings = AppSettings {
+        keep_source: true,
+        rename_pattern: Some("{stem}_opt.{ext}".to_string()),
+        ..dir.settings()
+    };
+    let mut img = dir.processed("truncate", &options(0));
+
+    img.validate(0, &settings).unwrap();
+    assert!(img.deleted().is_none());
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+    assert_eq!(read(&dir.path("photos/img_opt.jpg")), &SOURCE_CONTENT[..4]);
+
+    img.undo(&settings).unwrap();
+    assert!(!dir.path("photos/img_opt.jpg").exists());
+    assert!(dir.path("processing/test_img_processed_0.jpg").exists());
+}
+
+#[test]
+fn sidecars_are_carried_along() {
+    let dir = TestDir::new("sidecars");
+    fs::write(dir.path("photos/img.xmp"), "develop settings").unwrap();
+    fs::write(dir.path("photos/img.jpg.dop"), "dxo settings").unwrap();
+    let settings = AppSettings {
+        rename_pattern: Some("{stem}_opt.{ext}".to_string()),
+        carry_sidecars: true,
+        sidecar_patterns: vec!["{stem}.xmp".to_string(), "{name}.dop".to_string()],
+        ..dir.settings()
+    };
+    let mut img = dir.processed("truncate", &options(0));
+
+    img.validate(0, &settings).unwrap();
+    assert_eq!(read(&dir.path("photos/img_opt.xmp")), b"develop settings");
+    assert_eq!(read(&dir.path("photos/img_opt.jpg.dop")), b"dxo settings");
+    assert!(!dir.path("photos/img.xmp").exists());
+
+    img.undo(&settings).unwrap();
+    assert_eq!(read(&dir.path("photos/img.xmp")), b"develop settings");
+    assert_eq!(read(&dir.path("photos/img.jpg.dop")), b"dxo settings");
+    assert!(!dir.path("photos/img_opt.xmp").exists());
+}
+
+#[test]
+fn report_is_piped_to_the_report_command() {
+    let dir = TestDir::new("report");
+    let settings = AppSettings {
+        report_command: Some(format!("cat > {}", dir.path("report.json").display())),
+        ..dir.settings()
+    };
+    let mut img = dir.processed("truncate", &options(0));
+    img.validate(0, &settings).unwrap();
+
+    let report = SessionReport::from_imgs(&[img], &[dir.cmd("truncate")], "today".to_string());
+    assert_eq!((report.images, report.validated, report.undecided), (1, 1, 0));
+    assert_eq!(report.saved, SOURCE_CONTENT.len() as i64 - 4);
+    assert_eq!(report.directory, Some(dir.path("photos")));
+
+    send_report(&report.json(), &settings).unwrap();
+    assert_eq!(read(&dir.path("report.json")), report.json().as_bytes());
+
+    let failing = AppSettings { report_command: Some("exit 3".to_string()), ..dir.settings() };
+    assert!(send_report(&report.json(), &failing).is_err());
+}
+
+#[test]
+fn move_to_folder_then_undo() {
+    let dir = TestDir::new("move");
+    let mut img = ImgItem::new(&dir.source(), 1);
+
+    img.move_to_folder(&dir.path("photos/discard")).unwrap();
+    assert!(!dir.source().exists());
+    assert_eq!(read(&dir.path("photos/discard/img.jpg")), SOURCE_CONTENT);
+    assert!(img.validate(0, &dir.settings()).is_err());
+
+    img.undo(&dir.settings()).unwrap();
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+    assert!(!dir.path("photos/discard/img.jpg").exists());
+}
+
+#[test]
+fn moves_are_done_by_the_move_worker() {
+    let dir = TestDir::new("move_worker");
+    let mut img = dir.processed("truncate", &options(0));
+    let mut worker = MoveWorker::new(dir.settings());
+
+    let validate = MoveRequest { i: 0, operation: FileOperation::Validate(0), rules: false };
+    worker.send(validate.clone(), &img).unwrap();
+    assert!(worker.is_pending(0));
+    assert!(worker.send(validate, &img).is_err());
+
+    let results = worker.finish();
+    assert_eq!(results.len(), 1);
+    assert!(results[0].result.is_ok() && !img.is_validated());
+    let MoveResult { request, img: done, .. } = results.into_iter().next().unwrap();
+    img.take_decision(&request.operation, done);
+    assert!(img.is_validated() && worker.pending() == 0);
+    assert_eq!(read(&dir.source()), &SOURCE_CONTENT[..4]);
+
+    worker.send(MoveRequest { i: 0, operation: FileOperation::Undo, rules: false }, &img).unwrap();
+    let MoveResult { request, img: done, result } = worker.finish().pop().unwrap();
+    assert_eq!(result, Ok(()));
+    img.take_decision(&request.operation, done);
+    assert!(!img.is_decided());
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+    assert!(img.processed[0].as_ref().unwrap().is_processed());
+}
+
+#[test]
+fn modified_source_outdates_variants() {
+    let dir = TestDir::new("modified_source");
+    let mut img = dir.processed("truncate", &options(0));
+    assert!(!img.check_source());
+
+    fs::write(dir.source(), b"\xff\xd8\xff edited elsewhere").unwrap();
+    assert!(img.check_source());
+    assert!(img.validate(0, &dir.settings()).unwrap_err().contains("modified"));
+    assert_eq!(read(&dir.source()), b"\xff\xd8\xff edited elsewhere");
+
+    assert_eq!(img.reprocess(), 1);
+    assert!(!img.check_source() && img.processed[0].as_ref().unwrap().is_pending());
+}
+
+#[test]
+fn restored_validation_can_be_undone() {
+    let dir = TestDir::new("recover");
+    let settings = dir.settings();
+    let mut img = dir.processed("truncate", &options(0));
+    img.validate(0, &settings).unwrap();
+    let deleted = img.deleted().unwrap().to_path_buf();
+
+    // A new session only knows the paths recorded in the session file.
+    let mut resumed = ImgItem::new(&dir.source(), 1);
+    resumed
+        .restore_validated(0, &dir.path("processing/test_img_processed_0.jpg"), &dir.source(), Some(&deleted))
+        .unwrap();
+    assert_eq!(resumed.saved_bytes(), img.saved_bytes());
+
+    resumed.undo(&settings).unwrap();
+    assert_eq!(read(&dir.source()), SOURCE_CONTENT);
+    assert!(dir.path("processing/test_img_processed_0.jpg").exists());
+}
+
+#[test]
+fn trashed_original_is_restored_from_manifest() {
+    let dir = TestDir::new("trash_restore");
+    let mut img = dir.processed("copy", &options(0));
+    img.validate(0, &dir.settings()).unwrap();
+
+    // The validated variant is in the way of the original.
+    let original = img.get_validated().unwrap().validated_path().unwrap().to_path_buf();
+    assert!(trash::restore(&dir.path("trash"), &original).is_err());
+
+    fs::remove_file(&original).unwrap();
+    assert_eq!(trash::restore(&dir.path("trash"), &original), Ok(original.clone()));
+    assert_eq!(read(&original), SOURCE_CONTENT);
+    assert!(trash::manifest_entries(&dir.path("trash")).unwrap().is_empty());
+}
+
+#[test]
+fn deleting_the_original_makes_the_validation_permanent() {
+    let dir = TestDir::new("delete_original");
+    let settings = dir.settings();
+    let mut img = dir.processed("truncate", &options(0));
+    img.validate(0, &settings).unwrap();
+
+    let deleted = img.deleted().unwrap().to_path_buf();
+    trash::delete(&dir.path("trash"), &deleted).unwrap();
+    img.make_permanent();
+
+    // The validated file is the only copy left, undoing must not move it to
+    // the processing directory, where quitting removes it.
+    assert!(img.undo(&settings).is_err());
+    assert!(img.is_permanent());
+    assert_eq!(remove_tmp_files(&dir.path("processing"), "test"), Ok(0));
+    assert_eq!(read(&dir.source()), &SOURCE_CONTENT[..4]);
+}
+
+#[test]
+fn builtin_resize_validates_a_png_file() {
+    let dir = TestDir::new("builtin_resize");
+    let source = dir.path("photos/small.jpg");
+    PixelBuffer { width: 16, height: 8, data: [200, 40, 40, 255].repeat(16 * 8) }.save_jpeg(&source, 90).unwrap();
+
+    // Small enough already, the image is still converted.
+    let mut img = ImgItem::new(&source, 1);
+    img.processed[0].as_mut().unwrap().process(
+        source.clone(),
+        dir.path("processing"),
+        "builtin:resize?max=2000".to_string(),
+        0,
+        &options(0),
+    );
+    img.validate(0, &dir.settings()).unwrap();
+
+    let validated = dir.path("photos/small.png");
+    assert_eq!(img.get_validated().unwrap().validated_path(), Some(validated.as_path()));
+    assert!(read(&validated).starts_with(b"\x89PNG\r\n\x1a\n"));
+    assert!(!source.exists());
+}
+
+#[test]
+fn builtin_jpeg_validates_a_jpeg_file() {
+    let dir = TestDir::new("builtin_jpeg");
+    let source = dir.path("photos/drawing.png");
+    PixelBuffer { width: 16, height: 8, data: [40, 40, 200, 255].repeat(16 * 8) }.save_png(&source).unwrap();
+
+    let mut img = ImgItem::new(&source, 1);
+    img.processed[0].as_mut().unwrap().process(
+        source.clone(),
+        dir.path("processing"),
+        "builtin:jpeg?q=80".to_string(),
+        0,
+        &options(0),
+    );
+    img.validate(0, &dir.settings()).unwrap();
+
+    let validated = dir.path("photos/drawing.jpg");
+    assert_eq!(img.get_validated().unwrap().validated_path(), Some(validated.as_path()));
+    assert!(read(&validated).starts_with(b"\xff\xd8\xff"));
+    assert!(!source.exists());
+}
+
+
+/// Number of scenarios generated for the moves of files.
+const MOVE_SCENARIOS: u64 = 200;
+
+/// Seed of the generators of the scenarios, see Rng::of_scenario.
+const MOVE_SEED: u64 = 0x2545_f491_4f6c_dd1d;
+
+/// Deterministic pseudo-random generator (xorshift).
+struct Rng(u64);
+
+impl Rng {
+    /// Returns the generator of scenario n, seeded from its number only, so
+    /// that a failing scenario can be rerun alone from the number and seed
+    /// printed with it.
+    fn of_scenario(n: u64) -> Rng {
+        // Consecutive numbers are spread, xorshift seeded with close values
+        // starts with close outputs.
+        Rng(MOVE_SEED ^ (n + 1).wrapping_mul(0x9e37_79b9_7f4a_7c15))
+    }
+
+    fn next(&mut self) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0
+    }
+
+    /// Returns true with a probability of 1 in n.
+    fn one_in(&mut self, n: u64) -> bool {
+        self.next().is_multiple_of(n)
+    }
+
+    fn pick<'t, T>(&mut self, items: &'t [T]) -> &'t T {
+        &items[(self.next() % items.len() as u64) as usize]
+    }
+}
+
+/// Directory of a file in a move scenario.
+#[derive(Clone, Copy, Debug)]
+enum Location {
+    /// On the same device as the test directory.
+    Local,
+    /// On another device if possible (/dev/shm), so that files are copied.
+    Other,
+    /// A directory which doesn't exist.
+    Missing,
+    /// A directory without write permission. Root ignores the permission, the
+    /// directory then behaves as a local one.
+    ReadOnly,
+}
+
+/// Directories of a move scenario.
+struct MoveDirs {
+    local: PathBuf,
+    other: PathBuf,
+    read_only: PathBuf,
+}
+
+impl MoveDirs {
+    fn new(local_root: &Path, other_root: &Path) -> MoveDirs {
+        let dirs = MoveDirs {
+            local: local_root.join("local"),
+            other: other_root.join("other"),
+            read_only: local_root.join("read_only"),
+        };
+        for dir in [&dirs.local, &dirs.other, &dirs.read_only] {
+            fs::create_dir_all(dir).unwrap();
+        }
+        fs::set_permissions(&dirs.read_only, fs::Permissions::from_mode(0o555)).unwrap();
+
+        dirs
+    }
+
+    fn path(&self, location: Location, name: &str) -> PathBuf {
+        match location {
+            Location::Local => self.local.join(name),
+            Location::Other => self.other.join(name),
+            Location::Missing => self.local.join("missing").join(name),
+            Location::ReadOnly => self.read_only.join(name),
+        }
+    }
+
+    /// Returns the content of every file of the scenario.
+    fn snapshot(&self) -> BTreeMap<PathBuf, Vec<u8>> {
+        [&self.local, &self.other, &self.read_only]
+            .into_iter()
+            .flat_map(|dir| fs::read_dir(dir).unwrap())
+            .map(|entry| entry.unwrap().path())
+            .filter(|path| path.is_file())
+            .map(|path| {
+                let content = read(&path);
+                (path, content)
+            })
+            .collect()
+    }
+}
+
+impl Drop for MoveDirs {
+    fn drop(&mut self) {
+        let _ = fs::set_permissions(&self.read_only, fs::Permissions::from_mode(0o755));
+        let _ = fs::remove_dir_all(&self.other);
+    }
+}
+
+/// Returns a directory on another device than the test directory, or the test
+/// directory itself if there is none.
+fn other_device_root(dir: &TestDir) -> PathBuf {
+    let shm = Path::new("/dev/shm").join(format!("bimgo_test_{}_moves", std::process::id()));
+    let on_other_device = fs::create_dir_all(&shm).is_ok()
+        && fs::metadata(&shm).unwrap().st_dev() != fs::metadata(&dir.root).unwrap().st_dev();
+
+    match on_other_device {
+        true => shm,
+        false => {
+            let _ = fs::remove_dir_all(&shm);
+            dir.path("other_device")
+        }
+    }
+}
+
+#[test]
+fn double_move_completes_or_restores_initial_state() {
+    let dir = TestDir::new("double_move");
+    let other_root = other_device_root(&dir);
+    let locations = [Location::Local, Location::Other, Location::Missing, Location::ReadOnly];
+
+    for n in 0..MOVE_SCENARIOS {
+        let mut rng = Rng::of_scenario(n);
+        let seed = rng.0;
+        let dirs = MoveDirs::new(&dir.path(&n.to_string()), &other_root.join(n.to_string()));
+
+        // Sources usually exist, on a writable device.
+        let src_1 = dirs.path(*rng.pick(&[Location::Local, Location::Other]), "src_1");
+        let src_2 = dirs.path(*rng.pick(&[Location::Local, Location::Other]), "src_2");
+        if !rng.one_in(8) {
+            fs::write(&src_1, b"first").unwrap();
+        }
+        if !rng.one_in(8) {
+            fs::write(&src_2, b"second").unwrap();
+        }
+        let dst_1 = dirs.path(*rng.pick(&locations), "dst_1");
+        // As when validating, the second file often replaces the first one.
+        let dst_2 = match rng.one_in(3) {
+            true => src_1.clone(),
+            false => dirs.path(*rng.pick(&locations), "dst_2"),
+        };
+
+        let before = dirs.snapshot();
+        let result = attempt_double_move(&src_1, &dst_1, &src_2, &dst_2);
+        let after = dirs.snapshot();
+
+        let scenario = format!("scenario {n} (seed {seed:#x}): {src_1:?} -> {dst_1:?}, {src_2:?} -> {dst_2:?}, {result:?}");
+        match result {
+            Ok(()) => {
+                let mut expected = before.clone();
+                let first = expected.remove(&src_1).expect(&scenario);
+                let second = expected.remove(&src_2).expect(&scenario);
+                expected.insert(dst_1.clone(), first);
+                expected.insert(dst_2.clone(), second);
+                assert_eq!(after, expected, "{scenario}");
+            }
+            Err(_) => assert_eq!(after, before, "{scenario}"),
+        }
+    }
+
+    let _ = fs::remove_dir_all(&other_root);
+}
@@ -0,0 +1,174 @@
+//! Launcher screen, shown when bimgo is started without a list of images.
+//!
+//! It lists the last session, which can be resumed, the recent sessions and
+//! the directories reviewed most often, see recent. Choosing an entry reviews
+//! every image of its directory.
+
+use std::path::PathBuf;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::Color;
+use sdl2::rect::Point;
+use sdl2::render::Canvas;
+use sdl2::ttf::Font;
+use sdl2::video::Window;
+use sdl2::EventPump;
+
+use crate::recent::Recent;
+use crate::renderer::Renderer;
+use crate::sdl_utils::{Anchor, TextBox, TextCache};
+
+
+/// Number of recent sessions and of frequent directories listed.
+const LAUNCHER_ROWS: usize = 5;
+
+/// An entry of the launcher: the directory to review, and whether the last
+/// session is resumed.
+#[derive(Clone, Debug, PartialEq)]
+pub struct LauncherEntry {
+    pub label: String,
+    pub directory: PathBuf,
+    pub resume: bool,
+}
+
+/// Returns the entries of the launcher: resuming the last session if its
+/// session file exists, then the recent sessions, then the frequent
+/// directories.
+pub fn launcher_entries(recent: &Recent, session_exists: bool) -> Vec<LauncherEntry> {
+    let mut entries = Vec::new();
+
+    if let Some(last) = recent.sessions.last().filter(|_| session_exists) {
+        entries.push(LauncherEntry {
+            label: format!("Resume the last session in {}", last.directory.display()),
+            directory: last.directory.clone(),
+            resume: true,
+        });
+    }
+
+    for session in recent.latest(LAUNCHER_ROWS) {
+        entries.push(LauncherEntry {
+            label: format!("{}  {} images  {}", session.date, session.images, session.directory.display()),
+            directory: session.directory.clone(),
+            resume: false,
+        });
+    }
+
+    for (directory, uses) in recent.frequent(LAUNCHER_ROWS) {
+        entries.push(LauncherEntry {
+            label: format!("{uses} sessions  {}", directory.display()),
+            directory: directory.to_path_buf(),
+            resume: false,
+        });
+    }
+
+    entries
+}
+
+/// Selection of the launcher.
+#[derive(Debug, Default)]
+pub struct Launcher {
+    pub entries: Vec<LauncherEntry>,
+    pub selected: usize,
+}
+
+impl Launcher {
+    /// Handles a key press: j/k or arrows move the selection, return or the
+    /// number of an entry chooses it, escape or q quit.
+    ///
+    /// Returns Some once done, with the chosen entry or None to quit.
+    pub fn key(&mut self, key: Keycode) -> Option<Option<LauncherEntry>> {
+        let number = (key as i32 - Keycode::Num1 as i32) as usize;
+
+        match key {
+            Keycode::J | Keycode::Down if self.selected + 1 < self.entries.len() => self.selected += 1,
+            Keycode::K | Keycode::Up if self.selected > 0 => self.selected -= 1,
+            Keycode::Return | Keycode::KpEnter if !self.entries.is_empty() => {
+                return Some(Some(self.entries[self.selected].clone()));
+            }
+            Keycode::Num1 | Keycode::Num2 | Keycode::Num3 | Keycode::Num4 | Keycode::Num5
+            | Keycode::Num6 | Keycode::Num7 | Keycode::Num8 | Keycode::Num9 if number < self.entries.len() => {
+                return Some(Some(self.entries[number].clone()));
+            }
+            Keycode::Escape | Keycode::Q => return Some(None),
+            _ => (),
+        }
+
+        None
+    }
+
+    /// Returns the text of the launcher, one entry per line.
+    fn text(&self) -> String {
+        if self.entries.is_empty() {
+            return "No recent sessions yet.\nPipe a list of images to bimgo, e.g. fd .jpg | bimgo\n(q quit)".to_string();
+        }
+
+        let mut text = "bimgo, recent sessions (j/k select, return open, q quit)".to_string();
+        for (k, entry) in self.entries.iter().enumerate() {
+            let marker = if k == self.selected { ">" } else { " " };
+            text.push_str(&format!("\n{marker} {} {}", k + 1, entry.label));
+        }
+
+        text
+    }
+
+    /// Shows the launcher until an entry is chosen, returns None if the user
+    /// quits instead.
+    pub fn run(
+        mut self,
+        canvas: &mut Canvas<Window>,
+        events: &mut EventPump,
+        font: &Font,
+    ) -> Result<Option<LauncherEntry>, String> {
+        let texture_creator = canvas.texture_creator();
+        let mut cache = TextCache::new(&texture_creator);
+
+        loop {
+            let (w, h) = canvas.window().size();
+            Renderer::clear(canvas, Color::RGB(36, 40, 59));
+            TextBox::new(&self.text(), font)
+                .wrapped(w * 7 / 8)
+                .draw(canvas, &mut cache, Point::new(w as i32 / 16, h as i32 / 2), Anchor::Left)?;
+            Renderer::present(canvas);
+
+            match events.wait_event() {
+                Event::Quit { .. } => return Ok(None),
+                Event::KeyDown { keycode: Some(key), .. } => {
+                    if let Some(choice) = self.key(key) {
+                        return Ok(choice);
+                    }
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::recent::RecentSession;
+
+    #[test]
+    fn entries_resume_then_choose_by_key() {
+        let recent = Recent {
+            sessions: vec![RecentSession {
+                date: "2026-10-14 18:02 UTC".to_string(),
+                images: 1380,
+                directory: PathBuf::from("/photos/2024"),
+            }],
+        };
+        assert_eq!(launcher_entries(&recent, false).len(), 2);
+
+        let mut launcher = Launcher { entries: launcher_entries(&recent, true), selected: 0 };
+        assert_eq!(launcher.entries.len(), 3);
+        assert!(launcher.entries[0].resume);
+
+        assert_eq!(launcher.key(Keycode::J), None);
+        assert_eq!(launcher.key(Keycode::Return), Some(Some(launcher.entries[1].clone())));
+        assert_eq!(launcher.key(Keycode::Num3), Some(Some(launcher.entries[2].clone())));
+        assert_eq!(launcher.key(Keycode::Num4), None);
+        assert_eq!(launcher.key(Keycode::Q), Some(None));
+    }
+}
@@ -0,0 +1,224 @@
+//! Reading of the list of images in the background.
+//!
+//! The list can hold a million paths, piped from another program which may
+//! still be producing them. It is read and validated by a thread which sends
+//! the paths in chunks, so that the first image is displayed as soon as its
+//! path is read, and the total grows while the rest arrives. The images of a
+//! directory chosen in the launcher are listed the same way.
+
+use std::fs;
+use std::io::BufRead;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::thread;
+
+use crate::sidecar::is_sidecar;
+use crate::progress::diagnostic;
+
+/// Number of paths sent at once by the reader, after the first one which is
+/// sent alone.
+const LIST_CHUNK: usize = 4096;
+
+/// Files skipped when listing the images of a directory.
+#[derive(Clone, Debug, Default)]
+pub struct ListPolicy {
+    /// Skip the hidden files and directories, whose name starts with a dot.
+    pub skip_hidden: bool,
+
+    /// Extensions of the sidecar files, never listed.
+    pub sidecar_extensions: Vec<String>,
+}
+
+/// Extensions of the files listed as images in a directory, in lowercase.
+const IMAGE_EXTENSIONS: &[&str] = &[
+    "jpg", "jpeg", "png", "webp", "avif", "jxl", "gif", "bmp", "tif", "tiff", "heic",
+];
+
+/// Sends paths in chunks, the first path alone so that it can be displayed
+/// right away.
+struct ChunkSender {
+    tx: mpsc::Sender<Vec<PathBuf>>,
+    chunk: Vec<PathBuf>,
+    chunk_size: usize,
+}
+
+impl ChunkSender {
+    fn new(tx: mpsc::Sender<Vec<PathBuf>>) -> ChunkSender {
+        ChunkSender { tx, chunk: Vec::new(), chunk_size: 1 }
+    }
+
+    /// Adds a path, sending the chunk once full. Returns false once the
+    /// receiver is dropped.
+    fn push(&mut self, path: PathBuf) -> bool {
+        self.chunk.push(path);
+        if self.chunk.len() < self.chunk_size {
+            return true;
+        }
+
+        self.chunk_size = LIST_CHUNK;
+        self.tx.send(std::mem::take(&mut self.chunk)).is_ok()
+    }
+
+    /// Sends the last chunk.
+    fn finish(self) {
+        if !self.chunk.is_empty() {
+            let _ = self.tx.send(self.chunk);
+        }
+    }
+}
+
+/// Returns the path of a line of the list, or the reason it is skipped. Blank
+/// lines are skipped silently.
+fn parse_line(line: &str) -> Option<Result<PathBuf, String>> {
+    let line = line.trim_end_matches('\r');
+    if line.trim().is_empty() {
+        return None;
+    }
+
+    let path = PathBuf::from(line);
+    Some(match path.is_file() {
+        true => Ok(path),
+        false => Err(format!("{line} is not a file, skipped")),
+    })
+}
+
+/// Reads the list of images, one path per line, and sends the paths of the
+/// existing files in chunks. The first path is sent alone, so that it can be
+/// displayed right away.
+///
+/// Stops at the end of the list, at the first read error, or once the
+/// receiver is dropped.
+pub fn read_list<R: BufRead>(reader: R, tx: mpsc::Sender<Vec<PathBuf>>) {
+    let mut sender = ChunkSender::new(tx);
+
+    for line in reader.lines() {
+        let line = match line {
+            Ok(line) => line,
+            Err(e) => {
+                diagnostic!("Error: unable to read the list of images: {e}");
+                break;
+            }
+        };
+
+        let sent = match parse_line(&line) {
+            Some(Ok(path)) => sender.push(path),
+            Some(Err(e)) => {
+                diagnostic!("Error: {e}");
+                true
+            }
+            None => true,
+        };
+        if !sent {
+            return;
+        }
+    }
+
+    sender.finish();
+}
+
+/// Returns true if the path has the extension of an image.
+fn is_image(path: &Path) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| IMAGE_EXTENSIONS.contains(&e.to_lowercase().as_str()))
+}
+
+/// Sends the images of the directory and of its subdirectories, in
+/// alphabetical order, files before subdirectories, except the ones skipped
+/// by the policy. Returns false once the receiver is dropped.
+fn walk_directory(directory: &Path, policy: &ListPolicy, sender: &mut ChunkSender) -> bool {
+    let mut entries = match fs::read_dir(directory) {
+        Ok(entries) => entries.flatten().map(|e| e.path()).collect::<Vec<PathBuf>>(),
+        Err(e) => {
+            diagnostic!("Error: unable to read {}: {e}", directory.display());
+            return true;
+        }
+    };
+    if policy.skip_hidden {
+        entries.retain(|p| !p.file_name().is_some_and(|n| n.to_string_lossy().starts_with('.')));
+    }
+    entries.sort();
+
+    let (directories, files): (Vec<PathBuf>, Vec<PathBuf>) = entries.into_iter().partition(|p| p.is_dir());
+    files
+        .into_iter()
+        .filter(|p| is_image(p) && !is_sidecar(p, &policy.sidecar_extensions))
+        .all(|p| sender.push(p))
+        && directories.iter().all(|d| walk_directory(d, policy, sender))
+}
+
+/// Lists the images of a directory and of its subdirectories in a new
+/// thread, see walk_directory. The channel is closed once every image is
+/// listed.
+pub fn spawn_directory_reader(directory: PathBuf, policy: ListPolicy) -> mpsc::Receiver<Vec<PathBuf>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || {
+        let mut sender = ChunkSender::new(tx);
+        if walk_directory(&directory, &policy, &mut sender) {
+            sender.finish();
+        }
+    });
+
+    rx
+}
+
+/// Reads the list of images in a new thread, see read_list. The channel is
+/// closed once the whole list is read.
+pub fn spawn_list_reader<R: BufRead + Send + 'static>(reader: R) -> mpsc::Receiver<Vec<PathBuf>> {
+    let (tx, rx) = mpsc::channel();
+    thread::spawn(move || read_list(reader, tx));
+
+    rx
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+    use std::io::Cursor;
+
+    #[test]
+    fn list_is_sent_in_chunks_of_existing_files() {
+        let dir = std::env::temp_dir().join(format!("bimgo_list_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let paths = (0..LIST_CHUNK + 2)
+            .map(|i| dir.join(format!("{i}.jpg")))
+            .collect::<Vec<PathBuf>>();
+        for path in &paths {
+            fs::write(path, "").unwrap();
+        }
+
+        let mut content = format!("\n{}\r\n", paths[0].display());
+        content += &format!("{}\n", dir.join("missing.jpg").display());
+        for path in &paths[1..] {
+            content += &format!("{}\n", path.display());
+        }
+
+        let chunks = spawn_list_reader(Cursor::new(content)).iter().collect::<Vec<Vec<PathBuf>>>();
+        assert_eq!(chunks.iter().map(Vec::len).collect::<Vec<usize>>(), [1, LIST_CHUNK, 1]);
+        assert_eq!(chunks.concat(), paths);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn directory_images_are_listed() {
+        let dir = std::env::temp_dir().join(format!("bimgo_dir_list_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("trip")).unwrap();
+        fs::create_dir_all(dir.join(".thumbnails")).unwrap();
+        for name in ["b.JPG", "a.png", "notes.txt", "trip/c.webp", ".thumbnails/d.jpg"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+
+        let policy = ListPolicy { skip_hidden: true, sidecar_extensions: Vec::new() };
+        let paths = spawn_directory_reader(dir.clone(), policy).iter().flatten().collect::<Vec<PathBuf>>();
+        assert_eq!(paths, [dir.join("a.png"), dir.join("b.JPG"), dir.join("trip/c.webp")]);
+
+        let paths = spawn_directory_reader(dir.clone(), ListPolicy::default()).iter().flatten().collect::<Vec<PathBuf>>();
+        assert_eq!(paths.len(), 4);
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -89,7 +89,7 @@
 //! [-] Between image switch, option to : fit width, fit height, fit whole image, 
 //!     fill screen (i.e. fit best?), keep zoom level, reset zoom level to 1.
 //!
-//! [ ] As a safety measure, when the compressed image is kept, the original
+//! [x] As a safety measure, when the compressed image is kept, the original
 //!     image will be stored in a folder instead. The user will have to manually
 //!     delete these pictures.
 //!
@@ -108,7 +108,7 @@
 //!
 //! [ ] Add a switch to reverse hjkl direction (image moves, or view moves).
 //!
-//! [ ] Option to enable processing of all available commands at once (maybe with
+//! [x] Option to enable processing of all available commands at once (maybe with
 //!     a warning if commmand number is greater than 10 or so).
 //!
 //! [ ] Some statistical information on how fast the processing happenned for each
@@ -116,7 +116,7 @@
 //!
 //! [ ] The file path below each image.
 //!
-//! [ ] Some feedback on actions.
+//! [-] Some feedback on actions.
 //!
 //! [ ] Holding space sets a second zoom level with the image location following 
 //!     the pointer. Releasing space sets the image exactly to where it was.
@@ -188,11 +188,13 @@
 //!     with the GPU. Possibly changing the current command configuration method 
 //!     to allow commands that already work in batch mode.
 //! 
-//! [ ] File in trash should be named based on folder location, using the "%" 
+//! [x] File in trash should be named based on folder location, using the "%" 
 //!     separator instead of "/" (?), there is a small possibility that this fails 
 //!     if filename already has % in itself (could maybe be fixed by adding an 
 //!     escaping % sign if there is already a percent in the original path name, 
 //!     as it is unlikely to have two slashes in the filename.)
+//!     -> The original directory hierarchy is recreated in the trash instead,
+//!     and a manifest maps trash entries back to their origin for restore.
 //!
 //! [ ] Fix issue where views don't get updated properly after toggling fullscreen
 //!     because the windows parameter are not yet updated at the time of calling
@@ -210,33 +212,120 @@ mod processing_order;
 mod utils;
 mod sdl_utils;
 mod img;
-
-use std::path::PathBuf;
-
+mod trash;
+mod phash;
+mod rules;
+mod preferences;
+mod session;
+mod compute;
+mod cache;
+mod decode;
+mod palette;
+mod stats;
+mod blind;
+mod perf;
+mod renderer;
+mod builtin;
+mod action;
+mod metadata;
+mod theme;
+mod paths;
+mod config_check;
+mod list;
+mod recent;
+mod launcher;
+mod moves;
+mod sidecar;
+mod dir_summary;
+mod report;
+mod progress;
+mod artifacts;
+
+#[cfg(test)]
+mod integration_tests;
+#[cfg(test)]
+mod snapshot_tests;
+#[cfg(test)]
+mod benches;
+
+use std::io::{self, IsTerminal};
+use std::time::Duration;
+
+use sdl2::controller::Axis;
 use sdl2::event::{Event, WindowEvent};
-use sdl2::keyboard::Keycode;
+use sdl2::keyboard::{Keycode, Mod};
 use sdl2::image::InitFlag;
 
-use itertools::Itertools;
 
 
 //use std::env;
 
-use application::App;
+use application::{App, PanDirection};
+use progress::diagnostic;
 use settings::*;
 use clap::Parser;
 
+/// Returns the files skipped when listing the images of a directory.
+fn list_policy(settings: &AppSettings) -> list::ListPolicy {
+    list::ListPolicy {
+        skip_hidden: settings.skip_hidden,
+        sidecar_extensions: sidecar::pattern_extensions(&settings.sidecar_patterns),
+    }
+}
+
 fn main() -> Result<(), String> {
 
     /* CLI initialization */ 
     let cli = Cli::parse();
+    if cli.progress.is_some() {
+        progress::reserve_stdout();
+    }
+    paths::migrate_legacy_files();
+
+    if let Some(path) = &cli.restore {
+        let settings = AppSettings::new(cli.profile.as_deref()).map_err(|e| format!("Error: {e}"))?;
+        let original = trash::restore(&settings.trash_directory, path)?;
+        println!("Restored {}", original.display());
+
+        return Ok(());
+    }
 
+    if let Some(CliCommand::Stats { directory }) = &cli.command {
+        let settings = AppSettings::new(cli.profile.as_deref()).map_err(|e| format!("Error: {e}"))?;
+        let stats = stats::Stats::load(&settings.stats_file)?;
+        match directory {
+            Some(directory) => {
+                let images = list::spawn_directory_reader(directory.clone(), list_policy(&settings));
+                let summary = dir_summary::DirectorySummary::of(images.iter().flatten());
+                println!("{}", summary.text(directory, &stats));
+            }
+            None => println!("{}", stats.summary()),
+        }
+
+        return Ok(());
+    }
+
+    if let Some(CliCommand::Blind) = cli.command {
+        let settings = AppSettings::new(cli.profile.as_deref()).map_err(|e| format!("Error: {e}"))?;
+        println!("{}", blind::BlindResults::load(&settings.blind_file)?.summary());
+
+        return Ok(());
+    }
 
     /* Initialization of SDL libary components. */
     let sdl_context = sdl2::init()?;
     let video_subsystem = sdl_context.video()?;
     let _image_context = sdl2::image::init(InitFlag::PNG | InitFlag::JPG)?;
     let ttf_context = sdl2::ttf::init().map_err(|e| format!("{e}"))?;
+    let controller_subsystem = sdl_context.game_controller()?;
+    let timer_subsystem = sdl_context.timer()?;
+
+    // Text input is only enabled while text is typed, e.g. in the palette.
+    video_subsystem.text_input().stop();
+
+    // Controllers must be kept open to receive their events. SDL sends an
+    // added event for the controllers already connected at startup.
+    let mut controllers = Vec::new();
 
 
     // Create a window.
@@ -262,93 +351,167 @@ fn main() -> Result<(), String> {
 
     /* Here starts the application code */
 
-    //let mut first_file = String::new();
-    //stdin().read_line(&mut first_file).expect("Could not read stdin");
-
-    // Temporary list of img for testing. In final version this will come from 
-    // stdin
-    use utils::*;
-    let img_list_file = expand_tilde("~/bimgo/img_list")
-        .expect("img_list file not found");
-    let img_list: Vec<PathBuf> = 
-        read_file_lines(&img_list_file)
-        .expect("Unable to parse image list").into_iter()
-        .map(PathBuf::from)
-        .collect();
+    // The list of images is read from stdin when it is piped. Otherwise the
+    // launcher lists the recent sessions, and the images of the directory
+    // chosen are reviewed.
+    let (img_list, resume) = match io::stdin().is_terminal() {
+        false => (list::spawn_list_reader(io::BufReader::new(io::stdin())), cli.resume),
+        true => {
+            let settings = AppSettings::new(cli.profile.as_deref()).map_err(|e| format!("Error: {e}"))?;
+            let theme = theme::Theme::new(settings.accessible || cli.accessible, settings.color_palette);
+            let font_path = utils::expand_tilde(paths::font_file()).map_err(|e| e.to_string())?;
+            let font = ttf_context.load_font(&font_path, theme.font_size)?;
+
+            let recent = recent::Recent::load(&settings.recent_file)?;
+            let entries = launcher::launcher_entries(&recent, settings.session_file.is_file());
+            let launcher = launcher::Launcher { entries, selected: 0 };
+            match launcher.run(&mut canvas, &mut evt_pump, &font)? {
+                Some(entry) => {
+                    let policy = list_policy(&settings);
+                    (list::spawn_directory_reader(entry.directory, policy), entry.resume || cli.resume)
+                }
+                None => return Ok(()),
+            }
+        }
+    };
 
-    let mut app = App::new(&mut canvas, &texture_creator, &ttf_context, img_list)?;
+    let mut app = App::new(&mut canvas, &texture_creator, &ttf_context, img_list, resume, cli.profile.as_deref(), cli.accessible)?;
+    if let Some(path) = &cli.perf_log {
+        app.open_perf_log(path)?;
+    }
+    app.set_progress(cli.progress);
 
     'mainloop: loop {
         app.run()?;
 
-        // We skip events that are of same variant and only keep one (here the
-        // first even though it would be preferable to only keep the last).
-        let evts = evt_pump
-            .poll_iter()
-            .dedup_by(|a, b| std::mem::discriminant(a) == std::mem::discriminant(b));
+        // Consecutive mouse motions and resizes are merged, only the last
+        // one matters. Key presses are all kept, in order.
+        let evts = sdl_utils::coalesce_events(evt_pump.poll_iter());
 
         for event in evts {
             //println!("Event received: {event:?}");
+            let timestamp = event.get_timestamp();
             match event {
-                Event::Quit { .. }
-                | Event::KeyDown {keycode: Option::Some(Keycode::Escape), .. }
-                | Event::KeyDown {keycode: Option::Some(Keycode::Q), .. } 
-                    => break 'mainloop,
+                // The note input captures every key press and the typed text
+                // while open.
+                Event::KeyDown {keycode: Option::Some(k), .. } if app.note_input_is_open()
+                    => app.note_key(k)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::Semicolon), .. } 
-                    => app.next_image()?,
-                    
-                Event::KeyDown {keycode: Option::Some(Keycode::Comma), .. } 
-                    => app.prev_image()?,
+                Event::TextInput { text, .. } if app.note_input_is_open()
+                    => app.note_text(&text)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::N), .. } 
-                    => app.next_cmd()?,
-                    
-                Event::KeyDown {keycode: Option::Some(Keycode::P), .. } 
-                    => app.prev_cmd()?,
+                // The command palette captures every key press and the typed
+                // text while open.
+                Event::KeyDown {keycode: Option::Some(k), .. } if app.palette_is_open()
+                    => app.palette_key(k)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::Space), .. } 
-                    => app.validate_current()?,
+                Event::TextInput { text, .. } if app.palette_is_open()
+                    => app.palette_text(&text)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::U), .. } 
-                    => app.undo_current()?,
+                // The trash screen captures every key press while open.
+                Event::KeyDown {keycode: Option::Some(k), .. } if app.trash_screen_is_open()
+                    => app.trash_screen_key(k)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::O), .. } 
-                    => app.zoom_in()?,
+                // The statistics screen is closed by any key.
+                Event::KeyDown { .. } if app.stats_screen_is_open()
+                    => app.close_stats_screen()?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::I), .. } 
-                    => app.zoom_out()?,
+                // The command list popup captures every key press while open.
+                Event::KeyDown {keycode: Option::Some(k), .. } if app.cmd_list_is_open()
+                    => app.cmd_list_key(k)?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::H), .. } 
-                    => app.pan_left()?,
-
-                Event::KeyDown {keycode: Option::Some(Keycode::J), .. } 
-                    => app.pan_down()?,
+                Event::Quit { .. }
+                | Event::KeyDown {keycode: Option::Some(Keycode::Escape), .. }
+                    => break 'mainloop,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::K), .. } 
-                    => app.pan_up()?,
+                // Pan keys pan continuously while held, the key repeats of
+                // the OS are ignored.
+                Event::KeyDown {keycode: Option::Some(Keycode::H | Keycode::J | Keycode::K | Keycode::L), repeat: true, .. } 
+                    => (),
 
-                Event::KeyDown {keycode: Option::Some(Keycode::L), .. } 
-                    => app.pan_right()?,
+                Event::Window  {win_event: WindowEvent::FocusLost, .. } 
+                    => app.stop_all_pans(),
 
-                Event::KeyDown {keycode: Option::Some(Keycode::F), .. } 
-                    => app.toggle_fullscreen()?,
-                    
                 Event::Window  {win_event: WindowEvent::SizeChanged(_, _), .. } 
                     => app.update_views()?,
 
-                Event::KeyDown {keycode: Option::Some(Keycode::S), .. } 
-                    => app.update_views()?,
+                // Move actions bound in the configuration only apply to the
+                // keys without action.
+                Event::KeyDown {keycode: Option::Some(k), keymod, .. } => {
+                    let ctrl = keymod.intersects(Mod::LCTRLMOD | Mod::RCTRLMOD);
+                    let shift = keymod.intersects(Mod::LSHIFTMOD | Mod::RSHIFTMOD);
+                    match action::key_action(k, ctrl, shift) {
+                        Some(action) => app.perform(action)?,
+                        None => app.move_current(k)?,
+                    }
+                }
+
+                Event::KeyUp {keycode: Option::Some(k), .. } => {
+                    if let Some(action) = action::key_release_action(k) {
+                        app.perform(action)?;
+                    }
+                }
+
+                Event::Window  {win_event: WindowEvent::Leave, .. } 
+                    => app.clear_mouse_position()?,
+
+                Event::ControllerDeviceAdded { which, .. } => {
+                    match controller_subsystem.open(which) {
+                        Ok(controller) => controllers.push(controller),
+                        Err(e) => diagnostic!("Error: unable to open controller: {e}"),
+                    }
+                }
+
+                Event::ControllerDeviceRemoved { which, .. }
+                    => controllers.retain(|c| c.instance_id() != which),
+
+                Event::ControllerButtonDown { button, .. } => {
+                    if let Some(action) = action::controller_action(button) {
+                        app.perform(action)?;
+                    }
+                }
+
+                Event::ControllerAxisMotion { axis: Axis::LeftX, value, .. }
+                    => app.pan_axis(PanDirection::Left, PanDirection::Right, value)?,
+
+                Event::ControllerAxisMotion { axis: Axis::LeftY, value, .. }
+                    => app.pan_axis(PanDirection::Up, PanDirection::Down, value)?,
+
+                Event::MouseButtonDown { mouse_btn, clicks, x, y, .. } => {
+                    app.set_mouse_position(x, y)?;
+                    app.mouse_button(mouse_btn, clicks)?;
+                }
 
                 Event::MouseMotion { x, y, .. }
                     // => app.pan_mouse_relative(x, y)?,
-                    => (),
+                    => app.set_mouse_position(x, y)?,
 
                 _ => (),
             }
+
+            let latency = timer_subsystem.ticks().saturating_sub(timestamp);
+            app.record_event_latency(Duration::from_millis(latency as u64));
         }
     }
 
+    app.finish_moves();
+
+    // Committing can take minutes on slow disks, escape or closing the
+    // window again stops it after the current file.
+    app.commit_planned(|| evt_pump.poll_iter().any(|event| matches!(
+        event,
+        Event::Quit { .. } | Event::KeyDown { keycode: Some(Keycode::Escape), .. }
+    )));
+    app.save_session();
+    app.save_stats();
+    app.save_blind_results();
+    app.save_recent();
+    app.send_report();
+    app.remove_tmp_files();
+    if let Some(summary) = app.unreadable_summary() {
+        diagnostic!("{summary}");
+    }
+
     Ok(())
 }
 
@@ -0,0 +1,428 @@
+//! Metadata of image files, and the differences between the metadata of the
+//! source and of a processed variant.
+//!
+//! Only the headers are parsed, without decoding the image: the dimensions,
+//! the color type and bit depth, the EXIF tags present and the name of the
+//! ICC profile. JPEG, PNG and WebP are supported, other formats only report
+//! their format. This shows what an encoder changed beyond the pixels, e.g.
+//! a dropped color profile or GPS position.
+
+use std::fs::File;
+use std::io::Read;
+use std::path::Path;
+
+use crate::decode::image_format;
+
+/// Number of bytes read from the start of a file, the metadata is expected
+/// before the image data.
+const HEADER_LIMIT: u64 = 1 << 20;
+
+/// Names of the common EXIF tags, other tags are named by their number.
+const EXIF_TAGS: &[(u16, &str)] = &[
+    (0x010e, "ImageDescription"),
+    (0x010f, "Make"),
+    (0x0110, "Model"),
+    (0x0112, "Orientation"),
+    (0x011a, "XResolution"),
+    (0x011b, "YResolution"),
+    (0x0131, "Software"),
+    (0x0132, "DateTime"),
+    (0x013b, "Artist"),
+    (0x8298, "Copyright"),
+    (0x829a, "ExposureTime"),
+    (0x829d, "FNumber"),
+    (0x8825, "GPS"),
+    (0x8827, "ISO"),
+    (0x9003, "DateTimeOriginal"),
+    (0x9004, "DateTimeDigitized"),
+    (0x920a, "FocalLength"),
+    (0x927c, "MakerNote"),
+    (0x9286, "UserComment"),
+    (0xa001, "ColorSpace"),
+    (0xa002, "PixelXDimension"),
+    (0xa003, "PixelYDimension"),
+    (0xa434, "LensModel"),
+];
+
+/// EXIF tag pointing to the sub-directory of the EXIF tags.
+const EXIF_IFD_POINTER: u16 = 0x8769;
+
+
+/// Metadata of an image file. Fields are None when unknown.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FileMetadata {
+    pub format: Option<&'static str>,
+    pub dimensions: Option<(u32, u32)>,
+    pub color: Option<&'static str>,
+    pub bit_depth: Option<u8>,
+
+    /// Names of the EXIF tags present, in the order of the file.
+    pub exif: Vec<String>,
+
+    /// Description of the ICC profile, or "unnamed" if the profile has none.
+    pub icc_profile: Option<String>,
+}
+
+impl FileMetadata {
+    /// Reads the metadata of the image file at provided path.
+    pub fn read(path: &Path) -> Result<FileMetadata, String> {
+        let mut data = Vec::new();
+        File::open(path)
+            .and_then(|f| f.take(HEADER_LIMIT).read_to_end(&mut data))
+            .map_err(|e| format!("Couldn't read {}: {e}", path.display()))?;
+
+        Ok(FileMetadata::parse(&data))
+    }
+
+    /// Parses the metadata of an image file starting with the provided bytes.
+    pub fn parse(data: &[u8]) -> FileMetadata {
+        let mut metadata = FileMetadata { format: image_format(data), ..FileMetadata::default() };
+        match metadata.format {
+            Some("JPEG") => metadata.parse_jpeg(data),
+            Some("PNG") => metadata.parse_png(data),
+            Some("WebP") => metadata.parse_webp(data),
+            _ => (),
+        }
+
+        metadata
+    }
+
+    fn parse_jpeg(&mut self, data: &[u8]) {
+        let mut icc = Vec::new();
+        let mut rest = &data[2..];
+        while rest.len() >= 4 && rest[0] == 0xff {
+            let marker = rest[1];
+            let length = be16(&rest[2..]) as usize;
+            let segment = match rest.get(4..2 + length) {
+                Some(segment) => segment,
+                None => break,
+            };
+            match marker {
+                0xe1 if segment.starts_with(b"Exif\0\0") => self.exif = exif_tags(&segment[6..]),
+                // The profile may be split across several segments, after a
+                // sequence number and a count.
+                0xe2 if segment.starts_with(b"ICC_PROFILE\0") && segment.len() > 14 => {
+                    icc.extend_from_slice(&segment[14..]);
+                }
+                0xc0..=0xcf if ![0xc4, 0xc8, 0xcc].contains(&marker) && segment.len() >= 6 => {
+                    self.bit_depth = Some(segment[0]);
+                    self.dimensions = Some((be16(&segment[3..]) as u32, be16(&segment[1..]) as u32));
+                    self.color = match segment[5] {
+                        1 => Some("Grayscale"),
+                        3 => Some("YCbCr"),
+                        4 => Some("CMYK"),
+                        _ => None,
+                    };
+                }
+                // The image data follows the start of scan.
+                0xda => break,
+                _ => (),
+            }
+            rest = &rest[2 + length..];
+        }
+
+        if !icc.is_empty() {
+            self.icc_profile = Some(icc_description(&icc));
+        }
+    }
+
+    fn parse_png(&mut self, data: &[u8]) {
+        let mut rest = &data[8..];
+        while rest.len() >= 12 {
+            let length = be32(rest) as usize;
+            let kind = &rest[4..8];
+            let content = match rest.get(8..8 + length) {
+                Some(content) => content,
+                None => break,
+            };
+            match kind {
+                b"IHDR" if content.len() >= 10 => {
+                    self.dimensions = Some((be32(content), be32(&content[4..])));
+                    self.bit_depth = Some(content[8]);
+                    self.color = match content[9] {
+                        0 => Some("Grayscale"),
+                        2 => Some("RGB"),
+                        3 => Some("Palette"),
+                        4 => Some("Grayscale + alpha"),
+                        6 => Some("RGBA"),
+                        _ => None,
+                    };
+                }
+                // The profile itself is compressed, only its name is read.
+                b"iCCP" => {
+                    let name = content.split(|&b| b == 0).next().unwrap_or_default();
+                    self.icc_profile = Some(String::from_utf8_lossy(name).into_owned());
+                }
+                b"eXIf" => self.exif = exif_tags(content),
+                b"IEND" => break,
+                _ => (),
+            }
+            rest = rest.get(12 + length..).unwrap_or_default();
+        }
+    }
+
+    fn parse_webp(&mut self, data: &[u8]) {
+        self.bit_depth = Some(8);
+        let mut rest = &data[12..];
+        while rest.len() >= 8 {
+            let kind = &rest[..4];
+            let length = u32::from_le_bytes([rest[4], rest[5], rest[6], rest[7]]) as usize;
+            let content = &rest[8..(8 + length).min(rest.len())];
+            match kind {
+                b"VP8X" if content.len() >= 10 => {
+                    let alpha = content[0] & 0x10 != 0;
+                    self.dimensions = Some((le24(&content[4..]) + 1, le24(&content[7..]) + 1));
+                    self.color.get_or_insert(if alpha { "YUV + alpha" } else { "YUV" });
+                }
+                b"VP8 " if content.len() >= 10 => {
+                    self.dimensions.get_or_insert((
+                        (u16::from_le_bytes([content[6], content[7]]) & 0x3fff) as u32,
+                        (u16::from_le_bytes([content[8], content[9]]) & 0x3fff) as u32,
+                    ));
+                    self.color.get_or_insert("YUV");
+                }
+                b"VP8L" if content.len() >= 5 => {
+                    let bits = u32::from_le_bytes([content[1], content[2], content[3], content[4]]);
+                    self.dimensions.get_or_insert(((bits & 0x3fff) + 1, ((bits >> 14) & 0x3fff) + 1));
+                    self.color = Some("RGBA");
+                }
+                b"ICCP" => self.icc_profile = Some(icc_description(content)),
+                b"EXIF" => self.exif = exif_tags(content.strip_prefix(b"Exif\0\0").unwrap_or(content)),
+                _ => (),
+            }
+            // Chunks are padded to an even length.
+            rest = rest.get(8 + length + length % 2..).unwrap_or_default();
+        }
+    }
+}
+
+
+/// Returns the lines describing the metadata of the source and of the
+/// processed file, e.g. `dimensions: 4000x3000 -> 2000x1500` when they
+/// differ, or `bit depth: 8` when they don't.
+pub fn diff(source: &FileMetadata, processed: &FileMetadata) -> Vec<String> {
+    let show = |value: Option<String>| value.unwrap_or_else(|| "-".to_string());
+    let fields = [
+        ("format", source.format.map(str::to_string), processed.format.map(str::to_string)),
+        (
+            "dimensions",
+            source.dimensions.map(|(w, h)| format!("{w}x{h}")),
+            processed.dimensions.map(|(w, h)| format!("{w}x{h}")),
+        ),
+        ("color", source.color.map(str::to_string), processed.color.map(str::to_string)),
+        ("bit depth", source.bit_depth.map(|d| d.to_string()), processed.bit_depth.map(|d| d.to_string())),
+        ("ICC profile", source.icc_profile.clone(), processed.icc_profile.clone()),
+    ];
+
+    let mut lines = fields
+        .into_iter()
+        .map(|(name, a, b)| match a == b {
+            true => format!("{name}: {}", show(a)),
+            false => format!("{name}: {} -> {}", show(a), show(b)),
+        })
+        .collect::<Vec<String>>();
+
+    let removed = source.exif.iter().filter(|t| !processed.exif.contains(t)).cloned().collect::<Vec<String>>();
+    let added = processed.exif.iter().filter(|t| !source.exif.contains(t)).cloned().collect::<Vec<String>>();
+    let kept = source.exif.len() - removed.len();
+    lines.push(format!("EXIF: {kept} tags kept"));
+    if !removed.is_empty() {
+        lines.push(format!("EXIF removed: {}", removed.join(", ")));
+    }
+    if !added.is_empty() {
+        lines.push(format!("EXIF added: {}", added.join(", ")));
+    }
+
+    lines
+}
+
+
+/// Returns the names of the tags of the EXIF data (a TIFF structure), in the
+/// first directory and in the EXIF sub-directory.
+fn exif_tags(tiff: &[u8]) -> Vec<String> {
+    let big_endian = match tiff.get(..2) {
+        Some(b"MM") => true,
+        Some(b"II") => false,
+        _ => return Vec::new(),
+    };
+    let u16_at = |offset: usize| {
+        let bytes = [*tiff.get(offset)?, *tiff.get(offset + 1)?];
+        Some(match big_endian {
+            true => u16::from_be_bytes(bytes),
+            false => u16::from_le_bytes(bytes),
+        })
+    };
+    let u32_at = |offset: usize| {
+        let bytes = tiff.get(offset..offset + 4)?.try_into().ok()?;
+        Some(match big_endian {
+            true => u32::from_be_bytes(bytes),
+            false => u32::from_le_bytes(bytes),
+        })
+    };
+
+    let mut tags = Vec::new();
+    let mut directories = vec![u32_at(4).unwrap_or(0) as usize];
+    while let Some(directory) = directories.pop() {
+        let count = u16_at(directory).unwrap_or(0) as usize;
+        for entry in (0..count).map(|n| directory + 2 + n * 12) {
+            let tag = match u16_at(entry) {
+                Some(tag) => tag,
+                None => break,
+            };
+            // A corrupt pointer to an already parsed directory is ignored.
+            match (tag, u32_at(entry + 8)) {
+                (EXIF_IFD_POINTER, Some(offset)) if offset as usize > directory => directories.push(offset as usize),
+                (EXIF_IFD_POINTER, _) => (),
+                _ => tags.push(exif_tag_name(tag)),
+            }
+        }
+    }
+
+    tags
+}
+
+fn exif_tag_name(tag: u16) -> String {
+    match EXIF_TAGS.iter().find(|(t, _)| *t == tag) {
+        Some((_, name)) => name.to_string(),
+        None => format!("0x{tag:04x}"),
+    }
+}
+
+/// Returns the description of an ICC profile, from its `desc` tag.
+fn icc_description(profile: &[u8]) -> String {
+    let count = profile.get(128..132).map_or(0, be32) as usize;
+    let desc = (0..count)
+        .map(|n| 132 + n * 12)
+        .filter_map(|entry| profile.get(entry..entry + 12))
+        .find(|entry| &entry[..4] == b"desc")
+        .and_then(|entry| profile.get(be32(&entry[4..]) as usize..));
+
+    let description = desc.and_then(|tag| match tag.get(..4)? {
+        // ICC v2: an ASCII string.
+        b"desc" => {
+            let length = be32(tag.get(8..12)?) as usize;
+            let text = tag.get(12..12 + length)?;
+            Some(String::from_utf8_lossy(text).trim_end_matches('\0').to_string())
+        }
+        // ICC v4: the first record of a multi-localized UTF-16 string.
+        b"mluc" => {
+            let record = tag.get(16..28)?;
+            let (length, offset) = (be32(&record[4..]) as usize, be32(&record[8..]) as usize);
+            let text = tag.get(offset..offset + length)?;
+            let units = text.chunks_exact(2).map(|c| u16::from_be_bytes([c[0], c[1]])).collect::<Vec<u16>>();
+            Some(String::from_utf16_lossy(&units))
+        }
+        _ => None,
+    });
+
+    description.filter(|d| !d.is_empty()).unwrap_or_else(|| "unnamed".to_string())
+}
+
+fn be16(bytes: &[u8]) -> u16 {
+    u16::from_be_bytes([bytes[0], bytes[1]])
+}
+
+fn be32(bytes: &[u8]) -> u32 {
+    u32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+}
+
+fn le24(bytes: &[u8]) -> u32 {
+    u32::from_le_bytes([bytes[0], bytes[1], bytes[2], 0])
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Returns little-endian EXIF data with the provided tags in the first
+    /// directory.
+    fn exif(tags: &[u16]) -> Vec<u8> {
+        let mut data = b"II*\0\x08\0\0\0".to_vec();
+        data.extend_from_slice(&(tags.len() as u16).to_le_bytes());
+        for tag in tags {
+            data.extend_from_slice(&tag.to_le_bytes());
+            data.extend_from_slice(&[0; 10]);
+        }
+        data.extend_from_slice(&[0; 4]);
+        data
+    }
+
+    fn png_chunk(kind: &[u8; 4], content: &[u8]) -> Vec<u8> {
+        let mut chunk = (content.len() as u32).to_be_bytes().to_vec();
+        chunk.extend_from_slice(kind);
+        chunk.extend_from_slice(content);
+        chunk.extend_from_slice(&[0; 4]);
+        chunk
+    }
+
+    #[test]
+    fn parse_png_header() {
+        let mut data = b"\x89PNG\r\n\x1a\n".to_vec();
+        data.extend(png_chunk(b"IHDR", &[0, 0, 0, 64, 0, 0, 0, 32, 16, 6, 0, 0, 0]));
+        data.extend(png_chunk(b"iCCP", b"Display P3\0\0compressed"));
+        data.extend(png_chunk(b"eXIf", &exif(&[0x010f, 0x8825])));
+        data.extend(png_chunk(b"IEND", b""));
+
+        let metadata = FileMetadata::parse(&data);
+        assert_eq!(metadata.format, Some("PNG"));
+        assert_eq!(metadata.dimensions, Some((64, 32)));
+        assert_eq!(metadata.color, Some("RGBA"));
+        assert_eq!(metadata.bit_depth, Some(16));
+        assert_eq!(metadata.icc_profile.as_deref(), Some("Display P3"));
+        assert_eq!(metadata.exif, ["Make", "GPS"]);
+    }
+
+    #[test]
+    fn parse_jpeg_header() {
+        let mut data = b"\xff\xd8".to_vec();
+        let app1 = [b"Exif\0\0".as_slice(), &exif(&[0x0112, 0x1234])].concat();
+        data.extend_from_slice(&[0xff, 0xe1]);
+        data.extend_from_slice(&(app1.len() as u16 + 2).to_be_bytes());
+        data.extend(app1);
+        data.extend_from_slice(&[0xff, 0xc0, 0, 11, 8, 0, 20, 0, 30, 3, 0, 0, 0]);
+        data.extend_from_slice(&[0xff, 0xda, 0, 2]);
+
+        let metadata = FileMetadata::parse(&data);
+        assert_eq!(metadata.dimensions, Some((30, 20)));
+        assert_eq!(metadata.color, Some("YCbCr"));
+        assert_eq!(metadata.bit_depth, Some(8));
+        assert_eq!(metadata.exif, ["Orientation", "0x1234"]);
+        assert_eq!(metadata.icc_profile, None);
+    }
+
+    #[test]
+    fn unknown_formats_have_no_metadata() {
+        assert_eq!(FileMetadata::parse(b"not an image"), FileMetadata::default());
+    }
+
+    #[test]
+    fn diff_shows_changes() {
+        let source = FileMetadata {
+            format: Some("JPEG"),
+            dimensions: Some((4000, 3000)),
+            bit_depth: Some(8),
+            exif: vec!["Make".to_string(), "GPS".to_string()],
+            icc_profile: Some("sRGB".to_string()),
+            ..FileMetadata::default()
+        };
+        let processed = FileMetadata {
+            format: Some("WebP"),
+            dimensions: Some((4000, 3000)),
+            bit_depth: Some(8),
+            exif: vec!["Make".to_string(), "Software".to_string()],
+            ..FileMetadata::default()
+        };
+
+        assert_eq!(diff(&source, &processed), [
+            "format: JPEG -> WebP",
+            "dimensions: 4000x3000",
+            "color: -",
+            "bit depth: 8",
+            "ICC profile: sRGB -> -",
+            "EXIF: 1 tags kept",
+            "EXIF removed: GPS",
+            "EXIF added: Software",
+        ]);
+    }
+}
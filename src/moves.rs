@@ -0,0 +1,115 @@
+//! Thread moving the files of the decisions.
+//!
+//! Validating an image, moving it to a folder or undoing either moves files,
+//! possibly across file systems or over a slow network mount, which would
+//! freeze the window if done between two frames. The operations are sent to a
+//! single thread instead, which applies them in order on a copy of the image,
+//! and sends the copy back with the outcome. An image has a single operation
+//! in flight at a time.
+
+use std::collections::HashSet;
+use std::sync::mpsc;
+use std::thread;
+
+use crate::img::{FileOperation, ImgItem};
+use crate::settings::AppSettings;
+use crate::utils::catch_panic;
+
+
+/// An operation on the files of image i. Rules is true if the rules took the
+/// decision, rather than the user.
+#[derive(Clone, Debug, PartialEq)]
+pub struct MoveRequest {
+    pub i: usize,
+    pub operation: FileOperation,
+    pub rules: bool,
+}
+
+/// The outcome of a request: the copy of the image the operation was applied
+/// to, and the error if it failed.
+pub struct MoveResult {
+    pub request: MoveRequest,
+    pub img: ImgItem,
+    pub result: Result<(), String>,
+}
+
+/// Handle of the thread moving the files.
+pub struct MoveWorker {
+    tx: mpsc::Sender<(MoveRequest, ImgItem)>,
+    rx: mpsc::Receiver<MoveResult>,
+
+    /// Images whose operation is not done yet.
+    pending: HashSet<usize>,
+}
+
+impl MoveWorker {
+    /// Starts the thread, which moves the files according to the settings
+    /// (trash directory, rename pattern, keep source).
+    pub fn new(settings: AppSettings) -> MoveWorker {
+        let (tx, requests) = mpsc::channel::<(MoveRequest, ImgItem)>();
+        let (results, rx) = mpsc::channel();
+
+        thread::spawn(move || {
+            for (request, mut img) in requests {
+                let result = catch_panic(|| img.apply(&request.operation, &settings))
+                    .unwrap_or_else(|e| Err(format!("Moving the files panicked: {e}")));
+
+                // The receiver is only dropped when the application quits.
+                if results.send(MoveResult { request, img, result }).is_err() {
+                    return;
+                }
+            }
+        });
+
+        MoveWorker { tx, rx, pending: HashSet::new() }
+    }
+
+    /// Sends an operation on a copy of the image. Fails if an operation on the
+    /// image is still in flight.
+    pub fn send(&mut self, request: MoveRequest, img: &ImgItem) -> Result<(), String> {
+        if self.pending.contains(&request.i) {
+            return Err(format!("The files of {} are still being moved", img.source.display()));
+        }
+
+        self.pending.insert(request.i);
+        self.tx
+            .send((request, img.clone()))
+            .map_err(|_| "The thread moving the files stopped".to_string())
+    }
+
+    /// Returns true if an operation on image i is in flight.
+    pub fn is_pending(&self, i: usize) -> bool {
+        self.pending.contains(&i)
+    }
+
+    /// Returns the number of operations in flight.
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    /// Returns the results received since the last call, without waiting.
+    pub fn results(&mut self) -> Vec<MoveResult> {
+        let results = self.rx.try_iter().collect::<Vec<MoveResult>>();
+        for result in &results {
+            self.pending.remove(&result.request.i);
+        }
+
+        results
+    }
+
+    /// Waits for every operation in flight, and returns their results.
+    pub fn finish(&mut self) -> Vec<MoveResult> {
+        let mut results = Vec::new();
+        while !self.pending.is_empty() {
+            match self.rx.recv() {
+                Ok(result) => {
+                    self.pending.remove(&result.request.i);
+                    results.push(result);
+                }
+                Err(_) => break,
+            }
+        }
+
+        results
+    }
+}
@@ -0,0 +1,106 @@
+//! Command palette, a popup listing every action with a fuzzy search.
+//!
+//! The palette makes the actions discoverable without reading the key
+//! bindings, and reachable when they have no key. The list of actions itself
+//! lives in the application, this module only handles the query and the
+//! matching.
+
+
+/// Maximum number of matching actions displayed.
+pub const MAX_RESULTS: usize = 15;
+
+/// State of the open palette.
+#[derive(Default)]
+pub struct Palette {
+    pub query: String,
+
+    /// Index of the selected action among the matching ones.
+    pub selected: usize,
+}
+
+impl Palette {
+    /// Returns the indices of the names matching the query, best first.
+    pub fn matches<'n>(&self, names: impl IntoIterator<Item = &'n str>) -> Vec<usize> {
+        search(&self.query, names)
+    }
+
+    /// Appends typed text to the query, and selects the best match again.
+    pub fn push_str(&mut self, text: &str) {
+        self.query.push_str(text);
+        self.selected = 0;
+    }
+
+    /// Removes the last character of the query.
+    pub fn pop(&mut self) {
+        self.query.pop();
+        self.selected = 0;
+    }
+}
+
+
+/// Returns the score of the name for the query, or None if the characters of
+/// the query don't all appear in the name in the same order. The comparison
+/// ignores case and the spaces of the query.
+///
+/// Lower is better: the score is the number of characters skipped between the
+/// first and last matched characters, then the number skipped before the
+/// first one. Contiguous matches thus come first, and earlier ones break ties.
+pub fn fuzzy_score(query: &str, name: &str) -> Option<(usize, usize)> {
+    let mut chars = name.chars().flat_map(char::to_lowercase);
+    let mut gaps = 0;
+    let mut leading = 0;
+    let mut started = false;
+
+    for q in query.chars().filter(|c| !c.is_whitespace()).flat_map(char::to_lowercase) {
+        loop {
+            let c = chars.next()?;
+            if c == q {
+                break;
+            }
+            match started {
+                true => gaps += 1,
+                false => leading += 1,
+            }
+        }
+        started = true;
+    }
+
+    Some((gaps, leading))
+}
+
+/// Returns the indices of the names matching the query, best first. Names with
+/// the same score keep their order. An empty query matches every name.
+pub fn search<'n>(query: &str, names: impl IntoIterator<Item = &'n str>) -> Vec<usize> {
+    let mut scored = names
+        .into_iter()
+        .enumerate()
+        .filter_map(|(k, name)| Some((fuzzy_score(query, name)?, k)))
+        .collect::<Vec<_>>();
+    scored.sort_by_key(|&(score, _)| score);
+
+    scored.into_iter().map(|(_, k)| k).collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn fuzzy_score_subsequence() {
+        assert_eq!(fuzzy_score("", "Zoom in"), Some((0, 0)));
+        assert_eq!(fuzzy_score("zoom", "Zoom in"), Some((0, 0)));
+        assert_eq!(fuzzy_score("zi", "Zoom in"), Some((4, 0)));
+        assert_eq!(fuzzy_score("zoom", "Reset zoom"), Some((0, 6)));
+        assert_eq!(fuzzy_score("ni", "Zoom in"), None);
+    }
+
+    #[test]
+    fn search_orders_by_score() {
+        let names = ["Previous image", "Next image", "Next command", "Mirror"];
+        assert_eq!(search("next", names), vec![1, 2]);
+        assert_eq!(search("nxim", names), vec![1]);
+        assert_eq!(search("im", names), vec![1, 0]);
+        assert_eq!(search("", names), vec![0, 1, 2, 3]);
+    }
+}
@@ -0,0 +1,147 @@
+//! Default locations of the files of bimgo, following the XDG base directory
+//! specification.
+//!
+//! The configuration (`bimgo.toml`, commands file) is in `$XDG_CONFIG_HOME`,
+//! the data kept across sessions (fonts, trash, statistics) in
+//! `$XDG_DATA_HOME`, and the session file in `$XDG_STATE_HOME`, each in a
+//! `bimgo` directory. Files found at the locations used by older versions are
+//! moved to these locations when starting.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::progress::diagnostic;
+
+
+/// Returns the bimgo directory in the base directory, or in the fallback
+/// relative to home (e.g. "~/.config") if the base directory is unknown.
+fn bimgo_dir(base: Option<PathBuf>, fallback: &str) -> PathBuf {
+    base.unwrap_or_else(|| PathBuf::from(fallback)).join("bimgo")
+}
+
+/// Returns the directory of the configuration, `$XDG_CONFIG_HOME/bimgo`.
+pub fn config_dir() -> PathBuf {
+    bimgo_dir(dirs::config_dir(), "~/.config")
+}
+
+/// Returns the directory of the data, `$XDG_DATA_HOME/bimgo`.
+pub fn data_dir() -> PathBuf {
+    bimgo_dir(dirs::data_dir(), "~/.local/share")
+}
+
+/// Returns the directory of the state, `$XDG_STATE_HOME/bimgo`.
+pub fn state_dir() -> PathBuf {
+    bimgo_dir(dirs::state_dir(), "~/.local/state")
+}
+
+/// Returns the location of the configuration file.
+pub fn config_file() -> PathBuf {
+    config_dir().join("bimgo.toml")
+}
+
+/// Returns the location of the font of the interface.
+pub fn font_file() -> PathBuf {
+    data_dir().join("fonts").join("FiraMono-Medium.ttf")
+}
+
+/// Returns the location of the trash used by older versions, if there is a
+/// trash there and none at the current location.
+///
+/// The trash is not moved, since its manifest records the location of the
+/// trashed files.
+pub fn legacy_trash_directory() -> Option<PathBuf> {
+    let home = dirs::home_dir()?;
+    let legacy = home.join(".local/share/bimgo/trash");
+    let current = data_dir().join("trash");
+
+    (legacy != current && legacy.is_dir() && !current.exists()).then_some(legacy)
+}
+
+/// Returns the files and directories used by older versions, with their
+/// current location.
+fn legacy_locations(home: &Path) -> Vec<(PathBuf, PathBuf)> {
+    let old_config = home.join(".config/bimgo");
+    let old_data = home.join(".local/share/bimgo");
+
+    vec![
+        (old_config.join("bimgo.toml"), config_file()),
+        (old_config.join("cmds"), config_dir().join("cmds")),
+        (home.join("bimgo/fonts"), data_dir().join("fonts")),
+        (old_data.join("session"), state_dir().join("session")),
+        (old_data.join("stats"), data_dir().join("stats")),
+        (old_data.join("blind"), data_dir().join("blind")),
+    ]
+}
+
+/// Moves every file from its old location to its new location, unless the old
+/// location doesn't exist or the new one already exists. Returns the
+/// description of the moves, and of the failures.
+fn migrate(locations: &[(PathBuf, PathBuf)]) -> Vec<String> {
+    let mut messages = Vec::new();
+
+    for (old, new) in locations {
+        if old == new || !old.exists() || new.exists() {
+            continue;
+        }
+
+        let result = match new.parent() {
+            Some(parent) => fs::create_dir_all(parent).and_then(|_| fs::rename(old, new)),
+            None => fs::rename(old, new),
+        };
+        messages.push(match result {
+            Ok(()) => format!("Moved {} to {}", old.display(), new.display()),
+            Err(e) => format!("Error: unable to move {} to {}: {e}", old.display(), new.display()),
+        });
+    }
+
+    messages
+}
+
+/// Moves the files found at the locations used by older versions to their
+/// current location.
+pub fn migrate_legacy_files() {
+    if let Some(home) = dirs::home_dir() {
+        for message in migrate(&legacy_locations(&home)) {
+            diagnostic!("{message}");
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn migrate_moves_missing_files_only() {
+        let dir = std::env::temp_dir().join(format!("bimgo_paths_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(dir.join("old")).unwrap();
+        fs::write(dir.join("old/a"), "a").unwrap();
+        fs::write(dir.join("old/b"), "old b").unwrap();
+        fs::create_dir_all(dir.join("new")).unwrap();
+        fs::write(dir.join("new/b"), "new b").unwrap();
+
+        let locations = [
+            (dir.join("old/a"), dir.join("new/sub/a")),
+            (dir.join("old/b"), dir.join("new/b")),
+            (dir.join("old/c"), dir.join("new/c")),
+        ];
+        let messages = migrate(&locations);
+
+        assert_eq!(messages.len(), 1);
+        assert_eq!(fs::read_to_string(dir.join("new/sub/a")).unwrap(), "a");
+        assert!(!dir.join("old/a").exists());
+        assert_eq!(fs::read_to_string(dir.join("new/b")).unwrap(), "new b");
+        assert!(dir.join("old/b").exists());
+        assert!(!dir.join("new/c").exists());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn locations_are_in_bimgo_directories() {
+        assert!(config_file().ends_with("bimgo/bimgo.toml"));
+        assert!(font_file().ends_with("bimgo/fonts/FiraMono-Medium.ttf"));
+    }
+}
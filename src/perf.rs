@@ -0,0 +1,159 @@
+//! Performance counters, to guide optimization work.
+//!
+//! The counters measure the time taken to draw a frame, to upload images to
+//! textures, and to handle an input event after it was sent. They are shown
+//! by the performance overlay, and can be written to a CSV log with one line
+//! per frame drawn.
+
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::progress::diagnostic;
+
+/// Weight of the last sample in the smoothed value of a counter.
+const SMOOTHING: f64 = 0.1;
+
+/// Header of the CSV log.
+const LOG_HEADER: &str = "time_ms,frame_us,upload_us,queued,running,event_latency_ms";
+
+
+/// A measured duration: the last sample, a smoothed average, and the maximum.
+#[derive(Clone, Copy, Default)]
+pub struct Counter {
+    pub last: Duration,
+    pub smoothed: Duration,
+    pub max: Duration,
+}
+
+impl Counter {
+    pub fn record(&mut self, sample: Duration) {
+        self.smoothed = match self.max.is_zero() {
+            true => sample,
+            false => self.smoothed.mul_f64(1. - SMOOTHING) + sample.mul_f64(SMOOTHING),
+        };
+        self.last = sample;
+        self.max = self.max.max(sample);
+    }
+}
+
+
+/// Performance counters of the application.
+pub struct Perf {
+    pub frame: Counter,
+    pub upload: Counter,
+    pub event_latency: Counter,
+
+    /// Number of computations queued and running in the compute pool, when
+    /// the last frame was drawn.
+    pub queue: (usize, usize),
+    start: Instant,
+    log: Option<BufWriter<File>>,
+}
+
+impl Default for Perf {
+    fn default() -> Self {
+        Perf {
+            frame: Counter::default(),
+            upload: Counter::default(),
+            event_latency: Counter::default(),
+            queue: (0, 0),
+            start: Instant::now(),
+            log: None,
+        }
+    }
+}
+
+impl Perf {
+    /// Starts writing the counters to a CSV file at provided path.
+    pub fn open_log(&mut self, path: &Path) -> Result<(), String> {
+        let mut log = File::create(path)
+            .map(BufWriter::new)
+            .map_err(|e| format!("Unable to create {}: {e}", path.display()))?;
+        writeln!(log, "{LOG_HEADER}").map_err(|e| format!("Unable to write {}: {e}", path.display()))?;
+        self.log = Some(log);
+
+        Ok(())
+    }
+
+    /// Records the time taken to draw a frame, and writes the counters to the
+    /// log. The log is dropped if it can't be written anymore.
+    pub fn record_frame(&mut self, duration: Duration, queue: (usize, usize)) {
+        self.frame.record(duration);
+        self.queue = queue;
+
+        let line = self.log_line();
+        if let Some(log) = &mut self.log {
+            if let Err(e) = writeln!(log, "{line}") {
+                diagnostic!("Error: unable to write the performance log: {e}");
+                self.log = None;
+            }
+        }
+    }
+
+    /// Formats the counters as a line of the CSV log.
+    fn log_line(&self) -> String {
+        format!(
+            "{},{},{},{},{},{}",
+            self.start.elapsed().as_millis(),
+            self.frame.last.as_micros(),
+            self.upload.last.as_micros(),
+            self.queue.0,
+            self.queue.1,
+            self.event_latency.last.as_millis(),
+        )
+    }
+
+    /// Returns a summary of the counters, as shown by the overlay.
+    pub fn summary(&self) -> String {
+        let ms = |d: Duration| d.as_secs_f64() * 1000.;
+        format!(
+            "frame {:.1} ms (avg {:.1}, max {:.1})\nupload {:.1} ms (avg {:.1}, max {:.1})\n\
+             event latency {:.0} ms (max {:.0})\ncompute: {} queued, {} running",
+            ms(self.frame.last),
+            ms(self.frame.smoothed),
+            ms(self.frame.max),
+            ms(self.upload.last),
+            ms(self.upload.smoothed),
+            ms(self.upload.max),
+            ms(self.event_latency.last),
+            ms(self.event_latency.max),
+            self.queue.0,
+            self.queue.1,
+        )
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counter_smooths_samples() {
+        let mut c = Counter::default();
+        c.record(Duration::from_millis(10));
+        assert_eq!(c.smoothed, Duration::from_millis(10));
+
+        c.record(Duration::from_millis(20));
+        assert_eq!(c.last, Duration::from_millis(20));
+        assert_eq!(c.smoothed, Duration::from_millis(11));
+        assert_eq!(c.max, Duration::from_millis(20));
+
+        c.record(Duration::from_millis(5));
+        assert_eq!(c.max, Duration::from_millis(20));
+    }
+
+    #[test]
+    fn log_line_matches_header() {
+        let mut perf = Perf::default();
+        perf.upload.record(Duration::from_micros(1500));
+        perf.record_frame(Duration::from_micros(4200), (3, 2));
+
+        let line = perf.log_line();
+        let fields = line.split(',').collect::<Vec<&str>>();
+        assert_eq!(fields.len(), LOG_HEADER.split(',').count());
+        assert_eq!(&fields[1..], &["4200", "1500", "3", "2", "0"]);
+    }
+}
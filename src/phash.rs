@@ -0,0 +1,103 @@
+//! Perceptual hashing of images, used to detect images of the input list that
+//! are visually identical.
+//!
+//! The hash used is a difference hash (dHash): the image is reduced to a 9x8
+//! grayscale grid, and each bit of the hash tells whether a cell is brighter
+//! than its right neighbour. Visually identical images (e.g. the same picture
+//! encoded twice) yield hashes with a small hamming distance.
+
+use std::path::Path;
+
+use sdl2::image::LoadSurface;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::surface::Surface;
+
+const GRID_W: usize = 9;
+const GRID_H: usize = 8;
+
+
+/// Returns the number of differing bits between two hashes.
+pub fn hamming_distance(a: u64, b: u64) -> u32 {
+    (a ^ b).count_ones()
+}
+
+
+/// Computes the difference hash from the luminance grid.
+fn dhash_from_grid(grid: &[u64; GRID_W * GRID_H]) -> u64 {
+    let mut hash = 0;
+    for y in 0..GRID_H {
+        for x in 0..GRID_W - 1 {
+            hash <<= 1;
+            if grid[y * GRID_W + x] > grid[y * GRID_W + x + 1] {
+                hash |= 1;
+            }
+        }
+    }
+
+    hash
+}
+
+
+/// Computes the difference hash of the image at provided path.
+///
+/// The image is reduced to the grid by averaging the luminance of every pixel
+/// of a cell, which is slower than a scaled blit but robust to aliasing.
+pub fn dhash(path: &Path) -> Result<u64, String> {
+    let surface = Surface::from_file(path)?.convert_format(PixelFormatEnum::RGB24)?;
+    let (w, h) = (surface.width() as usize, surface.height() as usize);
+    let pitch = surface.pitch() as usize;
+
+    if w == 0 || h == 0 {
+        return Err(format!("{} is empty", path.display()));
+    }
+
+    let mut sums = [0u64; GRID_W * GRID_H];
+    let mut counts = [0u64; GRID_W * GRID_H];
+
+    surface.with_lock(|pixels| {
+        for y in 0..h {
+            let row = &pixels[y * pitch..y * pitch + w * 3];
+            let cell_y = y * GRID_H / h;
+            for (x, px) in row.chunks_exact(3).enumerate() {
+                let cell = cell_y * GRID_W + x * GRID_W / w;
+                // Integer approximation of the Rec. 601 luma.
+                sums[cell] += (299 * px[0] as u64 + 587 * px[1] as u64 + 114 * px[2] as u64) / 1000;
+                counts[cell] += 1;
+            }
+        }
+    });
+
+    let mut grid = [0u64; GRID_W * GRID_H];
+    for (g, (s, c)) in grid.iter_mut().zip(sums.iter().zip(counts.iter())) {
+        *g = if *c > 0 { s * 16 / c } else { 0 };
+    }
+
+    Ok(dhash_from_grid(&grid))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn dhash_flat_image() {
+        let grid = [10; GRID_W * GRID_H];
+        assert_eq!(dhash_from_grid(&grid), 0);
+    }
+
+    #[test]
+    fn dhash_decreasing_rows() {
+        let mut grid = [0; GRID_W * GRID_H];
+        for (i, g) in grid.iter_mut().enumerate() {
+            *g = (GRID_W - i % GRID_W) as u64;
+        }
+        assert_eq!(dhash_from_grid(&grid), u64::MAX);
+    }
+
+    #[test]
+    fn hamming_distance_counts_bits() {
+        assert_eq!(hamming_distance(0b1011, 0b0001), 2);
+        assert_eq!(hamming_distance(u64::MAX, u64::MAX), 0);
+    }
+}
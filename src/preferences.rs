@@ -0,0 +1,124 @@
+//! Tracking of the commands preferred by the user.
+//!
+//! Every time a variant is validated, its command index is counted for the
+//! directory and for the extension of the source. New images can then start
+//! from the command validated most for similar images, which reduces the
+//! number of key presses in homogeneous folders.
+
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::path::{Path, PathBuf};
+
+
+/// Counts of validated commands per source directory and per source extension.
+#[derive(Default)]
+pub struct CmdPreferences {
+    by_dir: HashMap<PathBuf, Vec<usize>>,
+    by_ext: HashMap<OsString, Vec<usize>>,
+}
+
+/// Returns the index with the highest count, the lowest index wins ties.
+/// Returns None if every count is 0.
+fn most_counted(counts: &[usize]) -> Option<usize> {
+    counts
+        .iter()
+        .enumerate()
+        .filter(|(_, &n)| n > 0)
+        .max_by(|(i, a), (j, b)| a.cmp(b).then(j.cmp(i)))
+        .map(|(i, _)| i)
+}
+
+fn increment(counts: &mut Vec<usize>, c: usize) {
+    if counts.len() <= c {
+        counts.resize(c + 1, 0);
+    }
+    counts[c] += 1;
+}
+
+fn decrement(counts: Option<&mut Vec<usize>>, c: usize) {
+    if let Some(n) = counts.and_then(|counts| counts.get_mut(c)) {
+        *n = n.saturating_sub(1);
+    }
+}
+
+impl CmdPreferences {
+    /// Records that command c was validated for the provided source.
+    pub fn record(&mut self, source: &Path, c: usize) {
+        if let Some(dir) = source.parent() {
+            increment(self.by_dir.entry(dir.to_path_buf()).or_default(), c);
+        }
+
+        if let Some(ext) = source.extension() {
+            increment(self.by_ext.entry(ext.to_os_string()).or_default(), c);
+        }
+    }
+
+    /// Reverts a previous record, i.e. when a validation is undone.
+    pub fn forget(&mut self, source: &Path, c: usize) {
+        if let Some(dir) = source.parent() {
+            decrement(self.by_dir.get_mut(dir), c);
+        }
+
+        if let Some(ext) = source.extension() {
+            decrement(self.by_ext.get_mut(ext), c);
+        }
+    }
+
+    /// Returns the command validated most for images in the same directory as
+    /// the source, or if there is none, for images with the same extension.
+    pub fn preferred(&self, source: &Path) -> Option<usize> {
+        let by_dir = source
+            .parent()
+            .and_then(|dir| self.by_dir.get(dir))
+            .and_then(|counts| most_counted(counts));
+
+        by_dir.or_else(|| {
+            source
+                .extension()
+                .and_then(|ext| self.by_ext.get(ext))
+                .and_then(|counts| most_counted(counts))
+        })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn preferred_by_directory_first() {
+        let mut prefs = CmdPreferences::default();
+        prefs.record(Path::new("/a/1.jpg"), 2);
+        prefs.record(Path::new("/a/2.jpg"), 2);
+        prefs.record(Path::new("/b/3.jpg"), 1);
+
+        assert_eq!(prefs.preferred(Path::new("/a/4.jpg")), Some(2));
+        assert_eq!(prefs.preferred(Path::new("/b/4.jpg")), Some(1));
+    }
+
+    #[test]
+    fn preferred_by_extension_fallback() {
+        let mut prefs = CmdPreferences::default();
+        prefs.record(Path::new("/a/1.png"), 3);
+
+        assert_eq!(prefs.preferred(Path::new("/c/1.png")), Some(3));
+        assert_eq!(prefs.preferred(Path::new("/c/1.jpg")), None);
+    }
+
+    #[test]
+    fn forget_reverts_record() {
+        let mut prefs = CmdPreferences::default();
+        prefs.record(Path::new("/a/1.jpg"), 1);
+        prefs.record(Path::new("/a/2.jpg"), 0);
+        prefs.forget(Path::new("/a/1.jpg"), 1);
+
+        assert_eq!(prefs.preferred(Path::new("/a/3.jpg")), Some(0));
+    }
+
+    #[test]
+    fn ties_favor_lowest_index() {
+        assert_eq!(most_counted(&[0, 2, 2]), Some(1));
+        assert_eq!(most_counted(&[0, 0]), None);
+    }
+}
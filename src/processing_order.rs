@@ -14,32 +14,135 @@ fn u_distance(a: usize, b: usize) -> usize {
 }
 
 
+/// Returns the distance between positions a and b on a 2D array, as used by
+/// Closest2D.
+pub fn distance_2d(a: (usize, usize), b: (usize, usize)) -> usize {
+    u_distance(a.0, b.0) + u_distance(a.1, b.1)
+}
+
+
 /// Iterator generator on a 2D array.
 ///
 /// Given a i_pos, i_min, i_max, j_pos j_min, j_max, produces an iterator which 
-/// will yield the elements closest to (i, j) first. The exact order of which
-/// element will be given first is unclear because it uses a sort algorithm.
+/// will yield the elements closest to (i, j) first, i.e. the ones reached with
+/// the fewest keystrokes. A row is made of the elements with the same i (an
+/// image), its columns are the j (the commands).
+///
+/// Elements at the same distance come in a fixed order, which draws a form of
+/// spiral around (i, j):
+/// - elements in line with (i, j) come before diagonal ones,
+/// - then the elements closest to the row of (i, j),
+/// - then the elements after (i, j) before the ones before it, first along i,
+///   then along j.
+///
+/// With a forward bias (see with_forward_bias), a step towards lower i counts
+/// as several steps, so the elements after (i, j) come sooner.
+///
+/// The elements are generated one ring of equal distance at a time, without
+/// allocating, so the iterator can be kept and advanced as work is scheduled.
+#[derive(Clone, Copy, Debug, PartialEq)]
 pub struct Closest2D {
-    elements: Vec<(usize, usize, usize)>,
+    i: usize,
+    i_min: usize,
+    i_max: usize,
+    j: usize,
+    j_min: usize,
+    j_max: usize,
+
+    /// Distance of a step towards lower i.
+    bias: usize,
+
+    /// Distance of the ring being generated.
+    distance: usize,
+
+    /// Position in the ring: the distance along i (see ring_row), and which
+    /// of the (up to) four elements at this distance along i.
+    step: usize,
+    sign: usize,
 }
 
 impl Closest2D {
     pub fn new(i: usize, i_min: usize, i_max: usize, j: usize, j_min: usize, j_max: usize) -> Closest2D {
-        
-        let mut elements: Vec<(usize, usize, usize)> = (i_min..=i_max)
-            .flat_map(|k| (j_min..=j_max)
-                 .map(move |l| (k, l, u_distance(i, k) + u_distance(j, l))))
-            .collect();
+        Self {
+            i,
+            i_min,
+            i_max,
+            j,
+            j_min,
+            j_max,
+            bias: 1,
+            distance: 0,
+            step: 0,
+            sign: 0,
+        }
+    }
+
+    /// Makes every step towards lower i count as bias steps, e.g. with a bias
+    /// of 2, (i - 1, j) comes with (i + 2, j). A bias of 0 is handled as 1.
+    pub fn with_forward_bias(mut self, bias: usize) -> Closest2D {
+        self.bias = bias.max(1);
+        self
+    }
 
-        elements.sort_unstable_by_key(|e| e.2);
+    /// Returns true if other iterates over the same array from the same
+    /// element with the same bias, whatever their progress.
+    pub fn same_grid(&self, other: &Closest2D) -> bool {
+        (self.i, self.i_min, self.i_max, self.j, self.j_min, self.j_max, self.bias)
+            == (other.i, other.i_min, other.i_max, other.j, other.j_min, other.j_max, other.bias)
+    }
 
-        let elements = elements
-            .into_iter()
-            .rev()
-            .collect();
+    /// Largest distance of an element of the array.
+    fn max_distance(&self) -> usize {
+        usize::max((self.i - self.i_min) * self.bias, self.i_max - self.i)
+            + usize::max(self.j - self.j_min, self.j_max - self.j)
+    }
 
-        Self {
-            elements,
+    /// Returns the distance along i of the elements at the step of the ring:
+    /// first the row itself, then the column, then the diagonal elements
+    /// closest to the row.
+    fn ring_row(&self) -> usize {
+        match self.step {
+            0 => 0,
+            1 => self.distance,
+            step => step - 1,
+        }
+    }
+
+    /// Returns the offset of the current element, or None if the ring has no
+    /// element for the current sign.
+    ///
+    /// The distance along i is weighted by the bias towards lower i, which
+    /// only has elements when it is a multiple of the bias.
+    fn offset(&self) -> Option<(isize, isize)> {
+        let d = self.distance as isize;
+        let a = self.ring_row() as isize;
+        let b = d - a;
+        let bias = self.bias as isize;
+
+        let behind = (a > 0 && a % bias == 0).then_some(-a / bias);
+        let signs: &[isize] = match b {
+            0 => &[1],
+            _ => &[1, -1],
+        };
+
+        std::iter::once(a)
+            .chain(behind)
+            .flat_map(|di| signs.iter().map(move |s| (di, s * b)))
+            .nth(self.sign)
+    }
+
+    /// Moves to the next element of the ring, or to the next ring.
+    fn advance(&mut self) {
+        self.sign += 1;
+        if self.offset().is_some() {
+            return;
+        }
+
+        self.sign = 0;
+        self.step += 1;
+        if self.step > self.distance {
+            self.step = 0;
+            self.distance += 1;
         }
     }
 }
@@ -47,11 +150,18 @@ impl Closest2D {
 impl Iterator for Closest2D {
     type Item = (usize, usize);
     fn next(&mut self) -> Option<Self::Item> {
-        if let Some((i, j, _d)) = self.elements.pop() {
-            Some((i, j))
-        } else {
-            None
+        while self.distance <= self.max_distance() {
+            let (di, dj) = self.offset()?;
+            self.advance();
+
+            let k = self.i.checked_add_signed(di).filter(|k| (self.i_min..=self.i_max).contains(k));
+            let l = self.j.checked_add_signed(dj).filter(|l| (self.j_min..=self.j_max).contains(l));
+            if let (Some(k), Some(l)) = (k, l) {
+                return Some((k, l));
+            }
         }
+
+        None
     }
 }
 
@@ -122,10 +232,148 @@ impl Iterator for VFirst2D {
 }
 
 
+/// Returns the order in which images should be reviewed to maximize the disk
+/// savings, given the bytes saved by every image.
+///
+/// Images with the largest savings come first. Images whose savings are not
+/// known yet (None) come last, in their original order.
+pub fn savings_order(savings: &[Option<i64>]) -> Vec<usize> {
+    let mut order: Vec<usize> = (0..savings.len()).collect();
+
+    // The sort is stable, so ties and unknown savings keep the original order.
+    order.sort_by_key(|&i| match savings[i] {
+        Some(s) => (0, -s),
+        None => (1, 0),
+    });
+
+    order
+}
+
+/// Review order by savings with a command, see savings_order, along with the
+/// position of every image in it.
+pub struct SavingsRanking {
+    pub cmd: usize,
+    pub order: Vec<usize>,
+    pub positions: Vec<usize>,
+}
+
+impl SavingsRanking {
+    pub fn new(cmd: usize, savings: &[Option<i64>]) -> SavingsRanking {
+        let order = savings_order(savings);
+        let mut positions = vec![0; order.len()];
+        for (pos, &i) in order.iter().enumerate() {
+            positions[i] = pos;
+        }
+
+        SavingsRanking { cmd, order, positions }
+    }
+}
+
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
+    #[test]
+    fn savings_order_largest_first() {
+        let savings = [Some(10), None, Some(300), Some(-5), None, Some(300)];
+        assert_eq!(savings_order(&savings), vec![2, 5, 0, 3, 1, 4]);
+        assert_eq!(SavingsRanking::new(0, &savings).positions, vec![2, 4, 0, 3, 5, 1]);
+    }
+
+    #[test]
+    fn closest2d_spiral_order() {
+        let v: Vec<(usize, usize)> = Closest2D::new(2, 0, 4, 2, 0, 4).take(13).collect();
+        let truth: Vec<(usize, usize)> = vec![
+            (2, 2),
+            (2, 3),
+            (2, 1),
+            (3, 2),
+            (1, 2),
+            (2, 4),
+            (2, 0),
+            (4, 2),
+            (0, 2),
+            (3, 3),
+            (3, 1),
+            (1, 3),
+            (1, 1),
+        ];
+
+        assert_eq!(v, truth);
+    }
+
+    #[test]
+    fn closest2d_clipped_to_bounds() {
+        let v: Vec<(usize, usize)> = Closest2D::new(0, 0, 2, 1, 0, 1).collect();
+        let truth: Vec<(usize, usize)> = vec![
+            (0, 1),
+            (0, 0),
+            (1, 1),
+            (2, 1),
+            (1, 0),
+            (2, 0),
+        ];
+
+        assert_eq!(v, truth);
+    }
+
+    #[test]
+    fn closest2d_is_deterministic() {
+        let a: Vec<(usize, usize)> = Closest2D::new(7, 2, 12, 3, 0, 6).collect();
+        let b: Vec<(usize, usize)> = Closest2D::new(7, 2, 12, 3, 0, 6).collect();
+        assert_eq!(a, b);
+        assert_eq!(a.len(), 11 * 7);
+    }
+
+    #[test]
+    fn closest2d_matches_sorted_grid() {
+        for (i, i_min, i_max, j, j_min, j_max) in [(7, 2, 12, 3, 0, 6), (0, 0, 0, 0, 0, 0), (5, 0, 5, 0, 0, 9)] {
+            let mut sorted: Vec<(usize, usize)> = (i_min..=i_max)
+                .flat_map(|k| (j_min..=j_max).map(move |l| (k, l)))
+                .collect();
+            sorted.sort_by_key(|&(k, l)| {
+                let diagonal = k != i && l != j;
+                (distance_2d((i, j), (k, l)), diagonal, u_distance(i, k), k < i, l < j)
+            });
+
+            let v: Vec<(usize, usize)> = Closest2D::new(i, i_min, i_max, j, j_min, j_max).collect();
+            assert_eq!(v, sorted);
+
+            // Same order, with a step backward counting as 3.
+            let weighted = |k: usize| if k < i { (i - k) * 3 } else { k - i };
+            sorted.sort_by_key(|&(k, l)| {
+                let diagonal = k != i && l != j;
+                (weighted(k) + u_distance(j, l), diagonal, weighted(k), k < i, l < j)
+            });
+
+            let v: Vec<(usize, usize)> = Closest2D::new(i, i_min, i_max, j, j_min, j_max)
+                .with_forward_bias(3)
+                .collect();
+            assert_eq!(v, sorted);
+        }
+    }
+
+    #[test]
+    fn closest2d_forward_bias() {
+        let v: Vec<(usize, usize)> = Closest2D::new(5, 0, 10, 0, 0, 0).with_forward_bias(2).collect();
+        let truth: Vec<(usize, usize)> = vec![
+            (5, 0),
+            (6, 0),
+            (7, 0),
+            (4, 0),
+            (8, 0),
+            (9, 0),
+            (3, 0),
+            (10, 0),
+            (2, 0),
+            (1, 0),
+            (0, 0),
+        ];
+
+        assert_eq!(v, truth);
+    }
+
     #[test]
     fn vfirst2d_case_1() {
         let v: Vec<(usize, usize)> = VFirst2D::new(0, 0, 3, 0, 0, 2).collect();
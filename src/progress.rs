@@ -0,0 +1,108 @@
+//! Machine-readable progress, printed on stdout with `--progress json`.
+//!
+//! Every event is a JSON object on its own line, so that a wrapper can monitor
+//! a long batch run:
+//!
+//! ```text
+//! {"event": "processed", "image": "/photos/a.jpg", "command": "cwebp %i -o %o.webp", "source_bytes": 3145728, "processed_bytes": 524288}
+//! {"event": "validated", "image": "/photos/a.jpg", "command": "cwebp %i -o %o.webp", "saved_bytes": 2621440}
+//! {"event": "failed", "image": "/photos/b.jpg", "command": "cwebp %i -o %o.webp", "reason": "exit status: 1"}
+//! ```
+//!
+//! A failure to move the files of a decision has a null command. Stdout is
+//! then reserved to the events: errors and the output of the commands are
+//! printed on stderr instead (see diagnostic!).
+
+use std::path::Path;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use crate::report::json_string;
+
+
+/// Whether stdout is reserved to the progress events.
+static STDOUT_RESERVED: AtomicBool = AtomicBool::new(false);
+
+/// Reserves stdout to the progress events, the diagnostics and the output of
+/// the commands are printed on stderr from then on.
+pub fn reserve_stdout() {
+    STDOUT_RESERVED.store(true, Ordering::Relaxed);
+}
+
+/// Returns whether stdout is reserved to the progress events.
+pub fn stdout_reserved() -> bool {
+    STDOUT_RESERVED.load(Ordering::Relaxed)
+}
+
+/// Prints a message for the user, like println!, but on stderr when stdout is
+/// reserved to the progress events.
+macro_rules! diagnostic {
+    ($($arg:tt)*) => {
+        match $crate::progress::stdout_reserved() {
+            true => eprintln!($($arg)*),
+            false => println!($($arg)*),
+        }
+    };
+}
+pub(crate) use diagnostic;
+
+/// An event of the progress of the session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum ProgressEvent<'e> {
+    /// Image processed with command.
+    Processed { image: &'e Path, command: &'e str, source_size: u64, processed_size: u64 },
+
+    /// Variant of command validated, replacing the image.
+    Validated { image: &'e Path, command: &'e str, saved: i64 },
+
+    /// Processing the image with command failed, or moving its files if the
+    /// command is None.
+    Failed { image: &'e Path, command: Option<&'e str>, reason: &'e str },
+}
+
+impl ProgressEvent<'_> {
+    /// Formats the event as a JSON object, on a single line.
+    pub fn json(&self) -> String {
+        let image = |image: &Path| json_string(&image.to_string_lossy());
+
+        match self {
+            ProgressEvent::Processed { image: i, command, source_size, processed_size } => format!(
+                r#"{{"event": "processed", "image": {}, "command": {}, "source_bytes": {source_size}, "processed_bytes": {processed_size}}}"#,
+                image(i),
+                json_string(command),
+            ),
+            ProgressEvent::Validated { image: i, command, saved } => format!(
+                r#"{{"event": "validated", "image": {}, "command": {}, "saved_bytes": {saved}}}"#,
+                image(i),
+                json_string(command),
+            ),
+            ProgressEvent::Failed { image: i, command, reason } => format!(
+                r#"{{"event": "failed", "image": {}, "command": {}, "reason": {}}}"#,
+                image(i),
+                command.map_or("null".to_string(), json_string),
+                json_string(reason),
+            ),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn events_are_json_lines() {
+        let image = Path::new("/photos/a \"b\".jpg");
+        let processed = ProgressEvent::Processed { image, command: "cwebp %i -o %o.webp", source_size: 300, processed_size: 100 };
+        assert_eq!(
+            processed.json(),
+            r#"{"event": "processed", "image": "/photos/a \"b\".jpg", "command": "cwebp %i -o %o.webp", "source_bytes": 300, "processed_bytes": 100}"#,
+        );
+
+        let failed = ProgressEvent::Failed { image, command: None, reason: "disk\nfull" };
+        assert_eq!(
+            failed.json(),
+            r#"{"event": "failed", "image": "/photos/a \"b\".jpg", "command": null, "reason": "disk\nfull"}"#,
+        );
+    }
+}
@@ -0,0 +1,176 @@
+//! Recent review sessions, kept across sessions for the launcher.
+//!
+//! When quitting, every session adds a line to the recent file with its date,
+//! its number of images, and the deepest directory containing all of them.
+//! The launcher lists the latest sessions and the directories reviewed most
+//! often.
+//!
+//! The file is a list of tab-separated lines:
+//! `<date> <images> <directory>`, oldest first.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+
+/// Number of sessions kept in the recent file, the oldest are dropped.
+const RECENT_KEPT: usize = 200;
+
+/// A review session.
+#[derive(Clone, Debug, PartialEq)]
+pub struct RecentSession {
+    pub date: String,
+    pub images: usize,
+    pub directory: PathBuf,
+}
+
+/// The recent sessions, oldest first.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Recent {
+    pub sessions: Vec<RecentSession>,
+}
+
+impl Recent {
+    /// Adds a session, dropping the oldest ones beyond RECENT_KEPT.
+    pub fn record(&mut self, session: RecentSession) {
+        self.sessions.push(session);
+        let excess = self.sessions.len().saturating_sub(RECENT_KEPT);
+        self.sessions.drain(..excess);
+    }
+
+    /// Returns the n latest sessions, latest first, with a single session per
+    /// directory.
+    pub fn latest(&self, n: usize) -> Vec<&RecentSession> {
+        let mut latest: Vec<&RecentSession> = Vec::new();
+        for session in self.sessions.iter().rev() {
+            if !latest.iter().any(|s| s.directory == session.directory) {
+                latest.push(session);
+            }
+        }
+        latest.truncate(n);
+
+        latest
+    }
+
+    /// Returns the n directories with the most sessions, with their number of
+    /// sessions. Ties go to the directory used last.
+    pub fn frequent(&self, n: usize) -> Vec<(&Path, usize)> {
+        let mut frequent: Vec<(&Path, usize)> = Vec::new();
+        for session in self.sessions.iter().rev() {
+            match frequent.iter_mut().find(|(d, _)| *d == session.directory) {
+                Some((_, uses)) => *uses += 1,
+                None => frequent.push((&session.directory, 1)),
+            }
+        }
+        // The sort is stable, the latest stay first among ties.
+        frequent.sort_by_key(|&(_, uses)| std::cmp::Reverse(uses));
+        frequent.truncate(n);
+
+        frequent
+    }
+
+    /// Formats the sessions as the content of the recent file.
+    pub fn format(&self) -> String {
+        self.sessions
+            .iter()
+            .map(|s| format!("{}\t{}\t{}\n", s.date, s.images, s.directory.display()))
+            .collect()
+    }
+
+    /// Parses the content of a recent file.
+    pub fn parse(content: &str) -> Result<Recent, String> {
+        let mut recent = Recent::default();
+
+        for line in content.lines().filter(|l| !l.is_empty()) {
+            let fields = line.splitn(3, '\t').collect::<Vec<&str>>();
+            match fields.as_slice() {
+                [date, images, directory] => recent.sessions.push(RecentSession {
+                    date: date.to_string(),
+                    images: images
+                        .parse()
+                        .map_err(|_| format!("Invalid number of images in recent sessions: '{line}'"))?,
+                    directory: PathBuf::from(directory),
+                }),
+                _ => return Err(format!("Invalid line in recent sessions: '{line}'")),
+            }
+        }
+
+        Ok(recent)
+    }
+
+    /// Reads the recent file at provided path. A missing file has no sessions
+    /// yet.
+    pub fn load(path: &Path) -> Result<Recent, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => Recent::parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Recent::default()),
+            Err(e) => Err(format!("Unable to read {}: {e}", path.display())),
+        }
+    }
+}
+
+/// Returns the deepest directory containing every path, or None if there
+/// are no paths.
+pub fn common_directory<'p, I: IntoIterator<Item = &'p Path>>(paths: I) -> Option<PathBuf> {
+    let mut common: Option<PathBuf> = None;
+
+    for path in paths {
+        let parent = path.parent().unwrap_or(path);
+        common = Some(match common {
+            None => parent.to_path_buf(),
+            Some(common) => common
+                .ancestors()
+                .find(|a| parent.starts_with(a))
+                .unwrap_or(Path::new(""))
+                .to_path_buf(),
+        });
+    }
+
+    common
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn session(date: &str, directory: &str) -> RecentSession {
+        RecentSession { date: date.to_string(), images: 10, directory: PathBuf::from(directory) }
+    }
+
+    #[test]
+    fn recent_round_trip() {
+        let mut recent = Recent::default();
+        for k in 0..RECENT_KEPT + 3 {
+            recent.record(session(&format!("day {k}"), "/photos/2024"));
+        }
+        assert_eq!(recent.sessions.len(), RECENT_KEPT);
+        assert_eq!(recent.sessions[0].date, "day 3");
+        assert_eq!(Recent::parse(&recent.format()), Ok(recent));
+        assert!(Recent::parse("day\tten\t/photos").is_err());
+    }
+
+    #[test]
+    fn latest_and_frequent_directories() {
+        let recent = Recent {
+            sessions: vec![
+                session("1", "/a"),
+                session("2", "/b"),
+                session("3", "/a"),
+                session("4", "/c"),
+            ],
+        };
+
+        let latest = recent.latest(5).iter().map(|s| s.date.as_str()).collect::<Vec<&str>>();
+        assert_eq!(latest, ["4", "3", "2"]);
+        assert_eq!(recent.frequent(2), [(Path::new("/a"), 2), (Path::new("/c"), 1)]);
+    }
+
+    #[test]
+    fn common_directory_of_paths() {
+        let paths = [Path::new("/photos/2024/a.jpg"), Path::new("/photos/2024/trip/b.jpg")];
+        assert_eq!(common_directory(paths), Some(PathBuf::from("/photos/2024")));
+        assert_eq!(common_directory([Path::new("/a/x.jpg"), Path::new("/b/y.jpg")]), Some(PathBuf::from("/")));
+        assert_eq!(common_directory([]), None);
+    }
+}
@@ -2,6 +2,12 @@
 
 use sdl2::rect::{Rect, Point};
 
+use crate::settings::SourcePosition;
+
+/// Maximum width or height of the virtual rectangle, whatever the zoom limits,
+/// so that computations on its coordinates can't overflow.
+const MAX_VIRT_SIZE: u32 = 1 << 20;
+
 trait RectExt {
     fn aspect_ratio(&self) -> f32;
     fn scale(&mut self, scale: f32);
@@ -61,7 +67,12 @@ pub struct ViewRect {
 
     /// dst Rect of the texture copy function.
     pub dst_rect: Rect,     
-    
+
+    /// Minimum zoom factor, i.e. virt_rect width / img_rect width.
+    min_zoom: f32,
+
+    /// Maximum zoom factor, i.e. virt_rect width / img_rect width.
+    max_zoom: f32,
 }
 
 impl Default for ViewRect {
@@ -73,6 +84,8 @@ impl Default for ViewRect {
             virt_rect: empty_rect,
             src_rect: empty_rect,
             dst_rect: empty_rect,
+            min_zoom: 0.,
+            max_zoom: f32::INFINITY,
         }
     }
 }
@@ -91,6 +104,8 @@ impl ViewRect {
 
             src_rect: img_rect,
             dst_rect: clip_rect, 
+            min_zoom: 0.,
+            max_zoom: f32::INFINITY,
         };
 
         view.set_img_rect(img_rect);
@@ -137,11 +152,39 @@ impl ViewRect {
         self.clip_rect = clip_rect;
     }
 
+    /// Sets the limits enforced when zooming. The zoom factor is the ratio
+    /// between the displayed size and the real size of the image.
+    pub fn set_zoom_limits(&mut self, min_zoom: f32, max_zoom: f32) {
+        self.min_zoom = min_zoom;
+        self.max_zoom = max_zoom;
+    }
+
+    /// Returns the width and height of the image, in pixels.
+    pub fn img_size(&self) -> (u32, u32) {
+        self.img_rect.size()
+    }
+
     /// Returns the zoom factor
-    fn zoom_factor(&self) -> f32 {
+    pub fn zoom_factor(&self) -> f32 {
         self.virt_rect.width() as f32 / self.img_rect.width() as f32
     }
 
+    /// Returns the pixel of the image displayed at a point in window
+    /// coordinates, or None if the point is outside of the image.
+    ///
+    /// The point must not be oriented, see Orientation::inverse_point.
+    pub fn img_point(&self, pt: Point) -> Option<(u32, u32)> {
+        let d = pt - self.virt_rect.top_left();
+        let x = (d.x as i64 * self.img_rect.width() as i64).div_euclid(self.virt_rect.width() as i64);
+        let y = (d.y as i64 * self.img_rect.height() as i64).div_euclid(self.virt_rect.height() as i64);
+
+        let (w, h) = self.img_size();
+        match (0..w as i64).contains(&x) && (0..h as i64).contains(&y) {
+            true => Some((x as u32, y as u32)),
+            false => None,
+        }
+    }
+
     pub fn fit_width_to_rect(&mut self, fit_rect: Rect){
         self.virt_rect.set_width(fit_rect.width());
         self.set_height_from_width();
@@ -182,11 +225,11 @@ impl ViewRect {
     }
 
     fn set_height_from_width(&mut self){
-        self.virt_rect.set_height((self.virt_rect.width() as f32 / self.img_rect.aspect_ratio()).round() as u32);
+        self.virt_rect.set_height(((self.virt_rect.width() as f32 / self.img_rect.aspect_ratio()).round() as u32).max(1));
     }
 
     fn set_width_from_height(&mut self){
-        self.virt_rect.set_width((self.virt_rect.height() as f32 * self.img_rect.aspect_ratio()).round() as u32);
+        self.virt_rect.set_width(((self.virt_rect.height() as f32 * self.img_rect.aspect_ratio()).round() as u32).max(1));
     }
 
     /// Updates the src and dst rectangles.
@@ -273,7 +316,16 @@ impl ViewRect {
     
     /// Zoom in on texture, while attempting to keep point at the same 
     /// coordinates. Point coordinates are relative to provided Rect.
+    ///
+    /// The scale is reduced if needed so that the zoom factor stays within the
+    /// zoom limits, and the virtual rectangle stays between 1 and
+    /// MAX_VIRT_SIZE pixels.
     pub fn zoom_towards_point_on_rect(&mut self, pt: Point, rect: Rect, scale: f32){
+        let zoom = self.zoom_factor();
+        let img_size = u32::max(self.img_rect.width(), self.img_rect.height()) as f32;
+        let max_zoom = f32::min(self.max_zoom, MAX_VIRT_SIZE as f32 / img_size);
+        let min_zoom = f32::max(self.min_zoom, 1. / self.img_rect.width().min(self.img_rect.height()) as f32);
+        let scale = (zoom * scale).max(min_zoom).min(max_zoom) / zoom;
         
         // Compute the position of the point relative to virt_rect.
         let point_virt_rect_distance = pt  + rect.top_left() - self.virt_rect.top_left();
@@ -285,7 +337,7 @@ impl ViewRect {
 
         let offset = point_virt_rect_distance - next_point_virt_rect_distance;
 
-        self.virt_rect.set_width((self.virt_rect.width() as f32 * scale).round() as u32);
+        self.virt_rect.set_width(((self.virt_rect.width() as f32 * scale).round() as u32).max(1));
         self.set_height_from_width();
 
         // Now correct by offseting rectangle with the difference between what 
@@ -304,4 +356,662 @@ impl ViewRect {
     pub fn zoom_towards_view_center(&mut self, scale: f32){
         self.zoom_towards_point(self.clip_rect.center(), scale);
     }
+
+    /// Moves the image so that its pixel is displayed at the point, in window
+    /// coordinates, keeping the zoom factor.
+    pub fn show_img_point(&mut self, pixel: Point, pt: Point) {
+        let x = pixel.x as i64 * self.virt_rect.width() as i64 / self.img_rect.width() as i64;
+        let y = pixel.y as i64 * self.virt_rect.height() as i64 / self.img_rect.height() as i64;
+        self.virt_rect.reposition(Point::new(pt.x - x as i32, pt.y - y as i32));
+        self.update();
+    }
+
+    /// Returns the rectangle of the image, in pixels, in window coordinates.
+    ///
+    /// The rectangle is not oriented, see Orientation::transform_rect.
+    pub fn window_rect(&self, rect: Rect) -> Rect {
+        let virt = self.virt_rect;
+        let (w, h) = (self.img_rect.width() as i64, self.img_rect.height() as i64);
+        let x = |x: i64| virt.left() + (x * virt.width() as i64 / w) as i32;
+        let y = |y: i64| virt.top() + (y * virt.height() as i64 / h) as i32;
+        let (left, top) = (x(rect.left() as i64), y(rect.top() as i64));
+        let (right, bottom) = (x(rect.right() as i64), y(rect.bottom() as i64));
+
+        Rect::new(left, top, (right - left).max(1) as u32, (bottom - top).max(1) as u32)
+    }
+
+    /// Returns the lines dividing the image in nx columns and ny rows, as
+    /// (start, end) tuples in window coordinates.
+    ///
+    /// Only the lines visible in dst_rect are returned, the image borders are
+    /// excluded. This allows drawing e.g. a pixel grid on a large image.
+    pub fn grid_lines(&self, nx: u32, ny: u32) -> Vec<(Point, Point)> {
+        let virt = self.virt_rect;
+        let dst = self.dst_rect;
+        let mut lines = Vec::new();
+
+        // Index of the first and last line between positions a and b, on an
+        // axis where the virtual rectangle starts at origin with given size.
+        let visible = |a: i32, b: i32, origin: i32, size: u32, n: u32| {
+            let first = ((a - origin) as i64 * n as i64).div_euclid(size as i64) + 1;
+            let last = ((b - origin) as i64 * n as i64).div_euclid(size as i64);
+            first.max(1)..=last.min(n as i64 - 1)
+        };
+
+        for i in visible(dst.left(), dst.right() - 1, virt.left(), virt.width(), nx) {
+            let x = virt.left() + (i * virt.width() as i64 / nx as i64) as i32;
+            lines.push((Point::new(x, dst.top()), Point::new(x, dst.bottom() - 1)));
+        }
+
+        for j in visible(dst.top(), dst.bottom() - 1, virt.top(), virt.height(), ny) {
+            let y = virt.top() + (j * virt.height() as i64 / ny as i64) as i32;
+            lines.push((Point::new(dst.left(), y), Point::new(dst.right() - 1, y)));
+        }
+
+        lines
+    }
+}
+
+
+/// View-only orientation of the displayed images, applied on top of the
+/// ViewRect geometry when copying textures with copy_ex.
+///
+/// The image is first mirrored horizontally (if enabled), then rotated
+/// clockwise by quarter turns around a center point. The ViewRect rectangles
+/// are left untouched, so panning and zooming keep working in image space.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Orientation {
+    /// Number of clockwise quarter turns, between 0 and 3.
+    quarter_turns: u8,
+
+    /// Whether the image is mirrored horizontally before rotation.
+    pub mirrored: bool,
+}
+
+impl Orientation {
+    /// Rotates by 90° clockwise.
+    pub fn rotate_cw(&mut self) {
+        self.quarter_turns = (self.quarter_turns + 1) % 4;
+    }
+
+    /// Toggles the horizontal mirroring.
+    pub fn toggle_mirror(&mut self) {
+        self.mirrored = !self.mirrored;
+    }
+
+    /// Returns the rotation angle in degrees, as expected by copy_ex.
+    pub fn angle(&self) -> f64 {
+        self.quarter_turns as f64 * 90.
+    }
+
+    /// Returns true if the width and height of the image are swapped on
+    /// screen.
+    pub fn swaps_axes(&self) -> bool {
+        self.quarter_turns % 2 == 1
+    }
+
+    /// Returns true if the orientation is the identity.
+    pub fn is_identity(&self) -> bool {
+        self.quarter_turns == 0 && !self.mirrored
+    }
+
+    /// Transforms a point in image space to screen space.
+    pub fn transform_point(&self, pt: Point, center: Point) -> Point {
+        let mut d = pt - center;
+        if self.mirrored {
+            d.x = -d.x;
+        }
+        for _ in 0..self.quarter_turns {
+            d = Point::new(-d.y, d.x);
+        }
+
+        center + d
+    }
+
+    /// Transforms a point in screen space back to image space.
+    pub fn inverse_point(&self, pt: Point, center: Point) -> Point {
+        let mut d = pt - center;
+        for _ in 0..self.quarter_turns {
+            d = Point::new(d.y, -d.x);
+        }
+        if self.mirrored {
+            d.x = -d.x;
+        }
+
+        center + d
+    }
+
+    /// Returns the dst rect to pass to copy_ex, with the angle and flip of
+    /// this orientation, so that the rect is displayed transformed around the
+    /// center. The size is kept since copy_ex rotates around the rect center.
+    pub fn copy_dst_rect(&self, rect: Rect, center: Point) -> Rect {
+        Rect::from_center(self.transform_point(rect.center(), center), rect.width(), rect.height())
+    }
+
+    /// Returns the rectangle covered on screen by the transformed rectangle.
+    pub fn transform_rect(&self, rect: Rect, center: Point) -> Rect {
+        let (w, h) = match self.swaps_axes() {
+            true => (rect.height(), rect.width()),
+            false => (rect.width(), rect.height()),
+        };
+
+        Rect::from_center(self.transform_point(rect.center(), center), w, h)
+    }
+}
+
+
+/// Returns true if an image of the provided size is displayed larger when the
+/// window is split side by side than when it is split top and bottom.
+pub fn split_side_by_side(img_size: (u32, u32), window_size: (u32, u32)) -> bool {
+    let (iw, ih) = (img_size.0.max(1) as f32, img_size.1.max(1) as f32);
+    let (w, h) = (window_size.0 as f32, window_size.1 as f32);
+
+    f32::min(w / 2. / iw, h / ih) >= f32::min(w / iw, h / 2. / ih)
+}
+
+
+/// Returns the rectangles of the source and processed panes in a window of
+/// the provided size. The source pane takes ratio of the window, and both
+/// panes are separated by twice the padding. Auto is handled as Left.
+pub fn pane_rects(window_size: (u32, u32), ratio: f32, padding: u32, position: SourcePosition) -> (Rect, Rect) {
+    let (w, h) = window_size;
+
+    // Width or height of the source pane, padding included.
+    let ratio = ratio.clamp(0.1, 0.9);
+    let sw = (w as f32 * ratio) as u32;
+    let sh = (h as f32 * ratio) as u32;
+
+    match position {
+        SourcePosition::Left | SourcePosition::Auto => (
+            Rect::new(0, 0, sw.saturating_sub(padding), h),
+            Rect::new((sw + padding) as i32, 0, (w - sw).saturating_sub(padding), h),
+        ),
+
+        SourcePosition::Top => (
+            Rect::new(0, 0, w, sh.saturating_sub(padding)),
+            Rect::new(0, (sh + padding) as i32, w, (h - sh).saturating_sub(padding)),
+        ),
+
+        SourcePosition::Right => (
+            Rect::new((w - sw + padding) as i32, 0, sw.saturating_sub(padding), h),
+            Rect::new(0, 0, (w - sw).saturating_sub(padding), h),
+        ),
+
+        SourcePosition::Bottom => (
+            Rect::new(0, (h - sh + padding) as i32, w, sh.saturating_sub(padding)),
+            Rect::new(0, 0, w, (h - sh).saturating_sub(padding)),
+        ),
+    }
+}
+
+/// Returns the sides of the border drawn around the visible part (clip) of a
+/// validated image in its pane: the side away from the source, and both sides
+/// perpendicular to the split. Its thickness is 1/fraction of the pane, e.g. a
+/// twentieth.
+pub fn selection_border(clip: Rect, pane: Rect, position: SourcePosition, fraction: u32) -> [Rect; 3] {
+    let thickness = std::cmp::min(pane.height(), pane.width()) / fraction.max(1);
+
+    let mut outer = clip;
+    let mut side_1 = clip;
+    let mut side_2;
+
+    match position {
+        SourcePosition::Left | SourcePosition::Right | SourcePosition::Auto => {
+            outer.set_width(thickness);
+            side_1.set_height(thickness);
+            side_1.set_y(clip.top());
+            side_2 = side_1;
+            side_2.set_bottom(clip.bottom());
+        }
+        SourcePosition::Top | SourcePosition::Bottom => {
+            outer.set_height(thickness);
+            side_1.set_width(thickness);
+            side_1.set_x(clip.left());
+            side_2 = side_1;
+            side_2.set_right(clip.right());
+        }
+    };
+
+    match position {
+        SourcePosition::Left | SourcePosition::Auto => outer.set_right(clip.right()),
+        SourcePosition::Right => outer.set_x(clip.left()),
+        SourcePosition::Top => outer.set_bottom(clip.bottom()),
+        SourcePosition::Bottom => outer.set_y(clip.top()),
+    }
+
+    [outer, side_1, side_2]
+}
+
+/// Returns the square where the validation checkmark is drawn: in the corner of
+/// the visible part (clip) of a validated image at the top of the side away
+/// from the source, or at the bottom when the source is on top.
+pub fn checkmark_rect(clip: Rect, position: SourcePosition, size: u32) -> Rect {
+    let size = size.min(clip.width()).min(clip.height());
+
+    let (x, y) = match position {
+        SourcePosition::Right => (clip.left(), clip.top()),
+        SourcePosition::Top => (clip.right() - size as i32, clip.bottom() - size as i32),
+        SourcePosition::Left | SourcePosition::Bottom | SourcePosition::Auto
+            => (clip.right() - size as i32, clip.top()),
+    };
+
+    Rect::new(x, y, size, size)
+}
+
+/// Returns the line segments drawing a checkmark in the square, thickened by
+/// repeating them with vertical offsets.
+pub fn checkmark_lines(rect: Rect) -> Vec<(Point, Point)> {
+    let at = |fx: f32, fy: f32| Point::new(
+        rect.left() + (rect.width() as f32 * fx) as i32,
+        rect.top() + (rect.height() as f32 * fy) as i32,
+    );
+    let (a, b, c) = (at(0.2, 0.5), at(0.42, 0.72), at(0.8, 0.28));
+
+    let thickness = (rect.height() / 10).max(1) as i32;
+    (-thickness / 2..thickness - thickness / 2)
+        .flat_map(|dy| {
+            let offset = Point::new(0, dy);
+            [(a + offset, b + offset), (b + offset, c + offset)]
+        })
+        .collect()
+}
+
+/// Returns the filled part of a progress bar drawn in the track rect, None
+/// until some progress is made.
+pub fn progress_fill(track: Rect, done: usize, total: usize) -> Option<Rect> {
+    let width = (track.width() as u64 * done.min(total) as u64)
+        .checked_div(total as u64)
+        .unwrap_or(0) as u32;
+
+    (width > 0).then(|| Rect::new(track.x(), track.y(), width, track.height()))
+}
+
+/// Returns the centers of the regions of a guided tour of an image, in image
+/// pixels: a grid of regions of at most region_size covering the image, row
+/// by row. Every other row goes from right to left, so that consecutive
+/// regions are next to each other.
+pub fn tour_regions(img_size: (u32, u32), region_size: (u32, u32)) -> Vec<Point> {
+    let centers = |size: u32, region: u32| {
+        let n = size.div_ceil(region.max(1)).max(1);
+        (0..n).map(move |k| ((2 * k + 1) as u64 * size as u64 / (2 * n) as u64) as i32)
+    };
+
+    let xs = centers(img_size.0, region_size.0).collect::<Vec<i32>>();
+    centers(img_size.1, region_size.1)
+        .enumerate()
+        .flat_map(|(row, y)| {
+            let mut points = xs.iter().map(|&x| Point::new(x, y)).collect::<Vec<Point>>();
+            if row % 2 == 1 {
+                points.reverse();
+            }
+            points
+        })
+        .collect()
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn panes_are_separated_by_padding() {
+        let (source, processed) = pane_rects((1000, 600), 0.4, 5, SourcePosition::Left);
+        assert_eq!(source, Rect::new(0, 0, 395, 600));
+        assert_eq!(processed, Rect::new(405, 0, 595, 600));
+
+        let (source, processed) = pane_rects((1000, 600), 0.5, 5, SourcePosition::Bottom);
+        assert_eq!(source, Rect::new(0, 305, 1000, 295));
+        assert_eq!(processed, Rect::new(0, 0, 1000, 295));
+    }
+
+    #[test]
+    fn selection_border_thickness_is_fraction_of_pane() {
+        let pane = Rect::new(0, 0, 400, 200);
+        let [outer, side_1, _] = selection_border(pane, pane, SourcePosition::Left, 20);
+        assert_eq!((outer.width(), side_1.height()), (10, 10));
+
+        let [outer, side_1, _] = selection_border(pane, pane, SourcePosition::Left, 10);
+        assert_eq!((outer.width(), side_1.height()), (20, 20));
+    }
+
+    #[test]
+    fn checkmark_is_in_corner_away_from_source() {
+        let clip = Rect::new(100, 50, 400, 300);
+        assert_eq!(checkmark_rect(clip, SourcePosition::Left, 40), Rect::new(460, 50, 40, 40));
+        assert_eq!(checkmark_rect(clip, SourcePosition::Right, 40), Rect::new(100, 50, 40, 40));
+        assert_eq!(checkmark_rect(clip, SourcePosition::Top, 40), Rect::new(460, 310, 40, 40));
+        assert_eq!(checkmark_rect(Rect::new(0, 0, 20, 30), SourcePosition::Left, 40), Rect::new(0, 0, 20, 20));
+    }
+
+    #[test]
+    fn checkmark_lines_stay_in_rect() {
+        let rect = Rect::new(10, 10, 40, 40);
+        let lines = checkmark_lines(rect);
+        assert_eq!(lines.len(), 8);
+        for (a, b) in lines {
+            assert!(rect.contains_point(a) && rect.contains_point(b), "{a:?} {b:?}");
+        }
+    }
+
+    #[test]
+    fn progress_fill_is_proportional() {
+        let track = Rect::new(10, 20, 300, 16);
+        assert_eq!(progress_fill(track, 0, 4), None);
+        assert_eq!(progress_fill(track, 1, 4), Some(Rect::new(10, 20, 75, 16)));
+        assert_eq!(progress_fill(track, 5, 4), Some(track));
+        assert_eq!(progress_fill(track, 0, 0), None);
+    }
+
+    #[test]
+    fn split_follows_image_orientation() {
+        assert!(split_side_by_side((500, 1000), (1600, 1200)));
+        assert!(!split_side_by_side((2000, 1000), (1600, 1200)));
+        assert!(split_side_by_side((1000, 1000), (1600, 1200)));
+        assert!(!split_side_by_side((1000, 1000), (1200, 1600)));
+    }
+
+    /// Image of 1000x500 (aspect ratio 2) in a 800x600 clip rect.
+    fn landscape_view() -> ViewRect {
+        ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600))
+    }
+
+    /// Returns the image coordinates of the point, i.e. the pixel of the
+    /// original image displayed at that point.
+    fn img_coordinates(view: &ViewRect, pt: Point) -> (f32, f32) {
+        let d = pt - view.virt_rect.top_left();
+        (
+            d.x as f32 / view.virt_rect.width() as f32 * view.img_rect.width() as f32,
+            d.y as f32 / view.virt_rect.height() as f32 * view.img_rect.height() as f32,
+        )
+    }
+
+    #[test]
+    fn new_displays_image_at_real_size() {
+        let view = landscape_view();
+        assert_eq!(view.virt_rect, Rect::new(0, 0, 1000, 500));
+        assert_eq!(view.dst_rect, Rect::new(0, 0, 800, 500));
+        assert_eq!(view.src_rect, Rect::new(0, 0, 800, 500));
+    }
+
+    #[test]
+    fn fit_width_keeps_aspect_ratio_and_centers() {
+        let mut view = landscape_view();
+        view.fit_width_to_rect(view.clip_rect);
+
+        assert_eq!(view.virt_rect, Rect::new(0, 100, 800, 400));
+        assert_eq!(view.dst_rect, view.virt_rect);
+        assert_eq!(view.src_rect, Rect::new(0, 0, 1000, 500));
+    }
+
+    #[test]
+    fn fit_height_keeps_aspect_ratio_and_centers() {
+        let mut view = landscape_view();
+        view.fit_height_to_rect(view.clip_rect);
+
+        assert_eq!(view.virt_rect, Rect::new(-200, 0, 1200, 600));
+        assert_eq!(view.dst_rect, view.clip_rect);
+        assert_eq!(view.src_rect.height(), 500);
+    }
+
+    #[test]
+    fn fit_best_shows_whole_image() {
+        let mut view = landscape_view();
+        view.fit_best_to_rect(view.clip_rect);
+        assert_eq!(view.virt_rect, Rect::new(0, 100, 800, 400));
+
+        let mut view = ViewRect::new((500, 1000), Rect::new(0, 0, 800, 600));
+        view.fit_best_to_rect(view.clip_rect);
+        assert_eq!(view.virt_rect, Rect::new(250, 0, 300, 600));
+    }
+
+    #[test]
+    fn fit_fill_covers_whole_rect() {
+        let mut view = landscape_view();
+        view.fit_fill_to_rect(view.clip_rect);
+        assert_eq!(view.virt_rect, Rect::new(-200, 0, 1200, 600));
+
+        let mut view = ViewRect::new((500, 1000), Rect::new(0, 0, 800, 600));
+        view.fit_fill_to_rect(view.clip_rect);
+        assert_eq!(view.virt_rect, Rect::new(0, -500, 800, 1600));
+    }
+
+    #[test]
+    fn fit_to_offset_rect() {
+        let mut view = ViewRect::new((1000, 500), Rect::new(400, 0, 400, 600));
+        view.fit_width_to_rect(view.clip_rect);
+
+        assert_eq!(view.virt_rect, Rect::new(400, 200, 400, 200));
+        assert_eq!(view.dst_rect, view.virt_rect);
+    }
+
+    #[test]
+    fn pan_moves_view() {
+        let mut view = landscape_view();
+        view.pan_left(50);
+        assert_eq!(view.virt_rect.top_left(), Point::new(-50, 0));
+        view.pan_right(20);
+        assert_eq!(view.virt_rect.top_left(), Point::new(-30, 0));
+        view.pan_up(10);
+        assert_eq!(view.virt_rect.top_left(), Point::new(-30, -10));
+        view.pan_down(10);
+        assert_eq!(view.virt_rect.top_left(), Point::new(-30, 0));
+        assert_eq!(view.virt_rect.size(), (1000, 500));
+    }
+
+    #[test]
+    fn pan_is_clamped_to_clip_rect() {
+        let mut view = landscape_view();
+        view.pan_right(100_000);
+        assert_eq!(view.virt_rect.left(), view.clip_rect.right() - 1);
+
+        view.pan_left(200_000);
+        assert_eq!(view.virt_rect.right(), view.clip_rect.left() + 1);
+
+        view.pan_down(100_000);
+        assert_eq!(view.virt_rect.top(), view.clip_rect.bottom() - 1);
+
+        view.pan_up(200_000);
+        assert_eq!(view.virt_rect.bottom(), view.clip_rect.top() + 1);
+    }
+
+    #[test]
+    fn zoom_keeps_anchored_point_fixed() {
+        let mut view = landscape_view();
+        view.fit_best_to_rect(view.clip_rect);
+
+        for &pt in &[Point::new(400, 300), Point::new(100, 150), Point::new(700, 450)] {
+            for &scale in &[1.1, 0.9, 2., 0.5] {
+                let before = img_coordinates(&view, pt);
+                view.zoom_towards_point(pt, scale);
+                let after = img_coordinates(&view, pt);
+
+                assert!((before.0 - after.0).abs() <= 2., "{before:?} {after:?}");
+                assert!((before.1 - after.1).abs() <= 2., "{before:?} {after:?}");
+            }
+        }
+    }
+
+    #[test]
+    fn img_point_maps_window_to_image() {
+        let mut view = landscape_view();
+        view.fit_width_to_rect(view.clip_rect);
+
+        // virt_rect is (0, 100, 800, 400), i.e. a zoom factor of 0.8.
+        assert_eq!(view.img_point(Point::new(0, 100)), Some((0, 0)));
+        assert_eq!(view.img_point(Point::new(400, 300)), Some((500, 250)));
+        assert_eq!(view.img_point(Point::new(799, 499)), Some((998, 498)));
+        assert_eq!(view.img_point(Point::new(400, 99)), None);
+        assert_eq!(view.img_point(Point::new(400, 500)), None);
+        assert_eq!(view.window_rect(Rect::new(500, 250, 100, 50)), Rect::new(400, 300, 80, 40));
+
+        view.zoom_towards_point(Point::new(400, 300), 4.);
+        assert_eq!(view.img_point(Point::new(400, 300)), Some((500, 250)));
+    }
+
+    #[test]
+    fn zoom_on_rect_uses_rect_coordinates() {
+        let mut a = ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600));
+        let mut b = ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600));
+        a.zoom_towards_point(Point::new(400, 300), 2.);
+        b.zoom_towards_point_on_rect(Point::new(300, 200), Rect::new(100, 100, 10, 10), 2.);
+
+        assert_eq!(a.virt_rect, b.virt_rect);
+    }
+
+    #[test]
+    fn zoom_keeps_aspect_ratio() {
+        let mut view = landscape_view();
+        view.zoom_towards_view_center(1.37);
+        assert_eq!(view.virt_rect.width(), 1370);
+        assert_eq!(view.virt_rect.height(), 685);
+    }
+
+    #[test]
+    fn sync_duplicate_offsets_by_clip_rects() {
+        let mut source = ViewRect::new((1000, 500), Rect::new(0, 0, 400, 600));
+        let mut processed = ViewRect::new((1000, 500), Rect::new(400, 0, 400, 600));
+        source.fit_width_to_rect(source.clip_rect);
+        source.pan_right(30);
+        processed.sync_duplicate_with(&source);
+
+        let mut expected = source.virt_rect;
+        expected.offset(400, 0);
+        assert_eq!(processed.virt_rect, expected);
+        assert_eq!(processed.src_rect, source.src_rect);
+    }
+
+    #[test]
+    fn grid_lines_thirds() {
+        let mut view = landscape_view();
+        view.fit_width_to_rect(view.clip_rect);
+        let lines = view.grid_lines(3, 3);
+
+        assert_eq!(lines, vec![
+            (Point::new(266, 100), Point::new(266, 499)),
+            (Point::new(533, 100), Point::new(533, 499)),
+            (Point::new(0, 233), Point::new(799, 233)),
+            (Point::new(0, 366), Point::new(799, 366)),
+        ]);
+    }
+
+    #[test]
+    fn grid_lines_only_visible() {
+        let mut view = ViewRect::new((100, 100), Rect::new(0, 0, 100, 100));
+        view.zoom_towards_point(Point::new(0, 0), 10.);
+        let lines = view.grid_lines(100, 100);
+
+        // 10x10 image pixels are visible, hence 9 lines in each direction.
+        assert_eq!(lines.len(), 18);
+        assert!(lines.iter().all(|(a, b)| view.dst_rect.contains_point(*a) && view.dst_rect.contains_point(*b)));
+    }
+
+    #[test]
+    fn orientation_round_trip() {
+        let center = Point::new(400, 300);
+        let mut orientation = Orientation::default();
+        for _ in 0..2 {
+            for _ in 0..4 {
+                let pt = Point::new(123, 45);
+                let transformed = orientation.transform_point(pt, center);
+                assert_eq!(orientation.inverse_point(transformed, center), pt);
+                orientation.rotate_cw();
+            }
+            orientation.toggle_mirror();
+        }
+    }
+
+    #[test]
+    fn orientation_rotates_clockwise() {
+        let center = Point::new(0, 0);
+        let mut orientation = Orientation::default();
+        orientation.rotate_cw();
+        assert_eq!(orientation.transform_point(Point::new(10, 0), center), Point::new(0, 10));
+        assert_eq!(orientation.angle(), 90.);
+        assert!(orientation.swaps_axes());
+
+        orientation.toggle_mirror();
+        assert_eq!(orientation.transform_point(Point::new(10, 0), center), Point::new(0, -10));
+
+        for _ in 0..3 {
+            orientation.rotate_cw();
+        }
+        orientation.toggle_mirror();
+        assert!(orientation.is_identity());
+    }
+
+    #[test]
+    fn orientation_transform_rect() {
+        let center = Point::new(400, 300);
+        let mut orientation = Orientation::default();
+        orientation.rotate_cw();
+        let rect = Rect::new(0, 100, 800, 400);
+
+        assert_eq!(orientation.transform_rect(rect, center), Rect::new(200, -100, 400, 800));
+        assert_eq!(orientation.copy_dst_rect(rect, center), rect);
+    }
+
+    #[test]
+    fn sync_continuous_shares_virt_rect() {
+        let mut source = ViewRect::new((1000, 500), Rect::new(0, 0, 400, 600));
+        let mut processed = ViewRect::new((1000, 500), Rect::new(400, 0, 400, 600));
+        source.fit_width_to_rect(Rect::new(0, 0, 800, 600));
+        processed.sync_continuous_with(&source);
+
+        assert_eq!(processed.virt_rect, source.virt_rect);
+        assert_eq!(source.dst_rect, Rect::new(0, 100, 400, 400));
+        assert_eq!(processed.dst_rect, Rect::new(400, 100, 400, 400));
+        assert_eq!(source.src_rect, Rect::new(0, 0, 500, 500));
+        assert_eq!(processed.src_rect, Rect::new(500, 0, 500, 500));
+    }
+
+    #[test]
+    fn zoom_out_stops_at_min_zoom() {
+        let mut view = ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600));
+        view.set_zoom_limits(0.1, 10.);
+        for _ in 0..100 {
+            view.zoom_towards_view_center(0.5);
+        }
+
+        assert_eq!(view.virt_rect.width(), 100);
+        assert_eq!(view.virt_rect.height(), 50);
+    }
+
+    #[test]
+    fn zoom_in_stops_at_max_zoom() {
+        let mut view = ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600));
+        view.set_zoom_limits(0.1, 10.);
+        for _ in 0..100 {
+            view.zoom_towards_view_center(2.);
+        }
+
+        assert_eq!(view.virt_rect.width(), 10000);
+        assert_eq!(view.virt_rect.height(), 5000);
+    }
+
+    #[test]
+    fn zoom_without_limits_keeps_rect_valid() {
+        let mut view = ViewRect::new((1000, 500), Rect::new(0, 0, 800, 600));
+        for _ in 0..100 {
+            view.zoom_towards_view_center(0.1);
+        }
+        assert!(view.virt_rect.height() >= 1);
+
+        for _ in 0..100 {
+            view.zoom_towards_view_center(10.);
+        }
+        assert!(view.virt_rect.width() <= MAX_VIRT_SIZE);
+    }
+
+    #[test]
+    fn tour_regions_snake_over_the_image() {
+        let regions = tour_regions((2500, 1000), (1000, 800));
+        let expected = [(416, 250), (1250, 250), (2083, 250), (2083, 750), (1250, 750), (416, 750)];
+        assert_eq!(regions, expected.map(|(x, y)| Point::new(x, y)));
+        assert_eq!(tour_regions((500, 300), (1000, 800)), [Point::new(250, 150)]);
+
+        let mut view = ViewRect::new((2500, 1000), Rect::new(0, 0, 1000, 800));
+        view.show_img_point(Point::new(1250, 250), Point::new(500, 400));
+        assert_eq!(view.virt_rect.top_left(), Point::new(-750, 150));
+    }
 }
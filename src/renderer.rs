@@ -0,0 +1,118 @@
+//! Drawing primitives used by the application.
+//!
+//! The application draws every frame through the Renderer trait instead of
+//! calling the SDL canvas directly, so that the drawing code doesn't depend
+//! on where the frame ends up. Any SDL canvas is a renderer, whether it draws
+//! to the window or to an off-screen surface. The latter renders frames
+//! headless, which the snapshot tests compare against stored images.
+
+use sdl2::pixels::Color;
+use sdl2::rect::{Point, Rect};
+use sdl2::render::{BlendMode, Canvas, RenderTarget, Texture};
+
+
+/// How a texture is copied to the output.
+pub struct Blit {
+    /// Part of the texture copied, the whole texture if None.
+    pub src: Option<Rect>,
+    pub dst: Rect,
+
+    /// Clockwise rotation in degrees, and horizontal mirroring, around the
+    /// center of dst.
+    pub angle: f64,
+    pub mirrored: bool,
+
+    /// Area outside of which nothing is drawn, if any.
+    pub clip: Option<Rect>,
+}
+
+impl Blit {
+    /// Returns a blit copying the src part of the texture to dst, without
+    /// rotation or clipping.
+    pub fn new(src: Option<Rect>, dst: Rect) -> Blit {
+        Blit { src, dst, angle: 0., mirrored: false, clip: None }
+    }
+}
+
+
+/// Output of the drawing of a frame.
+pub trait Renderer {
+    /// Fills the whole output with the color.
+    fn clear(&mut self, color: Color);
+
+    /// Copies a texture, e.g. the visible part of an image in its pane.
+    fn blit_view(&mut self, texture: &Texture, blit: &Blit) -> Result<(), String>;
+
+    /// Fills the rectangles, e.g. the sides of a border. Translucent colors are
+    /// blended with what is below.
+    fn draw_border(&mut self, rects: &[Rect], color: Color) -> Result<(), String>;
+
+    /// Draws the line segments, optionally clipped. Translucent colors are
+    /// blended with what is below.
+    fn draw_segments(&mut self, lines: &[(Point, Point)], color: Color, clip: Option<Rect>) -> Result<(), String>;
+
+    /// Copies the texture of rendered text to dst, over a background of
+    /// provided color filling the background rectangle. A translucent
+    /// background is blended with what is below.
+    fn draw_text(&mut self, text: &Texture, dst: Rect, background: Rect, color: Color) -> Result<(), String>;
+
+    /// Shows the frame drawn.
+    fn present(&mut self);
+}
+
+
+impl<T: RenderTarget> Renderer for Canvas<T> {
+    fn clear(&mut self, color: Color) {
+        self.set_draw_color(color);
+        Canvas::clear(self);
+    }
+
+    fn blit_view(&mut self, texture: &Texture, blit: &Blit) -> Result<(), String> {
+        self.set_clip_rect(blit.clip);
+        let result = match blit.angle == 0. && !blit.mirrored {
+            true => self.copy(texture, blit.src, Some(blit.dst)),
+            false => self.copy_ex(texture, blit.src, Some(blit.dst), blit.angle, None, blit.mirrored, false),
+        };
+        self.set_clip_rect(None);
+
+        result
+    }
+
+    fn draw_border(&mut self, rects: &[Rect], color: Color) -> Result<(), String> {
+        self.set_blend_mode(blend_mode(color));
+        self.set_draw_color(color);
+        let result = self.fill_rects(rects);
+        self.set_blend_mode(BlendMode::None);
+
+        result
+    }
+
+    fn draw_segments(&mut self, lines: &[(Point, Point)], color: Color, clip: Option<Rect>) -> Result<(), String> {
+        self.set_blend_mode(blend_mode(color));
+        self.set_draw_color(color);
+        self.set_clip_rect(clip);
+        let result = lines.iter().try_for_each(|&(a, b)| self.draw_line(a, b));
+        self.set_clip_rect(None);
+        self.set_blend_mode(BlendMode::None);
+
+        result
+    }
+
+    fn draw_text(&mut self, text: &Texture, dst: Rect, background: Rect, color: Color) -> Result<(), String> {
+        self.draw_border(&[background], color)?;
+        self.copy(text, None, Some(dst))
+    }
+
+    fn present(&mut self) {
+        Canvas::present(self);
+    }
+}
+
+
+/// Returns the blend mode drawing the color: blended if it is translucent.
+fn blend_mode(color: Color) -> BlendMode {
+    match color.a {
+        255 => BlendMode::None,
+        _ => BlendMode::Blend,
+    }
+}
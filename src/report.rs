@@ -0,0 +1,202 @@
+//! Report of a session, sent when quitting.
+//!
+//! The report is a JSON object with the outcome of the decisions and the
+//! bytes saved, in total and by command:
+//!
+//! ```text
+//! {"date": "2026-10-14 18:02 UTC", "directory": "/site/assets", "images": 120,
+//!  "validated": 80, "moved": 5, "undecided": 35, "unreadable": 0,
+//!  "saved_bytes": 5242880,
+//!  "commands": [{"command": "cwebp -q 80 %i -o %o.webp", "validated": 80, "saved_bytes": 5242880}]}
+//! ```
+//!
+//! It is written to the standard input of `report_command`, and posted to
+//! `report_webhook` with curl, so that the savings can be tracked without
+//! reading the output of bimgo.
+
+use std::io::Write;
+use std::path::PathBuf;
+use std::process::{Command, Stdio};
+
+use crate::img::ImgItem;
+use crate::recent::common_directory;
+use crate::settings::AppSettings;
+use crate::stats::Stats;
+use crate::utils::inherited_stdout;
+
+
+/// Seconds a webhook has to answer before the report is given up.
+const WEBHOOK_TIMEOUT: u32 = 30;
+
+/// Outcome of the decisions of a session.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct SessionReport {
+    pub date: String,
+    pub directory: Option<PathBuf>,
+    pub images: usize,
+    pub validated: usize,
+    pub moved: usize,
+    pub undecided: usize,
+    pub unreadable: usize,
+    pub saved: i64,
+    pub cmds: Stats,
+}
+
+impl SessionReport {
+    /// Collects the outcome of the decisions taken on the provided images.
+    pub fn from_imgs(imgs: &[ImgItem], cmds: &[String], date: String) -> SessionReport {
+        let mut report = SessionReport {
+            date,
+            directory: common_directory(imgs.iter().map(|img| img.source.as_path())),
+            images: imgs.len(),
+            cmds: Stats::from_imgs(imgs, cmds),
+            ..SessionReport::default()
+        };
+
+        for img in imgs {
+            match (img.is_decided(), img.is_validated()) {
+                (true, true) => report.validated += 1,
+                (true, false) => report.moved += 1,
+                (false, _) => report.undecided += 1,
+            }
+            report.unreadable += img.load_error.is_some() as usize;
+            report.saved += img.saved_bytes().unwrap_or(0);
+        }
+
+        report
+    }
+
+    /// Formats the report as a JSON object, on a single line. Only the
+    /// commands whose variant was validated are listed.
+    pub fn json(&self) -> String {
+        let directory = match &self.directory {
+            Some(directory) => json_string(&directory.to_string_lossy()),
+            None => "null".to_string(),
+        };
+        let cmds = self.cmds
+            .cmds
+            .iter()
+            .filter(|(_, s)| s.wins > 0)
+            .map(|(cmd, s)| format!(
+                r#"{{"command": {}, "validated": {}, "saved_bytes": {}}}"#,
+                json_string(cmd),
+                s.wins,
+                s.saved,
+            ))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        format!(
+            concat!(
+                r#"{{"date": {}, "directory": {}, "images": {}, "validated": {}, "moved": {}, "#,
+                r#""undecided": {}, "unreadable": {}, "saved_bytes": {}, "commands": [{}]}}"#,
+            ),
+            json_string(&self.date),
+            directory,
+            self.images,
+            self.validated,
+            self.moved,
+            self.undecided,
+            self.unreadable,
+            self.saved,
+            cmds,
+        )
+    }
+}
+
+/// Returns the text as a JSON string, quoted and escaped.
+pub fn json_string(text: &str) -> String {
+    let mut quoted = String::with_capacity(text.len() + 2);
+    quoted.push('"');
+    for c in text.chars() {
+        match c {
+            '"' => quoted.push_str("\\\""),
+            '\\' => quoted.push_str("\\\\"),
+            '\n' => quoted.push_str("\\n"),
+            '\r' => quoted.push_str("\\r"),
+            '\t' => quoted.push_str("\\t"),
+            c if (c as u32) < 0x20 => quoted.push_str(&format!("\\u{:04x}", c as u32)),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('"');
+
+    quoted
+}
+
+/// Runs the command with the report on its standard input, and waits for it.
+fn pipe_report(mut command: Command, json: &str) -> Result<(), String> {
+    let mut child = command
+        .stdin(Stdio::piped())
+        .stdout(inherited_stdout())
+        .spawn()
+        .map_err(|e| e.to_string())?;
+
+    // The child may exit without reading its input, which is not an error
+    // in itself, its exit status tells.
+    if let Some(mut stdin) = child.stdin.take() {
+        let _ = stdin.write_all(json.as_bytes());
+    }
+
+    let status = child.wait().map_err(|e| e.to_string())?;
+    match status.success() {
+        true => Ok(()),
+        false => Err(format!("exited with {status}")),
+    }
+}
+
+/// Sends the report to the report command and the webhook of the settings,
+/// if any. A failure of one doesn't prevent the other.
+pub fn send_report(json: &str, settings: &AppSettings) -> Result<(), String> {
+    let mut errors = Vec::new();
+
+    if let Some(script) = &settings.report_command {
+        let mut command = Command::new("sh");
+        command.arg("-c").arg(script);
+        if let Err(e) = pipe_report(command, json) {
+            errors.push(format!("report command '{script}' failed: {e}"));
+        }
+    }
+
+    if let Some(url) = &settings.report_webhook {
+        let mut command = Command::new("curl");
+        command
+            .args(["--fail", "--silent", "--show-error", "--output", "/dev/null"])
+            .args(["--max-time", &WEBHOOK_TIMEOUT.to_string()])
+            .args(["--header", "Content-Type: application/json", "--data-binary", "@-"])
+            .arg(url);
+        if let Err(e) = pipe_report(command, json) {
+            errors.push(format!("report webhook {url} failed: {e}"));
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(()),
+        false => Err(errors.join(", ")),
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn report_is_formatted_as_json() {
+        assert_eq!(json_string("a \"b\"\\\n\u{1}é"), r#""a \"b\"\\\n\u0001é""#);
+
+        let report = SessionReport {
+            date: "2026-10-14 18:02 UTC".to_string(),
+            images: 2,
+            undecided: 2,
+            ..SessionReport::default()
+        };
+        assert_eq!(
+            report.json(),
+            concat!(
+                r#"{"date": "2026-10-14 18:02 UTC", "directory": null, "images": 2, "validated": 0, "moved": 0, "#,
+                r#""undecided": 2, "unreadable": 0, "saved_bytes": 0, "commands": []}"#,
+            ),
+        );
+    }
+}
@@ -0,0 +1,252 @@
+//! Auto-accept rules engine.
+//!
+//! Rules are written in the configuration as plain strings, and evaluated for
+//! every processed variant. The first rule that matches a variant decides its
+//! outcome. The syntax is the following:
+//!
+//! `<accept|reject> if <condition> [and <condition>]...`
+//!
+//! Where a condition is either:
+//! - `larger`   The processed file is larger than the source.
+//! - `smaller`  The processed file is smaller than the source.
+//! - `<variable> <operator> <value>` where operator is one of `>`, `>=`, `<`,
+//!   `<=`, `==`, and value is a number, optionally followed by `%`.
+//!
+//! Available variables are `saving`, the fraction of the source size saved by
+//! the processing (i.e. `saving > 30%` or `saving > 0.3`), and the name of any
+//! configured metric. A condition on a metric that was not computed never
+//! matches.
+//!
+//! Tokens must be separated by whitespace, e.g.
+//! `accept if saving > 30% and ssim > 0.99` or `reject if larger`.
+
+use std::str::FromStr;
+
+
+/// The outcome of a rule.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RuleAction {
+    Accept,
+    Reject,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Operator {
+    Gt,
+    Ge,
+    Lt,
+    Le,
+    Eq,
+}
+
+impl Operator {
+    fn apply(&self, a: f64, b: f64) -> bool {
+        match self {
+            Operator::Gt => a > b,
+            Operator::Ge => a >= b,
+            Operator::Lt => a < b,
+            Operator::Le => a <= b,
+            Operator::Eq => a == b,
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Condition {
+    Larger,
+    Smaller,
+    Compare { variable: String, operator: Operator, value: f64 },
+}
+
+/// A list of conditions which must all match, i.e. `<condition> [and
+/// <condition>]...`.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Filter {
+    conditions: Vec<Condition>,
+}
+
+/// A rule, parsed from its string representation.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Rule {
+    pub action: RuleAction,
+    filter: Filter,
+}
+
+/// The data of a processed variant on which rules are evaluated.
+pub struct Variant<'a> {
+    pub source_size: u64,
+    pub processed_size: u64,
+    pub metrics: &'a [(String, f64)],
+}
+
+impl Variant<'_> {
+    /// Fraction of the source size saved by the processing, negative if the
+    /// processed file is larger.
+    fn saving(&self) -> f64 {
+        if self.source_size == 0 {
+            return 0.0;
+        }
+
+        (self.source_size as f64 - self.processed_size as f64) / self.source_size as f64
+    }
+
+    fn variable(&self, name: &str) -> Option<f64> {
+        match name {
+            "saving" => Some(self.saving()),
+            _ => self.metrics.iter().find(|(n, _)| n == name).map(|(_, v)| *v),
+        }
+    }
+}
+
+impl Condition {
+    fn matches(&self, variant: &Variant) -> bool {
+        match self {
+            Condition::Larger => variant.processed_size > variant.source_size,
+            Condition::Smaller => variant.processed_size < variant.source_size,
+            Condition::Compare { variable, operator, value } => variant
+                .variable(variable)
+                .is_some_and(|v| operator.apply(v, *value)),
+        }
+    }
+}
+
+impl Filter {
+    /// Returns true if every condition matches the variant. An empty filter
+    /// matches every variant.
+    pub fn matches(&self, variant: &Variant) -> bool {
+        self.conditions.iter().all(|c| c.matches(variant))
+    }
+}
+
+impl Rule {
+    /// Returns true if every condition of the rule matches the variant.
+    pub fn matches(&self, variant: &Variant) -> bool {
+        self.filter.matches(variant)
+    }
+}
+
+
+/// Returns the action of the first rule matching the variant, if any.
+pub fn evaluate(rules: &[Rule], variant: &Variant) -> Option<RuleAction> {
+    rules.iter().find(|r| r.matches(variant)).map(|r| r.action)
+}
+
+
+fn parse_condition(tokens: &[&str]) -> Result<Condition, String> {
+    match tokens {
+        ["larger"] => Ok(Condition::Larger),
+        ["smaller"] => Ok(Condition::Smaller),
+        [variable, operator, value] => {
+            let operator = match *operator {
+                ">" => Operator::Gt,
+                ">=" => Operator::Ge,
+                "<" => Operator::Lt,
+                "<=" => Operator::Le,
+                "==" => Operator::Eq,
+                _ => return Err(format!("Unknown operator '{operator}'")),
+            };
+
+            let value = match value.strip_suffix('%') {
+                Some(percent) => percent.parse::<f64>().map(|v| v / 100.0),
+                None => value.parse::<f64>(),
+            }
+            .map_err(|_| format!("Invalid value '{value}'"))?;
+
+            Ok(Condition::Compare { variable: variable.to_string(), operator, value })
+        }
+        _ => Err(format!("Invalid condition '{}'", tokens.join(" "))),
+    }
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s.split_whitespace().collect::<Vec<&str>>();
+
+        let conditions = tokens
+            .split(|t| *t == "and")
+            .map(parse_condition)
+            .collect::<Result<Vec<Condition>, String>>()?;
+
+        Ok(Filter { conditions })
+    }
+}
+
+impl FromStr for Rule {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let tokens = s.split_whitespace().collect::<Vec<&str>>();
+
+        let action = match tokens.first() {
+            Some(&"accept") => RuleAction::Accept,
+            Some(&"reject") => RuleAction::Reject,
+            _ => return Err(format!("Rule '{s}' must start with accept or reject")),
+        };
+
+        if tokens.get(1) != Some(&"if") || tokens.len() < 3 {
+            return Err(format!("Rule '{s}' is missing 'if <condition>'"));
+        }
+
+        let filter = tokens[2..]
+            .join(" ")
+            .parse::<Filter>()
+            .map_err(|e| format!("Rule '{s}': {e}"))?;
+
+        Ok(Rule { action, filter })
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn variant<'a>(source_size: u64, processed_size: u64, metrics: &'a [(String, f64)]) -> Variant<'a> {
+        Variant { source_size, processed_size, metrics }
+    }
+
+    #[test]
+    fn parse_accept_rule() {
+        let rule: Rule = "accept if saving > 30% and ssim >= 0.99".parse().unwrap();
+        assert_eq!(rule.action, RuleAction::Accept);
+        assert_eq!(rule.filter.conditions, vec![
+            Condition::Compare { variable: "saving".to_string(), operator: Operator::Gt, value: 0.3 },
+            Condition::Compare { variable: "ssim".to_string(), operator: Operator::Ge, value: 0.99 },
+        ]);
+    }
+
+    #[test]
+    fn parse_invalid_rules() {
+        assert!("keep if larger".parse::<Rule>().is_err());
+        assert!("accept larger".parse::<Rule>().is_err());
+        assert!("accept if".parse::<Rule>().is_err());
+        assert!("accept if saving ~ 3".parse::<Rule>().is_err());
+        assert!("accept if saving > x".parse::<Rule>().is_err());
+    }
+
+    #[test]
+    fn filter_matches_all_conditions() {
+        let filter: Filter = "smaller and ssim > 0.99".parse().unwrap();
+        let metrics = vec![("ssim".to_string(), 0.995)];
+
+        assert!(filter.matches(&variant(100, 50, &metrics)));
+        assert!(!filter.matches(&variant(100, 150, &metrics)));
+        assert!(Filter::default().matches(&variant(100, 150, &[])));
+    }
+
+    #[test]
+    fn evaluate_first_matching_rule() {
+        let rules: Vec<Rule> = ["reject if larger", "accept if saving > 30% and ssim > 0.99"]
+            .iter()
+            .map(|r| r.parse().unwrap())
+            .collect();
+        let metrics = vec![("ssim".to_string(), 0.995)];
+
+        assert_eq!(evaluate(&rules, &variant(100, 120, &metrics)), Some(RuleAction::Reject));
+        assert_eq!(evaluate(&rules, &variant(100, 50, &metrics)), Some(RuleAction::Accept));
+        assert_eq!(evaluate(&rules, &variant(100, 80, &metrics)), None);
+        assert_eq!(evaluate(&rules, &variant(100, 50, &[])), None);
+    }
+}
@@ -1,12 +1,26 @@
 //! Module with generic helping code related to SDL
 
+use std::collections::HashSet;
+
+use sdl2::event::{Event, WindowEvent};
 use sdl2::rect::{Rect,Point};
-use sdl2::pixels::Color;
-use sdl2::render::{Canvas, TextureCreator};
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::render::{BlendMode, Texture, TextureCreator};
+use sdl2::surface::Surface;
 use sdl2::ttf::Font;
+use sdl2::video::WindowContext;
+
+use crate::cache::LruCache;
+use crate::renderer::Renderer;
+use crate::progress::diagnostic;
+
+
+/// Size of the text textures kept by the TextCache, in bytes.
+const TEXT_CACHE_BYTES: u64 = 32 << 20;
 
 
 #[allow(unused)]
+#[derive(Clone, Copy)]
 pub enum Anchor{
     TopLeft,
     Top,
@@ -21,22 +35,87 @@ pub enum Anchor{
     BottomRight,
 }
 
+/// Key of a rendered text: the text and its wrapping width.
+type TextKey = (String, Option<u32>);
+
+/// Textures of the text drawn by TextBox, so that text which doesn't change
+/// between frames is rendered once instead of every frame.
+///
+/// Text which can't be rendered is reported once, and then skipped.
+pub struct TextCache<'a> {
+    texture_creator: &'a TextureCreator<WindowContext>,
+    textures: LruCache<TextKey, Texture<'a>>,
+    failed: HashSet<TextKey>,
+}
+
+impl<'a> TextCache<'a> {
+    pub fn new(texture_creator: &'a TextureCreator<WindowContext>) -> TextCache<'a> {
+        TextCache {
+            texture_creator,
+            textures: LruCache::default(),
+            failed: HashSet::new(),
+        }
+    }
+}
+
+/// Color of the text, unless a segment has another color.
+const TEXT_COLOR: Color = Color::RGB(255, 255, 255);
+
+/// Part of a text drawn with the same style. Segments may contain line breaks.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Segment {
+    pub text: String,
+    pub color: Color,
+    pub bold: bool,
+}
+
+impl Segment {
+    /// Returns a segment in the default style.
+    pub fn plain(text: impl Into<String>) -> Segment {
+        Segment { text: text.into(), color: TEXT_COLOR, bold: false }
+    }
+
+    /// Returns a segment of provided color.
+    pub fn colored(text: impl Into<String>, color: Color) -> Segment {
+        Segment { text: text.into(), color, bold: false }
+    }
+
+    /// Returns a segment in bold.
+    pub fn bold(text: impl Into<String>) -> Segment {
+        Segment { text: text.into(), color: TEXT_COLOR, bold: true }
+    }
+}
+
 /// Helper struct to generate a "textbox"
-pub struct TextBox<'a, T> {
-    texture_creator: &'a TextureCreator<T>,
+///
+/// The text is drawn over a background, opaque black and tight around the
+/// text by default. Rich text, made of segments of several styles, is laid out
+/// segment after segment, a segment which doesn't fit in the wrapping width
+/// goes on the next line.
+pub struct TextBox<'a> {
     font: &'a Font<'a, 'a>,
-    txt: &'a str,
+    bold_font: &'a Font<'a, 'a>,
+    segments: Vec<Segment>,
     width: Option<u32>,
+    padding: u32,
+    background: Color,
 }
 
 
-impl<'a, T> TextBox<'a, T>{
-    pub fn new(txt: &'a str, font: &'a Font, texture_creator: &'a TextureCreator<T>) -> TextBox<'a, T> {
+impl<'a> TextBox<'a>{
+    pub fn new(txt: &str, font: &'a Font) -> TextBox<'a> {
+        TextBox::rich(vec![Segment::plain(txt)], font, font)
+    }
+
+    /// Returns a text box drawing the segments, the bold ones with bold_font.
+    pub fn rich(segments: Vec<Segment>, font: &'a Font, bold_font: &'a Font) -> TextBox<'a> {
         TextBox{
-            texture_creator,
             font,
-            txt,
+            bold_font,
+            segments,
             width: None,
+            padding: 0,
+            background: Color::RGB(0, 0, 0),
         }
     }
 
@@ -45,57 +124,432 @@ impl<'a, T> TextBox<'a, T>{
         self
     }
 
-    pub fn draw<C>(&self, canvas: &mut Canvas<C>, position: Point, anchor: Anchor) -> Result<(), String>
-    where
-        C: sdl2::render::RenderTarget,
-    {
-        let s_text = self.font
-            .render(self.txt);
+    /// Sets the space between the text and the border of its background, in
+    /// pixels.
+    pub fn padded(mut self, padding: u32) -> Self {
+        self.padding = padding;
+        self
+    }
+
+    /// Sets the color of the background, translucent colors are blended with
+    /// what is below.
+    pub fn background(mut self, color: Color) -> Self {
+        self.background = color;
+        self
+    }
+
+    /// Returns the text of every segment.
+    fn text(&self) -> String {
+        self.segments.iter().map(|s| s.text.as_str()).collect()
+    }
+
+    /// Draws the text, with the texture of the cache if it was already
+    /// rendered.
+    ///
+    /// Empty text is not drawn, and text which can't be rendered is reported
+    /// instead of failing the drawing of the frame.
+    pub fn draw(&self, renderer: &mut dyn Renderer, cache: &mut TextCache, position: Point, anchor: Anchor) -> Result<(), String> {
+        if self.segments.iter().all(|s| s.text.is_empty()) {
+            return Ok(());
+        }
+
+        let key = match self.segments.as_slice() {
+            [s] if *s == Segment::plain(s.text.as_str()) => (s.text.clone(), self.width),
+            segments => (format!("{segments:?}"), self.width),
+        };
+        let texture = match cache.textures.take(&key) {
+            Some(texture) => texture,
+            None if cache.failed.contains(&key) => return Ok(()),
+            None => match self.render(cache.texture_creator) {
+                Ok(texture) => texture,
+                Err(e) => {
+                    diagnostic!("Error: unable to render text '{}': {e}", self.text());
+                    cache.failed.insert(key);
+                    return Ok(());
+                }
+            },
+        };
+
+        let query = texture.query();
+        let (bg_rect, dst_rect) = text_box_rects(position, (query.width, query.height), self.padding, anchor);
+
+        let result = renderer.draw_text(&texture, dst_rect, bg_rect, self.background);
+        cache.textures.insert(key, texture, query.width as u64 * query.height as u64 * 4);
+        cache.textures.evict_to(TEXT_CACHE_BYTES);
+
+        result
+    }
+
+    /// Renders the text to a texture.
+    fn render<'t>(&self, texture_creator: &'t TextureCreator<WindowContext>) -> Result<Texture<'t>, String> {
+        let s_text = match self.segments.as_slice() {
+            [segment] if !segment.bold => self.render_segment(segment, self.width)?,
+            _ => self.render_rich()?,
+        };
+
+        s_text.as_texture(texture_creator).map_err(|e| e.to_string())
+    }
+
+    /// Renders a segment, wrapped at provided width if any.
+    fn render_segment(&self, segment: &Segment, width: Option<u32>) -> Result<Surface<'static>, String> {
+        let font = match segment.bold {
+            true => self.bold_font,
+            false => self.font,
+        };
+
+        let s_text = font
+            .render(&segment.text);
             //.solid(Color::RGB(255,255,255))
             //.blended(Color::RGB(255, 255, 255))
             //.shaded(Color::RGB(255,255,255), Color::RGB(0,128,128))
             //.map_err(|e| format!("{e}"))?;
 
-        let s_text = match self.width {
-            Some(width) => s_text.blended_wrapped(Color::RGB(255,255,255), width),
-            None => s_text.blended(Color::RGB(255,255,255)),
-        }.map_err(|e| format!("{e}"))?;
+        match width {
+            Some(width) => s_text.blended_wrapped(segment.color, width),
+            None => s_text.blended(segment.color),
+        }.map_err(|e| format!("{e}"))
+    }
 
-        
+    /// Renders every segment, line by line, to a single surface.
+    fn render_rich(&self) -> Result<Surface<'static>, String> {
+        // Pieces of the segments between line breaks, with whether they start
+        // a new line. Empty pieces can't be rendered, they only keep the
+        // height of their line.
+        let mut pieces = Vec::new();
+        for segment in &self.segments {
+            for (k, text) in segment.text.split('\n').enumerate() {
+                let piece = Segment { text: text.to_string(), ..segment.clone() };
+                pieces.push((piece, k > 0));
+            }
+        }
 
-        let src_rect = s_text.rect();
+        let mut surfaces = Vec::new();
+        for (piece, _) in &pieces {
+            let surface = match piece.text.is_empty() {
+                true => None,
+                false => {
+                    let (w, _) = self.font.size_of(&piece.text).map_err(|e| e.to_string())?;
+                    // Only a piece wider than the whole line is wrapped.
+                    let width = self.width.filter(|&width| w > width);
+                    Some(self.render_segment(piece, width)?)
+                }
+            };
+            surfaces.push(surface);
+        }
 
-        let t_text = s_text
-            .as_texture(self.texture_creator)
-            .map_err(|e| format!("{e}"))?;
+        let line_height = self.font.height().max(1) as u32;
+        let items = pieces
+            .iter()
+            .zip(surfaces.iter())
+            .map(|((_, new_line), surface)| {
+                let size = surface.as_ref().map_or((0, line_height), |s| s.size());
+                (size, *new_line)
+            })
+            .collect::<Vec<_>>();
+        let (positions, (w, h)) = layout_segments(&items, self.width);
 
-        let (w, h) = src_rect.size();
+        let mut target = Surface::new(w.max(1), h.max(1), PixelFormatEnum::RGBA32)?;
+        for (surface, position) in surfaces.iter_mut().zip(positions) {
+            if let Some(surface) = surface {
+                // Pieces don't overlap, their pixels are copied as is.
+                surface.set_blend_mode(BlendMode::None)?;
+                let dst = Rect::new(position.x, position.y, surface.width(), surface.height());
+                surface.blit(None, &mut target, dst)?;
+            }
+        }
 
-        let position = match anchor {
-            Anchor::TopLeft     => position,
-            Anchor::Top         => position - Point::new(w as i32 / 2, 0),
-            Anchor::TopRight    => position - Point::new(w as i32, 0),
+        Ok(target)
+    }
+}
 
-            Anchor::Left        => position - Point::new(0, h as i32 / 2),
-            Anchor::Center      => position - Point::new(w as i32 / 2, h as i32 / 2),
-            Anchor::Right       => position - Point::new(w as i32, h as i32 / 2),
 
-            Anchor::BottomLeft  => position - Point::new(0, h as i32),
-            Anchor::Bottom      => position - Point::new(w as i32 / 2, h as i32),
-            Anchor::BottomRight => position - Point::new(w as i32, h as i32),
-        };
-        let dst_rect = Rect::new(position.x, position.y, src_rect.width(), src_rect.height());
+/// Returns the position of pieces of text of provided size, and the size of
+/// the whole text. A piece starting a new line, or which doesn't fit in the
+/// rest of the line, goes on the next line, below the highest piece of the
+/// line.
+fn layout_segments(items: &[((u32, u32), bool)], width: Option<u32>) -> (Vec<Point>, (u32, u32)) {
+    let (mut x, mut y, mut line_height, mut total_width) = (0, 0, 0, 0);
 
+    let positions = items
+        .iter()
+        .map(|&((w, h), new_line)| {
+            let overflows = width.is_some_and(|width| x > 0 && x + w > width);
+            if new_line || overflows {
+                y += line_height;
+                x = 0;
+                line_height = 0;
+            }
 
-        let bg_rect = match self.width {
-            Some(width) => Rect::new(position.x, position.y, width, src_rect.height()),
-            None        => Rect::new(position.x, position.y, src_rect.width(), src_rect.height()),
-        };
+            let position = Point::new(x as i32, y as i32);
+            x += w;
+            line_height = line_height.max(h);
+            total_width = total_width.max(x);
 
-        canvas.set_draw_color(Color::RGB(0, 0, 0));
-        canvas.fill_rect(bg_rect)?;
-        canvas.copy(&t_text, Some(src_rect), Some(dst_rect))?;
+            position
+        })
+        .collect();
 
-        Ok(())
+    (positions, (total_width, y + line_height))
+}
+
+
+/// Returns the rectangle of provided size placed so that its anchor point is
+/// at position.
+pub fn anchored_rect(position: Point, size: (u32, u32), anchor: Anchor) -> Rect {
+    let (w, h) = size;
+
+    let position = match anchor {
+        Anchor::TopLeft     => position,
+        Anchor::Top         => position - Point::new(w as i32 / 2, 0),
+        Anchor::TopRight    => position - Point::new(w as i32, 0),
+
+        Anchor::Left        => position - Point::new(0, h as i32 / 2),
+        Anchor::Center      => position - Point::new(w as i32 / 2, h as i32 / 2),
+        Anchor::Right       => position - Point::new(w as i32, h as i32 / 2),
+
+        Anchor::BottomLeft  => position - Point::new(0, h as i32),
+        Anchor::Bottom      => position - Point::new(w as i32 / 2, h as i32),
+        Anchor::BottomRight => position - Point::new(w as i32, h as i32),
+    };
+
+    Rect::new(position.x, position.y, w, h)
+}
+
+/// Returns the background and the text rectangles of a text of provided size,
+/// surrounded by padding, so that the anchor point of the background is at
+/// position.
+///
+/// The background fits the rendered text, which is as wide as its longest line
+/// when wrapped, and as high as all its lines.
+pub fn text_box_rects(position: Point, text_size: (u32, u32), padding: u32, anchor: Anchor) -> (Rect, Rect) {
+    let (w, h) = text_size;
+    let background = anchored_rect(position, (w + 2 * padding, h + 2 * padding), anchor);
+    let text = Rect::new(background.x() + padding as i32, background.y() + padding as i32, w, h);
+
+    (background, text)
+}
+
+/// Returns true if both events are of a kind where only the latest matters,
+/// i.e. mouse motions and window resizes.
+fn is_same_coalescable(a: &Event, b: &Event) -> bool {
+    matches!(
+        (a, b),
+        (Event::MouseMotion { .. }, Event::MouseMotion { .. })
+            | (
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. },
+                Event::Window { win_event: WindowEvent::SizeChanged(..), .. },
+            )
+            | (
+                Event::Window { win_event: WindowEvent::Resized(..), .. },
+                Event::Window { win_event: WindowEvent::Resized(..), .. },
+            )
+    )
+}
+
+/// Coalesces consecutive mouse motions and window resizes, keeping only the
+/// latest of each run. Every other event, e.g. key presses, is kept in order.
+pub fn coalesce_events<I: IntoIterator<Item = Event>>(events: I) -> Vec<Event> {
+    let mut coalesced: Vec<Event> = Vec::new();
+    for event in events {
+        match coalesced.last_mut() {
+            Some(last) if is_same_coalescable(last, &event) => *last = event,
+            _ => coalesced.push(event),
+        }
+    }
+
+    coalesced
+}
+
+
+/// Minimum exposure adjustment, in half stops.
+const MIN_EXPOSURE_STEPS: i32 = -6;
+
+/// Maximum exposure adjustment, in half stops.
+const MAX_EXPOSURE_STEPS: i32 = 4;
+
+/// Exposure adjustment of the rendering, by half stops (i.e. each step
+/// multiplies or divides the brightness by the square root of 2).
+///
+/// SDL texture color modulation can only darken, so brightening is achieved by
+/// drawing the texture additional times with additive blending.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Exposure {
+    steps: i32,
+}
+
+impl Exposure {
+    /// Brightens by half a stop, up to the maximum.
+    pub fn brighten(&mut self) {
+        self.steps = (self.steps + 1).min(MAX_EXPOSURE_STEPS);
+    }
+
+    /// Darkens by half a stop, down to the minimum.
+    pub fn darken(&mut self) {
+        self.steps = (self.steps - 1).max(MIN_EXPOSURE_STEPS);
+    }
+
+    /// Resets to the original brightness.
+    pub fn reset(&mut self) {
+        self.steps = 0;
+    }
+
+    /// Returns the exposure adjustment in stops (EV).
+    pub fn stops(&self) -> f32 {
+        self.steps as f32 / 2.
+    }
+
+    /// Returns the factor the brightness is multiplied by.
+    pub fn factor(&self) -> f32 {
+        2f32.powf(self.stops())
+    }
+
+    /// Returns the color modulation of every pass needed to render the
+    /// exposure. The first pass is drawn normally, the following ones with
+    /// additive blending.
+    pub fn passes(&self) -> Vec<u8> {
+        let factor = self.factor();
+        if factor <= 1. {
+            return vec![(factor * 255.).round() as u8];
+        }
+
+        let mut passes = vec![255];
+        let mut remaining = factor - 1.;
+        while remaining > 0.002 {
+            passes.push((remaining.min(1.) * 255.).round() as u8);
+            remaining -= 1.;
+        }
+
+        passes
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use sdl2::keyboard::{Keycode, Mod};
+    use sdl2::mouse::MouseState;
+
+    fn key_down(keycode: Keycode) -> Event {
+        Event::KeyDown {
+            timestamp: 0,
+            window_id: 0,
+            keycode: Some(keycode),
+            scancode: None,
+            keymod: Mod::NOMOD,
+            repeat: false,
+        }
+    }
+
+    fn mouse_motion(x: i32) -> Event {
+        Event::MouseMotion {
+            timestamp: 0,
+            window_id: 0,
+            which: 0,
+            mousestate: MouseState::from_sdl_state(0),
+            x,
+            y: 0,
+            xrel: 0,
+            yrel: 0,
+        }
+    }
+
+    fn size_changed(w: i32) -> Event {
+        Event::Window { timestamp: 0, window_id: 0, win_event: WindowEvent::SizeChanged(w, 100) }
+    }
+
+    #[test]
+    fn coalesce_keeps_every_key() {
+        let events = coalesce_events(vec![
+            key_down(Keycode::Semicolon),
+            key_down(Keycode::Space),
+            key_down(Keycode::Space),
+        ]);
+        assert_eq!(events.len(), 3);
+        assert!(matches!(events[0], Event::KeyDown { keycode: Some(Keycode::Semicolon), .. }));
+        assert!(matches!(events[1], Event::KeyDown { keycode: Some(Keycode::Space), .. }));
+    }
+
+    #[test]
+    fn coalesce_keeps_latest_motion_and_resize() {
+        let events = coalesce_events(vec![
+            mouse_motion(1),
+            mouse_motion(2),
+            key_down(Keycode::O),
+            mouse_motion(3),
+            size_changed(10),
+            size_changed(20),
+        ]);
+        assert_eq!(events.len(), 4);
+        assert!(matches!(events[0], Event::MouseMotion { x: 2, .. }));
+        assert!(matches!(events[1], Event::KeyDown { keycode: Some(Keycode::O), .. }));
+        assert!(matches!(events[2], Event::MouseMotion { x: 3, .. }));
+        assert!(matches!(events[3], Event::Window { win_event: WindowEvent::SizeChanged(20, 100), .. }));
+    }
+
+    #[test]
+    fn segments_are_laid_out_by_line() {
+        let items = [((30, 10), false), ((20, 12), false), ((40, 10), true), ((10, 10), false)];
+        let (positions, size) = layout_segments(&items, None);
+        assert_eq!(positions, vec![Point::new(0, 0), Point::new(30, 0), Point::new(0, 12), Point::new(40, 12)]);
+        assert_eq!(size, (50, 22));
+    }
+
+    #[test]
+    fn segments_overflowing_width_go_on_next_line() {
+        let items = [((30, 10), false), ((30, 10), false), ((60, 20), false)];
+        let (positions, size) = layout_segments(&items, Some(50));
+        assert_eq!(positions, vec![Point::new(0, 0), Point::new(0, 10), Point::new(0, 20)]);
+        assert_eq!(size, (60, 40));
+    }
+
+    #[test]
+    fn text_box_is_anchored_with_padding() {
+        let (background, text) = text_box_rects(Point::new(100, 0), (40, 30), 4, Anchor::TopRight);
+        assert_eq!(background, Rect::new(52, 0, 48, 38));
+        assert_eq!(text, Rect::new(56, 4, 40, 30));
+
+        let (background, text) = text_box_rects(Point::new(0, 100), (40, 30), 0, Anchor::BottomLeft);
+        assert_eq!(background, Rect::new(0, 70, 40, 30));
+        assert_eq!(text, background);
+    }
+
+    #[test]
+    fn exposure_default_is_single_pass() {
+        assert_eq!(Exposure::default().passes(), vec![255]);
+    }
+
+    #[test]
+    fn exposure_darken_modulates_color() {
+        let mut exposure = Exposure::default();
+        exposure.darken();
+        exposure.darken();
+        assert_eq!(exposure.stops(), -1.);
+        assert_eq!(exposure.passes(), vec![128]);
+    }
+
+    #[test]
+    fn exposure_brighten_adds_passes() {
+        let mut exposure = Exposure::default();
+        exposure.brighten();
+        exposure.brighten();
+        assert_eq!(exposure.passes(), vec![255, 255]);
+
+        exposure.brighten();
+        assert_eq!(exposure.passes(), vec![255, 255, 211]);
+    }
+
+    #[test]
+    fn exposure_is_clamped() {
+        let mut exposure = Exposure::default();
+        for _ in 0..20 {
+            exposure.brighten();
+        }
+        assert_eq!(exposure.factor(), 4.);
+        exposure.reset();
+        for _ in 0..20 {
+            exposure.darken();
+        }
+        assert_eq!(exposure.factor(), 0.125);
     }
 }
@@ -0,0 +1,240 @@
+//! Session file, storing the decisions taken during a review session.
+//!
+//! Decisions are applied to files immediately, but the knowledge of which
+//! images were reviewed (and how to undo them) only lives in memory. The
+//! session file is written periodically, so that after a crash the review can
+//! be resumed with `--resume` where it was left.
+//!
+//! The file is a list of tab-separated lines:
+//! - `index <n>`   the index of the image displayed.
+//! - `validated <source> <cmd index> <tmp path> <validated path> <deleted path> <permanent>`
+//!   where the deleted path is empty if the source was kept or deleted from
+//!   trash, and permanent is `permanent` once it was deleted from trash, empty
+//!   otherwise. Files of older versions without the permanent field are read
+//!   as not permanent.
+//! - `moved <source> <moved path>`
+//! - `note <source> <note>`
+//! - `unreadable <source> <error>` for sources which couldn't be loaded.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::img::{ImageDecision, ImgItem};
+
+
+/// A decision taken on an image.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Decision {
+    Validated {
+        cmd_index: usize,
+        tmp_path: PathBuf,
+        validated_path: PathBuf,
+        deleted_path: Option<PathBuf>,
+        permanent: bool,
+    },
+    Moved {
+        moved_path: PathBuf,
+    },
+}
+
+/// The state of a review session.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Session {
+    pub index: usize,
+    pub decisions: Vec<(PathBuf, Decision)>,
+    pub notes: Vec<(PathBuf, String)>,
+    pub unreadable: Vec<(PathBuf, String)>,
+}
+
+impl Session {
+    /// Collects the decisions taken on the provided images.
+    pub fn from_imgs(index: usize, imgs: &[ImgItem]) -> Session {
+        let decisions = imgs
+            .iter()
+            .filter_map(|img| match img.decision() {
+                ImageDecision::Undecided => None,
+                ImageDecision::Moved { moved_path } => {
+                    Some((img.source.clone(), Decision::Moved { moved_path: moved_path.clone() }))
+                }
+                ImageDecision::Validated { cmd_index, deleted, permanent } => {
+                    let p = img.get_validated()?;
+                    Some((img.source.clone(), Decision::Validated {
+                        cmd_index: *cmd_index,
+                        tmp_path: p.tmp_path()?.to_path_buf(),
+                        validated_path: p.validated_path()?.to_path_buf(),
+                        deleted_path: deleted.clone(),
+                        permanent: *permanent,
+                    }))
+                }
+            })
+            .collect();
+
+        let notes = imgs
+            .iter()
+            .filter_map(|img| Some((img.source.clone(), img.note.clone()?)))
+            .collect();
+
+        let unreadable = imgs
+            .iter()
+            .filter_map(|img| Some((img.source.clone(), img.load_error.clone()?)))
+            .collect();
+
+        Session { index, decisions, notes, unreadable }
+    }
+
+    /// Formats the session as the content of the session file.
+    pub fn format(&self) -> String {
+        let mut content = format!("index\t{}\n", self.index);
+
+        for (source, decision) in &self.decisions {
+            let line = match decision {
+                Decision::Validated { cmd_index, tmp_path, validated_path, deleted_path, permanent } => format!(
+                    "validated\t{}\t{cmd_index}\t{}\t{}\t{}\t{}\n",
+                    source.display(),
+                    tmp_path.display(),
+                    validated_path.display(),
+                    deleted_path.as_ref().map(|p| p.display().to_string()).unwrap_or_default(),
+                    if *permanent { "permanent" } else { "" },
+                ),
+                Decision::Moved { moved_path } => {
+                    format!("moved\t{}\t{}\n", source.display(), moved_path.display())
+                }
+            };
+            content.push_str(&line);
+        }
+
+        for (source, note) in &self.notes {
+            // Notes are typed on a single line, tabs would break the format.
+            content.push_str(&format!("note\t{}\t{}\n", source.display(), note.replace('\t', " ")));
+        }
+
+        for (source, error) in &self.unreadable {
+            let error = error.replace(['\t', '\n'], " ");
+            content.push_str(&format!("unreadable\t{}\t{error}\n", source.display()));
+        }
+
+        content
+    }
+
+    /// Parses the content of a session file.
+    pub fn parse(content: &str) -> Result<Session, String> {
+        let mut session = Session::default();
+
+        for line in content.lines().filter(|l| !l.is_empty()) {
+            let fields = line.split('\t').collect::<Vec<&str>>();
+            match fields.as_slice() {
+                ["index", index] => {
+                    session.index = index
+                        .parse()
+                        .map_err(|_| format!("Invalid index in session: '{line}'"))?;
+                }
+                ["validated", source, cmd_index, tmp_path, validated_path, deleted_path, permanent @ ..] => {
+                    let cmd_index = cmd_index
+                        .parse()
+                        .map_err(|_| format!("Invalid command index in session: '{line}'"))?;
+                    let permanent = match permanent {
+                        [] | [""] => false,
+                        ["permanent"] => true,
+                        _ => return Err(format!("Invalid permanent field in session: '{line}'")),
+                    };
+                    session.decisions.push((PathBuf::from(source), Decision::Validated {
+                        cmd_index,
+                        tmp_path: PathBuf::from(tmp_path),
+                        validated_path: PathBuf::from(validated_path),
+                        deleted_path: (!deleted_path.is_empty()).then(|| PathBuf::from(deleted_path)),
+                        permanent,
+                    }));
+                }
+                ["moved", source, moved_path] => {
+                    session.decisions.push((PathBuf::from(source), Decision::Moved {
+                        moved_path: PathBuf::from(moved_path),
+                    }));
+                }
+                ["note", source, note] => {
+                    session.notes.push((PathBuf::from(source), note.to_string()));
+                }
+                ["unreadable", source, error] => {
+                    session.unreadable.push((PathBuf::from(source), error.to_string()));
+                }
+                _ => return Err(format!("Invalid line in session: '{line}'")),
+            }
+        }
+
+        Ok(session)
+    }
+
+    /// Reads the session file at provided path.
+    pub fn load(path: &Path) -> Result<Session, String> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+
+        Session::parse(&content)
+    }
+}
+
+
+/// Writes the content to the session file.
+///
+/// The content is first written to a temporary file which is then renamed, so
+/// that a crash while writing never leaves a truncated session file.
+pub fn write_session_file(path: &Path, content: &str) -> Result<(), String> {
+    if let Some(parent) = path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Unable to create {}: {e}", parent.display()))?;
+    }
+
+    let mut tmp_path = path.as_os_str().to_os_string();
+    tmp_path.push(".tmp");
+
+    fs::write(&tmp_path, content)
+        .and_then(|_| fs::rename(&tmp_path, path))
+        .map_err(|e| format!("Unable to write {}: {e}", path.display()))
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn session_round_trip() {
+        let session = Session {
+            index: 12,
+            decisions: vec![
+                (PathBuf::from("/a/1.jpg"), Decision::Validated {
+                    cmd_index: 2,
+                    tmp_path: PathBuf::from("/tmp/1_processed_2.jpg"),
+                    validated_path: PathBuf::from("/a/1.jpg"),
+                    deleted_path: Some(PathBuf::from("/trash/a/1.jpg")),
+                    permanent: false,
+                }),
+                (PathBuf::from("/a/2.jpg"), Decision::Validated {
+                    cmd_index: 0,
+                    tmp_path: PathBuf::from("/tmp/2_processed_0.webp"),
+                    validated_path: PathBuf::from("/a/2.webp"),
+                    deleted_path: None,
+                    permanent: true,
+                }),
+                (PathBuf::from("/a/3.jpg"), Decision::Moved { moved_path: PathBuf::from("/a/discard/3.jpg") }),
+            ],
+            notes: vec![(PathBuf::from("/a/2.jpg"), "crop before compressing".to_string())],
+            unreadable: vec![(PathBuf::from("/a/4.jpg"), "Unsupported image format".to_string())],
+        };
+
+        assert_eq!(Session::parse(&session.format()), Ok(session));
+    }
+
+    #[test]
+    fn session_invalid_lines() {
+        assert!(Session::parse("index\tx").is_err());
+        assert!(Session::parse("moved\t/a/1.jpg").is_err());
+        assert!(Session::parse("validated\t/a\tx\t/b\t/c\t").is_err());
+        assert!(Session::parse("validated\t/a\t0\t/b\t/c\t\tyes").is_err());
+    }
+
+    #[test]
+    fn session_without_permanent_field() {
+        let session = Session::parse("validated\t/a/1.jpg\t0\t/tmp/1.jpg\t/a/1.jpg\t/trash/a/1.jpg\n").unwrap();
+        assert!(matches!(session.decisions[0].1, Decision::Validated { permanent: false, .. }));
+    }
+}
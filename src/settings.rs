@@ -1,12 +1,17 @@
 #![allow(dead_code)]
 
+use std::collections::BTreeMap;
 use std::path::{Path, PathBuf};
-use clap::Parser;
-use serde::Deserialize;
+use clap::{ArgEnum, Parser, Subcommand};
+use serde::{Deserialize, Serialize};
 use std::fs;
 use std::io;
 
+use crate::action::{Action, ZoomDirection};
 use crate::utils::expand_tilde;
+use crate::paths;
+use crate::sidecar::check_pattern;
+use crate::config_check::{field_names, format_problems, key_position, unknown_keys, ConfigProblem};
 
 /// Setting to select the image fitting method, applied when switching image. 
 /// - FitWidth  fits the image to the width of the window/split (depends 
@@ -20,7 +25,7 @@ use crate::utils::expand_tilde;
 /// - ClearZoom resets the zoom to 1, showing the real size of the image.
 /// - KeepZoom  keeps the same zoom level.
 /// - NoFit     Does nothing.
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub enum FitMode {
     FitWidth,
     FitHeight,
@@ -35,32 +40,79 @@ impl Default for FitMode { fn default() -> Self { FitMode::FitBest } }
 
 /// Setting to select whether the image is duplicated on both sections or 
 /// continued from one section to the next.
-#[derive(Deserialize)]
+///
+/// Single hides the source and displays the processed image over the whole
+/// window, the split view can be toggled back at runtime.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
 pub enum DisplayMode {
     Duplicate,
     Continuous,
+    Single,
 }
 
 impl Default for DisplayMode { fn default() -> Self { DisplayMode::Continuous } }
 
 
+/// How the outputs of the commands are verified before being displayed.
+///
+/// Header checks that the output starts like an image of a known format,
+/// Decode additionally decodes it entirely, which also catches truncated
+/// outputs.
+#[derive(Clone, Copy, Default, Deserialize, PartialEq)]
+pub enum OutputCheck {
+    None,
+    #[default]
+    Header,
+    Decode,
+}
+
+
 /// The position of the source image on the screen.
 ///
 /// This controls wether the screen is split vertically or horizontally as
 /// well.
-#[derive(Deserialize)]
+///
+/// Auto places the source on the left for images displayed larger side by
+/// side (e.g. portrait images), and on top otherwise.
+#[derive(Clone, Copy, Deserialize, PartialEq, Serialize)]
 pub enum SourcePosition {
     Top,
     Bottom,
     Left,
     Right,
+    Auto,
 }
 impl Default for SourcePosition { fn default() -> Self { SourcePosition::Left } }
 
+/// Colors of the validation indicators and of the size changes.
+///
+/// Deuteranopia and Protanopia are palettes which stay distinguishable with
+/// these kinds of red-green color blindness.
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq)]
+pub enum ColorPalette {
+    #[default]
+    Default,
+    Deuteranopia,
+    Protanopia,
+}
+
+impl SourcePosition {
+    /// Returns the position on the other side of the split.
+    pub fn mirrored(self) -> SourcePosition {
+        match self {
+            SourcePosition::Top => SourcePosition::Bottom,
+            SourcePosition::Bottom => SourcePosition::Top,
+            SourcePosition::Left => SourcePosition::Right,
+            SourcePosition::Right => SourcePosition::Left,
+            SourcePosition::Auto => SourcePosition::Auto,
+        }
+    }
+}
+
 
 /// Setting to choose whether movement key move the image, or the view (i.e.
 /// in image mode, up moves image up, while in View mode, up moves image down).
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 pub enum MoveMode {
     Image,
     View,
@@ -72,10 +124,173 @@ impl Default for MoveMode { fn default() -> Self { MoveMode::Image } }
 #[clap(author, version, about, long_about = None)]
 pub struct Cli {
     #[clap(long)]
-    /// Location of the configuration file.
-    #[clap(default_value_t = String::from("~/.config/bimgo/bimgo.toml"))]
-    config: String,
-    
+    /// Location of the configuration file, $XDG_CONFIG_HOME/bimgo/bimgo.toml
+    /// by default.
+    config: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Name of the profile of the config file to use, e.g. `web` for the
+    /// `[profile.web]` table.
+    pub profile: Option<String>,
+
+    #[clap(long)]
+    /// Restore a file from the trash to its original location, then exit. The
+    /// path can either be the original location or the location in trash.
+    pub restore: Option<PathBuf>,
+
+    #[clap(long)]
+    /// Resume the previous session: the decisions recorded in the session
+    /// file are restored, so they can still be undone.
+    pub resume: bool,
+
+    #[clap(long)]
+    /// Write the performance counters (frame time, texture upload time,
+    /// compute queue, event latency) to a CSV file, one line per frame.
+    pub perf_log: Option<PathBuf>,
+
+    #[clap(long, arg_enum)]
+    /// Print the progress of the session on stdout, one JSON line per
+    /// processed, validated or failed image.
+    pub progress: Option<ProgressFormat>,
+
+    #[clap(long)]
+    /// Accessibility preset: larger text, a thicker validation border, and
+    /// high contrast colors safe for color blindness.
+    pub accessible: bool,
+
+    #[clap(subcommand)]
+    pub command: Option<CliCommand>,
+}
+
+/// Format of the progress printed on stdout, see progress.
+#[derive(ArgEnum, Clone, Copy, Debug, PartialEq)]
+pub enum ProgressFormat {
+    Json,
+}
+
+#[derive(Subcommand)]
+pub enum CliCommand {
+    /// Print how often the variant of each command was validated, and its
+    /// average savings, then exit. With a directory, summarize its images by
+    /// format and project the savings of reviewing them instead.
+    Stats {
+        /// Directory whose images are summarized.
+        directory: Option<PathBuf>,
+    },
+
+    /// Print how often the source was picked over the variant of each command
+    /// in blind mode, then exit.
+    Blind,
+}
+
+/// A decision action which moves the source image into a folder when the
+/// associated key is pressed, e.g. `d` moves it to `./discard`.
+///
+/// key     is the SDL name of the key (e.g. "d", "a", "F1").
+/// folder  is the destination folder. A relative folder is resolved against
+///         the directory of the source image, an absolute folder is used as is.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MoveAction {
+    pub key: String,
+    pub folder: PathBuf,
+}
+
+/// A named set of settings replacing the ones of the config file, selected
+/// with `--profile <name>`, e.g. `[profile.web]`. A setting missing from the
+/// profile keeps its value.
+#[derive(Clone, Default, Deserialize)]
+pub struct Profile {
+    pub cmds_file: Option<PathBuf>,
+    pub trash_directory: Option<PathBuf>,
+    pub rules: Option<Vec<String>>,
+    pub fit_mode: Option<FitMode>,
+}
+
+impl MoveAction {
+    /// Returns the folder where the provided source image will be moved.
+    pub fn folder_for(&self, source: &Path) -> PathBuf {
+        if self.folder.is_absolute() {
+            self.folder.clone()
+        } else {
+            source
+                .parent()
+                .unwrap_or_else(|| Path::new(""))
+                .join(&self.folder)
+        }
+    }
+}
+
+/// An external command computing a quality metric between the source and a
+/// processed variant, e.g. `butteraugli %a %b`.
+///
+/// name     is the name of the metric, shown in the overlay.
+/// command  is the command, where %a is replaced by the source and %b by the
+///          processed variant. The first number printed on stdout is used as
+///          the value of the metric.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MetricCommand {
+    pub name: String,
+    pub command: String,
+}
+
+/// A mouse button, as named in the configuration.
+#[derive(Clone, Copy, Deserialize, PartialEq)]
+pub enum MouseButtonName {
+    Left,
+    Middle,
+    Right,
+    X1,
+    X2,
+}
+
+/// An action which can be bound to a mouse button.
+///
+/// ToggleActualSize switches between the real size of the image (zooming
+/// towards the mouse cursor) and the fit mode.
+#[derive(Clone, Copy, Deserialize)]
+pub enum MouseAction {
+    NextImage,
+    PrevImage,
+    NextCmd,
+    PrevCmd,
+    Validate,
+    Undo,
+    ZoomIn,
+    ZoomOut,
+    ToggleActualSize,
+    ToggleFullscreen,
+}
+
+impl MouseAction {
+    /// Returns the action of the application run by the mouse action.
+    pub fn action(self) -> Action {
+        match self {
+            MouseAction::NextImage => Action::NextImage,
+            MouseAction::PrevImage => Action::PrevImage,
+            MouseAction::NextCmd => Action::NextCmd,
+            MouseAction::PrevCmd => Action::PrevCmd,
+            MouseAction::Validate => Action::Validate,
+            MouseAction::Undo => Action::Undo,
+            MouseAction::ZoomIn => Action::Zoom(ZoomDirection::In),
+            MouseAction::ZoomOut => Action::Zoom(ZoomDirection::Out),
+            MouseAction::ToggleActualSize => Action::ToggleActualSize,
+            MouseAction::ToggleFullscreen => Action::ToggleFullscreen,
+        }
+    }
+}
+
+/// Binds a click of a mouse button to an action.
+///
+/// clicks  is 1 for a single click, 2 for a double click.
+#[derive(Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct MouseBinding {
+    pub button: MouseButtonName,
+    #[serde(default = "default_clicks")]
+    pub clicks: u8,
+    pub action: MouseAction,
 }
 
 /// Struct that stores the commands, which are loaded from a file.
@@ -85,7 +300,8 @@ pub struct Commands {
 
 /// Settings of the app, some of these will be loaded from the config file, 
 /// possibly overwritten from command line arguments.
-#[derive(Default, Deserialize)]
+#[derive(Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
 pub struct AppSettings{
 
     #[serde(default = "default_processing_directory")]
@@ -94,6 +310,15 @@ pub struct AppSettings{
     #[serde(default = "default_trash_directory")]
     pub trash_directory: PathBuf,
 
+    #[serde(default)]
+    pub tmp_namespace: Option<String>,
+
+    #[serde(default)]
+    pub copy_sources_from: Vec<PathBuf>,
+
+    #[serde(default)]
+    pub output_check: OutputCheck,
+
     #[serde(default = "default_cmd_file")]
     pub cmds_file: PathBuf,
 
@@ -111,42 +336,400 @@ pub struct AppSettings{
 
     #[serde(default)]
     pub move_mode: MoveMode,
+
+    #[serde(default)]
+    pub move_actions: Vec<MoveAction>,
+
+    #[serde(default)]
+    pub rename_pattern: Option<String>,
+
+    #[serde(default)]
+    pub keep_source: bool,
+
+    #[serde(default = "default_skip_hidden")]
+    pub skip_hidden: bool,
+
+    #[serde(default = "default_sidecar_patterns")]
+    pub sidecar_patterns: Vec<String>,
+
+    #[serde(default)]
+    pub carry_sidecars: bool,
+
+    #[serde(default)]
+    pub confirm_destructive: bool,
+
+    #[serde(default = "default_detect_duplicates")]
+    pub detect_duplicates: bool,
+
+    #[serde(default = "default_duplicate_distance")]
+    pub duplicate_distance: u32,
+
+    #[serde(default)]
+    pub metrics: Vec<MetricCommand>,
+
+    #[serde(default)]
+    pub rules: Vec<String>,
+
+    #[serde(default)]
+    pub quality_threshold: Option<String>,
+
+    #[serde(default)]
+    pub auto_select_best: bool,
+
+    #[serde(default)]
+    pub process_all_commands: bool,
+
+    #[serde(default = "default_remember_preferred_cmd")]
+    pub remember_preferred_cmd: bool,
+
+    #[serde(default = "default_min_zoom")]
+    pub min_zoom: f32,
+
+    #[serde(default = "default_max_zoom")]
+    pub max_zoom: f32,
+
+    #[serde(default = "default_session_file")]
+    pub session_file: PathBuf,
+
+    #[serde(default = "default_stats_file")]
+    pub stats_file: PathBuf,
+
+    #[serde(default = "default_blind_file")]
+    pub blind_file: PathBuf,
+
+    #[serde(default = "default_recent_file")]
+    pub recent_file: PathBuf,
+
+    #[serde(default)]
+    pub report_command: Option<String>,
+
+    #[serde(default)]
+    pub report_webhook: Option<String>,
+
+    #[serde(default = "default_autosave_interval")]
+    pub autosave_interval: u64,
+
+    #[serde(default = "default_tour_pause")]
+    pub tour_pause: u64,
+
+    #[serde(default = "default_quality")]
+    pub quality: u8,
+
+    #[serde(default = "default_quality_step")]
+    pub quality_step: u8,
+
+    #[serde(default = "default_compute_threads")]
+    pub compute_threads: usize,
+
+    #[serde(default = "default_texture_budget")]
+    pub texture_budget: u64,
+
+    #[serde(default = "default_progressive_size")]
+    pub progressive_size: u64,
+
+    #[serde(default)]
+    pub fast_preview: bool,
+
+    #[serde(default)]
+    pub accessible: bool,
+
+    #[serde(default)]
+    pub color_palette: ColorPalette,
+
+    #[serde(default = "default_forward_bias")]
+    pub forward_bias: u32,
+
+    #[serde(default = "default_max_process_weight")]
+    pub max_process_weight: u32,
+
+    #[serde(default)]
+    pub sandbox: bool,
+
+    #[serde(default)]
+    pub allowed_commands: Vec<String>,
+
+    #[serde(default = "default_process_retries")]
+    pub process_retries: u32,
+
+    #[serde(default = "default_retry_backoff")]
+    pub retry_backoff: u64,
+
+    #[serde(default)]
+    pub process_niceness: i32,
+
+    #[serde(default)]
+    pub process_cpu_limit: u32,
+
+    #[serde(default = "default_split_ratio")]
+    pub split_ratio: f32,
+
+    #[serde(default = "default_mouse_bindings")]
+    pub mouse_bindings: Vec<MouseBinding>,
+
+    #[serde(default)]
+    pub profile: BTreeMap<String, Profile>,
 }
 
 impl AppSettings {
 
-    pub fn new() -> io::Result<AppSettings> {
-        let config_path = expand_tilde("~/.config/bimgo/bimgo.toml")?;
-        let mut settings = Self::from_file(&config_path)?;
-        
-        settings.expand_home()?;
+    /// Reads the config file, with the settings of the provided profile.
+    pub fn new(profile: Option<&str>) -> io::Result<AppSettings> {
+        Self::from_file(&Self::config_path()?, profile)
+    }
 
-        Ok(settings)
+    /// Returns the location of the config file.
+    pub fn config_path() -> io::Result<PathBuf> {
+        expand_tilde(paths::config_file())
     }
 
 
+    /// Replaces the settings by the ones of the profile.
+    pub fn apply_profile(&mut self, name: &str) -> Result<(), String> {
+        let profile = match self.profile.get(name) {
+            Some(profile) => profile.clone(),
+            None if self.profile.is_empty() => {
+                return Err(format!("unknown profile '{name}', the config file has no profiles"));
+            }
+            None => {
+                let names = self.profile.keys().cloned().collect::<Vec<String>>().join(", ");
+                return Err(format!("unknown profile '{name}', expected one of {names}"));
+            }
+        };
+
+        if let Some(cmds_file) = profile.cmds_file {
+            self.cmds_file = cmds_file;
+        }
+        if let Some(trash_directory) = profile.trash_directory {
+            self.trash_directory = trash_directory;
+        }
+        if let Some(rules) = profile.rules {
+            self.rules = rules;
+        }
+        if let Some(fit_mode) = profile.fit_mode {
+            self.fit_mode = fit_mode;
+        }
+
+        Ok(())
+    }
+
     /// Expands ~ to home in settings
     fn expand_home(&mut self) -> io::Result<()> {
         self.processing_directory = expand_tilde(&self.processing_directory)?;
         self.trash_directory = expand_tilde(&self.trash_directory)?;
         self.cmds_file = expand_tilde(&self.cmds_file)?;
+        self.session_file = expand_tilde(&self.session_file)?;
+        self.stats_file = expand_tilde(&self.stats_file)?;
+        self.blind_file = expand_tilde(&self.blind_file)?;
+        self.recent_file = expand_tilde(&self.recent_file)?;
+        for directory in self.copy_sources_from.iter_mut() {
+            *directory = expand_tilde(&directory)?;
+        }
+        for action in self.move_actions.iter_mut() {
+            action.folder = expand_tilde(&action.folder)?;
+        }
 
         Ok(())
     }
 
-    /// Atempts to read config file at provided path.
-    pub fn from_file(config_file: &Path) -> io::Result<AppSettings> {
-        let config_string = fs::read_to_string(config_file)?;
+    /// Returns the configured directories which don't exist, positioned at
+    /// their key in the config file.
+    fn missing_directories(&self, text: &str) -> Vec<ConfigProblem> {
+        let directories = std::iter::once(("processing_directory", &self.processing_directory))
+            .chain(self.copy_sources_from.iter().map(|d| ("copy_sources_from", d)));
+
+        directories
+            .filter(|(_, d)| !d.is_dir())
+            .map(|(key, d)| ConfigProblem::at_key(text, key, format!("{key}: {} is not a directory", d.display())))
+            .collect()
+    }
+
+    /// Parses the content of a config file. Every unknown key is reported,
+    /// otherwise the first invalid value, otherwise every invalid sidecar
+    /// pattern.
+    pub fn parse(text: &str) -> Result<AppSettings, Vec<ConfigProblem>> {
+        let value = toml::from_str::<toml::Value>(text).map_err(|e| vec![ConfigProblem::from_toml(text, &e)])?;
+
+        let mut problems = Vec::new();
+        if let Some(table) = value.as_table() {
+            problems.extend(unknown_keys(text, table, field_names::<AppSettings>(), ""));
+
+            let sections = [
+                ("move_actions", field_names::<MoveAction>()),
+                ("metrics", field_names::<MetricCommand>()),
+                ("mouse_bindings", field_names::<MouseBinding>()),
+            ];
+            for (section, fields) in sections {
+                let entries = table.get(section).and_then(|v| v.as_array()).into_iter().flatten();
+                for entry in entries.filter_map(|e| e.as_table()) {
+                    problems.extend(unknown_keys(text, entry, fields, section));
+                }
+            }
+
+            let profiles = table.get("profile").and_then(|v| v.as_table()).into_iter().flatten();
+            for (name, profile) in profiles {
+                if let Some(profile) = profile.as_table() {
+                    problems.extend(unknown_keys(text, profile, field_names::<Profile>(), &format!("profile.{name}")));
+                }
+            }
+        }
+        if !problems.is_empty() {
+            return Err(problems);
+        }
+
+        let settings: AppSettings = toml::from_str(text).map_err(|e| vec![ConfigProblem::from_toml(text, &e)])?;
+        let problems = settings
+            .sidecar_patterns
+            .iter()
+            .filter_map(|p| check_pattern(p).err())
+            .map(|e| ConfigProblem::at_key(text, "sidecar_patterns", e))
+            .collect::<Vec<ConfigProblem>>();
+        match problems.is_empty() {
+            true => Ok(settings),
+            false => Err(problems),
+        }
+    }
+
+    /// Atempts to read config file at provided path, applies the profile if
+    /// any, and checks that the configured directories exist.
+    pub fn from_file(config_file: &Path, profile: Option<&str>) -> io::Result<AppSettings> {
+        let text = fs::read_to_string(config_file)
+            .map_err(|e| io::Error::new(e.kind(), format!("Unable to read config file {}: {e}", config_file.display())))?;
+        let invalid = |problems: Vec<ConfigProblem>| {
+            let message = format_problems(&config_file.display().to_string(), &text, &problems);
+            io::Error::new(io::ErrorKind::InvalidData, message)
+        };
+
+        let mut settings = Self::parse(&text).map_err(invalid)?;
+        if let Some(name) = profile {
+            settings
+                .apply_profile(name)
+                .map_err(|e| io::Error::new(io::ErrorKind::InvalidInput, format!("{}: {e}", config_file.display())))?;
+        }
+        settings.expand_home()?;
+
+        let problems = settings.missing_directories(&text);
+        match problems.is_empty() {
+            true => Ok(settings),
+            false => Err(invalid(problems)),
+        }
+    }
+}
+
+/// Values of the settings which can be changed at runtime, written back to
+/// the config file by the "save settings" action.
+#[derive(Default, Serialize)]
+pub struct RuntimeSettings {
+    pub process_all_commands: bool,
+    pub fast_preview: bool,
+    pub source_position: SourcePosition,
+    pub display_mode: DisplayMode,
+}
 
-        toml::from_str(&config_string)
-            .map_err(|e| 
-                     io::Error::new(io::ErrorKind::InvalidData, format!("Unable to parse config file: {e}")))
+impl RuntimeSettings {
+    /// Returns the content of the config file with these settings, keeping
+    /// its comments and layout. A setting already in the file is replaced on
+    /// its line, a setting missing from the file is added before the first
+    /// table unless it has its default value.
+    pub fn update_config(&self, text: &str) -> Result<String, String> {
+        let to_table = |settings: &RuntimeSettings| match toml::Value::try_from(settings) {
+            Ok(toml::Value::Table(table)) => Ok(table),
+            Ok(_) => Err("Settings are not a table".to_string()),
+            Err(e) => Err(format!("Unable to serialize settings: {e}")),
+        };
+        let (values, defaults) = (to_table(self)?, to_table(&RuntimeSettings::default())?);
+
+        let mut lines = text.lines().map(str::to_string).collect::<Vec<String>>();
+        let first_table = lines.iter().position(|l| l.trim_start().starts_with('[')).unwrap_or(lines.len());
+
+        let mut missing = Vec::new();
+        for (key, value) in values.iter() {
+            match lines[..first_table].iter_mut().find(|l| line_key(l) == Some(key)) {
+                Some(line) => *line = replace_value(line, value),
+                None if key_position(text, key).is_none() && defaults.get(key) != Some(value) => {
+                    missing.push(format!("{key} = {value}"));
+                }
+                None => {}
+            }
+        }
+
+        // Comments and blank lines just before the first table belong to it.
+        let mut insert = first_table;
+        while insert > 0 && (lines[insert - 1].trim().is_empty() || lines[insert - 1].trim_start().starts_with('#')) {
+            insert -= 1;
+        }
+        lines.splice(insert..insert, missing);
+
+        Ok(lines.join("\n") + "\n")
     }
 }
 
+/// Returns the key defined on a line of the config file, if any.
+fn line_key(line: &str) -> Option<&str> {
+    let (key, _) = line.split_once('=')?;
+    let key = key.trim();
+
+    (!key.starts_with('#')).then_some(key)
+}
+
+/// Returns the line defining a key with the value replaced, keeping the
+/// comment at the end of the line.
+fn replace_value(line: &str, value: &toml::Value) -> String {
+    let (key, rest) = line.split_once('=').unwrap_or((line, ""));
+
+    // The comment starts at the first # outside of a string.
+    let mut quote = None;
+    let comment_start = rest.char_indices().find_map(|(i, c)| {
+        match (quote, c) {
+            (None, '"' | '\'') => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (None, '#') => return Some(i),
+            _ => {}
+        }
+        None
+    });
+    let comment = comment_start.map_or("", |i| &rest[rest[..i].trim_end().len()..]);
+
+    format!("{key}= {value}{comment}")
+}
+
 fn default_processing_directory() -> PathBuf { PathBuf::from("/tmp/") }
-fn default_trash_directory() -> PathBuf { PathBuf::from("~/.local/share/bimgo/trash")}
-fn default_cmd_file() -> PathBuf { PathBuf::from("~/.config/bimgo/cmds")}
+fn default_trash_directory() -> PathBuf { paths::legacy_trash_directory().unwrap_or_else(|| paths::data_dir().join("trash")) }
+fn default_cmd_file() -> PathBuf { paths::config_dir().join("cmds") }
+fn default_detect_duplicates() -> bool { true }
+fn default_skip_hidden() -> bool { true }
+fn default_sidecar_patterns() -> Vec<String> {
+    ["{stem}.xmp", "{name}.xmp", "{name}.pp3", "{name}.dop", "{stem}.aae"].map(String::from).to_vec()
+}
+fn default_duplicate_distance() -> u32 { 2 }
+fn default_remember_preferred_cmd() -> bool { true }
+fn default_min_zoom() -> f32 { 0.01 }
+fn default_max_zoom() -> f32 { 50. }
+fn default_session_file() -> PathBuf { paths::state_dir().join("session") }
+fn default_stats_file() -> PathBuf { paths::data_dir().join("stats") }
+fn default_blind_file() -> PathBuf { paths::data_dir().join("blind") }
+fn default_recent_file() -> PathBuf { paths::state_dir().join("recent") }
+fn default_autosave_interval() -> u64 { 10 }
+fn default_tour_pause() -> u64 { 2000 }
+fn default_quality() -> u8 { 80 }
+fn default_quality_step() -> u8 { 5 }
+fn default_compute_threads() -> usize { 2 }
+fn default_forward_bias() -> u32 { 1 }
+fn default_max_process_weight() -> u32 {
+    std::thread::available_parallelism().map_or(4, |n| n.get() as u32)
+}
+fn default_texture_budget() -> u64 { 1024 }
+fn default_progressive_size() -> u64 { 8 }
+fn default_process_retries() -> u32 { 2 }
+fn default_retry_backoff() -> u64 { 1000 }
+fn default_split_ratio() -> f32 { 0.5 }
+fn default_clicks() -> u8 { 1 }
+fn default_mouse_bindings() -> Vec<MouseBinding> {
+    vec![
+        MouseBinding { button: MouseButtonName::Left, clicks: 2, action: MouseAction::ToggleActualSize },
+        MouseBinding { button: MouseButtonName::X1, clicks: 1, action: MouseAction::PrevImage },
+        MouseBinding { button: MouseButtonName::X2, clicks: 1, action: MouseAction::NextImage },
+    ]
+}
 
 
 #[test]
@@ -154,3 +737,35 @@ fn verify_app() {
     use clap::IntoApp;
     Cli::into_app().debug_assert()
 }
+
+#[test]
+fn runtime_settings_keep_comments() {
+    let text = "# Settings\nprocess_all_commands = false # slow\nfit_mode = \"Fill\"\n\n# Keys\n[[move_actions]]\nkey = \"d\"\nfolder = \"discard\"\n";
+    let settings = RuntimeSettings {
+        process_all_commands: true,
+        source_position: SourcePosition::Right,
+        ..RuntimeSettings::default()
+    };
+
+    assert_eq!(
+        settings.update_config(text).unwrap(),
+        "# Settings\nprocess_all_commands = true # slow\nfit_mode = \"Fill\"\nsource_position = \"Right\"\n\n# Keys\n[[move_actions]]\nkey = \"d\"\nfolder = \"discard\"\n",
+    );
+    assert_eq!(RuntimeSettings::default().update_config("").unwrap(), "\n");
+}
+
+#[test]
+fn profiles_replace_settings() {
+    let text = "fit_mode = \"Fill\"\nrules = [\"reject if larger\"]\n\n[profile.web]\ncmds_file = \"~/web_cmds\"\nrules = []\n\n[profile.archive]\nfit_mode = \"ClearZoom\"\n";
+    let mut settings = AppSettings::parse(text).unwrap();
+    assert_eq!(settings.profile.len(), 2);
+
+    settings.apply_profile("web").unwrap();
+    assert_eq!(settings.cmds_file, PathBuf::from("~/web_cmds"));
+    assert!(settings.rules.is_empty());
+    assert!(matches!(settings.fit_mode, FitMode::Fill));
+
+    let error = settings.apply_profile("photos").unwrap_err();
+    assert!(error.ends_with("expected one of archive, web"), "{error}");
+    assert_eq!(AppSettings::parse("[profile.web]\nfit = \"Fill\"\n").err().map(|p| p.len()), Some(1));
+}
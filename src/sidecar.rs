@@ -0,0 +1,121 @@
+//! Sidecar files, e.g. the develop settings written by photo editors next to
+//! an image (`IMG_1234.xmp`, `IMG_1234.jpg.dop`).
+//!
+//! The name of a sidecar is given by a pattern, in the directory of its image.
+//! The following placeholders are replaced in the pattern:
+//! - {stem}  The filename of the image without its extension.
+//! - {name}  The filename of the image.
+//!
+//! Sidecars are never listed as images, and can be carried along when a
+//! validated variant is named differently from the original.
+
+use std::path::{Path, PathBuf};
+
+use crate::utils::move_file;
+use crate::progress::diagnostic;
+
+
+/// Returns an error if the pattern can't name a sidecar: it must name a file
+/// after its image, in the same directory.
+pub fn check_pattern(pattern: &str) -> Result<(), String> {
+    if !pattern.contains("{stem}") && !pattern.contains("{name}") {
+        return Err(format!("sidecar pattern '{pattern}' contains neither {{stem}} nor {{name}}"));
+    }
+    if pattern.contains('/') {
+        return Err(format!("sidecar pattern '{pattern}' is not a file name"));
+    }
+
+    Ok(())
+}
+
+/// Returns the extensions of the sidecars named by the patterns, in lowercase.
+pub fn pattern_extensions(patterns: &[String]) -> Vec<String> {
+    let mut extensions = patterns
+        .iter()
+        .filter_map(|p| Path::new(p).extension())
+        .map(|e| e.to_string_lossy().to_lowercase())
+        .collect::<Vec<String>>();
+    extensions.sort();
+    extensions.dedup();
+
+    extensions
+}
+
+/// Returns true if the file has the extension of a sidecar, in any case.
+pub fn is_sidecar(path: &Path, extensions: &[String]) -> bool {
+    path.extension()
+        .and_then(|e| e.to_str())
+        .is_some_and(|e| extensions.iter().any(|s| s.eq_ignore_ascii_case(e)))
+}
+
+/// Returns the sidecar of the image named by the pattern, or None if the
+/// image has no filename.
+fn sidecar_name(image: &Path, pattern: &str) -> Option<PathBuf> {
+    let name = image.file_name()?.to_string_lossy();
+    let stem = image.file_stem()?.to_string_lossy();
+
+    Some(image.with_file_name(pattern.replace("{stem}", &stem).replace("{name}", &name)))
+}
+
+/// Returns the sidecars of the image `from` which exist and whose name differs
+/// for the image `to`, each with that name.
+pub fn sidecar_moves(from: &Path, to: &Path, patterns: &[String]) -> Vec<(PathBuf, PathBuf)> {
+    let mut moves = patterns
+        .iter()
+        .filter_map(|pattern| Some((sidecar_name(from, pattern)?, sidecar_name(to, pattern)?)))
+        .filter(|(sidecar, renamed)| sidecar != renamed && sidecar.is_file())
+        .collect::<Vec<(PathBuf, PathBuf)>>();
+
+    // Patterns may name the same sidecar, e.g. for images without extension.
+    moves.sort();
+    moves.dedup_by(|a, b| a.0 == b.0);
+
+    moves
+}
+
+/// Renames the sidecars of the image `from` after the image `to`. A sidecar
+/// whose new name is taken is left as is. Failures are reported, but don't
+/// stop the other renames.
+pub fn carry_sidecars(from: &Path, to: &Path, patterns: &[String]) {
+    for (sidecar, renamed) in sidecar_moves(from, to, patterns) {
+        let result = match renamed.exists() {
+            true => Err(format!("{} already exists", renamed.display())),
+            false => move_file(&sidecar, &renamed).map_err(|e| e.to_string()),
+        };
+        if let Err(e) = result {
+            diagnostic!("Error: unable to move sidecar {}: {e}", sidecar.display());
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    #[test]
+    fn sidecars_follow_their_image() {
+        let dir = std::env::temp_dir().join(format!("bimgo_sidecar_{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        for name in ["IMG_1234.jpg", "IMG_1234.xmp", "IMG_1234.jpg.dop"] {
+            fs::write(dir.join(name), "").unwrap();
+        }
+        let patterns = ["{stem}.xmp", "{name}.dop", "{name}.pp3"].map(String::from);
+
+        assert_eq!(pattern_extensions(&patterns), ["dop", "pp3", "xmp"]);
+        assert!(is_sidecar(&dir.join("IMG_1234.XMP"), &pattern_extensions(&patterns)));
+        assert!(check_pattern("{name}.dop").is_ok());
+        assert!(check_pattern("develop.xmp").is_err());
+        assert!(check_pattern("xmp/{stem}.xmp").is_err());
+
+        let moves = sidecar_moves(&dir.join("IMG_1234.jpg"), &dir.join("IMG_1234.webp"), &patterns);
+        assert_eq!(moves, [(dir.join("IMG_1234.jpg.dop"), dir.join("IMG_1234.webp.dop"))]);
+        let moves = sidecar_moves(&dir.join("IMG_1234.jpg"), &dir.join("IMG_1234_opt.webp"), &patterns);
+        assert_eq!(moves.len(), 2);
+        assert!(sidecar_moves(&dir.join("IMG_1234.jpg"), &dir.join("IMG_1234.jpg"), &patterns).is_empty());
+
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,149 @@
+//! Snapshot tests of the drawing, rendered headless to an off-screen surface.
+//!
+//! Every test draws a frame through the Renderer trait, with the layout
+//! functions used by the application, and compares it pixel by pixel against
+//! a PNG stored in the snapshots directory. A frame which differs is written
+//! to the temporary directory, to be looked at.
+//!
+//! They need the SDL libraries to render, and are ignored by default, run them
+//! with `cargo test snapshot_tests -- --ignored`. After an intended change of
+//! the drawing, the stored PNGs are rewritten by running them with
+//! BIMGO_UPDATE_SNAPSHOTS=1.
+
+use std::env;
+use std::path::PathBuf;
+
+use sdl2::pixels::{Color, PixelFormatEnum};
+use sdl2::rect::{Point, Rect};
+use sdl2::render::Canvas;
+use sdl2::surface::Surface;
+
+use crate::decode::PixelBuffer;
+use crate::rect_utils::{pane_rects, selection_border};
+use crate::renderer::Renderer;
+use crate::sdl_utils::{anchored_rect, Anchor};
+use crate::settings::SourcePosition;
+
+/// Size of the rendered frames.
+const FRAME_SIZE: (u32, u32) = (160, 96);
+
+/// Environment variable which rewrites the stored snapshots when set.
+const UPDATE_VAR: &str = "BIMGO_UPDATE_SNAPSHOTS";
+
+const BACKGROUND: Color = Color::RGB(36, 40, 59);
+const SOURCE_PANE: Color = Color::RGB(200, 60, 60);
+const PROCESSED_PANE: Color = Color::RGB(60, 60, 200);
+const SELECTED: Color = Color::RGB(0, 128, 128);
+const TEXT: Color = Color::RGB(255, 200, 0);
+
+
+/// Returns an off-screen canvas of FRAME_SIZE, cleared to the background.
+fn headless() -> Canvas<Surface<'static>> {
+    let (w, h) = FRAME_SIZE;
+    let surface = Surface::new(w, h, PixelFormatEnum::RGBA32).unwrap();
+    let mut canvas = surface.into_canvas().unwrap();
+    Renderer::clear(&mut canvas, BACKGROUND);
+
+    canvas
+}
+
+/// Fills both panes of the split with a plain color each, standing in for the
+/// images.
+fn draw_panes(canvas: &mut Canvas<Surface>, position: SourcePosition) -> Rect {
+    let (source, processed) = pane_rects(FRAME_SIZE, 0.5, 4, position);
+    canvas.draw_border(&[source], SOURCE_PANE).unwrap();
+    canvas.draw_border(&[processed], PROCESSED_PANE).unwrap();
+
+    processed
+}
+
+/// Compares the frame drawn on the canvas with the stored snapshot of provided
+/// name, or rewrites the snapshot if UPDATE_VAR is set.
+fn assert_snapshot(name: &str, canvas: Canvas<Surface>) {
+    let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("snapshots").join(format!("{name}.png"));
+    let actual = PixelBuffer::from_surface(canvas.surface()).unwrap();
+
+    if env::var_os(UPDATE_VAR).is_some() {
+        actual.save_png(&path).unwrap();
+        return;
+    }
+
+    let expected = PixelBuffer::decode(&path)
+        .unwrap_or_else(|e| panic!("Snapshot {name} can't be read ({e}), record it with {UPDATE_VAR}=1"));
+    assert_eq!((actual.width, actual.height), (expected.width, expected.height), "Snapshot {name} has another size");
+
+    let different = actual.data.chunks(4).zip(expected.data.chunks(4)).position(|(a, e)| a != e);
+    if let Some(k) = different {
+        let (x, y) = (k as u32 % actual.width, k as u32 / actual.width);
+        let actual_path = env::temp_dir().join(format!("bimgo_snapshot_{name}.png"));
+        let saved = actual.save_png(&actual_path);
+        panic!("Snapshot {name} differs from pixel ({x}, {y}), frame written to {} ({saved:?})", actual_path.display());
+    }
+}
+
+
+#[test]
+#[ignore]
+fn split_layout() {
+    for (name, position) in [
+        ("split_left", SourcePosition::Left),
+        ("split_top", SourcePosition::Top),
+        ("split_right", SourcePosition::Right),
+        ("split_bottom", SourcePosition::Bottom),
+    ] {
+        let mut canvas = headless();
+        draw_panes(&mut canvas, position);
+        assert_snapshot(name, canvas);
+    }
+}
+
+#[test]
+#[ignore]
+fn selection_border_layout() {
+    for (name, position) in [
+        ("border_left", SourcePosition::Left),
+        ("border_top", SourcePosition::Top),
+    ] {
+        let mut canvas = headless();
+        let pane = draw_panes(&mut canvas, position);
+
+        // An image filling its pane, then a smaller one centered in it.
+        canvas.draw_border(&selection_border(pane, pane, position, 20), SELECTED).unwrap();
+        let clip = Rect::from_center(pane.center(), 40, 30);
+        canvas.draw_border(&selection_border(clip, pane, position, 20), SELECTED).unwrap();
+
+        assert_snapshot(name, canvas);
+    }
+}
+
+#[test]
+#[ignore]
+fn overlay_placement() {
+    let (w, h) = (FRAME_SIZE.0 as i32, FRAME_SIZE.1 as i32);
+    let mut text = Surface::new(12, 8, PixelFormatEnum::RGBA32).unwrap();
+    text.fill_rect(None, TEXT).unwrap();
+
+    let mut canvas = headless();
+    let texture_creator = canvas.texture_creator();
+    let texture = text.as_texture(&texture_creator).unwrap();
+    for (position, anchor) in [
+        (Point::new(0, 0), Anchor::TopLeft),
+        (Point::new(w / 2, 0), Anchor::Top),
+        (Point::new(w, 0), Anchor::TopRight),
+        (Point::new(0, h / 2), Anchor::Left),
+        (Point::new(w, h / 2), Anchor::Right),
+        (Point::new(0, h), Anchor::BottomLeft),
+        (Point::new(w / 2, h), Anchor::Bottom),
+        (Point::new(w, h), Anchor::BottomRight),
+    ] {
+        let dst = anchored_rect(position, text.size(), anchor);
+        canvas.draw_text(&texture, dst, dst, Color::RGB(0, 0, 0)).unwrap();
+    }
+
+    // Wrapped text is drawn over a background as wide as the wrapping.
+    let dst = anchored_rect(Point::new(w / 2, h / 2), text.size(), Anchor::Center);
+    let background = Rect::new(dst.x(), dst.y(), 30, dst.height());
+    canvas.draw_text(&texture, dst, background, Color::RGB(0, 0, 0)).unwrap();
+
+    assert_snapshot("overlay_anchors", canvas);
+}
@@ -0,0 +1,228 @@
+//! Statistics of the commands, kept across sessions.
+//!
+//! For every command, the stats file counts how often its variant was
+//! validated (a win), how often another variant of the same image was
+//! validated instead (a loss), and the bytes saved by its validated variants.
+//! The decisions of a session are added to the file when quitting. Commands
+//! which never win can then be pruned from the commands file.
+//!
+//! The file is a list of tab-separated lines:
+//! `<wins> <losses> <saved bytes> <command>`
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use crate::img::ImgItem;
+use crate::utils::human_readable_size;
+
+
+/// Statistics of a command.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CmdStats {
+    pub wins: u64,
+    pub losses: u64,
+    pub saved: i64,
+}
+
+impl CmdStats {
+    /// Returns the share of the decisions won by the command, between 0 and 1.
+    pub fn win_rate(&self) -> f64 {
+        match self.wins + self.losses {
+            0 => 0.,
+            total => self.wins as f64 / total as f64,
+        }
+    }
+
+    /// Returns the average bytes saved by the validated variants.
+    pub fn average_saved(&self) -> i64 {
+        match self.wins {
+            0 => 0,
+            wins => self.saved / wins as i64,
+        }
+    }
+}
+
+/// Statistics of every command, by command.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Stats {
+    pub cmds: BTreeMap<String, CmdStats>,
+}
+
+impl Stats {
+    /// Collects the statistics of the decisions taken on the provided images.
+    pub fn from_imgs(imgs: &[ImgItem], cmds: &[String]) -> Stats {
+        let mut stats = Stats::default();
+
+        for img in imgs {
+            let (winner, saved) = match (img.validated_index(), img.saved_bytes()) {
+                (Some(winner), Some(saved)) => (winner, saved),
+                _ => continue,
+            };
+
+            for (c, p) in img.processed.touched() {
+                let entry = stats.cmds.entry(cmds[c].clone()).or_default();
+                if c == winner {
+                    entry.wins += 1;
+                    entry.saved += saved;
+                } else if p.as_ref().is_some_and(|p| p.is_processed()) {
+                    entry.losses += 1;
+                }
+            }
+        }
+        stats.cmds.retain(|_, s| s.wins + s.losses > 0);
+
+        stats
+    }
+
+    /// Adds the statistics of other to these statistics.
+    pub fn merge(&mut self, other: &Stats) {
+        for (cmd, s) in &other.cmds {
+            let entry = self.cmds.entry(cmd.clone()).or_default();
+            entry.wins += s.wins;
+            entry.losses += s.losses;
+            entry.saved += s.saved;
+        }
+    }
+
+    /// Removes the statistics of other from these statistics, which must
+    /// include them.
+    pub fn remove(&mut self, other: &Stats) {
+        for (cmd, s) in &other.cmds {
+            if let Some(entry) = self.cmds.get_mut(cmd) {
+                entry.wins = entry.wins.saturating_sub(s.wins);
+                entry.losses = entry.losses.saturating_sub(s.losses);
+                entry.saved -= s.saved;
+            }
+        }
+        self.cmds.retain(|_, s| s.wins + s.losses > 0);
+    }
+
+    /// Formats the statistics as the content of the stats file.
+    pub fn format(&self) -> String {
+        self.cmds
+            .iter()
+            .map(|(cmd, s)| format!("{}\t{}\t{}\t{cmd}\n", s.wins, s.losses, s.saved))
+            .collect()
+    }
+
+    /// Parses the content of a stats file.
+    pub fn parse(content: &str) -> Result<Stats, String> {
+        let mut stats = Stats::default();
+
+        for line in content.lines().filter(|l| !l.is_empty()) {
+            let invalid = || format!("Invalid line in stats: '{line}'");
+            let fields = line.splitn(4, '\t').collect::<Vec<&str>>();
+            match fields.as_slice() {
+                [wins, losses, saved, cmd] => {
+                    let s = CmdStats {
+                        wins: wins.parse().map_err(|_| invalid())?,
+                        losses: losses.parse().map_err(|_| invalid())?,
+                        saved: saved.parse().map_err(|_| invalid())?,
+                    };
+                    stats.merge(&Stats { cmds: BTreeMap::from([(cmd.to_string(), s)]) });
+                }
+                _ => return Err(invalid()),
+            }
+        }
+
+        Ok(stats)
+    }
+
+    /// Reads the stats file at provided path. A missing file has no
+    /// statistics yet.
+    pub fn load(path: &Path) -> Result<Stats, String> {
+        match fs::read_to_string(path) {
+            Ok(content) => Stats::parse(&content),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Stats::default()),
+            Err(e) => Err(format!("Unable to read {}: {e}", path.display())),
+        }
+    }
+
+    /// Returns the bytes a reviewed image is expected to save, the average
+    /// savings of every command weighted by its win rate, or None without
+    /// statistics yet.
+    pub fn projected_saved(&self) -> Option<i64> {
+        let weights = self.cmds.values().map(|s| s.win_rate()).sum::<f64>();
+        if weights == 0. {
+            return None;
+        }
+
+        let weighted = self.cmds.values().map(|s| s.win_rate() * s.average_saved() as f64).sum::<f64>();
+        Some((weighted / weights).round() as i64)
+    }
+
+    /// Returns a summary of the statistics, one command per line, the best
+    /// win rates first.
+    pub fn summary(&self) -> String {
+        if self.cmds.is_empty() {
+            return "No statistics yet, validate some images first".to_string();
+        }
+
+        let mut cmds = self.cmds.iter().collect::<Vec<_>>();
+        cmds.sort_by(|(_, a), (_, b)| b.win_rate().total_cmp(&a.win_rate()));
+
+        cmds.iter()
+            .map(|(cmd, s)| {
+                let average = match s.average_saved() {
+                    saved if saved >= 0 => format!("saves {} on average", human_readable_size(saved as u64)),
+                    saved => format!("grows {} on average", human_readable_size(saved.unsigned_abs())),
+                };
+                format!(
+                    "{:3.0}% won ({} of {}), {average}: {cmd}",
+                    s.win_rate() * 100.,
+                    s.wins,
+                    s.wins + s.losses,
+                )
+            })
+            .collect::<Vec<String>>()
+            .join("\n")
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stats(entries: &[(&str, u64, u64, i64)]) -> Stats {
+        Stats {
+            cmds: entries
+                .iter()
+                .map(|&(cmd, wins, losses, saved)| (cmd.to_string(), CmdStats { wins, losses, saved }))
+                .collect(),
+        }
+    }
+
+    #[test]
+    fn stats_round_trip() {
+        let s = stats(&[("cwebp -q 80 %i -o %o.webp", 3, 1, 120000), ("avifenc %i %o.avif", 0, 4, 0)]);
+        assert_eq!(Stats::parse(&s.format()), Ok(s));
+        assert!(Stats::parse("1\tx\t0\tcmd").is_err());
+        assert!(Stats::parse("1\t2").is_err());
+    }
+
+    #[test]
+    fn stats_merge_adds_counts() {
+        let mut s = stats(&[("a", 1, 2, 100)]);
+        s.merge(&stats(&[("a", 2, 0, 50), ("b", 0, 1, 0)]));
+        assert_eq!(s, stats(&[("a", 3, 2, 150), ("b", 0, 1, 0)]));
+        assert_eq!(s.cmds["a"].win_rate(), 0.6);
+        assert_eq!(s.cmds["a"].average_saved(), 50);
+        assert_eq!(s.cmds["b"].average_saved(), 0);
+
+        s.remove(&stats(&[("a", 1, 1, 20), ("b", 0, 1, 0)]));
+        assert_eq!(s, stats(&[("a", 2, 1, 130)]));
+    }
+
+    #[test]
+    fn projection_weights_savings_by_win_rate() {
+        assert_eq!(Stats::default().projected_saved(), None);
+        assert_eq!(stats(&[("a", 0, 3, 0)]).projected_saved(), None);
+
+        // a wins 3 of 4 images saving 100 each, b wins the last saving 500.
+        let s = stats(&[("a", 3, 1, 300), ("b", 1, 3, 500)]);
+        assert_eq!(s.projected_saved(), Some(200));
+    }
+}
@@ -0,0 +1,119 @@
+//! Sizes and colors of the interface.
+//!
+//! The default theme can be replaced by an accessibility preset, with larger
+//! text, a thicker validation border, and colors which stay distinguishable
+//! with the common color vision deficiencies. The colors can also be chosen
+//! among palettes derived from the Okabe-Ito palette, which was designed for
+//! deuteranopia and protanopia.
+
+use sdl2::pixels::Color;
+
+use crate::settings::ColorPalette;
+
+
+/// Sizes and colors used to draw the interface.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Theme {
+    /// Point size of the text.
+    pub font_size: u16,
+
+    /// Thickness of the validation border, as a fraction of the smallest side
+    /// of the pane, i.e. 1/border_fraction.
+    pub border_fraction: u32,
+
+    /// Color of the validation border.
+    pub selected: Color,
+
+    /// Color of the size of a variant smaller than its source.
+    pub savings: Color,
+
+    /// Color of the size of a variant larger than its source.
+    pub growth: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            font_size: 30,
+            border_fraction: 20,
+            selected: Color::RGB(0, 128, 128),
+            savings: Color::RGB(90, 210, 120),
+            growth: Color::RGB(235, 90, 90),
+        }
+    }
+}
+
+impl Theme {
+    /// Returns the accessibility preset: high contrast colors safe for color
+    /// blindness, large text, and a border twice as thick.
+    pub fn accessible() -> Theme {
+        Theme {
+            font_size: 44,
+            border_fraction: 10,
+            ..Theme::default().with_palette(ColorPalette::Deuteranopia)
+        }
+    }
+
+    /// Returns the theme with the colors of the palette.
+    pub fn with_palette(self, palette: ColorPalette) -> Theme {
+        let (selected, savings, growth) = match palette {
+            ColorPalette::Default => {
+                let theme = Theme::default();
+                (theme.selected, theme.savings, theme.growth)
+            }
+            // Yellow, sky blue and orange.
+            ColorPalette::Deuteranopia => (
+                Color::RGB(240, 228, 66),
+                Color::RGB(86, 180, 233),
+                Color::RGB(230, 159, 0),
+            ),
+            // Reds look dark with protanopia, growth is yellow instead.
+            ColorPalette::Protanopia => (
+                Color::RGB(230, 159, 0),
+                Color::RGB(86, 180, 233),
+                Color::RGB(240, 228, 66),
+            ),
+        };
+
+        Theme { selected, savings, growth, ..self }
+    }
+
+    /// Returns the accessibility preset if enabled, otherwise the default
+    /// theme, with the colors of the palette unless it is the default one.
+    pub fn new(accessible: bool, palette: ColorPalette) -> Theme {
+        let theme = match accessible {
+            true => Theme::accessible(),
+            false => Theme::default(),
+        };
+
+        match palette {
+            ColorPalette::Default => theme,
+            palette => theme.with_palette(palette),
+        }
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accessible_theme_is_larger() {
+        let (default, accessible) = (Theme::new(false, ColorPalette::Default), Theme::new(true, ColorPalette::Default));
+        assert!(accessible.font_size > default.font_size);
+        assert!(accessible.border_fraction < default.border_fraction);
+        assert_ne!(accessible.savings, accessible.growth);
+    }
+
+    #[test]
+    fn palette_overrides_colors_only() {
+        let theme = Theme::new(true, ColorPalette::Protanopia);
+        assert_eq!(theme.font_size, Theme::accessible().font_size);
+        assert_eq!(theme.growth, Color::RGB(240, 228, 66));
+
+        let theme = Theme::new(false, ColorPalette::Deuteranopia);
+        assert_eq!(theme.font_size, Theme::default().font_size);
+        assert_eq!(theme.selected, Theme::accessible().selected);
+    }
+}
@@ -0,0 +1,237 @@
+//! Module handling the trash, where the original images are moved when a
+//! processed variant is validated.
+//!
+//! The trash recreates the directory hierarchy of the original files, e.g.
+//! `/home/user/2024/05/shoot/img.jpg` is moved to
+//! `<trash>/home/user/2024/05/shoot/img.jpg`. A manifest file at the root of
+//! the trash maps every trash entry back to its original location and records
+//! when it was trashed, so that it can be restored.
+
+use std::fs;
+use std::io::Write;
+use std::path::{Component, Path, PathBuf};
+use chrono::Utc;
+
+use crate::utils::{check_is_existing_directory, move_file};
+
+/// Name of the manifest file, located at the root of the trash directory.
+const MANIFEST_FILENAME: &str = "manifest";
+
+/// Format of the dates recorded in the manifest.
+const DATE_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+
+
+/// An entry of the trash manifest.
+///
+/// The date is missing for entries recorded by older versions.
+#[derive(Debug, PartialEq)]
+pub struct TrashEntry {
+    pub trash_path: PathBuf,
+    pub original: PathBuf,
+    pub date: Option<String>,
+}
+
+
+/// Returns the path of the source relative to the root of the file system,
+/// i.e. only the normal components of the path are kept.
+fn hierarchy_path(source: &Path) -> PathBuf {
+    source
+        .components()
+        .filter(|c| matches!(c, Component::Normal(_)))
+        .collect()
+}
+
+
+/// Given the source path, and the trash directory path, generates the
+/// deleted file path.
+///
+/// The deleted file path is generated as follows:
+/// - The absolute path of the source is recreated inside the trash directory,
+///   the missing directories are created.
+/// - If a file with the same name is already in trash, the date and time are
+///   appended to the filename, before the extension.
+pub fn deleted_file_path(source: &Path, trash_directory: &Path) -> Result<PathBuf, String> {
+    check_is_existing_directory(trash_directory)?;
+
+    let source = fs::canonicalize(source)
+        .map_err(|e| format!("Unable to resolve {}: {e}", source.display()))?;
+
+    let mut output_path = trash_directory.to_path_buf();
+    output_path.push(hierarchy_path(&source));
+
+    if let Some(parent) = output_path.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Unable to create {}: {e}", parent.display()))?;
+    }
+
+    if output_path.exists() {
+        let mut filename = source
+            .file_stem()
+            .ok_or_else(|| "Missing file name".to_string())?
+            .to_os_string();
+
+        filename.push(format!("_{}", Utc::now().format("%y-%m-%d_%Hh%Mm%Ss")));
+
+        if let Some(extension) = source.extension() {
+            filename.push(".");
+            filename.push(extension);
+        }
+
+        output_path.set_file_name(filename);
+    }
+
+    Ok(output_path)
+}
+
+
+/// Formats a manifest line, mapping a trash entry to its original location.
+fn format_manifest_line(entry: &TrashEntry) -> String {
+    match &entry.date {
+        Some(date) => format!("{}\t{}\t{date}\n", entry.trash_path.display(), entry.original.display()),
+        None => format!("{}\t{}\n", entry.trash_path.display(), entry.original.display()),
+    }
+}
+
+
+/// Parses a manifest line into an entry.
+fn parse_manifest_line(line: &str) -> Option<TrashEntry> {
+    let mut fields = line.splitn(3, '\t');
+    let trash_path = PathBuf::from(fields.next()?);
+    let original = PathBuf::from(fields.next()?);
+    let date = fields.next().map(str::to_string);
+
+    Some(TrashEntry { trash_path, original, date })
+}
+
+
+fn manifest_path(trash_directory: &Path) -> PathBuf {
+    trash_directory.join(MANIFEST_FILENAME)
+}
+
+
+/// Reads every entry of the manifest, oldest first.
+///
+/// A missing manifest is considered empty.
+pub fn manifest_entries(trash_directory: &Path) -> Result<Vec<TrashEntry>, String> {
+    let path = manifest_path(trash_directory);
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    let content = fs::read_to_string(&path)
+        .map_err(|e| format!("Unable to read {}: {e}", path.display()))?;
+
+    Ok(content.lines().filter_map(parse_manifest_line).collect())
+}
+
+
+/// Adds an entry to the manifest of the trash directory.
+pub fn manifest_add(trash_directory: &Path, trash_path: &Path, original: &Path) -> Result<(), String> {
+    let path = manifest_path(trash_directory);
+    let original = fs::canonicalize(original.parent().unwrap_or_else(|| Path::new(".")))
+        .map(|p| p.join(original.file_name().unwrap_or_default()))
+        .unwrap_or_else(|_| original.to_path_buf());
+    let entry = TrashEntry {
+        trash_path: trash_path.to_path_buf(),
+        original,
+        date: Some(Utc::now().format(DATE_FORMAT).to_string()),
+    };
+
+    fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .and_then(|mut f| f.write_all(format_manifest_line(&entry).as_bytes()))
+        .map_err(|e| format!("Unable to write {}: {e}", path.display()))
+}
+
+
+/// Removes the entry of the provided trash path from the manifest.
+pub fn manifest_remove(trash_directory: &Path, trash_path: &Path) -> Result<(), String> {
+    let path = manifest_path(trash_directory);
+    let content: String = manifest_entries(trash_directory)?
+        .into_iter()
+        .filter(|e| e.trash_path != trash_path)
+        .map(|e| format_manifest_line(&e))
+        .collect();
+
+    fs::write(&path, content)
+        .map_err(|e| format!("Unable to write {}: {e}", path.display()))
+}
+
+
+/// Restores a file from the trash to its original location.
+///
+/// The provided path can either be the trash entry, or the original location
+/// of the file. If several entries match, the latest is restored. The restore
+/// is refused if a file already exists at the original location.
+///
+/// Returns the original location of the restored file.
+pub fn restore(trash_directory: &Path, path: &Path) -> Result<PathBuf, String> {
+    let TrashEntry { trash_path, original, .. } = manifest_entries(trash_directory)?
+        .into_iter()
+        .rev()
+        .find(|e| e.trash_path == path || e.original == path)
+        .ok_or_else(|| format!("{} not found in trash manifest", path.display()))?;
+
+    if original.exists() {
+        return Err(format!("{} already exists", original.display()));
+    }
+
+    if let Some(parent) = original.parent() {
+        fs::create_dir_all(parent)
+            .map_err(|e| format!("Unable to create {}: {e}", parent.display()))?;
+    }
+
+    move_file(&trash_path, &original).map_err(|e| format!("Unable to move file : {e}"))?;
+    manifest_remove(trash_directory, &trash_path)?;
+
+    Ok(original)
+}
+
+
+/// Permanently deletes a file from the trash, and removes its entry from the
+/// manifest.
+pub fn delete(trash_directory: &Path, trash_path: &Path) -> Result<(), String> {
+    if trash_path.exists() {
+        fs::remove_file(trash_path)
+            .map_err(|e| format!("Unable to delete {}: {e}", trash_path.display()))?;
+    }
+
+    manifest_remove(trash_directory, trash_path)
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn hierarchy_path_is_relative() {
+        let p = hierarchy_path(Path::new("/2024/05/shoot/img.jpg"));
+        assert_eq!(p, PathBuf::from("2024/05/shoot/img.jpg"));
+    }
+
+    #[test]
+    fn manifest_line_round_trip() {
+        let entry = TrashEntry {
+            trash_path: PathBuf::from("/trash/a/img.jpg"),
+            original: PathBuf::from("/a/img.jpg"),
+            date: Some("2024-05-12 18:03 UTC".to_string()),
+        };
+        let line = format_manifest_line(&entry);
+        assert_eq!(parse_manifest_line(line.trim_end()), Some(entry));
+    }
+
+    #[test]
+    fn manifest_line_without_date() {
+        let entry = parse_manifest_line("/trash/a/img.jpg\t/a/img.jpg").unwrap();
+        assert_eq!(entry.original, PathBuf::from("/a/img.jpg"));
+        assert_eq!(entry.date, None);
+    }
+
+    #[test]
+    fn manifest_line_invalid() {
+        assert_eq!(parse_manifest_line("/trash/a/img.jpg"), None);
+    }
+}
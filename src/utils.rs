@@ -3,8 +3,31 @@
 use std::path::{Path, PathBuf};
 use std::fs;
 use std::os::linux::fs::MetadataExt;
-use std::io::{self, BufRead, BufReader};
-use std::process::Command;
+use std::io::{self, BufRead, BufReader, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::os::unix::process::ExitStatusExt;
+use std::process::{Command, ExitStatus, Output, Stdio};
+
+use crate::builtin::BUILTIN_PREFIX;
+use crate::progress::{diagnostic, stdout_reserved};
+
+/// Exit code of commands reporting a temporary failure (EX_TEMPFAIL of
+/// sysexits.h).
+const EX_TEMPFAIL: i32 = 75;
+
+/// Prefix of the commands run by the shell.
+const SHELL_PREFIX: &str = "sh:";
+
+/// Placeholder replaced by the quality of the variant in the commands.
+pub const QUALITY_PLACEHOLDER: &str = "%q";
+
+/// Signal sent to processes killed when the system is out of memory.
+const SIGKILL: i32 = 9;
+
+/// Maximum number of lines and characters of the error output of a command
+/// kept in a failure reason.
+const STDERR_SUMMARY_LINES: usize = 3;
+const STDERR_SUMMARY_CHARS: usize = 300;
 
 /// Simple helper function to verify that path is an existing file or return
 /// an error.
@@ -84,9 +107,12 @@ pub fn move_file(src_file_path: &Path, dst_path: &Path) -> io::Result<()> {
         fs::rename(src_file_path, dst_file_path)?;
 
     // Source and destination are on different device, we must copy then delete.
+    // If either step fails, the copy is removed so that only the source is left.
     } else {
-        fs::copy(src_file_path, dst_file_path)?;
-        fs::remove_file(src_file_path)?;
+        if let Err(e) = fs::copy(src_file_path, &dst_file_path).and_then(|_| fs::remove_file(src_file_path)) {
+            let _ = fs::remove_file(&dst_file_path);
+            return Err(e);
+        }
     }
     
     Ok(())
@@ -98,7 +124,7 @@ pub fn move_file(src_file_path: &Path, dst_path: &Path) -> io::Result<()> {
 /// If the move fails, the function fail, attempts to revert back to the state
 /// before the call. i.e. if it fails on the first move, nothing is done, if
 /// it fails on the second move, the function tries to move back dst_1 to src_1
-/// before exiting.
+/// before exiting. Either way an error is returned.
 pub fn attempt_double_move(
     src_1: &Path,
     dst_1: &Path,
@@ -109,7 +135,7 @@ pub fn attempt_double_move(
 
     // Move trash back to original
     if let Err(e) = move_file(src_2, dst_2) {
-        println!(
+        diagnostic!(
             "Unable to move {}, attempting to revert. Err: {}",
             src_2.display(),
             e
@@ -122,6 +148,8 @@ pub fn attempt_double_move(
                 e
             )
         })?;
+
+        return Err(format!("Unable to move file : {}", e));
     }
 
 Ok(())
@@ -141,23 +169,308 @@ pub fn command_to_string(command: &Command) -> String {
 }
 
 
-/// Executes a &str as a command. Replacing %i with input_file and %o with
-/// output_file.
-pub fn execute_command_str(command: &str, input_file: &Path, output_file: &Path) {
+/// Returns the command with every %q replaced by the quality, anywhere in
+/// the command, e.g. `builtin:jpeg?q=%q` or `cwebp -q %q %i -o %o.webp`.
+pub fn with_quality(command: &str, quality: u8) -> String {
+    command.replace(QUALITY_PLACEHOLDER, &quality.to_string())
+}
+
+
+/// Builds a Command from a &str, replacing every argument equal to one of the
+/// provided placeholders with the associated path.
+///
+/// With a sandbox directory, the command runs in this directory with an
+/// environment reduced to PATH, and the paths are made absolute so that they
+/// don't depend on the working directory.
+pub fn build_command(command: &str, substitutions: &[(&str, &Path)], sandbox: Option<&Path>) -> io::Result<Option<Command>> {
     let split = command.split(' ').collect::<Vec<&str>>();
-    if !split.is_empty() {
-        let mut cmd = Command::new(split[0]);
-        for item in split[1..].iter() {
-            if *item == "%i" {
-                cmd.arg(input_file);
-            } else if *item == "%o" {
-                cmd.arg(output_file);
-            } else {
-                cmd.arg(item);
+    if split.is_empty() {
+        return Ok(None);
+    }
+
+    let mut cmd = Command::new(split[0]);
+    for item in split[1..].iter() {
+        match substitutions.iter().find(|(placeholder, _)| placeholder == item) {
+            Some((_, path)) if sandbox.is_some() => cmd.arg(std::path::absolute(path)?),
+            Some((_, path)) => cmd.arg(path),
+            None => cmd.arg(item),
+        };
+    }
+
+    if let Some(dir) = sandbox {
+        restrict(&mut cmd, dir);
+    }
+
+    Ok(Some(cmd))
+}
+
+
+/// Runs the command in the provided directory, with an environment reduced
+/// to PATH.
+fn restrict(cmd: &mut Command, dir: &Path) {
+    cmd.env_clear().current_dir(dir);
+    if let Some(path) = std::env::var_os("PATH") {
+        cmd.env("PATH", path);
+    }
+}
+
+
+/// Builds the Command running a shell-mode command, `sh:<script>`, with
+/// `sh -c`. The paths are not substituted in the script but exported as
+/// BIMGO_IN and BIMGO_OUT, so that they are never interpreted by the shell.
+/// Returns None if the command is not in shell mode.
+fn build_shell_command(command: &str, input_file: &Path, output_file: &Path, sandbox: Option<&Path>) -> io::Result<Option<Command>> {
+    let script = match command.strip_prefix(SHELL_PREFIX) {
+        Some(script) => script,
+        None => return Ok(None),
+    };
+
+    let mut cmd = Command::new("sh");
+    cmd.arg("-c").arg(script);
+    match sandbox {
+        Some(dir) => {
+            restrict(&mut cmd, dir);
+            cmd.env("BIMGO_IN", std::path::absolute(input_file)?);
+            cmd.env("BIMGO_OUT", std::path::absolute(output_file)?);
+        }
+        None => {
+            cmd.env("BIMGO_IN", input_file).env("BIMGO_OUT", output_file);
+        }
+    }
+
+    Ok(Some(cmd))
+}
+
+
+/// Returns the stages of a command, separated by ` | `: the output of every
+/// stage is the input of the next one. A shell-mode stage extends to the end
+/// of the command, as its script may contain pipes.
+pub fn pipeline_stages(command: &str) -> Vec<&str> {
+    let mut stages = Vec::new();
+    let mut rest = command;
+    while !rest.starts_with(SHELL_PREFIX) {
+        match rest.split_once(" | ") {
+            Some((stage, next)) => {
+                stages.push(stage);
+                rest = next;
             }
+            None => break,
+        }
+    }
+    stages.push(rest);
+
+    stages
+}
+
+
+/// Checks that the executables of the command are in the list of allowed
+/// executables, written either as a name looked up in PATH or as a path.
+pub fn check_allowed_command(command: &str, allowed: &[String]) -> Result<(), String> {
+    for stage in pipeline_stages(command) {
+        // Built-in commands don't run any executable.
+        if stage.starts_with(BUILTIN_PREFIX) {
+            continue;
         }
-        cmd.status().expect("Failed to execute command");
+
+        // Shell-mode commands run sh, which can then run anything.
+        let program = match stage.starts_with(SHELL_PREFIX) {
+            true => "sh",
+            false => stage.split(' ').next().unwrap_or_default(),
+        };
+        if !allowed.iter().any(|a| a == program) {
+            return Err(format!("'{program}' is not in allowed_commands, required by '{command}'"));
+        }
+    }
+
+    Ok(())
+}
+
+
+/// Executes a &str as a command. Replacing %i with input_file and %o with
+/// output_file. For commands working on streams, %I feeds input_file to the
+/// standard input, and %O writes the standard output to output_file.
+/// Shell-mode commands are run by the shell (see build_shell_command).
+///
+/// The standard output of the command is shown in the terminal, unless it is
+/// written to output_file. Its error output is captured, then shown too.
+///
+/// Returns the exit status and the error output of the command, or an error
+/// if it couldn't run. With a sandbox directory, the command is sandboxed
+/// (see build_command).
+pub fn execute_command_str(command: &str, input_file: &Path, output_file: &Path, sandbox: Option<&Path>) -> io::Result<Output> {
+    if let Some(mut cmd) = build_shell_command(command, input_file, output_file, sandbox)? {
+        return echo_stderr(cmd.stdout(inherited_stdout()).output());
+    }
+
+    let tokens = command.split(' ').collect::<Vec<&str>>();
+    let arguments = tokens.iter().copied().filter(|t| !["%I", "%O"].contains(t)).collect::<Vec<&str>>().join(" ");
+    let mut cmd = build_command(&arguments, &[("%i", input_file), ("%o", output_file)], sandbox)?
+        .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidInput, "Empty command"))?;
+
+    if tokens.contains(&"%I") {
+        cmd.stdin(fs::File::open(input_file)?);
+    }
+    match tokens.contains(&"%O") {
+        true => cmd.stdout(Stdio::from(fs::File::create(output_file)?)),
+        false => cmd.stdout(inherited_stdout()),
+    };
+
+    echo_stderr(cmd.output())
+}
+
+/// Returns where the output of the commands is shown to the user: stdout, or
+/// stderr when stdout is reserved to the progress events.
+pub fn inherited_stdout() -> Stdio {
+    match stdout_reserved() {
+        true => Stdio::from(io::stderr()),
+        false => Stdio::inherit(),
+    }
+}
+
+/// Shows the captured error output of a command in the terminal, in one
+/// write so that the outputs of parallel commands don't interleave.
+fn echo_stderr(output: io::Result<Output>) -> io::Result<Output> {
+    if let Ok(output) = &output {
+        let _ = io::stderr().lock().write_all(&output.stderr);
     }
+
+    output
+}
+
+
+/// Returns the last lines of the error output of a command, which usually
+/// hold the reason of its failure, short enough to be displayed.
+pub fn stderr_summary(stderr: &str) -> String {
+    let lines = stderr.lines().map(str::trim).filter(|l| !l.is_empty()).collect::<Vec<&str>>();
+    let summary = lines[lines.len().saturating_sub(STDERR_SUMMARY_LINES)..].join(" / ");
+
+    match summary.char_indices().nth(STDERR_SUMMARY_CHARS) {
+        Some((k, _)) => format!("{}...", &summary[..k]),
+        None if summary.is_empty() => "no error output".to_string(),
+        None => summary,
+    }
+}
+
+
+/// Runs f, returning the message of its panic as an error if it panics, so
+/// that background threads report panics instead of silently ending.
+pub fn catch_panic<T>(f: impl FnOnce() -> T) -> Result<T, String> {
+    panic::catch_unwind(AssertUnwindSafe(f)).map_err(|payload| {
+        if let Some(msg) = payload.downcast_ref::<&str>() {
+            msg.to_string()
+        } else if let Some(msg) = payload.downcast_ref::<String>() {
+            msg.clone()
+        } else {
+            "unknown panic".to_string()
+        }
+    })
+}
+
+
+/// Returns true if the exit status denotes a failure which may not happen
+/// again: a command killed by the kernel when out of memory, or reporting a
+/// temporary failure.
+pub fn is_transient_status(status: &ExitStatus) -> bool {
+    status.signal() == Some(SIGKILL) || status.code() == Some(EX_TEMPFAIL)
+}
+
+
+/// Returns true if the error of a command which couldn't run may not happen
+/// again, e.g. too many processes or a full disk.
+pub fn is_transient_error(error: &io::Error) -> bool {
+    matches!(
+        error.kind(),
+        io::ErrorKind::WouldBlock
+            | io::ErrorKind::Interrupted
+            | io::ErrorKind::OutOfMemory
+            | io::ErrorKind::StorageFull
+    )
+}
+
+
+/// Parses a line of the commands file: the command, optionally preceded by
+/// `weight=N` (or `threads=N`), the number of threads the command uses.
+/// Commands without weight have a weight of 1.
+pub fn parse_command_line(line: &str) -> Result<(String, u32), String> {
+    let weight = line
+        .split_once(' ')
+        .and_then(|(first, rest)| {
+            let value = first.strip_prefix("weight=").or_else(|| first.strip_prefix("threads="))?;
+            Some((value, rest))
+        });
+
+    match weight {
+        Some((value, rest)) => {
+            let weight = value
+                .parse::<u32>()
+                .ok()
+                .filter(|&w| w > 0)
+                .ok_or_else(|| format!("Invalid weight in command '{line}'"))?;
+            Ok((rest.trim_start().to_string(), weight))
+        }
+        None => Ok((line.to_string(), 1)),
+    }
+}
+
+
+/// Returns the command prefixed so that it runs with the provided niceness
+/// (`nice`) and at most the provided percentage of a CPU (`cpulimit`). A
+/// niceness or limit of 0 leaves the command unchanged.
+///
+/// Every stage of a pipeline is throttled. The script of a shell-mode
+/// command is run by a throttled inner shell, so that every command of the
+/// script is throttled. Built-in commands run in process and are never
+/// throttled.
+pub fn throttled_command(command: &str, niceness: i32, cpu_limit: u32) -> String {
+    pipeline_stages(command)
+        .iter()
+        .map(|stage| throttled_stage(stage, niceness, cpu_limit))
+        .collect::<Vec<String>>()
+        .join(" | ")
+}
+
+fn throttled_stage(command: &str, niceness: i32, cpu_limit: u32) -> String {
+    if command.starts_with(BUILTIN_PREFIX) {
+        return command.to_string();
+    }
+
+    let mut prefix = String::new();
+    if niceness != 0 {
+        prefix += &format!("nice -n {niceness} ");
+    }
+    if cpu_limit != 0 {
+        prefix += &format!("cpulimit -f -l {cpu_limit} -- ");
+    }
+
+    match command.strip_prefix(SHELL_PREFIX) {
+        Some(script) if !prefix.is_empty() => {
+            format!("{SHELL_PREFIX}{prefix}sh -c '{}'", script.replace('\'', "'\\''"))
+        }
+        _ => prefix + command,
+    }
+}
+
+
+/// Executes a &str as a metric command. Replacing %a with file_a and %b with
+/// file_b.
+///
+/// The first number found in the standard output of the command is returned.
+pub fn execute_metric_command_str(command: &str, file_a: &Path, file_b: &Path, sandbox: Option<&Path>) -> Result<f64, String> {
+    let mut cmd = build_command(command, &[("%a", file_a), ("%b", file_b)], sandbox)
+        .map_err(|e| e.to_string())?
+        .ok_or_else(|| "Empty metric command".to_string())?;
+
+    let output = cmd.output().map_err(|e| format!("Failed to execute {command}: {e}"))?;
+
+    parse_first_number(&String::from_utf8_lossy(&output.stdout))
+        .ok_or_else(|| format!("No number in output of {command}"))
+}
+
+
+/// Returns the first whitespace separated token which can be parsed as a
+/// number.
+fn parse_first_number(s: &str) -> Option<f64> {
+    s.split_whitespace().find_map(|token| token.parse::<f64>().ok())
 }
 
 
@@ -242,3 +555,132 @@ pub fn human_readable_size(byte_size: u64) -> String {
     }
 }
 
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn command_with_streams() {
+        let dir = std::env::temp_dir().join(format!("bimgo_streams_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let (input, output) = (dir.join("in"), dir.join("out"));
+        fs::write(&input, "image content").unwrap();
+
+        let result = execute_command_str("tr a-z A-Z %I %O", &input, &output, None).unwrap();
+        assert!(result.status.success());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "IMAGE CONTENT");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+
+    #[test]
+    fn parse_first_number_dssim() {
+        assert_eq!(parse_first_number("0.00123\timg.png\n"), Some(0.00123));
+    }
+
+    #[test]
+    fn parse_first_number_skips_text() {
+        assert_eq!(parse_first_number("score: 1.5 (3-norm 0.9)"), Some(1.5));
+        assert_eq!(parse_first_number("no number"), None);
+    }
+
+    #[test]
+    fn stderr_summary_keeps_last_lines() {
+        assert_eq!(stderr_summary(""), "no error output");
+        assert_eq!(stderr_summary("a\n\nb\nc\nd\n"), "b / c / d");
+        assert_eq!(stderr_summary(&"x".repeat(400)), format!("{}...", "x".repeat(300)));
+    }
+
+    #[test]
+    fn catch_panic_returns_message() {
+        assert_eq!(catch_panic(|| 3), Ok(3));
+        assert_eq!(catch_panic(|| panic!("static")), Err::<(), _>("static".to_string()));
+        assert_eq!(catch_panic(|| panic!("formatted {}", 3)), Err::<(), _>("formatted 3".to_string()));
+    }
+
+    #[test]
+    fn allowed_commands() {
+        let allowed = vec!["cwebp".to_string(), "/opt/bin/avifenc".to_string()];
+        assert!(check_allowed_command("cwebp %i -o %o.webp", &allowed).is_ok());
+        assert!(check_allowed_command("/opt/bin/avifenc %i %o.avif", &allowed).is_ok());
+        assert!(check_allowed_command("avifenc %i %o.avif", &allowed).is_err());
+        assert!(check_allowed_command("./cwebp %i -o %o.webp", &allowed).is_err());
+        assert!(check_allowed_command("builtin:pngstrip", &allowed).is_ok());
+        assert!(check_allowed_command("builtin:resize?max=2000 | cwebp %i -o %o", &allowed).is_ok());
+        assert!(check_allowed_command("builtin:resize?max=2000 | magick %i %o", &allowed).is_err());
+        assert!(check_allowed_command("sh:cwebp \"$BIMGO_IN\" -o \"$BIMGO_OUT\"", &allowed).is_err());
+    }
+
+    #[test]
+    fn transient_statuses() {
+        assert!(is_transient_status(&ExitStatus::from_raw(SIGKILL)));
+        assert!(is_transient_status(&ExitStatus::from_raw(EX_TEMPFAIL << 8)));
+        assert!(!is_transient_status(&ExitStatus::from_raw(1 << 8)));
+        assert!(!is_transient_status(&ExitStatus::from_raw(0)));
+    }
+
+    #[test]
+    fn parse_command_line_weight() {
+        assert_eq!(parse_command_line("cwebp %i -o %o.webp"), Ok(("cwebp %i -o %o.webp".to_string(), 1)));
+        assert_eq!(parse_command_line("weight=8 avifenc %i %o.avif"), Ok(("avifenc %i %o.avif".to_string(), 8)));
+        assert_eq!(parse_command_line("threads=4 cjxl %i %o.jxl"), Ok(("cjxl %i %o.jxl".to_string(), 4)));
+        assert!(parse_command_line("weight=0 cjxl %i %o.jxl").is_err());
+        assert!(parse_command_line("weight=x cjxl %i %o.jxl").is_err());
+    }
+
+    #[test]
+    fn quality_is_substituted() {
+        assert_eq!(with_quality("builtin:jpeg?q=%q", 75), "builtin:jpeg?q=75");
+        assert_eq!(with_quality("sh:cjpeg -quality %q < \"$BIMGO_IN\" > \"$BIMGO_OUT\"", 5), "sh:cjpeg -quality 5 < \"$BIMGO_IN\" > \"$BIMGO_OUT\"");
+        assert_eq!(with_quality("cwebp %i -o %o.webp", 75), "cwebp %i -o %o.webp");
+    }
+
+    #[test]
+    fn throttled_command_prefixes() {
+        assert_eq!(throttled_command("cwebp %i -o %o.webp", 0, 0), "cwebp %i -o %o.webp");
+        assert_eq!(throttled_command("cwebp %i -o %o.webp", 10, 0), "nice -n 10 cwebp %i -o %o.webp");
+        assert_eq!(
+            throttled_command("cwebp %i -o %o.webp", 5, 50),
+            "nice -n 5 cpulimit -f -l 50 -- cwebp %i -o %o.webp"
+        );
+        assert_eq!(
+            throttled_command("sh:cjpeg < \"$BIMGO_IN\" > 'out'", 10, 0),
+            "sh:nice -n 10 sh -c 'cjpeg < \"$BIMGO_IN\" > '\\''out'\\'''"
+        );
+    }
+
+    #[test]
+    fn pipeline_stages_split() {
+        assert_eq!(pipeline_stages("cwebp %i -o %o"), ["cwebp %i -o %o"]);
+        assert_eq!(
+            pipeline_stages("builtin:resize?max=2000 | cwebp %i -o %o"),
+            ["builtin:resize?max=2000", "cwebp %i -o %o"]
+        );
+        assert_eq!(
+            pipeline_stages("builtin:resize?max=2000 | sh:djpeg \"$BIMGO_IN\" | cjpeg > \"$BIMGO_OUT\""),
+            ["builtin:resize?max=2000", "sh:djpeg \"$BIMGO_IN\" | cjpeg > \"$BIMGO_OUT\""]
+        );
+        assert_eq!(
+            throttled_command("builtin:resize?max=2000 | cwebp %i -o %o", 10, 0),
+            "builtin:resize?max=2000 | nice -n 10 cwebp %i -o %o"
+        );
+    }
+
+    #[test]
+    fn shell_command_exports_paths() {
+        let dir = std::env::temp_dir().join(format!("bimgo_shell_{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        // The paths are never interpreted by the shell.
+        let (input, output) = (dir.join("in $(false)"), dir.join("out"));
+        fs::write(&input, "image content").unwrap();
+
+        let script = throttled_command("sh:tr a-z A-Z < \"$BIMGO_IN\" | tr ' ' _ > \"$BIMGO_OUT\"", 5, 0);
+        let result = execute_command_str(&script, &input, &output, None).unwrap();
+        assert!(result.status.success());
+        assert_eq!(fs::read_to_string(&output).unwrap(), "IMAGE_CONTENT");
+
+        let _ = fs::remove_dir_all(&dir);
+    }
+}